@@ -0,0 +1,307 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use crate::frontmatter::split_frontmatter;
+use crate::links::locate_phrase;
+use crate::offsets::byte_to_utf16_offset;
+use crate::outline::parse_headings;
+
+/// A child note as proposed by the model, before its range is checked against the note
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct ProposedChild {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub reason: String,
+    /// Heading whose section should move, if the split follows the note's headings
+    #[serde(default)]
+    pub heading: Option<String>,
+    /// First and last few words of the content to move, copied from the note
+    #[serde(default)]
+    pub start_quote: String,
+    #[serde(default)]
+    pub end_quote: String,
+    /// Wiki link targets for the child (the parent first)
+    #[serde(default)]
+    pub links: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct ProposedAtomicity {
+    #[serde(default)]
+    pub score: f32,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub children: Vec<ProposedChild>,
+}
+
+/// How a child note's range was found in the parent
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitRangeSource {
+    /// The whole section under a heading
+    Heading,
+    /// Located from the quoted first and last words
+    Quotes,
+}
+
+/// A child note to split out. `start`/`end` are char offsets into the parent and `content` is
+/// exactly that range, so a split command can move it as is.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChildNote {
+    pub title: String,
+    pub reason: String,
+    pub heading: Option<String>,
+    pub start: usize,
+    pub end: usize,
+    /// The same range in bytes and UTF-16 units (see `offsets`)
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+    pub content: String,
+    pub source: SplitRangeSource,
+    /// The range was trimmed to avoid another child's, or its end quote wasn't found and it was
+    /// extended to the end of the section
+    pub adjusted: bool,
+    /// Wiki links for the child, the parent first ("[[Parent]]")
+    pub back_links: Vec<String>,
+}
+
+/// A proposed child that was dropped, and why
+#[derive(Serialize, Debug, Clone)]
+pub struct RejectedChild {
+    pub title: String,
+    pub reason: String,
+}
+
+/// Result of `analyze_atomicity_with_llm`
+#[derive(Serialize, Debug, Clone)]
+pub struct AtomicityAnalysis {
+    /// 0.0 (many unrelated topics) to 1.0 (one focused idea)
+    pub score: f32,
+    pub reason: String,
+    /// Non-overlapping, in document order
+    pub children: Vec<ChildNote>,
+    pub rejected: Vec<RejectedChild>,
+}
+
+/// Scores given out of 10 or 100 are rescaled; the result is clamped to 0.0..=1.0
+fn normalize_atomicity_score(score: f32) -> f32 {
+    if !score.is_finite() {
+        return 0.0;
+    }
+    let score = if score > 10.0 { score / 100.0 } else if score > 1.0 { score / 10.0 } else { score };
+    score.clamp(0.0, 1.0)
+}
+
+/// Title usable as a file name and wiki link target
+fn sanitize_note_title(title: &str) -> String {
+    title.chars()
+        .map(|c| if matches!(c, '[' | ']' | '|' | '#' | '^' | ':' | '\\' | '/' | '*' | '?' | '"' | '<' | '>') { ' ' } else { c })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Wiki link target from "[[Target|Alias]]", "[[Target]]" or a bare title
+fn link_target(link: &str) -> String {
+    let inner = link.trim().trim_start_matches("[[").trim_end_matches("]]");
+    let target = inner.split('|').next().unwrap_or_default();
+    // Keep a heading anchor but clean the note name
+    match target.split_once('#') {
+        Some((note, anchor)) => {
+            let note = sanitize_note_title(note);
+            let anchor = anchor.trim();
+            if note.is_empty() || anchor.is_empty() { note } else { format!("{}#{}", note, anchor) }
+        }
+        None => sanitize_note_title(target),
+    }
+}
+
+struct Heading {
+    level: usize,
+    text: String,
+    /// Byte offset of the heading line
+    start: usize,
+}
+
+/// Headings outside fenced code and the frontmatter, with the byte offset of their line
+fn note_headings(content: &str) -> Vec<Heading> {
+    parse_headings(content).into_iter()
+        .map(|h| Heading { level: h.level, text: h.text, start: h.start })
+        .collect()
+}
+
+fn normalize_heading(text: &str) -> String {
+    text.trim()
+        .trim_start_matches('#')
+        .trim()
+        .trim_end_matches(':')
+        .trim()
+        .to_lowercase()
+}
+
+/// Index of the heading the model named: exact (ignoring case and #s) first, then the first
+/// heading that contains the name or is contained in it
+fn find_heading(headings: &[Heading], wanted: &str) -> Option<usize> {
+    let wanted = normalize_heading(wanted);
+    if wanted.is_empty() {
+        return None;
+    }
+    headings.iter().position(|h| normalize_heading(&h.text) == wanted).or_else(|| {
+        headings.iter().position(|h| {
+            let text = normalize_heading(&h.text);
+            !text.is_empty() && (text.contains(&wanted) || wanted.contains(&text))
+        })
+    })
+}
+
+/// End of the section starting at heading `index`: the next heading of the same or a higher level
+fn section_end(headings: &[Heading], index: usize, len: usize) -> usize {
+    headings[index + 1..].iter()
+        .find(|h| h.level <= headings[index].level)
+        .map_or(len, |h| h.start)
+}
+
+fn line_start(content: &str, at: usize) -> usize {
+    content[..at].rfind('\n').map_or(0, |i| i + 1)
+}
+
+/// End of the line containing `at`, after its newline
+fn line_end(content: &str, at: usize) -> usize {
+    content[at..].find('\n').map_or(content.len(), |i| at + i + 1)
+}
+
+/// Byte range for a child from its quoted boundaries, snapped to whole lines. A missing or
+/// unmatched end quote extends the range to the next heading. Returns (start, end, adjusted).
+fn quoted_range(content: &str, headings: &[Heading], body: usize, child: &ProposedChild) -> Option<(usize, usize, bool)> {
+    let start_match = locate_phrase(&content[body..], &child.start_quote)?;
+    let start = line_start(content, body + start_match.start_byte);
+    let from = body + start_match.end_byte;
+
+    if !child.end_quote.trim().is_empty() {
+        if let Some(end_match) = locate_phrase(&content[from..], &child.end_quote) {
+            let at = from + end_match.end_byte;
+            let end = if content[..at].ends_with('\n') { at } else { line_end(content, at) };
+            return Some((start, end, false));
+        }
+    }
+    let end = headings.iter().map(|h| h.start).find(|&h| h > start).unwrap_or(content.len());
+    Some((start, end, !child.end_quote.trim().is_empty()))
+}
+
+/// Turn the model's proposal into children whose ranges exist in `content`. Ranges come from
+/// the named heading's section when it's found, otherwise from the quoted boundaries (fuzzy
+/// matched). Where ranges overlap, heading sections and exactly located ranges win; the other
+/// range is trimmed, or dropped if less than half of it is left. Ranges that would leave the
+/// parent empty are dropped too.
+pub(crate) fn resolve_atomicity(proposal: ProposedAtomicity, parent_title: &str, content: &str) -> AtomicityAnalysis {
+    let headings = note_headings(content);
+    let body = split_frontmatter(content).1;
+    let parent = sanitize_note_title(parent_title);
+    let mut rejected = Vec::new();
+    let mut located: Vec<(ChildNote, usize, usize)> = Vec::new();
+    let mut titles: HashSet<String> = HashSet::new();
+
+    for child in proposal.children {
+        let title = sanitize_note_title(&child.title);
+        let mut reject = |reason: &str| rejected.push(RejectedChild { title: child.title.clone(), reason: reason.to_string() });
+        if title.is_empty() {
+            reject("missing title");
+            continue;
+        }
+        if title.eq_ignore_ascii_case(&parent) || !titles.insert(title.to_lowercase()) {
+            reject("duplicate title");
+            continue;
+        }
+
+        let heading_index = child.heading.as_deref()
+            .and_then(|h| find_heading(&headings, h))
+            .filter(|&i| headings[i].start >= body);
+        let (start, end, adjusted, source, heading) = match heading_index {
+            Some(i) => (headings[i].start, section_end(&headings, i, content.len()), false, SplitRangeSource::Heading, Some(headings[i].text.clone())),
+            None => match quoted_range(content, &headings, body, &child) {
+                Some((start, end, adjusted)) => (start, end, adjusted, SplitRangeSource::Quotes, None),
+                None => {
+                    reject("content not found in the note");
+                    continue;
+                }
+            },
+        };
+
+        let mut back_links = Vec::new();
+        let mut seen: HashSet<String> = HashSet::new();
+        for target in std::iter::once(parent.clone()).chain(child.links.iter().map(|l| link_target(l))) {
+            if !target.is_empty() && !target.eq_ignore_ascii_case(&title) && seen.insert(target.to_lowercase()) {
+                back_links.push(format!("[[{}]]", target));
+            }
+        }
+
+        located.push((ChildNote {
+            title,
+            reason: child.reason.trim().to_string(),
+            heading,
+            start: 0,
+            end: 0,
+            offset_bytes: 0,
+            end_bytes: 0,
+            offset_utf16: 0,
+            end_utf16: 0,
+            content: String::new(),
+            source,
+            adjusted,
+            back_links,
+        }, start, end));
+    }
+
+    // Exact heading sections claim their range first, then exact quoted ranges, then the rest
+    located.sort_by_key(|(child, _, _)| (child.adjusted, child.source != SplitRangeSource::Heading));
+    let mut claimed: Vec<(usize, usize)> = Vec::new();
+    let mut children = Vec::new();
+    for (mut child, mut start, mut end) in located {
+        let original_len = end - start;
+        for &(a, b) in &claimed {
+            if start >= b || end <= a {
+                continue;
+            }
+            if start >= a {
+                start = b;
+            } else if end <= b {
+                end = a;
+            } else {
+                // Would swallow another child whole
+                end = start;
+            }
+            child.adjusted = true;
+        }
+        // Trimming that leaves less than half of the range means the model meant the same content
+        if end <= start || (end - start) * 2 < original_len || content[start..end].trim().is_empty() {
+            rejected.push(RejectedChild { title: child.title, reason: "overlaps another child note".to_string() });
+            continue;
+        }
+        if content[body..start].trim().is_empty() && content[end..].trim().is_empty() {
+            rejected.push(RejectedChild { title: child.title, reason: "would move the whole note".to_string() });
+            continue;
+        }
+
+        child.start = content[..start].chars().count();
+        child.content = content[start..end].to_string();
+        child.end = child.start + child.content.chars().count();
+        child.offset_bytes = start;
+        child.end_bytes = end;
+        child.offset_utf16 = byte_to_utf16_offset(content, start);
+        child.end_utf16 = child.offset_utf16 + child.content.encode_utf16().count();
+        claimed.push((start, end));
+        children.push(child);
+    }
+    children.sort_by_key(|child| child.start);
+
+    AtomicityAnalysis {
+        score: normalize_atomicity_score(proposal.score),
+        reason: proposal.reason.trim().to_string(),
+        children,
+        rejected,
+    }
+}
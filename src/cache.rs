@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use crate::clock::now_ms;
 
 /// Cache file format version and metadata
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -7,6 +9,64 @@ pub struct CacheHeader {
     pub version: u32,
     pub format: String,  // "msgpack" or "json"
     pub created_at: u64,
+    /// xxhash64 of the serialized `data` section (absent in caches written before checksums)
+    #[serde(default)]
+    pub checksum: Option<u64>,
+    /// Embedding model that produced the cached vectors (embeddings caches only)
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+    /// Dimension of the cached vectors (embeddings caches only)
+    #[serde(default)]
+    pub embedding_dim: Option<usize>,
+    /// Task prefix the vectors were embedded with ("document", "query" or "none")
+    #[serde(default)]
+    pub embedding_task: Option<String>,
+    /// Model, dimension and size of every embedding space (embedding spaces caches only)
+    #[serde(default)]
+    pub embedding_spaces: Option<Vec<EmbeddingSpaceInfo>>,
+}
+
+/// Errors raised while loading a versioned cache
+#[derive(Debug)]
+pub enum CacheError {
+    /// The bytes could not be decoded as a versioned cache
+    Decode(rmp_serde::decode::Error),
+    /// The data section does not match the checksum recorded in the header
+    ChecksumMismatch { expected: u64, actual: u64 },
+    /// The header decoded but the data section after it, starting at byte `offset`, didn't
+    Body { header: Box<CacheHeader>, offset: usize, source: rmp_serde::decode::Error },
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Decode(e) => write!(f, "{}", e),
+            CacheError::ChecksumMismatch { expected, actual } => write!(
+                f, "checksum mismatch (expected {:016x}, found {:016x}) - cache file is corrupt", expected, actual
+            ),
+            CacheError::Body { offset, source, .. } => write!(f, "{} (data section starting at byte {})", source, offset),
+        }
+    }
+}
+
+impl From<rmp_serde::decode::Error> for CacheError {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        CacheError::Decode(e)
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    xxhash_rust::xxh64::xxh64(bytes, 0)
+}
+
+/// The one way a JS timestamp in ms (`stat.mtime`, which can have a fractional part) becomes a
+/// cache index mtime: rounded to the nearest ms. Negative, NaN and infinite values become 0,
+/// values beyond u64 saturate.
+pub fn normalize_mtime(ms: f64) -> u64 {
+    if !ms.is_finite() || ms <= 0.0 {
+        return 0;
+    }
+    ms.round() as u64
 }
 
 /// Unified cache index for tracking file modification times and ignored suggestions.
@@ -21,8 +81,199 @@ pub struct CacheIndex {
     pub suggestion_mtimes: HashMap<String, u64>,
     /// Ignored suggestions (key: "source|target" -> timestamp when ignored)
     pub ignored_suggestions: HashMap<String, u64>,
-    /// Insertion cache (key: "filepath::linktitle" -> InsertionResult as JSON string)
-    pub insertion_cache: HashMap<String, String>,
+    /// Insertion cache (key: "filepath::linktitle" -> InsertionResult)
+    /// Older caches stored these as JSON strings; they are converted on load.
+    #[serde(deserialize_with = "deserialize_insertion_cache")]
+    pub insertion_cache: HashMap<String, InsertionResult>,
+    /// Expiry times for temporary ignores (key: "source|target" -> expires_at in ms)
+    #[serde(default)]
+    pub ignore_expirations: HashMap<String, u64>,
+    /// Target path prefixes that are never suggested (prefix -> timestamp when ignored)
+    #[serde(default)]
+    pub ignored_target_prefixes: HashMap<String, u64>,
+    /// Processing failures used for retry backoff (key: "kind|path")
+    #[serde(default)]
+    pub processing_failures: HashMap<String, FailureRecord>,
+    /// LLM reranking results (key: rerank_cache_key of content, candidates and model)
+    #[serde(default)]
+    pub rerank_cache: HashMap<String, RerankCacheEntry>,
+    /// When the last full vault scan finished (ms), for incremental scans
+    #[serde(default)]
+    pub last_scan_time: Option<u64>,
+    /// Embedding model `embedding_mtimes` belongs to; None in indexes written before embedding
+    /// spaces, or until a model is activated
+    #[serde(default)]
+    pub embedding_space: Option<String>,
+    /// `embedding_mtimes` of the other embedding models (model -> path -> mtime in ms)
+    #[serde(default)]
+    pub inactive_embedding_mtimes: HashMap<String, HashMap<String, u64>>,
+}
+
+/// Merge `theirs` into `mine`, counting what changed in `summary`
+fn merge_mtimes(mine: &mut HashMap<String, u64>, theirs: &HashMap<String, u64>, strategy: MergeStrategy, summary: &mut MergeSummary) {
+    for (path, &their_mtime) in theirs {
+        match mine.get(path) {
+            None => {
+                mine.insert(path.clone(), their_mtime);
+                summary.mtimes_added += 1;
+            }
+            Some(&my_mtime) if my_mtime != their_mtime => {
+                let take_theirs = match strategy {
+                    MergeStrategy::NewestWins => their_mtime > my_mtime,
+                    MergeStrategy::PreferOther => true,
+                    MergeStrategy::PreferLocal => false,
+                };
+                if take_theirs {
+                    mine.insert(path.clone(), their_mtime);
+                    summary.mtimes_updated += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Optional fields are always written (as undefined in JS), so the rerank cache can store
+// suggestions in MessagePack, which lays fields out by position
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RankedSuggestion {
+    pub path: String,
+    pub title: String,
+    pub similarity: f32,
+    #[serde(default)]
+    pub llm_score: Option<f32>,
+    #[serde(default)]
+    pub llm_reason: Option<String>,
+    pub context: String,
+    /// Blend of LLM score and similarity used for the final ordering (0.0 - 1.0)
+    #[serde(default)]
+    pub combined_score: f32,
+}
+
+/// What rerank_suggestions_with_llm returns
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RerankResult {
+    pub suggestions: Vec<RankedSuggestion>,
+    /// True if the LLM wasn't asked (too few candidates or an empty document); the
+    /// suggestions are then ranked by embedding similarity alone
+    pub skipped_llm: bool,
+    /// How many candidates got a valid LLM score; the rest are ranked on embeddings alone
+    pub ranking_coverage: usize,
+}
+
+/// Most rerank results kept; the oldest are evicted beyond this
+pub const MAX_RERANK_CACHE_ENTRIES: usize = 500;
+
+/// Rerank results older than this (7 days) are evicted, and never returned
+pub const RERANK_CACHE_MAX_AGE_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+/// Cached output of rerank_suggestions_with_llm
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RerankCacheEntry {
+    /// Note the suggestions were ranked for, so the entry is dropped when it changes
+    pub source_path: String,
+    pub result: RerankResult,
+    pub created_at: u64,
+}
+
+/// Key for the rerank cache: a hash of the document content, the candidate paths
+/// (order-independent) and the model, as 16 hex digits
+pub fn make_rerank_key(document_content: &str, candidate_paths: &[String], model: &str) -> String {
+    let mut paths: Vec<&str> = candidate_paths.iter().map(String::as_str).collect();
+    paths.sort_unstable();
+    paths.dedup();
+    let content_hash = checksum(document_content.as_bytes());
+    let material = format!("{:016x}|{}|{}", content_hash, paths.join("\n"), model);
+    format!("{:016x}", checksum(material.as_bytes()))
+}
+
+/// Kind of per-file processing tracked by the cache index
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessingKind {
+    Embedding,
+    Keyword,
+    Suggestion,
+}
+
+impl ProcessingKind {
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "embedding" => Some(ProcessingKind::Embedding),
+            "keyword" => Some(ProcessingKind::Keyword),
+            "suggestion" => Some(ProcessingKind::Suggestion),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ProcessingKind::Embedding => "embedding",
+            ProcessingKind::Keyword => "keyword",
+            ProcessingKind::Suggestion => "suggestion",
+        }
+    }
+}
+
+/// Consecutive processing failures for one (path, kind)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FailureRecord {
+    pub count: u32,
+    /// Time of the last failed attempt (ms since epoch)
+    pub last_attempt: u64,
+}
+
+impl FailureRecord {
+    /// Backoff before the next retry: 1m after the first failure, 10m after the second, then 1h
+    pub fn backoff_ms(&self) -> u64 {
+        match self.count {
+            0 => 0,
+            1 => 60_000,
+            2 => 600_000,
+            _ => 3_600_000,
+        }
+    }
+}
+
+/// Cached LLM insertion-point suggestion for a (file, link) pair
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct InsertionResult {
+    pub phrase: Option<String>,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub confidence: f32,
+    /// When the entry was cached (ms since epoch)
+    #[serde(default)]
+    pub created_at: u64,
+    /// Model that produced the suggestion (empty if unknown)
+    #[serde(default)]
+    pub model: String,
+}
+
+/// Insertion cache values as they may appear on disk: typed entries, or the
+/// JSON strings written by caches created before entries were typed.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredInsertion {
+    Typed(InsertionResult),
+    Legacy(String),
+}
+
+fn deserialize_insertion_cache<'de, D>(deserializer: D) -> Result<HashMap<String, InsertionResult>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let raw: HashMap<String, StoredInsertion> = HashMap::deserialize(deserializer)?;
+    Ok(raw.into_iter()
+        .filter_map(|(key, value)| match value {
+            StoredInsertion::Typed(result) => Some((key, result)),
+            // Malformed legacy strings are dropped rather than failing the whole index
+            StoredInsertion::Legacy(json) => serde_json::from_str::<InsertionResult>(&json)
+                .ok()
+                .map(|result| (key, result)),
+        })
+        .collect())
 }
 
 impl CacheIndex {
@@ -32,39 +283,81 @@ impl CacheIndex {
 
     /// Check if a file's embedding is fresh (unchanged since last processing)
     pub fn is_embedding_fresh(&self, path: &str, current_mtime: u64) -> bool {
-        self.embedding_mtimes.get(path).map_or(false, |&cached| cached == current_mtime)
+        self.embedding_mtimes.get(path).is_some_and(|&cached| cached == current_mtime)
     }
 
     /// Check if a file's keywords are fresh
     pub fn is_keyword_fresh(&self, path: &str, current_mtime: u64) -> bool {
-        self.keyword_mtimes.get(path).map_or(false, |&cached| cached == current_mtime)
+        self.keyword_mtimes.get(path).is_some_and(|&cached| cached == current_mtime)
     }
 
     /// Check if a file's suggestions are fresh
     pub fn is_suggestion_fresh(&self, path: &str, current_mtime: u64) -> bool {
-        self.suggestion_mtimes.get(path).map_or(false, |&cached| cached == current_mtime)
+        self.suggestion_mtimes.get(path).is_some_and(|&cached| cached == current_mtime)
     }
 
     /// Mark a file's embedding as processed with the given mtime
     pub fn mark_embedding_processed(&mut self, path: &str, mtime: u64) {
         self.embedding_mtimes.insert(path.to_string(), mtime);
+        self.clear_processing_failure(path, ProcessingKind::Embedding);
     }
 
     /// Mark a file's keywords as processed
     pub fn mark_keyword_processed(&mut self, path: &str, mtime: u64) {
         self.keyword_mtimes.insert(path.to_string(), mtime);
+        self.clear_processing_failure(path, ProcessingKind::Keyword);
     }
 
     /// Mark a file's suggestions as processed
     pub fn mark_suggestion_processed(&mut self, path: &str, mtime: u64) {
         self.suggestion_mtimes.insert(path.to_string(), mtime);
+        self.clear_processing_failure(path, ProcessingKind::Suggestion);
+    }
+
+    // --- Failure Tracking ---
+
+    fn make_failure_key(path: &str, kind: ProcessingKind) -> String {
+        format!("{}|{}", kind.as_str(), path)
+    }
+
+    /// Record a failed processing attempt for a file
+    pub fn record_processing_failure(&mut self, path: &str, kind: ProcessingKind, now_ms: u64) {
+        let record = self.processing_failures
+            .entry(Self::make_failure_key(path, kind))
+            .or_default();
+        record.count += 1;
+        record.last_attempt = now_ms;
+    }
+
+    /// Check whether a file that previously failed may be retried yet (exponential backoff)
+    pub fn should_retry(&self, path: &str, kind: ProcessingKind, now_ms: u64) -> bool {
+        self.processing_failures
+            .get(&Self::make_failure_key(path, kind))
+            .is_none_or(|record| now_ms >= record.last_attempt.saturating_add(record.backoff_ms()))
+    }
+
+    /// Forget failures for a file (called on successful processing)
+    pub fn clear_processing_failure(&mut self, path: &str, kind: ProcessingKind) {
+        self.processing_failures.remove(&Self::make_failure_key(path, kind));
+    }
+
+    /// Get the failure record for a file, if any
+    pub fn get_processing_failure(&self, path: &str, kind: ProcessingKind) -> Option<&FailureRecord> {
+        self.processing_failures.get(&Self::make_failure_key(path, kind))
     }
 
     /// Invalidate all caches for a specific file
     pub fn invalidate_file(&mut self, path: &str) {
         self.embedding_mtimes.remove(path);
+        for mtimes in self.inactive_embedding_mtimes.values_mut() {
+            mtimes.remove(path);
+        }
         self.keyword_mtimes.remove(path);
         self.suggestion_mtimes.remove(path);
+        // Changed content deserves a fresh attempt
+        for kind in [ProcessingKind::Embedding, ProcessingKind::Keyword, ProcessingKind::Suggestion] {
+            self.clear_processing_failure(path, kind);
+        }
         // Also remove insertion cache entries for this file
         let keys_to_remove: Vec<String> = self.insertion_cache.keys()
             .filter(|k| k.starts_with(&format!("{}::", path)))
@@ -73,15 +366,158 @@ impl CacheIndex {
         for key in keys_to_remove {
             self.insertion_cache.remove(&key);
         }
+        self.rerank_cache.retain(|_, entry| entry.source_path != path);
+    }
+
+    /// Load a cache index in either the versioned or the legacy (raw) format
+    pub fn from_msgpack_any(data: &[u8]) -> Result<CacheIndex, CacheError> {
+        match VersionedCache::<CacheIndex>::from_msgpack(data) {
+            Ok(versioned) => Ok(versioned.data),
+            Err(e @ CacheError::ChecksumMismatch { .. }) => Err(e),
+            Err(e) => rmp_serde::from_slice::<CacheIndex>(data).map_err(|_| e),
+        }
+    }
+
+    /// Compute per-path differences between two cache indexes
+    pub fn diff(a: &CacheIndex, b: &CacheIndex) -> CacheIndexDiff {
+        let ignored_a = a.get_ignored_suggestions();
+        let ignored_b = b.get_ignored_suggestions();
+        let only_in = |left: &[IgnoredSuggestion], right: &[IgnoredSuggestion]| {
+            let mut result: Vec<IgnoredSuggestion> = left.iter()
+                .filter(|l| !right.iter().any(|r| r.kind == l.kind && r.source_file == l.source_file && r.target_file == l.target_file))
+                .cloned()
+                .collect();
+            result.sort_by(|x, y| (&x.source_file, &x.target_file).cmp(&(&y.source_file, &y.target_file)));
+            result
+        };
+
+        CacheIndexDiff {
+            embeddings: MtimeMapDiff::between(&a.embedding_mtimes, &b.embedding_mtimes),
+            keywords: MtimeMapDiff::between(&a.keyword_mtimes, &b.keyword_mtimes),
+            suggestions: MtimeMapDiff::between(&a.suggestion_mtimes, &b.suggestion_mtimes),
+            ignored_only_in_a: only_in(&ignored_a, &ignored_b),
+            ignored_only_in_b: only_in(&ignored_b, &ignored_a),
+        }
+    }
+
+    /// Merge another cache index into this one.
+    /// Mtime maps follow `strategy`, embedding mtimes only with those of the same model;
+    /// ignores and insertion results are always unioned.
+    /// Only the index is merged - callers taking newer mtimes should also take the
+    /// matching embeddings, otherwise stale vectors will be considered fresh.
+    pub fn merge(&mut self, other: &CacheIndex, strategy: MergeStrategy) -> MergeSummary {
+        let mut summary = MergeSummary::default();
+
+        // Embedding mtimes of each model on the other side; an index from before embedding
+        // spaces is taken to be of the same model as this one
+        let mut their_spaces: Vec<(Option<&String>, &HashMap<String, u64>)> = vec![
+            (other.embedding_space.as_ref().or(self.embedding_space.as_ref()), &other.embedding_mtimes),
+        ];
+        their_spaces.extend(other.inactive_embedding_mtimes.iter().map(|(model, mtimes)| (Some(model), mtimes)));
+        for (model, theirs) in their_spaces {
+            if model == self.embedding_space.as_ref() {
+                merge_mtimes(&mut self.embedding_mtimes, theirs, strategy, &mut summary);
+            } else if let Some(model) = model {
+                let mine = self.inactive_embedding_mtimes.entry(model.clone()).or_default();
+                merge_mtimes(mine, theirs, strategy, &mut summary);
+            }
+        }
+        merge_mtimes(&mut self.keyword_mtimes, &other.keyword_mtimes, strategy, &mut summary);
+        merge_mtimes(&mut self.suggestion_mtimes, &other.suggestion_mtimes, strategy, &mut summary);
+        self.inactive_embedding_mtimes.retain(|_, mtimes| !mtimes.is_empty());
+
+        // Union ignores. A permanent ignore on either side stays permanent;
+        // otherwise the later expiry wins.
+        for (key, &timestamp) in &other.ignored_suggestions {
+            let existed = self.ignored_suggestions.contains_key(key);
+            let entry = self.ignored_suggestions.entry(key.clone()).or_insert(timestamp);
+            *entry = (*entry).max(timestamp);
+
+            match (existed, self.ignore_expirations.get(key).copied(), other.ignore_expirations.get(key).copied()) {
+                (false, _, Some(theirs)) => { self.ignore_expirations.insert(key.clone(), theirs); }
+                (true, Some(_), None) => { self.ignore_expirations.remove(key); }
+                (true, Some(mine), Some(theirs)) if theirs > mine => { self.ignore_expirations.insert(key.clone(), theirs); }
+                _ => {}
+            }
+            if !existed {
+                summary.ignores_added += 1;
+            }
+        }
+        for (prefix, &timestamp) in &other.ignored_target_prefixes {
+            if !self.ignored_target_prefixes.contains_key(prefix) {
+                self.ignored_target_prefixes.insert(prefix.clone(), timestamp);
+                summary.ignores_added += 1;
+            }
+        }
+
+        // The later scan covers the earlier one only for files both sides have seen, so keep
+        // the earlier time to avoid skipping files the other side never scanned
+        self.last_scan_time = match (self.last_scan_time, other.last_scan_time) {
+            (Some(mine), Some(theirs)) => Some(mine.min(theirs)),
+            _ => None,
+        };
+
+        // Insertion results: keep the most recently created entry
+        for (key, result) in &other.insertion_cache {
+            let newer = self.insertion_cache.get(key).is_none_or(|mine| result.created_at > mine.created_at);
+            if newer {
+                self.insertion_cache.insert(key.clone(), result.clone());
+            }
+        }
+
+        summary
     }
 
-    /// Clear all cache data
+    /// Clear all cache data. The active embedding model stays active.
     pub fn clear(&mut self) {
         self.embedding_mtimes.clear();
+        self.inactive_embedding_mtimes.clear();
         self.keyword_mtimes.clear();
         self.suggestion_mtimes.clear();
         self.ignored_suggestions.clear();
         self.insertion_cache.clear();
+        self.ignore_expirations.clear();
+        self.ignored_target_prefixes.clear();
+        self.processing_failures.clear();
+        self.rerank_cache.clear();
+        self.last_scan_time = None;
+    }
+
+    // --- Embedding Spaces ---
+
+    /// Make `model`'s embedding mtimes the active ones, setting aside those of the model that
+    /// was active. An index that has no active model yet takes its mtimes to be `model`'s.
+    pub fn switch_embedding_space(&mut self, model: &str) {
+        if self.embedding_space.as_deref() == Some(model) {
+            return;
+        }
+        let restored = self.inactive_embedding_mtimes.remove(model).unwrap_or_default();
+        match self.embedding_space.replace(model.to_string()) {
+            Some(previous) => {
+                let set_aside = std::mem::replace(&mut self.embedding_mtimes, restored);
+                if !set_aside.is_empty() {
+                    self.inactive_embedding_mtimes.insert(previous, set_aside);
+                }
+            }
+            None if self.embedding_mtimes.is_empty() => self.embedding_mtimes = restored,
+            None => {}
+        }
+    }
+
+    /// Forget the embedding mtimes of a model that isn't active
+    pub fn drop_embedding_space(&mut self, model: &str) -> bool {
+        self.inactive_embedding_mtimes.remove(model).is_some()
+    }
+
+    // --- Scan Time ---
+
+    /// Remember that a vault scan finished at `now_ms`
+    pub fn record_scan_completed(&mut self, now_ms: u64) {
+        self.last_scan_time = Some(now_ms);
+    }
+
+    pub fn get_last_scan_time(&self) -> Option<u64> {
+        self.last_scan_time
     }
 
     // --- Ignored Suggestions ---
@@ -90,25 +526,61 @@ impl CacheIndex {
         format!("{}|{}", source, target)
     }
 
-    /// Check if a suggestion is ignored
-    pub fn is_suggestion_ignored(&self, source_file: &str, target_file: &str) -> bool {
+    /// Check if a suggestion is ignored, either as an exact pair that has not
+    /// expired yet or because the target matches an ignored prefix
+    pub fn is_suggestion_ignored(&self, source_file: &str, target_file: &str, now_ms: u64) -> bool {
         let key = Self::make_ignored_key(source_file, target_file);
-        self.ignored_suggestions.contains_key(&key)
+        let pair_ignored = self.ignored_suggestions.contains_key(&key)
+            && self.ignore_expirations.get(&key).is_none_or(|&expires_at| now_ms < expires_at);
+
+        pair_ignored || self.ignored_target_prefixes.keys().any(|prefix| target_file.starts_with(prefix.as_str()))
     }
 
-    /// Ignore a suggestion
+    /// Ignore a suggestion permanently
     pub fn ignore_suggestion(&mut self, source_file: &str, target_file: &str) {
         let key = Self::make_ignored_key(source_file, target_file);
-        self.ignored_suggestions.insert(key, js_sys::Date::now() as u64);
+        self.ignore_expirations.remove(&key);
+        self.ignored_suggestions.insert(key, now_ms() as u64);
+    }
+
+    /// Ignore a suggestion until the given time (ms since epoch)
+    pub fn ignore_suggestion_until(&mut self, source_file: &str, target_file: &str, expires_at_ms: u64) {
+        let key = Self::make_ignored_key(source_file, target_file);
+        self.ignored_suggestions.insert(key.clone(), now_ms() as u64);
+        self.ignore_expirations.insert(key, expires_at_ms);
     }
 
     /// Unignore a suggestion
     pub fn unignore_suggestion(&mut self, source_file: &str, target_file: &str) {
         let key = Self::make_ignored_key(source_file, target_file);
         self.ignored_suggestions.remove(&key);
+        self.ignore_expirations.remove(&key);
+    }
+
+    /// Never suggest targets whose path starts with `prefix` (e.g. "Templates/")
+    pub fn ignore_target_prefix(&mut self, prefix: &str) {
+        self.ignored_target_prefixes.insert(prefix.to_string(), now_ms() as u64);
+    }
+
+    /// Remove a target prefix ignore
+    pub fn unignore_target_prefix(&mut self, prefix: &str) -> bool {
+        self.ignored_target_prefixes.remove(prefix).is_some()
+    }
+
+    /// Drop temporary ignores that have expired. Returns the number removed.
+    pub fn prune_expired_ignores(&mut self, now_ms: u64) -> usize {
+        let expired: Vec<String> = self.ignore_expirations.iter()
+            .filter(|(_, &expires_at)| expires_at <= now_ms)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.ignored_suggestions.remove(key);
+            self.ignore_expirations.remove(key);
+        }
+        expired.len()
     }
 
-    /// Get all ignored suggestions as a list
+    /// Get all ignored suggestions (exact pairs and prefix patterns) as a list
     pub fn get_ignored_suggestions(&self) -> Vec<IgnoredSuggestion> {
         let mut result: Vec<IgnoredSuggestion> = self.ignored_suggestions.iter()
             .filter_map(|(key, &timestamp)| {
@@ -118,20 +590,31 @@ impl CacheIndex {
                         source_file: parts[0].to_string(),
                         target_file: parts[1].to_string(),
                         timestamp,
+                        kind: IgnoreKind::Exact,
+                        expires_at: self.ignore_expirations.get(key).copied(),
                     })
                 } else {
                     None
                 }
             })
             .collect();
+        result.extend(self.ignored_target_prefixes.iter().map(|(prefix, &timestamp)| IgnoredSuggestion {
+            source_file: String::new(),
+            target_file: prefix.clone(),
+            timestamp,
+            kind: IgnoreKind::Pattern,
+            expires_at: None,
+        }));
         // Sort by timestamp (most recently ignored first)
-        result.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        result.sort_by_key(|s| std::cmp::Reverse(s.timestamp));
         result
     }
 
-    /// Clear all ignored suggestions
+    /// Clear all ignored suggestions, including temporary and prefix ignores
     pub fn clear_ignored_suggestions(&mut self) {
         self.ignored_suggestions.clear();
+        self.ignore_expirations.clear();
+        self.ignored_target_prefixes.clear();
     }
 
     // --- Insertion Cache ---
@@ -141,15 +624,43 @@ impl CacheIndex {
     }
 
     /// Get a cached insertion result
-    pub fn get_cached_insertion(&self, file_path: &str, link_title: &str) -> Option<&String> {
+    pub fn get_cached_insertion(&self, file_path: &str, link_title: &str) -> Option<&InsertionResult> {
         let key = Self::make_insertion_key(file_path, link_title);
         self.insertion_cache.get(&key)
     }
 
-    /// Cache an insertion result
-    pub fn cache_insertion(&mut self, file_path: &str, link_title: &str, result_json: &str) {
+    /// Cache an insertion result, stamping `created_at` if the caller left it unset
+    pub fn cache_insertion(&mut self, file_path: &str, link_title: &str, mut result: InsertionResult) {
+        if result.created_at == 0 {
+            result.created_at = now_ms() as u64;
+        }
         let key = Self::make_insertion_key(file_path, link_title);
-        self.insertion_cache.insert(key, result_json.to_string());
+        self.insertion_cache.insert(key, result);
+    }
+
+    /// Cache an insertion result given as JSON (compatibility path for JS callers).
+    /// The JSON is validated here so malformed entries never reach the cache.
+    pub fn cache_insertion_json(&mut self, file_path: &str, link_title: &str, result_json: &str) -> Result<(), serde_json::Error> {
+        let result: InsertionResult = serde_json::from_str(result_json)?;
+        self.cache_insertion(file_path, link_title, result);
+        Ok(())
+    }
+
+    /// Cache the per-link results of a batch insertion call, skipping links that got no answer.
+    /// Returns the number of entries written.
+    pub fn cache_insertion_batch(&mut self, file_path: &str, results: HashMap<String, Option<InsertionResult>>, model: &str) -> usize {
+        let mut count = 0;
+        for (link_title, result) in results {
+            let Some(mut result) = result else {
+                continue;
+            };
+            if result.model.is_empty() {
+                result.model = model.to_string();
+            }
+            self.cache_insertion(file_path, &link_title, result);
+            count += 1;
+        }
+        count
     }
 
     /// Invalidate insertion cache entries for a specific file
@@ -169,14 +680,132 @@ impl CacheIndex {
     pub fn clear_insertion_cache(&mut self) {
         self.insertion_cache.clear();
     }
+
+    // --- Rerank Cache ---
+
+    /// Cached rerank results, ignoring entries older than `max_age_ms` (at most
+    /// RERANK_CACHE_MAX_AGE_MS)
+    pub fn get_cached_rerank(&self, key: &str, max_age_ms: Option<u64>, now_ms: u64) -> Option<&RerankCacheEntry> {
+        let max_age = max_age_ms.map_or(RERANK_CACHE_MAX_AGE_MS, |max_age| max_age.min(RERANK_CACHE_MAX_AGE_MS));
+        self.rerank_cache.get(key)
+            .filter(|entry| now_ms.saturating_sub(entry.created_at) <= max_age)
+    }
+
+    /// Cache rerank results for `source_path`, evicting entries past RERANK_CACHE_MAX_AGE_MS
+    /// and then the oldest beyond MAX_RERANK_CACHE_ENTRIES
+    pub fn cache_rerank(&mut self, key: &str, source_path: &str, result: RerankResult, now_ms: u64) {
+        self.rerank_cache.insert(key.to_string(), RerankCacheEntry {
+            source_path: source_path.to_string(),
+            result,
+            created_at: now_ms,
+        });
+        self.rerank_cache.retain(|_, entry| now_ms.saturating_sub(entry.created_at) <= RERANK_CACHE_MAX_AGE_MS);
+        if self.rerank_cache.len() > MAX_RERANK_CACHE_ENTRIES {
+            let mut by_age: Vec<(u64, String)> = self.rerank_cache.iter()
+                .map(|(key, entry)| (entry.created_at, key.clone()))
+                .collect();
+            by_age.sort_unstable();
+            let excess = self.rerank_cache.len() - MAX_RERANK_CACHE_ENTRIES;
+            for (_, key) in by_age.into_iter().take(excess) {
+                self.rerank_cache.remove(&key);
+            }
+        }
+    }
+
+    pub fn clear_rerank_cache(&mut self) {
+        self.rerank_cache.clear();
+    }
+}
+
+/// Differences between two mtime maps. "newer_in_*" lists paths present in both
+/// whose mtime is more recent on that side.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MtimeMapDiff {
+    pub only_in_a: Vec<String>,
+    pub only_in_b: Vec<String>,
+    pub newer_in_a: Vec<String>,
+    pub newer_in_b: Vec<String>,
+}
+
+impl MtimeMapDiff {
+    fn between(a: &HashMap<String, u64>, b: &HashMap<String, u64>) -> Self {
+        let mut diff = MtimeMapDiff::default();
+        for (path, &mtime_a) in a {
+            match b.get(path) {
+                None => diff.only_in_a.push(path.clone()),
+                Some(&mtime_b) if mtime_a > mtime_b => diff.newer_in_a.push(path.clone()),
+                Some(&mtime_b) if mtime_b > mtime_a => diff.newer_in_b.push(path.clone()),
+                _ => {}
+            }
+        }
+        diff.only_in_b = b.keys().filter(|path| !a.contains_key(*path)).cloned().collect();
+
+        diff.only_in_a.sort();
+        diff.only_in_b.sort();
+        diff.newer_in_a.sort();
+        diff.newer_in_b.sort();
+        diff
+    }
+}
+
+/// Differences between two cache indexes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheIndexDiff {
+    pub embeddings: MtimeMapDiff,
+    pub keywords: MtimeMapDiff,
+    pub suggestions: MtimeMapDiff,
+    pub ignored_only_in_a: Vec<IgnoredSuggestion>,
+    pub ignored_only_in_b: Vec<IgnoredSuggestion>,
+}
+
+/// How conflicting mtimes are resolved when merging cache indexes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// Keep whichever side processed the file more recently
+    NewestWins,
+    /// Always take the other index's mtime
+    PreferOther,
+    /// Only add paths missing locally
+    PreferLocal,
+}
+
+impl MergeStrategy {
+    pub fn parse(strategy: &str) -> Option<Self> {
+        match strategy {
+            "newest_wins" | "newest" => Some(MergeStrategy::NewestWins),
+            "prefer_other" => Some(MergeStrategy::PreferOther),
+            "prefer_local" => Some(MergeStrategy::PreferLocal),
+            _ => None,
+        }
+    }
 }
 
-/// Represents an ignored suggestion for serialization
+/// Counts of what a merge changed
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MergeSummary {
+    pub mtimes_added: usize,
+    pub mtimes_updated: usize,
+    pub ignores_added: usize,
+}
+
+/// Whether an ignore applies to one (source, target) pair or to a target path prefix
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IgnoreKind {
+    Exact,
+    Pattern,
+}
+
+/// Represents an ignored suggestion for serialization.
+/// For pattern ignores `source_file` is empty and `target_file` holds the prefix.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IgnoredSuggestion {
     pub source_file: String,
     pub target_file: String,
     pub timestamp: u64,
+    pub kind: IgnoreKind,
+    pub expires_at: Option<u64>,
 }
 
 impl CacheHeader {
@@ -184,7 +813,12 @@ impl CacheHeader {
         CacheHeader {
             version: 1,
             format: "msgpack".to_string(),
-            created_at: js_sys::Date::now() as u64,
+            created_at: now_ms() as u64,
+            checksum: None,
+            embedding_model: None,
+            embedding_dim: None,
+            embedding_task: None,
+            embedding_spaces: None,
         }
     }
 
@@ -192,7 +826,12 @@ impl CacheHeader {
         CacheHeader {
             version: 1,
             format: "json".to_string(),
-            created_at: js_sys::Date::now() as u64,
+            created_at: now_ms() as u64,
+            checksum: None,
+            embedding_model: None,
+            embedding_dim: None,
+            embedding_task: None,
+            embedding_spaces: None,
         }
     }
 }
@@ -215,9 +854,33 @@ impl<T: Serialize> VersionedCache<T> {
         VersionedCache { header, data }
     }
 
-    /// Serialize to MessagePack binary format
+    /// Record the embedding model and vector dimension in the header
+    pub fn with_embedding_meta(mut self, model: &str, dim: usize) -> Self {
+        self.header.embedding_model = Some(model.to_string());
+        self.header.embedding_dim = Some(dim);
+        self
+    }
+
+    /// Record the embedding task type (prefix family) in the header
+    pub fn with_embedding_task(mut self, task: &str) -> Self {
+        self.header.embedding_task = Some(task.to_string());
+        self
+    }
+
+    /// Serialize to MessagePack binary format.
+    /// The layout is identical to `rmp_serde::to_vec(self)` (a 2-element array of
+    /// header and data), with the header carrying a checksum of the data bytes.
     pub fn to_msgpack(&self) -> Result<Vec<u8>, rmp_serde::encode::Error> {
-        rmp_serde::to_vec(self)
+        let data_bytes = rmp_serde::to_vec(&self.data)?;
+        let mut header = self.header.clone();
+        header.checksum = Some(checksum(&data_bytes));
+
+        let mut out = Vec::with_capacity(data_bytes.len() + 64);
+        rmp::encode::write_array_len(&mut out, 2)
+            .map_err(rmp_serde::encode::Error::InvalidValueWrite)?;
+        out.extend(rmp_serde::to_vec(&header)?);
+        out.extend(data_bytes);
+        Ok(out)
     }
 
     /// Serialize to JSON string format
@@ -227,9 +890,36 @@ impl<T: Serialize> VersionedCache<T> {
 }
 
 impl<T: for<'de> Deserialize<'de>> VersionedCache<T> {
-    /// Deserialize from MessagePack binary format
-    pub fn from_msgpack(data: &[u8]) -> Result<Self, rmp_serde::decode::Error> {
-        rmp_serde::from_slice(data)
+    /// Deserialize from MessagePack binary format, verifying the data checksum
+    /// when the header has one. Caches without a checksum load unverified.
+    pub fn from_msgpack(data: &[u8]) -> Result<Self, CacheError> {
+        let mut cursor = std::io::Cursor::new(data);
+        let len = rmp::decode::read_array_len(&mut cursor)
+            .map_err(|e| CacheError::Decode(e.into()))?;
+        if len != 2 {
+            return Err(CacheError::Decode(rmp_serde::decode::Error::LengthMismatch(len)));
+        }
+
+        let header = CacheHeader::deserialize(&mut rmp_serde::Deserializer::new(&mut cursor))?;
+        let data_bytes = &data[cursor.position() as usize..];
+
+        if let Some(expected) = header.checksum {
+            let actual = checksum(data_bytes);
+            if actual != expected {
+                return Err(CacheError::ChecksumMismatch { expected, actual });
+            }
+        }
+
+        let offset = data.len() - data_bytes.len();
+        match rmp_serde::from_slice(data_bytes) {
+            Ok(data) => Ok(VersionedCache { header, data }),
+            Err(source) => Err(CacheError::Body { header: Box::new(header), offset, source }),
+        }
+    }
+
+    /// Whether the loaded data was verified against a checksum
+    pub fn is_verified(&self) -> bool {
+        self.header.checksum.is_some()
     }
 
     /// Deserialize from JSON string format
@@ -238,9 +928,159 @@ impl<T: for<'de> Deserialize<'de>> VersionedCache<T> {
     }
 }
 
+/// Why a cache file couldn't be loaded in either the versioned or the legacy format. Returned to
+/// JS as an object so the plugin can explain the failure and offer to rebuild the cache.
+#[derive(Serialize, Debug, Clone)]
+pub struct CacheLoadError {
+    /// One-line summary for logs and notices
+    pub message: String,
+    pub versioned_error: String,
+    /// None when the legacy format wasn't tried (a checksum mismatch means the file is a
+    /// versioned cache, just corrupt)
+    pub legacy_error: Option<String>,
+    pub checksum_mismatch: bool,
+    /// The versioned header, if it decoded but the data section didn't
+    pub header: Option<CacheHeader>,
+    /// Where the data section starts, if the header decoded
+    pub data_offset: Option<usize>,
+    pub input_len: usize,
+    /// A likely cause, when the input gives one away
+    pub hint: Option<String>,
+}
+
+impl CacheLoadError {
+    fn new(data: &[u8], versioned: CacheError, legacy: Option<rmp_serde::decode::Error>) -> Box<Self> {
+        let checksum_mismatch = matches!(versioned, CacheError::ChecksumMismatch { .. });
+        let (header, data_offset) = match &versioned {
+            CacheError::Body { header, offset, .. } => (Some(header.as_ref().clone()), Some(*offset)),
+            _ => (None, None),
+        };
+        let first = data.iter().find(|b| !b.is_ascii_whitespace());
+        let hint = if data.is_empty() {
+            Some("the cache file is empty".to_string())
+        } else if first == Some(&b'{') || first == Some(&b'[') {
+            Some("looks like JSON — did you pass the legacy .json file?".to_string())
+        } else if checksum_mismatch {
+            Some("it may be truncated or partly overwritten; rebuild the cache".to_string())
+        } else {
+            data_offset.map(|offset| format!("the header is intact but the data after byte {} isn't; the file may be truncated, rebuild the cache", offset))
+        };
+        let message = match &legacy {
+            Some(legacy) => format!("not a versioned cache ({}) nor a legacy one ({})", versioned, legacy),
+            None => versioned.to_string(),
+        };
+        Box::new(CacheLoadError {
+            message,
+            versioned_error: versioned.to_string(),
+            legacy_error: legacy.map(|e| e.to_string()),
+            checksum_mismatch,
+            header,
+            data_offset,
+            input_len: data.len(),
+            hint,
+        })
+    }
+}
+
+impl fmt::Display for CacheLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(hint) = &self.hint {
+            write!(f, " - {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Box<CacheLoadError>> for wasm_bindgen::JsValue {
+    fn from(e: Box<CacheLoadError>) -> Self {
+        serde_wasm_bindgen::to_value(&*e).unwrap_or_else(|_| wasm_bindgen::JsValue::from_str(&e.to_string()))
+    }
+}
+
+/// Load a cache in the versioned format, falling back to the legacy (raw data) one. The header
+/// is None for legacy caches. A checksum mismatch isn't retried as legacy.
+pub fn load_versioned_or_legacy<T: for<'de> Deserialize<'de>>(data: &[u8]) -> Result<(Option<CacheHeader>, T), Box<CacheLoadError>> {
+    match VersionedCache::<T>::from_msgpack(data) {
+        Ok(versioned) => Ok((Some(versioned.header), versioned.data)),
+        Err(e @ CacheError::ChecksumMismatch { .. }) => Err(CacheLoadError::new(data, e, None)),
+        Err(e) => rmp_serde::from_slice::<T>(data)
+            .map(|legacy| (None, legacy))
+            .map_err(|legacy| CacheLoadError::new(data, e, Some(legacy))),
+    }
+}
+
 /// Embeddings cache data structure
 pub type EmbeddingsData = HashMap<String, Vec<f32>>;
 
+/// Vectors of one embedding model as stored in an embedding spaces cache
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StoredEmbeddingSpace {
+    pub embeddings: EmbeddingsData,
+    /// Paths whose vector came from generate_local_embedding
+    #[serde(default)]
+    pub local_paths: Vec<String>,
+}
+
+/// Embedding spaces cache data: the vectors of every embedding model used in the vault
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EmbeddingSpacesData {
+    /// Vectors of the active model, which the header names
+    pub active: StoredEmbeddingSpace,
+    /// Vectors of the other models, by model
+    pub inactive: BTreeMap<String, StoredEmbeddingSpace>,
+}
+
+/// One embedding space: its model (None for vectors stored before any model was set), vector
+/// dimension and number of vectors
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EmbeddingSpaceInfo {
+    pub model: Option<String>,
+    pub dim: Option<usize>,
+    pub count: usize,
+    pub active: bool,
+}
+
+/// Outcome of loading an embeddings cache against the expected model/dimension
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingCacheStatus {
+    /// All vectors were loaded
+    Accepted,
+    /// Nothing was loaded because the cache belongs to a different model
+    Rejected,
+    /// Loaded, but vectors with the wrong dimension were dropped
+    Filtered,
+}
+
+/// Result of a checked embeddings cache load, reported to the plugin
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmbeddingCacheLoadResult {
+    pub status: EmbeddingCacheStatus,
+    /// Model recorded in the cache header, if any
+    pub cached_model: Option<String>,
+    /// Dimension recorded in the cache header, if any
+    pub cached_dim: Option<usize>,
+    pub loaded: usize,
+    pub dropped: usize,
+    /// True when the cache predates model metadata, so the model could not be checked
+    pub missing_metadata: bool,
+    /// Task type recorded in the cache header, if any
+    pub cached_task: Option<String>,
+}
+
+/// Summary of the in-memory cache state
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheStats {
+    pub embedding_count: usize,
+    pub file_count: usize,
+    pub keyword_count: usize,
+    pub ignored_count: usize,
+    pub insertion_cache_count: usize,
+    pub active_model: Option<String>,
+    pub embedding_dim: Option<usize>,
+}
+
 /// Keywords cache entry
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct KeywordEntry {
@@ -250,3 +1090,288 @@ pub struct KeywordEntry {
 
 /// Keywords cache data structure
 pub type KeywordsData = HashMap<String, KeywordEntry>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranked(path: &str, llm_score: Option<f32>) -> RankedSuggestion {
+        RankedSuggestion {
+            path: path.to_string(),
+            title: path.trim_end_matches(".md").to_string(),
+            similarity: 0.8,
+            llm_score,
+            llm_reason: llm_score.map(|_| "Related".to_string()),
+            context: String::new(),
+            combined_score: 0.75,
+        }
+    }
+
+    fn rerank_result() -> RerankResult {
+        RerankResult {
+            suggestions: vec![ranked("A.md", Some(8.0)), ranked("B.md", None)],
+            skipped_llm: false,
+            ranking_coverage: 1,
+        }
+    }
+
+    fn populated_index() -> CacheIndex {
+        let mut index = CacheIndex::new();
+        index.switch_embedding_space("nomic-embed-text");
+        index.mark_embedding_processed("Notes/A.md", 1_700_000_000_123);
+        index.switch_embedding_space("bge-m3");
+        index.mark_embedding_processed("Notes/B.md", 42);
+        index.mark_keyword_processed("Notes/A.md", 7);
+        index.mark_suggestion_processed("日本語.md", u64::MAX);
+        index.ignore_suggestion("Notes/A.md", "Notes/B.md");
+        index.ignore_suggestion_until("Notes/A.md", "Café.md", 9_000);
+        index.ignore_target_prefix("Archive/");
+        index.record_processing_failure("Notes/C.md", ProcessingKind::Embedding, 100);
+        index.cache_insertion("Notes/A.md", "B", InsertionResult {
+            phrase: Some("see B".to_string()),
+            reason: "Mentions B".to_string(),
+            confidence: 0.5,
+            created_at: 3,
+            model: "llama3".to_string(),
+        });
+        index.cache_rerank("key", "Notes/A.md", rerank_result(), 1_000);
+        index.record_scan_completed(2_000);
+        index
+    }
+
+    fn assert_same_index(loaded: &CacheIndex, index: &CacheIndex) {
+        assert_eq!(loaded.embedding_mtimes, index.embedding_mtimes);
+        assert_eq!(loaded.inactive_embedding_mtimes, index.inactive_embedding_mtimes);
+        assert_eq!(loaded.embedding_space, index.embedding_space);
+        assert_eq!(loaded.keyword_mtimes, index.keyword_mtimes);
+        assert_eq!(loaded.suggestion_mtimes, index.suggestion_mtimes);
+        assert_eq!(loaded.ignored_suggestions, index.ignored_suggestions);
+        assert_eq!(loaded.ignore_expirations, index.ignore_expirations);
+        assert_eq!(loaded.ignored_target_prefixes, index.ignored_target_prefixes);
+        assert_eq!(loaded.insertion_cache, index.insertion_cache);
+        assert_eq!(loaded.rerank_cache, index.rerank_cache);
+        assert_eq!(loaded.last_scan_time, index.last_scan_time);
+        let failure = loaded.get_processing_failure("Notes/C.md", ProcessingKind::Embedding).unwrap();
+        assert_eq!((failure.count, failure.last_attempt), (1, 100));
+    }
+
+    #[test]
+    fn cache_index_round_trips_through_msgpack() {
+        let index = populated_index();
+        let versioned = VersionedCache::new(index.clone(), "msgpack").to_msgpack().unwrap();
+        assert_same_index(&CacheIndex::from_msgpack_any(&versioned).unwrap(), &index);
+
+        let legacy = rmp_serde::to_vec(&index).unwrap();
+        assert_same_index(&CacheIndex::from_msgpack_any(&legacy).unwrap(), &index);
+    }
+
+    #[test]
+    fn rerank_entries_round_trip_through_msgpack() {
+        let mut index = CacheIndex::new();
+        index.cache_rerank("key", "Source.md", rerank_result(), 1_000);
+        let bytes = VersionedCache::new(index, "msgpack").to_msgpack().unwrap();
+        let loaded = CacheIndex::from_msgpack_any(&bytes).unwrap();
+        let entry = loaded.get_cached_rerank("key", None, 2_000).unwrap();
+        assert_eq!(entry.result, rerank_result());
+        assert_eq!(entry.source_path, "Source.md");
+    }
+
+    #[test]
+    fn rerank_cache_evicts_the_oldest_beyond_the_cap() {
+        let mut index = CacheIndex::new();
+        for i in 0..MAX_RERANK_CACHE_ENTRIES + 5 {
+            index.cache_rerank(&format!("key{}", i), "Source.md", rerank_result(), 1_000 + i as u64);
+        }
+        assert_eq!(index.rerank_cache.len(), MAX_RERANK_CACHE_ENTRIES);
+        let now = 1_000 + MAX_RERANK_CACHE_ENTRIES as u64 + 5;
+        assert!((0..5).all(|i| index.get_cached_rerank(&format!("key{}", i), None, now).is_none()));
+        assert!(index.get_cached_rerank("key5", None, now).is_some());
+    }
+
+    #[test]
+    fn rerank_cache_drops_expired_entries() {
+        let mut index = CacheIndex::new();
+        index.cache_rerank("old", "Old.md", rerank_result(), 0);
+        let later = RERANK_CACHE_MAX_AGE_MS + 1;
+        assert!(index.get_cached_rerank("old", None, later).is_none());
+        assert!(index.get_cached_rerank("old", Some(u64::MAX), later).is_none());
+        assert!(index.get_cached_rerank("old", Some(10), 5).is_some());
+
+        index.cache_rerank("new", "New.md", rerank_result(), later);
+        assert!(!index.rerank_cache.contains_key("old"));
+        assert!(index.rerank_cache.contains_key("new"));
+    }
+
+    fn embeddings() -> EmbeddingsData {
+        HashMap::from([
+            ("Notes/A.md".to_string(), vec![0.25, -1.0, 3.5]),
+            ("Notes/B.md".to_string(), vec![1.0, 0.0, 0.0]),
+        ])
+    }
+
+    fn load_error(data: &[u8]) -> Box<CacheLoadError> {
+        match load_versioned_or_legacy::<EmbeddingsData>(data) {
+            Ok(_) => panic!("{} bytes loaded", data.len()),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn load_versioned_or_legacy_reads_both_formats() {
+        let versioned = VersionedCache::new(embeddings(), "msgpack").to_msgpack().unwrap();
+        let (header, data) = load_versioned_or_legacy::<EmbeddingsData>(&versioned).unwrap();
+        assert!(header.is_some_and(|header| header.checksum.is_some()));
+        assert_eq!(data, embeddings());
+
+        let legacy = rmp_serde::to_vec(&embeddings()).unwrap();
+        let (header, data) = load_versioned_or_legacy::<EmbeddingsData>(&legacy).unwrap();
+        assert!(header.is_none());
+        assert_eq!(data, embeddings());
+    }
+
+    #[test]
+    fn load_versioned_or_legacy_explains_truncated_msgpack() {
+        let versioned = VersionedCache::new(embeddings(), "msgpack").to_msgpack().unwrap();
+        for len in 0..versioned.len() {
+            let e = load_error(&versioned[..len]);
+            assert_eq!(e.input_len, len);
+        }
+
+        let e = load_error(&versioned[..versioned.len() - 3]);
+        assert!(e.checksum_mismatch);
+        assert!(e.legacy_error.is_none());
+        assert!(e.hint.as_deref().is_some_and(|hint| hint.contains("truncated")));
+
+        // Without a checksum the header still decodes and the hint points past it
+        let mut unchecked = Vec::new();
+        rmp::encode::write_array_len(&mut unchecked, 2).unwrap();
+        unchecked.extend(rmp_serde::to_vec(&CacheHeader::new_msgpack()).unwrap());
+        let offset = unchecked.len();
+        let data = rmp_serde::to_vec(&embeddings()).unwrap();
+        unchecked.extend(&data[..data.len() - 3]);
+        let e = load_error(&unchecked);
+        assert!(!e.checksum_mismatch);
+        assert_eq!(e.data_offset, Some(offset));
+        assert!(e.header.is_some());
+        assert!(e.hint.as_deref().is_some_and(|hint| hint.contains(&format!("after byte {}", offset))));
+    }
+
+    #[test]
+    fn load_versioned_or_legacy_explains_json_and_empty_input() {
+        let json = serde_json::to_vec(&embeddings()).unwrap();
+        let e = load_error(&json);
+        assert!(e.legacy_error.is_some());
+        assert!(e.hint.as_deref().is_some_and(|hint| hint.contains("JSON")));
+
+        let versioned_json = VersionedCache::new(embeddings(), "json").to_json().unwrap();
+        assert!(load_error(format!("\n  {}", versioned_json).as_bytes()).hint.as_deref().is_some_and(|hint| hint.contains("JSON")));
+
+        let e = load_error(&[]);
+        assert_eq!(e.input_len, 0);
+        assert_eq!(e.hint.as_deref(), Some("the cache file is empty"));
+        assert!(!e.to_string().is_empty());
+    }
+
+    #[test]
+    fn normalize_mtime_rounds_fractions_to_the_nearest_ms() {
+        assert_eq!(normalize_mtime(1_700_000_000_123.4), 1_700_000_000_123);
+        assert_eq!(normalize_mtime(1_700_000_000_123.5), 1_700_000_000_124);
+        assert_eq!(normalize_mtime(0.4), 0);
+        assert_eq!(normalize_mtime(0.6), 1);
+        for bad in [-1.5, -0.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(normalize_mtime(bad), 0, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn normalize_mtime_keeps_large_values_and_saturates_past_u64() {
+        let two_53 = 2f64.powi(53);
+        assert_eq!(normalize_mtime(two_53), 1 << 53);
+        assert_eq!(normalize_mtime(two_53 + 2.0), (1 << 53) + 2);
+        assert_eq!(normalize_mtime(2f64.powi(60)), 1 << 60);
+        assert_eq!(normalize_mtime(2f64.powi(64)), u64::MAX);
+        assert_eq!(normalize_mtime(f64::MAX), u64::MAX);
+
+        let mut index = CacheIndex::new();
+        index.mark_embedding_processed("Big.md", normalize_mtime(two_53 + 2.0));
+        assert!(index.is_embedding_fresh("Big.md", normalize_mtime(two_53 + 2.0)));
+        assert!(!index.is_embedding_fresh("Big.md", normalize_mtime(two_53)));
+    }
+
+    /// Two devices' indexes: both processed Shared.md (at different times), each has a path the
+    /// other lacks, and their ignores don't overlap
+    fn device_indexes() -> (CacheIndex, CacheIndex) {
+        let mut a = CacheIndex::new();
+        a.mark_embedding_processed("Shared.md", 200);
+        a.mark_embedding_processed("Same.md", 50);
+        a.mark_embedding_processed("OnlyA.md", 10);
+        a.mark_keyword_processed("Shared.md", 100);
+        a.ignore_suggestion("Shared.md", "OnlyA.md");
+        a.ignore_target_prefix("Archive/");
+
+        let mut b = CacheIndex::new();
+        b.mark_embedding_processed("Shared.md", 100);
+        b.mark_embedding_processed("Same.md", 50);
+        b.mark_embedding_processed("OnlyB.md", 20);
+        b.mark_keyword_processed("Shared.md", 300);
+        b.ignore_suggestion_until("Shared.md", "OnlyB.md", 9_000);
+        b.ignore_target_prefix("Templates/");
+        (a, b)
+    }
+
+    #[test]
+    fn diff_reports_conflicting_mtimes_and_disjoint_ignores() {
+        let (a, b) = device_indexes();
+        let diff = CacheIndex::diff(&a, &b);
+        assert_eq!(diff.embeddings.only_in_a, ["OnlyA.md"]);
+        assert_eq!(diff.embeddings.only_in_b, ["OnlyB.md"]);
+        assert_eq!(diff.embeddings.newer_in_a, ["Shared.md"]);
+        assert!(diff.embeddings.newer_in_b.is_empty());
+        assert_eq!(diff.keywords.newer_in_b, ["Shared.md"]);
+        assert!(diff.suggestions.only_in_a.is_empty() && diff.suggestions.only_in_b.is_empty());
+
+        let targets = |ignores: &[IgnoredSuggestion]| ignores.iter().map(|i| (i.kind, i.target_file.clone())).collect::<Vec<_>>();
+        assert_eq!(targets(&diff.ignored_only_in_a), [(IgnoreKind::Pattern, "Archive/".to_string()), (IgnoreKind::Exact, "OnlyA.md".to_string())]);
+        assert_eq!(targets(&diff.ignored_only_in_b), [(IgnoreKind::Pattern, "Templates/".to_string()), (IgnoreKind::Exact, "OnlyB.md".to_string())]);
+        assert_eq!(diff.ignored_only_in_b[1].expires_at, Some(9_000));
+
+        let reverse = CacheIndex::diff(&b, &a);
+        assert_eq!(reverse.embeddings.newer_in_b, ["Shared.md"]);
+        assert_eq!(reverse.embeddings.only_in_a, ["OnlyB.md"]);
+    }
+
+    #[test]
+    fn merge_resolves_conflicting_mtimes_by_strategy() {
+        let cases = [
+            (MergeStrategy::NewestWins, 200, 300, 1),
+            (MergeStrategy::PreferOther, 100, 300, 2),
+            (MergeStrategy::PreferLocal, 200, 100, 0),
+        ];
+        for (strategy, embedding, keyword, updated) in cases {
+            let (mut a, b) = device_indexes();
+            let summary = a.merge(&b, strategy);
+            assert!(a.is_embedding_fresh("Shared.md", embedding), "{:?}", strategy);
+            assert!(a.is_keyword_fresh("Shared.md", keyword), "{:?}", strategy);
+            assert!(a.is_embedding_fresh("OnlyA.md", 10) && a.is_embedding_fresh("OnlyB.md", 20));
+            assert!(a.is_embedding_fresh("Same.md", 50));
+            assert_eq!(summary.mtimes_added, 1, "{:?}", strategy);
+            assert_eq!(summary.mtimes_updated, updated, "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn merge_unions_disjoint_ignores() {
+        let (mut a, b) = device_indexes();
+        let summary = a.merge(&b, MergeStrategy::NewestWins);
+        assert_eq!(summary.ignores_added, 2);
+        assert!(a.is_suggestion_ignored("Shared.md", "OnlyA.md", 10_000));
+        assert!(a.is_suggestion_ignored("Shared.md", "OnlyB.md", 5_000));
+        assert!(!a.is_suggestion_ignored("Shared.md", "OnlyB.md", 10_000));
+        assert!(a.is_suggestion_ignored("Any.md", "Archive/Old.md", 0));
+        assert!(a.is_suggestion_ignored("Any.md", "Templates/Daily.md", 0));
+        assert!(CacheIndex::diff(&a, &b).ignored_only_in_b.is_empty());
+
+        // Merging the same ignores again adds nothing
+        assert_eq!(a.merge(&b, MergeStrategy::NewestWins).ignores_added, 0);
+    }
+}
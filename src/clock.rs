@@ -0,0 +1,17 @@
+//! Wall-clock time. In the browser this is `Date.now()`; on the host (native builds and
+//! `cargo test`) or without the `wasm` feature, js_sys calls panic, so the system clock
+//! stands in.
+
+/// Milliseconds since the Unix epoch
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub(crate) fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+/// Milliseconds since the Unix epoch
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub(crate) fn now_ms() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64() * 1000.0)
+}
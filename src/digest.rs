@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use crate::frontmatter::note_body;
+use crate::tokens::{estimate_tokens, truncate_to_tokens};
+use crate::extract_title_from_path;
+
+/// Tokens the model may write for a digest, or for the summary of one batch of notes
+pub(crate) const DIGEST_OUTPUT_TOKENS: usize = 1536;
+
+/// Fewest tokens of a note worth sending. When the notes don't all fit with this much each,
+/// they're summarized in batches first.
+const MIN_DIGEST_SNIPPET_TOKENS: usize = 48;
+
+/// Tokens of the "### " and blank lines around each entry in a prompt
+const ENTRY_OVERHEAD_TOKENS: usize = 4;
+
+/// Notes the model didn't mention are listed under this heading
+pub(crate) const DIGEST_MISSING_HEADING: &str = "Other changes";
+
+/// A note changed in the digest's period, as given in `recent_notes_json`
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct DigestNote {
+    pub path: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub mtime: f64,
+}
+
+/// The notes of `recent_notes_json`, most recently modified first, then by path. A missing
+/// title is taken from the path; entries without a path are dropped.
+pub(crate) fn parse_digest_notes(recent_notes_json: &str) -> Result<Vec<DigestNote>, String> {
+    let mut notes: Vec<DigestNote> = serde_json::from_str(recent_notes_json)
+        .map_err(|e| format!("Failed to parse recent notes JSON: {}", e))?;
+    notes.retain(|note| !note.path.trim().is_empty());
+    for note in &mut notes {
+        if note.title.trim().is_empty() {
+            note.title = extract_title_from_path(&note.path);
+        }
+    }
+    notes.sort_by(|a, b| b.mtime.total_cmp(&a.mtime).then_with(|| a.path.cmp(&b.path)));
+    Ok(notes)
+}
+
+/// One section of a digest prompt: a note, or the summary of a batch of notes
+pub(crate) struct DigestEntry {
+    pub heading: String,
+    pub text: String,
+}
+
+impl DigestEntry {
+    pub(crate) fn from_note(note: &DigestNote) -> Self {
+        DigestEntry {
+            heading: format!("[[{}]] (Path: {})", note.title, note.path),
+            text: note_body(&note.content).trim().to_string(),
+        }
+    }
+
+    /// Tokens of the entry in a prompt with `text_tokens` of its text
+    fn cost(&self, text_tokens: usize) -> usize {
+        estimate_tokens(&self.heading) + ENTRY_OVERHEAD_TOKENS + text_tokens
+    }
+}
+
+/// Runs of consecutive entries that fit `budget_tokens` with at least MIN_DIGEST_SNIPPET_TOKENS
+/// of each; a single run when they all do. Every run but the last holds at least two entries,
+/// so summarizing each run always leaves fewer entries than before.
+pub(crate) fn digest_batches(entries: &[DigestEntry], budget_tokens: usize) -> Vec<Range<usize>> {
+    let mut batches = Vec::new();
+    let (mut start, mut used) = (0, 0);
+    for (i, entry) in entries.iter().enumerate() {
+        let cost = entry.cost(estimate_tokens(&entry.text).min(MIN_DIGEST_SNIPPET_TOKENS));
+        if used + cost > budget_tokens && i - start >= 2 {
+            batches.push(start..i);
+            (start, used) = (i, 0);
+        }
+        used += cost;
+    }
+    if start < entries.len() {
+        batches.push(start..entries.len());
+    }
+    batches
+}
+
+/// `entries` as prompt text within `budget_tokens`. The budget is shared evenly: entries shorter
+/// than their share are sent whole and leave the rest to the longer ones, which are cut at a
+/// sentence or word boundary.
+pub(crate) fn fit_digest_entries(entries: &[DigestEntry], budget_tokens: usize) -> String {
+    let headings: usize = entries.iter().map(|entry| entry.cost(0)).sum();
+    let mut remaining = budget_tokens.saturating_sub(headings);
+    let costs: Vec<usize> = entries.iter().map(|entry| estimate_tokens(&entry.text)).collect();
+    let mut by_cost: Vec<usize> = (0..entries.len()).collect();
+    by_cost.sort_by_key(|&i| costs[i]);
+    let mut shares = vec![0; entries.len()];
+    for (done, &i) in by_cost.iter().enumerate() {
+        shares[i] = costs[i].min(remaining / (entries.len() - done));
+        remaining -= shares[i];
+    }
+
+    let mut text = String::new();
+    for (entry, share) in entries.iter().zip(shares) {
+        text.push_str(&format!("### {}\n{}\n\n", entry.heading, truncate_to_tokens(&entry.text, share)));
+    }
+    text
+}
+
+/// What a digest prompt asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DigestStage {
+    /// The digest, written from the notes themselves
+    Notes,
+    /// Themed bullets for one batch of notes, combined into the digest later
+    Batch,
+    /// The digest, written from the batches' summaries
+    Combine,
+}
+
+/// Prompt for `stage` over `entries_text` (from fit_digest_entries)
+pub(crate) fn build_digest_prompt(period_label: &str, entries_text: &str, stage: DigestStage) -> String {
+    if stage == DigestStage::Batch {
+        return format!(
+            r#"Below are some of the notes created or modified during {}, most recent first. Each starts with a heading holding its wiki link.
+
+{}
+Instructions:
+1. Summarize what these notes are about and what was worked on, grouped into a few themes.
+2. Write each theme as a bold line (**Theme**) followed by bullet points.
+3. Link every note you mention with its wiki link exactly as its heading shows, e.g. [[Note Title]].
+4. Only state what the notes say. Output ONLY the themes and bullets, with no preamble.
+"#,
+            period_label, entries_text
+        );
+    }
+
+    let source = match stage {
+        DigestStage::Combine => "Below are summaries of the notes created or modified during PERIOD, written in parts. They link the notes with [[wiki links]]; keep those links exactly as written.",
+        _ => "Below are the notes created or modified during PERIOD, most recent first. Each starts with a heading holding its wiki link.",
+    };
+    format!(
+        r###"You are writing a "week in review" digest for a personal knowledge base.
+
+{}
+
+{}
+Instructions:
+1. Start with a "# {}" heading, then a "## Highlights" section of 2-4 sentences on the main themes of the period.
+2. Group the notes into a few "## Theme" sections of bullet points saying what each note covers or what changed.
+3. Use strict Obsidian WikiLink format and link every note you mention exactly as written above: [[Note Title]].
+4. Only state what the notes say. Output ONLY the Markdown digest, with no preamble.
+"###,
+        source.replace("PERIOD", period_label), entries_text, period_label
+    )
+}
+
+/// The model's digest with a "# <period>" heading at the top, adding it if the model left it out
+pub(crate) fn with_digest_title(markdown: &str, period_label: &str) -> String {
+    let markdown = markdown.trim();
+    if markdown.starts_with("# ") {
+        format!("{}\n", markdown)
+    } else {
+        format!("# {}\n\n{}\n", period_label, markdown)
+    }
+}
+
+/// Result of `generate_digest_with_llm`
+#[derive(Serialize, Debug, Clone)]
+pub struct DigestResult {
+    /// The digest, starting with a "# <period>" heading, ready to save as a note
+    pub markdown: String,
+    /// Linked titles that weren't among the given notes; their links became plain text
+    pub hallucinated_links_removed: Vec<String>,
+    /// Given notes the digest didn't mention, appended under "## Other changes"
+    pub missing_notes_added: Vec<String>,
+    pub notes: usize,
+    /// Batches of notes summarized before the digest was written; 0 when every note fit in one prompt
+    pub batches: usize,
+}
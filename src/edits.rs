@@ -0,0 +1,295 @@
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A grammar fix as returned in FormattingAnalysis.grammar
+#[derive(Deserialize, Debug, Clone)]
+struct FixInput {
+    original: String,
+    corrected: String,
+    #[serde(default)]
+    reason: String,
+    /// Char offset located by analyze_formatting_with_llm; preferred among repeated occurrences
+    #[serde(default)]
+    start: Option<usize>,
+}
+
+/// A fix that was applied. Offsets are char positions in the original content.
+#[derive(Serialize, Debug, Clone)]
+pub struct AppliedFix {
+    pub index: usize,
+    pub original: String,
+    pub corrected: String,
+    pub reason: String,
+    pub start: usize,
+    pub end: usize,
+    /// The same range in bytes and UTF-16 units (see `offsets`)
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+    /// How many times `original` occurs in the content; above 1 the match was disambiguated
+    pub occurrences: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SkippedFix {
+    pub index: usize,
+    pub reason: String,
+}
+
+/// Changed lines: the original text of lines `line..` and what they became
+#[derive(Serialize, Debug, Clone)]
+pub struct DiffHunk {
+    /// 1-based line number of the first changed line
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PatchResult {
+    pub patched_content: String,
+    pub applied: Vec<AppliedFix>,
+    pub skipped: Vec<SkippedFix>,
+    pub diff: Vec<DiffHunk>,
+}
+
+/// A located fix: byte range in the original content
+struct PlannedFix {
+    index: usize,
+    start: usize,
+    end: usize,
+    corrected: String,
+}
+
+/// Locate each selected fix, lowest index first. A fix whose `original` appears several times
+/// tries its located `start` first, then the first occurrence not already used by an identical
+/// fix, so repeated fixes for the same phrase land on successive occurrences. A fix that
+/// partially overlaps an earlier one is skipped.
+fn plan_fixes(content: &str, issues: &[FixInput], selected_indices: &[usize]) -> (Vec<PlannedFix>, Vec<AppliedFix>, Vec<SkippedFix>) {
+    let mut indices = selected_indices.to_vec();
+    indices.sort_unstable();
+    indices.dedup();
+
+    let mut planned: Vec<PlannedFix> = Vec::new();
+    let mut applied: Vec<AppliedFix> = Vec::new();
+    let mut skipped: Vec<SkippedFix> = Vec::new();
+    let skip = |skipped: &mut Vec<SkippedFix>, index: usize, reason: String| skipped.push(SkippedFix { index, reason });
+
+    for index in indices {
+        let Some(issue) = issues.get(index) else {
+            skip(&mut skipped, index, "index out of range".to_string());
+            continue;
+        };
+        if issue.original.is_empty() {
+            skip(&mut skipped, index, "empty original text".to_string());
+            continue;
+        }
+        if issue.original == issue.corrected {
+            skip(&mut skipped, index, "correction is identical to the original".to_string());
+            continue;
+        }
+
+        let mut occurrences: Vec<usize> = content.match_indices(&issue.original).map(|(pos, _)| pos).collect();
+        if occurrences.is_empty() {
+            skip(&mut skipped, index, "original text not found in content".to_string());
+            continue;
+        }
+        // Try the located occurrence first
+        let located = issue.start.and_then(|start| content.char_indices().nth(start).map(|(pos, _)| pos));
+        if let Some(pos) = located.and_then(|byte| occurrences.iter().position(|&o| o == byte)) {
+            occurrences[..=pos].rotate_right(1);
+        }
+
+        let mut chosen = None;
+        let mut conflict = None;
+        for &start in &occurrences {
+            let end = start + issue.original.len();
+            match planned.iter().find(|p| start < p.end && p.start < end) {
+                // Same span already fixed by an identical issue: try the next occurrence
+                Some(p) if p.start == start && p.end == end => continue,
+                Some(p) => {
+                    conflict.get_or_insert(p.index);
+                    continue;
+                }
+                None => {
+                    chosen = Some((start, end));
+                    break;
+                }
+            }
+        }
+
+        let Some((start, end)) = chosen else {
+            let reason = match conflict {
+                Some(other) => format!("overlaps fix {}", other),
+                None => "every occurrence is already fixed by an identical issue".to_string(),
+            };
+            skip(&mut skipped, index, reason);
+            continue;
+        };
+
+        let before = &content[..start];
+        let start_char = before.chars().count();
+        let start_utf16 = before.encode_utf16().count();
+        applied.push(AppliedFix {
+            index,
+            original: issue.original.clone(),
+            corrected: issue.corrected.clone(),
+            reason: issue.reason.clone(),
+            start: start_char,
+            end: start_char + issue.original.chars().count(),
+            offset_bytes: start,
+            end_bytes: end,
+            offset_utf16: start_utf16,
+            end_utf16: start_utf16 + issue.original.encode_utf16().count(),
+            occurrences: occurrences.len(),
+        });
+        planned.push(PlannedFix { index, start, end, corrected: issue.corrected.clone() });
+    }
+
+    (planned, applied, skipped)
+}
+
+/// Apply fixes (byte ranges relative to `text`, starting at `offset`) back to front
+fn apply_ranges(text: &str, offset: usize, fixes: &[&PlannedFix]) -> String {
+    let mut patched = text.to_string();
+    let mut ordered: Vec<&&PlannedFix> = fixes.iter().collect();
+    ordered.sort_by_key(|fix| std::cmp::Reverse(fix.start));
+    for fix in ordered {
+        patched.replace_range(fix.start - offset..fix.end - offset, &fix.corrected);
+    }
+    patched
+}
+
+/// Group fixes into whole-line hunks, merging fixes whose lines overlap
+fn build_diff(content: &str, planned: &[PlannedFix]) -> Vec<DiffHunk> {
+    let mut spans: Vec<(usize, usize, Vec<&PlannedFix>)> = Vec::new();
+    let mut ordered: Vec<&PlannedFix> = planned.iter().collect();
+    ordered.sort_by_key(|fix| fix.start);
+
+    for fix in ordered {
+        let line_start = content[..fix.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = content[fix.end..].find('\n').map_or(content.len(), |i| fix.end + i);
+        match spans.last_mut() {
+            Some((_, end, fixes)) if line_start <= *end => {
+                *end = (*end).max(line_end);
+                fixes.push(fix);
+            }
+            _ => spans.push((line_start, line_end, vec![fix])),
+        }
+    }
+
+    spans.into_iter()
+        .map(|(start, end, fixes)| DiffHunk {
+            line: content[..start].matches('\n').count() + 1,
+            before: content[start..end].to_string(),
+            after: apply_ranges(&content[start..end], start, &fixes),
+        })
+        .collect()
+}
+
+/// Apply the selected fixes to `content`
+fn patch_content(content: &str, issues: &[FixInput], selected_indices: &[usize]) -> PatchResult {
+    let (planned, applied, skipped) = plan_fixes(content, issues, selected_indices);
+    let all: Vec<&PlannedFix> = planned.iter().collect();
+    PatchResult {
+        patched_content: apply_ranges(content, 0, &all),
+        diff: build_diff(content, &planned),
+        applied,
+        skipped,
+    }
+}
+
+/// Apply the grammar fixes at `selected_indices` of `issues_json` (the `grammar` array from
+/// analyze_formatting_with_llm) to `content`. Returns {patched_content, applied: [{index, original,
+/// corrected, reason, start, end, occurrences}], skipped: [{index, reason}], diff: [{line, before, after}]}.
+/// Offsets are char positions in the original content.
+#[wasm_bindgen]
+pub fn apply_grammar_fixes(content: &str, issues_json: &str, selected_indices: Vec<usize>) -> Result<JsValue, JsValue> {
+    let issues: Vec<FixInput> = serde_json::from_str(issues_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse grammar issues JSON: {}", e)))?;
+    let result = patch_content(content, &issues, &selected_indices);
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in apply_grammar_fixes: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(content: &str, issues_json: &str, selected: &[usize]) -> PatchResult {
+        let issues: Vec<FixInput> = serde_json::from_str(issues_json).unwrap();
+        patch_content(content, &issues, selected)
+    }
+
+    fn skipped(result: &PatchResult) -> Vec<(usize, &str)> {
+        result.skipped.iter().map(|s| (s.index, s.reason.as_str())).collect()
+    }
+
+    #[test]
+    fn repeated_phrases_land_on_successive_occurrences() {
+        let content = "teh cat and teh dog and teh bird";
+        let issues = r#"[
+            {"original": "teh", "corrected": "the"},
+            {"original": "teh", "corrected": "the"},
+            {"original": "teh", "corrected": "the"},
+            {"original": "teh", "corrected": "the"}
+        ]"#;
+        let result = patch(content, issues, &[0, 1, 2, 3]);
+        assert_eq!(result.patched_content, "the cat and the dog and the bird");
+        assert_eq!(result.applied.iter().map(|a| a.start).collect::<Vec<_>>(), [0, 12, 24]);
+        assert!(result.applied.iter().all(|a| a.occurrences == 3));
+        assert_eq!(skipped(&result), [(3, "every occurrence is already fixed by an identical issue")]);
+
+        // A located start picks the occurrence the analysis meant
+        let located = patch(content, r#"[{"original": "teh", "corrected": "the", "start": 12}]"#, &[0]);
+        assert_eq!(located.patched_content, "teh cat and the dog and teh bird");
+    }
+
+    #[test]
+    fn multi_byte_content_keeps_char_byte_and_utf16_offsets_apart() {
+        let content = "Café 😀 naïve idea.\n日本語 teh end";
+        let issues = r#"[
+            {"original": "naïve idea", "corrected": "naive idea"},
+            {"original": "teh", "corrected": "the"},
+            {"original": "😀", "corrected": "🙂"}
+        ]"#;
+        let result = patch(content, issues, &[2, 0, 1, 0]);
+        assert_eq!(result.patched_content, "Café 🙂 naive idea.\n日本語 the end");
+        for fix in &result.applied {
+            assert_eq!(&content[fix.offset_bytes..fix.end_bytes], fix.original);
+            assert_eq!(content.chars().skip(fix.start).take(fix.end - fix.start).collect::<String>(), fix.original);
+            let utf16: Vec<u16> = content.encode_utf16().collect();
+            assert_eq!(String::from_utf16(&utf16[fix.offset_utf16..fix.end_utf16]).unwrap(), fix.original);
+        }
+        let naive = &result.applied[0];
+        assert_eq!((naive.start, naive.offset_bytes, naive.offset_utf16), (7, 11, 8));
+        assert_eq!(result.diff.len(), 2);
+        assert_eq!((result.diff[1].line, result.diff[1].after.as_str()), (2, "日本語 the end"));
+    }
+
+    #[test]
+    fn overlapping_fixes_skip_the_later_one() {
+        let content = "This are a example sentence.";
+        let issues = r#"[
+            {"original": "This are", "corrected": "This is"},
+            {"original": "are a example", "corrected": "are an example"},
+            {"original": "a example", "corrected": "an example"},
+            {"original": "missing", "corrected": "found"},
+            {"original": "same", "corrected": "same"},
+            {"original": "", "corrected": "x"}
+        ]"#;
+        let result = patch(content, issues, &[0, 1, 2, 3, 4, 5, 9]);
+        assert_eq!(result.patched_content, "This is an example sentence.");
+        assert_eq!(result.applied.iter().map(|a| a.index).collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(skipped(&result), [
+            (1, "overlaps fix 0"),
+            (3, "original text not found in content"),
+            (4, "correction is identical to the original"),
+            (5, "empty original text"),
+            (9, "index out of range"),
+        ]);
+        assert_eq!(result.diff.len(), 1);
+        assert_eq!(result.diff[0].before, content);
+    }
+}
@@ -1,10 +1,26 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(feature = "wasm")]
+use futures::stream::{FuturesUnordered, StreamExt};
+#[cfg(feature = "wasm")]
+use crate::http::{self, JsOptions, RequestSettings};
+#[cfg(feature = "wasm")]
+use crate::frontmatter::note_body;
+#[cfg(feature = "wasm")]
+use crate::progress::Progress;
+
+/// Maximum number of inputs sent to /api/embed in one request
+#[cfg(feature = "wasm")]
+const EMBED_BATCH_SIZE: usize = 32;
 
 #[derive(Serialize, Deserialize)]
 pub struct OllamaEmbeddingRequest {
     pub model: String,
     pub prompt: String,
+    /// How long Ollama keeps the model loaded after the call (e.g. "30m", "-1" for forever)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -12,38 +28,652 @@ pub struct OllamaEmbeddingResponse {
     pub embedding: Vec<f32>,
 }
 
+/// Request body for Ollama's batch /api/embed endpoint
+#[derive(Serialize, Deserialize)]
+pub struct OllamaEmbedRequest {
+    pub model: String,
+    pub input: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OllamaEmbedResponse {
+    pub embeddings: Vec<Vec<f32>>,
+}
+
+/// One entry of a batch embedding result, in input order
+#[derive(Serialize, Deserialize)]
+pub struct BatchEmbeddingResult {
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[cfg(feature = "wasm")]
+enum BatchEmbedError {
+    /// Server does not know /api/embed (older Ollama)
+    Unsupported,
+    Failed(String),
+}
+
+#[cfg(feature = "wasm")]
+impl From<String> for BatchEmbedError {
+    fn from(e: String) -> Self {
+        BatchEmbedError::Failed(e)
+    }
+}
+
+/// What an embedding will be used for; some models expect a different prefix for each
 #[wasm_bindgen]
-pub async fn generate_embedding_ollama(
-    endpoint: String,
-    model: String,
-    text: String,
-) -> Result<JsValue, JsValue> {
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingTaskType {
+    None,
+    Document,
+    Query,
+}
+
+impl EmbeddingTaskType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmbeddingTaskType::None => "none",
+            EmbeddingTaskType::Document => "document",
+            EmbeddingTaskType::Query => "query",
+        }
+    }
+}
+
+/// Instruction used by BGE-style retrieval models for queries
+#[cfg(feature = "wasm")]
+const RETRIEVAL_QUERY_INSTRUCTION: &str = "Represent this sentence for searching relevant passages: ";
+
+/// Prefix a model expects for the given task, matched on the model name (tag ignored).
+/// `override_prefix` wins for any task other than None, for models not in the table.
+#[cfg(feature = "wasm")]
+pub(crate) fn task_prefix(model: &str, task: EmbeddingTaskType, override_prefix: Option<&str>) -> Option<String> {
+    if task == EmbeddingTaskType::None {
+        return None;
+    }
+    if let Some(prefix) = override_prefix {
+        return Some(prefix.to_string());
+    }
+
+    let name = model.split(':').next().unwrap_or(model).to_lowercase();
+    let is_query = task == EmbeddingTaskType::Query;
+    let prefix = if name.contains("nomic-embed") {
+        if is_query { "search_query: " } else { "search_document: " }
+    } else if name.contains("e5") {
+        if is_query { "query: " } else { "passage: " }
+    } else if name.contains("mxbai-embed") || name.contains("bge") || name.contains("snowflake-arctic-embed") {
+        // These only instruct the query side
+        if is_query { RETRIEVAL_QUERY_INSTRUCTION } else { return None }
+    } else {
+        return None;
+    };
+    Some(prefix.to_string())
+}
+
+/// Part of a note worth embedding: the body without frontmatter, so short notes aren't
+/// dominated by their metadata. Notes that are all frontmatter are embedded whole.
+#[cfg(feature = "wasm")]
+fn embeddable_text(content: &str) -> &str {
+    let body = note_body(content);
+    if body.trim().is_empty() { content } else { body }
+}
+
+/// Options of the embedding exports, e.g. `{task_type: "query", keep_alive: "30m", abort_signal}`.
+/// Transient failures (network errors, 5xx) are retried `max_attempts` times (default 3) with
+/// exponential backoff starting at `retry_base_delay_ms` (default 500). With `timeout_ms` set, a
+/// call to the server (the request, its retries and reading the reply) that runs past it is
+/// cancelled and fails with "timeout after N ms". Aborting `abort_signal` rejects with "aborted".
+#[cfg(feature = "wasm")]
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct EmbedOptions {
+    pub max_attempts: Option<u32>,
+    pub retry_base_delay_ms: Option<u32>,
+    pub timeout_ms: Option<u32>,
+    /// "document" or "query" adds the model's prefix for that task (see `task_prefix`)
+    pub task_type: Option<EmbeddingTaskType>,
+    /// Prefix used instead of the model's own, for models not in the table
+    pub prefix_override: Option<String>,
+    /// How long Ollama keeps the model loaded between calls (e.g. "30m", "-1")
+    pub keep_alive: Option<String>,
+    #[serde(skip)]
+    pub abort_signal: Option<web_sys::AbortSignal>,
+}
+
+#[cfg(feature = "wasm")]
+impl JsOptions for EmbedOptions {
+    fn set_abort_signal(&mut self, abort_signal: Option<web_sys::AbortSignal>) {
+        self.abort_signal = abort_signal;
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl EmbedOptions {
+    fn settings(&self) -> RequestSettings {
+        RequestSettings {
+            abort_signal: self.abort_signal.clone(),
+            ..RequestSettings::from_options(self.max_attempts, self.retry_base_delay_ms, self.timeout_ms)
+        }
+    }
+
+    /// `text` with the prefix `model` expects for `task_type`
+    fn prefixed(&self, model: &str, text: String) -> String {
+        match self.task_type.and_then(|task| task_prefix(model, task, self.prefix_override.as_deref())) {
+            Some(prefix) => format!("{}{}", prefix, text),
+            None => text,
+        }
+    }
+}
+
+/// Embed a single text via the legacy /api/embeddings endpoint
+/// The timeout covers the request, its retries and reading the body.
+#[cfg(feature = "wasm")]
+pub(crate) async fn fetch_embedding(endpoint: &str, model: &str, text: String, keep_alive: Option<&str>, settings: &RequestSettings) -> Result<Vec<f32>, String> {
+    http::with_timeout(settings, |settings| async move {
+        fetch_embedding_inner(endpoint, model, text, keep_alive, &settings).await
+    }).await
+}
+
+#[cfg(feature = "wasm")]
+async fn fetch_embedding_inner(endpoint: &str, model: &str, text: String, keep_alive: Option<&str>, settings: &RequestSettings) -> Result<Vec<f32>, String> {
     let request = OllamaEmbeddingRequest {
-        model,
+        model: model.to_string(),
         prompt: text,
+        keep_alive: keep_alive.map(str::to_string),
     };
+    let body = serde_json::to_string(&request).map_err(|e| format!("Serialization error in fetch_embedding_inner: {}", e))?;
 
-    let client = gloo_net::http::Request::post(&format!("{}/api/embeddings", endpoint))
-        .json(&request)
-        .map_err(|e| JsValue::from_str(&format!("Request error: {}", e)))?;
+    let response = http::post_json(&format!("{}/api/embeddings", endpoint), &body, settings).await?;
 
-    let response = client
-        .send()
-        .await
-        .map_err(|e| JsValue::from_str(&format!("Network error: {}", e)))?;
+    settings.check_aborted()?;
 
     // Check if response is successful
     if !response.ok() {
         let status = response.status();
         let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(JsValue::from_str(&format!("HTTP {}: {}", status, error_text)));
+        return Err(format!("HTTP {}: {}", status, error_text));
     }
 
     let embedding_response: OllamaEmbeddingResponse = response
         .json()
         .await
-        .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
+        .map_err(|e| format!("Parse error: {}", e))?;
+
+    Ok(embedding_response.embedding)
+}
+
+/// Embed several texts in one round trip via /api/embed
+#[cfg(feature = "wasm")]
+async fn fetch_embeddings_batch(endpoint: &str, model: &str, texts: &[String], keep_alive: Option<&str>, settings: &RequestSettings) -> Result<Vec<Vec<f32>>, BatchEmbedError> {
+    http::with_timeout(settings, |settings| async move {
+        fetch_embeddings_batch_inner(endpoint, model, texts, keep_alive, &settings).await
+    }).await
+}
+
+#[cfg(feature = "wasm")]
+async fn fetch_embeddings_batch_inner(endpoint: &str, model: &str, texts: &[String], keep_alive: Option<&str>, settings: &RequestSettings) -> Result<Vec<Vec<f32>>, BatchEmbedError> {
+    let request = OllamaEmbedRequest {
+        model: model.to_string(),
+        input: texts.to_vec(),
+        keep_alive: keep_alive.map(str::to_string),
+    };
+    let body = serde_json::to_string(&request)
+        .map_err(|e| BatchEmbedError::Failed(format!("Serialization error in fetch_embeddings_batch_inner: {}", e)))?;
+
+    let response = http::post_json(&format!("{}/api/embed", endpoint), &body, settings)
+        .await
+        .map_err(BatchEmbedError::Failed)?;
 
-    serde_wasm_bindgen::to_value(&embedding_response.embedding)
-        .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    settings.check_aborted()?;
+    if response.status() == 404 {
+        return Err(BatchEmbedError::Unsupported);
+    }
+    if !response.ok() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(BatchEmbedError::Failed(format!("HTTP {}: {}", status, error_text)));
+    }
+
+    let embed_response: OllamaEmbedResponse = response
+        .json()
+        .await
+        .map_err(|e| BatchEmbedError::Failed(format!("Parse error: {}", e)))?;
+
+    if embed_response.embeddings.len() != texts.len() {
+        return Err(BatchEmbedError::Failed(format!("Expected {} embeddings, got {}",
+            texts.len(), embed_response.embeddings.len())));
+    }
+
+    Ok(embed_response.embeddings)
+}
+
+/// Generate an embedding for one text. `options` is an EmbedOptions object: retries, timeout,
+/// abort, task prefix and keep_alive; every field is optional.
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub async fn generate_embedding_ollama(
+    endpoint: String,
+    model: String,
+    text: String,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = EmbedOptions::from_js(options, "generate_embedding_ollama")?;
+    let settings = options.settings();
+    let text = options.prefixed(&model, text);
+    let embedding = fetch_embedding(&endpoint, &model, text, options.keep_alive.as_deref(), &settings)
+        .await
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    serde_wasm_bindgen::to_value(&embedding)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in generate_embedding_ollama: {}", e)))
+}
+
+/// Scale a vector to unit length in place; zero vectors are left untouched
+pub(crate) fn l2_normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Generate an embedding for one text and return it as `{dim, normalized, data}`,
+/// where `data` is a Float32Array that can go straight to `set_embedding`.
+/// With `normalize`, the vector is L2-normalized first (cosine similarity is unaffected).
+/// `options` is an EmbedOptions object, as in generate_embedding_ollama.
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub async fn generate_embedding_ollama_f32(
+    endpoint: String,
+    model: String,
+    text: String,
+    normalize: bool,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = EmbedOptions::from_js(options, "generate_embedding_ollama_f32")?;
+    let settings = options.settings();
+    let text = options.prefixed(&model, text);
+    let mut embedding = fetch_embedding(&endpoint, &model, text, options.keep_alive.as_deref(), &settings)
+        .await
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    if normalize {
+        l2_normalize(&mut embedding);
+    }
+
+    let result = js_sys::Object::new();
+    js_sys::Reflect::set(&result, &"dim".into(), &JsValue::from(embedding.len() as u32))?;
+    js_sys::Reflect::set(&result, &"normalized".into(), &JsValue::from(normalize))?;
+    js_sys::Reflect::set(&result, &"data".into(), &js_sys::Float32Array::from(embedding.as_slice()))?;
+    Ok(result.into())
+}
+
+/// Embed texts in input order, batching through /api/embed where the server supports it.
+/// Per-item failures are returned in place; only an abort fails the whole call.
+#[cfg(feature = "wasm")]
+async fn embed_texts(endpoint: &str, model: &str, texts: &[String], keep_alive: Option<&str>, settings: &RequestSettings) -> Result<Vec<Result<Vec<f32>, String>>, String> {
+    let mut results: Vec<Result<Vec<f32>, String>> = Vec::with_capacity(texts.len());
+    let mut batch_supported = true;
+
+    for chunk in texts.chunks(EMBED_BATCH_SIZE) {
+        // Stop scheduling work once the caller has given up on the batch
+        settings.check_aborted()?;
+
+        if batch_supported {
+            match fetch_embeddings_batch(endpoint, model, chunk, keep_alive, settings).await {
+                Ok(embeddings) => {
+                    results.extend(embeddings.into_iter().map(Ok));
+                    continue;
+                }
+                Err(BatchEmbedError::Unsupported) => {
+                    log_debug!("/api/embed not available, falling back to /api/embeddings");
+                    batch_supported = false;
+                }
+                Err(BatchEmbedError::Failed(e)) => {
+                    // Retry this chunk item by item so one bad input doesn't sink the rest
+                    log_warn!("Batch embedding failed ({}), retrying items individually", e);
+                }
+            }
+        }
+
+        for text in chunk {
+            settings.check_aborted()?;
+            results.push(fetch_embedding(endpoint, model, text.clone(), keep_alive, settings).await);
+        }
+    }
+
+    Ok(results)
+}
+
+/// Generate embeddings for many texts, batching through /api/embed.
+/// Falls back to one /api/embeddings request per text on servers without /api/embed.
+/// Returns [{index, embedding?, error?}] in input order; individual failures do not fail the batch.
+/// `options` is an EmbedOptions object; its `timeout_ms` bounds each call to the server (one per
+/// batch, or per text on the fallback path). Aborting rejects the whole call with "aborted".
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub async fn generate_embeddings_batch_ollama(
+    endpoint: String,
+    model: String,
+    texts: Vec<String>,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = EmbedOptions::from_js(options, "generate_embeddings_batch_ollama")?;
+    let settings = options.settings();
+    let texts: Vec<String> = texts.into_iter()
+        .map(|text| options.prefixed(&model, text))
+        .collect();
+    let results: Vec<BatchEmbeddingResult> = embed_texts(&endpoint, &model, &texts, options.keep_alive.as_deref(), &settings)
+        .await
+        .map_err(|e| JsValue::from_str(&e))?
+        .into_iter()
+        .enumerate()
+        .map(|(index, result)| match result {
+            Ok(embedding) => BatchEmbeddingResult { index, embedding: Some(embedding), error: None },
+            Err(e) => BatchEmbeddingResult { index, embedding: None, error: Some(e) },
+        })
+        .collect();
+
+    serde_wasm_bindgen::to_value(&results)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in generate_embeddings_batch_ollama: {}", e)))
+}
+
+/// One note to embed in embed_files_ollama
+#[cfg(feature = "wasm")]
+#[derive(Deserialize)]
+struct EmbedFileInput {
+    path: String,
+    content: String,
+}
+
+#[cfg(feature = "wasm")]
+#[derive(Serialize)]
+pub struct EmbedFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[cfg(feature = "wasm")]
+#[derive(Serialize)]
+pub struct EmbedFilesResult {
+    pub embeddings: HashMap<String, Vec<f32>>,
+    pub failures: Vec<EmbedFailure>,
+    /// True if the abort signal fired; files never scheduled are in neither list
+    pub aborted: bool,
+}
+
+/// Embed a batch of notes (`[{path, content}]`, frontmatter left out) with at most
+/// `concurrency` requests in flight.
+/// `progress_callback` gets ProgressEvents (`detail` is the path of the file that just finished)
+/// as files finish.
+/// Returns `{embeddings: {path: vector}, failures: [{path, error}], aborted}`.
+/// `options` is an EmbedOptions object. Once its `abort_signal` fires no new requests are scheduled, no more progress is reported, and
+/// whatever finished is returned.
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub async fn embed_files_ollama(
+    endpoint: String,
+    model: String,
+    files_json: String,
+    concurrency: usize,
+    progress_callback: js_sys::Function,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let files: Vec<EmbedFileInput> = serde_json::from_str(&files_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid files JSON: {}", e)))?;
+    let options = EmbedOptions::from_js(options, "embed_files_ollama")?;
+    let settings = options.settings();
+
+    let total = files.len();
+    let concurrency = concurrency.max(1);
+    let mut pending = files.into_iter();
+    let mut in_flight = FuturesUnordered::new();
+    let mut result = EmbedFilesResult {
+        embeddings: HashMap::with_capacity(total),
+        failures: Vec::new(),
+        aborted: false,
+    };
+    let mut done = 0;
+    let mut progress = Progress::new(Some(&progress_callback), "embed_files_ollama", total)
+        .with_abort(settings.abort_signal.as_ref());
+
+    let (endpoint, model, settings, options) = (&endpoint, &model, &settings, &options);
+    let keep_alive = options.keep_alive.as_deref();
+    loop {
+        // Top up the in-flight set unless the caller has given up
+        while in_flight.len() < concurrency && !settings.is_aborted() {
+            let Some(file) = pending.next() else { break };
+            in_flight.push(async move {
+                let text = options.prefixed(model, embeddable_text(&file.content).to_string());
+                let embedding = fetch_embedding(endpoint, model, text, keep_alive, settings).await;
+                (file.path, embedding)
+            });
+        }
+
+        let Some((path, embedding)) = in_flight.next().await else { break };
+        done += 1;
+
+        // Requests in flight still finish after an abort, but Progress stops reporting them
+        progress.update(done, || Some(path.clone()));
+
+        match embedding {
+            Ok(embedding) => {
+                result.embeddings.insert(path, embedding);
+            }
+            Err(error) => result.failures.push(EmbedFailure { path, error }),
+        }
+    }
+
+    progress.finish(None);
+    result.aborted = settings.is_aborted();
+
+    let serializer = serde_wasm_bindgen::Serializer::new().serialize_maps_as_objects(true);
+    result.serialize(&serializer)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in embed_files_ollama: {}", e)))
+}
+
+/// Embed text locally with a hashed bag-of-words, for when Ollama is unreachable.
+/// Tokens are lowercased alphanumeric runs, hashed into `dim` buckets with a hash-derived
+/// sign, weighted by sublinear TF (1 + ln tf) and L2-normalized. Deterministic across runs.
+/// These vectors only make sense compared with other local vectors, never with model vectors.
+#[wasm_bindgen]
+pub fn generate_local_embedding(text: &str, dim: usize) -> Vec<f32> {
+    let dim = dim.max(1);
+    let mut counts: HashMap<String, u32> = HashMap::new();
+    for token in text.split(|c: char| !c.is_alphanumeric()) {
+        if token.chars().count() < 2 {
+            continue;
+        }
+        *counts.entry(token.to_lowercase()).or_insert(0) += 1;
+    }
+
+    let mut vector = vec![0.0f32; dim];
+    for (token, count) in counts {
+        let hash = xxhash_rust::xxh64::xxh64(token.as_bytes(), 0);
+        let index = (hash % dim as u64) as usize;
+        // Signed hashing keeps bucket collisions from only ever adding up
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[index] += sign * (1.0 + (count as f32).ln());
+    }
+
+    l2_normalize(&mut vector);
+    vector
+}
+
+/// A slice of a document, with offsets in chars (not bytes)
+#[cfg(feature = "wasm")]
+pub(crate) struct TextChunk {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// Embedding of one document chunk
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChunkEmbedding {
+    pub start: usize,
+    pub end: usize,
+    pub embedding: Vec<f32>,
+}
+
+/// Whole-document embedding plus the chunk embeddings it was averaged from
+#[derive(Serialize, Deserialize)]
+pub struct DocumentEmbedding {
+    pub embedding: Vec<f32>,
+    pub chunks: Vec<ChunkEmbedding>,
+    /// Chunks that failed to embed and were left out of the mean
+    pub failed_chunks: usize,
+}
+
+/// Split content into chunks of roughly `chunk_size` chars, preferring to break at
+/// paragraph boundaries, then line breaks, then whitespace. Consecutive chunks share
+/// up to `overlap` chars. Works on char boundaries so multi-byte text is never split.
+#[cfg(feature = "wasm")]
+pub(crate) fn chunk_document(content: &str, chunk_size: usize, overlap: usize) -> Vec<TextChunk> {
+    let chunk_size = chunk_size.max(1);
+    // Overlap larger than half a chunk would make progress crawl
+    let overlap = overlap.min(chunk_size / 2);
+
+    let chars: Vec<char> = content.chars().collect();
+    let total = chars.len();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < total {
+        let limit = start + chunk_size;
+        let end = if limit >= total {
+            total
+        } else {
+            // Only look for a break in the back half so chunks don't get tiny
+            let min_end = start + (chunk_size / 2).max(1);
+            let window = min_end..limit;
+            let paragraph = window.clone().rev().find(|&i| i >= 2 && chars[i - 1] == '\n' && chars[i - 2] == '\n');
+            let line = || window.clone().rev().find(|&i| chars[i - 1] == '\n');
+            let space = || window.clone().rev().find(|&i| chars[i - 1].is_whitespace());
+            paragraph.or_else(line).or_else(space).unwrap_or(limit)
+        };
+
+        let text: String = chars[start..end].iter().collect();
+        if !text.trim().is_empty() {
+            chunks.push(TextChunk { start, end, text });
+        }
+
+        if end >= total {
+            break;
+        }
+        start = (end - overlap).max(start + 1);
+    }
+
+    chunks
+}
+
+/// Length-weighted mean of chunk vectors; None if there are none or dimensions disagree
+#[cfg(feature = "wasm")]
+fn weighted_mean(chunks: &[ChunkEmbedding]) -> Option<Vec<f32>> {
+    let dim = chunks.first()?.embedding.len();
+    if chunks.iter().any(|c| c.embedding.len() != dim) {
+        return None;
+    }
+
+    let mut mean = vec![0.0f32; dim];
+    let mut total_weight = 0.0f32;
+    for chunk in chunks {
+        let weight = (chunk.end - chunk.start) as f32;
+        for (m, v) in mean.iter_mut().zip(&chunk.embedding) {
+            *m += v * weight;
+        }
+        total_weight += weight;
+    }
+    if total_weight > 0.0 {
+        mean.iter_mut().for_each(|m| *m /= total_weight);
+    }
+    Some(mean)
+}
+
+/// Embed a long document in chunks so content past the model's context isn't lost.
+/// The body (without frontmatter) is split on paragraph boundaries near `chunk_size_chars`,
+/// with `overlap_chars` shared between neighbours. Returns {embedding, chunks: [{start, end, embedding}], failed_chunks},
+/// where `embedding` is the length-weighted mean of the chunk vectors and offsets are in chars.
+/// `options` is an EmbedOptions object, as in generate_embedding_ollama.
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub async fn generate_document_embedding_ollama(
+    endpoint: String,
+    model: String,
+    content: String,
+    chunk_size_chars: usize,
+    overlap_chars: usize,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    if chunk_size_chars == 0 {
+        return Err(JsValue::from_str("chunk_size_chars must be greater than 0"));
+    }
+
+    let options = EmbedOptions::from_js(options, "generate_document_embedding_ollama")?;
+    let settings = options.settings();
+
+    // Chunk the body only; offsets still count from the start of `content`
+    let body = embeddable_text(&content);
+    let body_offset = content[..content.len() - body.len()].chars().count();
+    let mut text_chunks = chunk_document(body, chunk_size_chars, overlap_chars);
+    for chunk in &mut text_chunks {
+        chunk.start += body_offset;
+        chunk.end += body_offset;
+    }
+    if text_chunks.is_empty() {
+        return Err(JsValue::from_str("Document is empty"));
+    }
+
+    let texts: Vec<String> = text_chunks.iter()
+        .map(|c| options.prefixed(&model, c.text.clone()))
+        .collect();
+    let results = embed_texts(&endpoint, &model, &texts, options.keep_alive.as_deref(), &settings)
+        .await
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    let mut chunks = Vec::with_capacity(text_chunks.len());
+    let mut failed_chunks = 0;
+    for (chunk, result) in text_chunks.iter().zip(results) {
+        match result {
+            Ok(embedding) => chunks.push(ChunkEmbedding { start: chunk.start, end: chunk.end, embedding }),
+            Err(e) => {
+                log_warn!("Chunk {}..{} failed to embed: {}", chunk.start, chunk.end, e);
+                failed_chunks += 1;
+            }
+        }
+    }
+
+    let embedding = weighted_mean(&chunks)
+        .ok_or_else(|| JsValue::from_str("No chunk embeddings could be generated"))?;
+
+    let document = DocumentEmbedding { embedding, chunks, failed_chunks };
+    serde_wasm_bindgen::to_value(&document)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in generate_document_embedding_ollama: {}", e)))
+}
+
+#[cfg(all(test, feature = "wasm"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embed_options_prefix_text_for_their_task() {
+        let Ok(options) = serde_json::from_str::<EmbedOptions>(
+            r#"{"task_type": "query", "keep_alive": "30m", "timeout_ms": 5000}"#,
+        ) else {
+            panic!("options not parsed");
+        };
+        assert_eq!(options.task_type, Some(EmbeddingTaskType::Query));
+        assert_eq!((options.keep_alive.as_deref(), options.timeout_ms), (Some("30m"), Some(5000)));
+        assert_eq!(options.prefixed("nomic-embed-text:latest", "cats".to_string()), "search_query: cats");
+
+        let Ok(defaults) = serde_json::from_str::<EmbedOptions>("{}") else {
+            panic!("options not parsed");
+        };
+        assert_eq!(defaults.prefixed("nomic-embed-text", "cats".to_string()), "cats");
+    }
 }
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+/// Why a file was left out of a scan
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Already processed and not modified since
+    Unchanged,
+    /// Matches an exclusion pattern
+    Excluded,
+    /// Inclusions are set and none of them matches
+    NotIncluded,
+    /// The note opted out in its frontmatter (see `SmartVault::set_opt_out_property`)
+    OptedOut,
+}
+
+/// A file `plan_scan` won't process, and why
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: SkipReason,
+    /// The exclusion pattern that matched, for `Excluded`
+    pub pattern: Option<String>,
+}
+
+/// Match `path` against a glob: `*` and `?` stay within one folder level, `**` spans any number
+/// of them ("a/**/b" matches "a/b" too). Everything else must match exactly, case included.
+fn glob_match(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', rest @ ..] => {
+            (rest.first() == Some(&'/') && glob_match(&rest[1..], path))
+                || (0..=path.len()).any(|i| glob_match(rest, &path[i..]))
+        }
+        ['*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| glob_match(rest, &path[i..])),
+        ['?', rest @ ..] => path.first().is_some_and(|&c| c != '/') && glob_match(rest, &path[1..]),
+        [c, rest @ ..] => path.first() == Some(c) && glob_match(rest, &path[1..]),
+    }
+}
+
+/// Whether a vault path matches an exclusion or inclusion pattern. Patterns without wildcards
+/// are a folder ("Templates" or "Templates/") or file path from the vault root. Glob patterns
+/// (`*`, `**`, `?`) containing a '/' match the whole path; ones without match the file name
+/// anywhere ("*.excalidraw.md").
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim().trim_start_matches('/');
+    if pattern.is_empty() {
+        return false;
+    }
+    if !pattern.contains(['*', '?']) {
+        let folder = pattern.trim_end_matches('/');
+        return path == folder || path.strip_prefix(folder).is_some_and(|rest| rest.starts_with('/'));
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let target = if pattern.contains(&'/') { path } else { path.rsplit('/').next().unwrap_or(path) };
+    glob_match(&pattern, &target.chars().collect::<Vec<_>>())
+}
+
+/// Exclusion patterns and an optional inclusion allow-list, shared by `VaultScanner` and
+/// `SmartVault::plan_scan`. With inclusions set, only paths matching one of them are scanned;
+/// exclusions apply on top of that.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    exclusions: Vec<String>,
+    inclusions: Vec<String>,
+}
+
+impl PathFilter {
+    /// Blank patterns are dropped
+    fn clean(patterns: Vec<String>) -> Vec<String> {
+        patterns.into_iter()
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.trim_start_matches('/').is_empty())
+            .collect()
+    }
+
+    pub fn set_exclusions(&mut self, patterns: Vec<String>) {
+        self.exclusions = Self::clean(patterns);
+    }
+
+    /// An empty list turns the allow-list off
+    pub fn set_inclusions(&mut self, patterns: Vec<String>) {
+        self.inclusions = Self::clean(patterns);
+    }
+
+    /// Why `path` is filtered out, with the exclusion pattern that matched; None if it's scanned
+    pub fn skip_reason(&self, path: &str) -> Option<(SkipReason, Option<String>)> {
+        if !self.inclusions.is_empty() && !self.inclusions.iter().any(|p| path_matches(p, path)) {
+            return Some((SkipReason::NotIncluded, None));
+        }
+        self.exclusions.iter()
+            .find(|p| path_matches(p, path))
+            .map(|p| (SkipReason::Excluded, Some(p.clone())))
+    }
+
+    pub fn allows(&self, path: &str) -> bool {
+        self.skip_reason(path).is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_patterns_cover_nested_folders_but_not_lookalikes() {
+        assert!(path_matches("Archive/Old", "Archive/Old/2023/Jan/note.md"));
+        assert!(path_matches("/Archive/Old/", "Archive/Old/note.md"));
+        assert!(!path_matches("Archive/Old", "Archive/Older/note.md"));
+        assert!(!path_matches("Archive/Old", "Work/Archive/Old/note.md"));
+
+        assert!(path_matches("Archive/*", "Archive/note.md"));
+        assert!(!path_matches("Archive/*", "Archive/2023/note.md"));
+        assert!(path_matches("Archive/**", "Archive/2023/Jan/note.md"));
+        assert!(path_matches("**/Drafts/**", "Work/Projects/Drafts/idea.md"));
+        assert!(path_matches("**/Drafts/**", "Drafts/idea.md"));
+        assert!(path_matches("Work/**/*.md", "Work/note.md"));
+        assert!(!path_matches("**/Drafts/**", "Work/MyDrafts/idea.md"));
+    }
+
+    #[test]
+    fn patterns_may_contain_spaces() {
+        assert!(path_matches("Daily Notes", "Daily Notes/2024-01-01.md"));
+        assert!(path_matches("  Daily Notes/  ", "Daily Notes/2024/01.md"));
+        assert!(!path_matches("Daily Notes", "Daily Notes Backup/01.md"));
+        assert!(!path_matches("Daily Notes", "DailyNotes/01.md"));
+        assert!(path_matches("* copy.md", "Work/Plan copy.md"));
+        assert!(!path_matches("* copy.md", "Work/Plan.md"));
+        assert!(path_matches("My Vault Stuff/**/Old ?.md", "My Vault Stuff/a b/Old 1.md"));
+
+        let mut filter = PathFilter::default();
+        filter.set_exclusions(vec!["Daily Notes".to_string(), "   ".to_string(), "**/Old Drafts/**".to_string()]);
+        assert_eq!(filter.skip_reason("Daily Notes/01.md"), Some((SkipReason::Excluded, Some("Daily Notes".to_string()))));
+        assert_eq!(filter.skip_reason("Work/Old Drafts/x.md"), Some((SkipReason::Excluded, Some("**/Old Drafts/**".to_string()))));
+        assert!(filter.allows("Work/Old Draft/x.md"));
+
+        filter.set_inclusions(vec!["Work Projects".to_string()]);
+        assert_eq!(filter.skip_reason("Work/x.md"), Some((SkipReason::NotIncluded, None)));
+        assert!(filter.allows("Work Projects/Sub Folder/x.md"));
+    }
+}
@@ -0,0 +1,627 @@
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+/// New (not yet in the vault) tags kept from one suggestion
+pub const MAX_NEW_TAGS: usize = 3;
+
+/// Aliases kept from one suggestion
+const MAX_ALIASES: usize = 5;
+
+/// Tag as Obsidian accepts it: no '#', spaces joined with '-', only letters, digits, '-', '_'
+/// and '/'. None if nothing is left or it is all digits (Obsidian ignores numeric tags).
+pub fn clean_tag(raw: &str) -> Option<String> {
+    let joined = raw.trim().trim_start_matches('#').split_whitespace().collect::<Vec<_>>().join("-");
+    let tag: String = joined.chars()
+        .filter(|&c| c.is_alphanumeric() || matches!(c, '-' | '_' | '/'))
+        .collect();
+    let tag = tag.trim_matches('/').to_string();
+    if tag.is_empty() || tag.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(tag)
+}
+
+/// Comparison key for tags: case, '#', and '-'/'_'/space differences are ignored
+fn tag_key(tag: &str) -> String {
+    tag.trim()
+        .trim_start_matches('#')
+        .chars()
+        .map(|c| if matches!(c, '_' | ' ') { '-' } else { c })
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Sort the model's tag suggestions into tags the vault already uses (in the vault's spelling)
+/// and new ones, at most `max_new` of them. Suggestions filed under the wrong list are moved,
+/// and duplicates dropped.
+pub fn match_vault_tags(existing: &[String], new: &[String], vault_tags: &[String], max_new: usize) -> (Vec<String>, Vec<String>) {
+    let vault: Vec<(String, String)> = vault_tags.iter()
+        .filter_map(|tag| clean_tag(tag))
+        .map(|tag| (tag_key(&tag), tag))
+        .collect();
+
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut matched = Vec::new();
+    let mut unmatched = Vec::new();
+    for tag in existing.iter().chain(new).filter_map(|tag| clean_tag(tag)) {
+        let key = tag_key(&tag);
+        if !seen.insert(key.clone()) {
+            continue;
+        }
+        match vault.iter().find(|(vault_key, _)| *vault_key == key) {
+            Some((_, spelling)) => matched.push(spelling.clone()),
+            None if unmatched.len() < max_new => unmatched.push(tag),
+            None => {}
+        }
+    }
+    (matched, unmatched)
+}
+
+/// Alias safe to use as link text: no brackets, pipes, '#' or '^', and on one line. Aliases
+/// equal to the title are dropped.
+pub fn sanitize_aliases(aliases: &[String], title: &str) -> Vec<String> {
+    let mut seen: HashSet<String> = HashSet::new();
+    seen.insert(title.trim().to_lowercase());
+    aliases.iter()
+        .map(|alias| {
+            alias.chars()
+                .map(|c| if matches!(c, '[' | ']' | '|' | '#' | '^') { ' ' } else { c })
+                .collect::<String>()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|alias| !alias.is_empty() && seen.insert(alias.to_lowercase()))
+        .take(MAX_ALIASES)
+        .collect()
+}
+
+/// Whether a plain YAML scalar would be misread (as another type, a comment, a mapping, ...)
+fn needs_quotes(value: &str) -> bool {
+    const RESERVED: &[&str] = &["true", "false", "yes", "no", "on", "off", "null", "~", "y", "n"];
+    value.is_empty()
+        || value.trim() != value
+        || value.starts_with(['-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%', '@', '`'])
+        || value.contains(": ")
+        || value.ends_with(':')
+        || value.contains(" #")
+        || value.contains(['\n', '\r', '\t'])
+        || RESERVED.contains(&value.to_lowercase().as_str())
+        || value.parse::<f64>().is_ok()
+}
+
+/// Render a string as a YAML scalar, double-quoted when a plain one would be misread
+pub fn yaml_scalar(value: &str) -> String {
+    if !needs_quotes(value) {
+        return value.to_string();
+    }
+    let escaped = value.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+/// Value of a quoted or plain YAML scalar
+fn parse_scalar(raw: &str) -> String {
+    let raw = raw.trim();
+    if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') {
+        let inner = &raw[1..raw.len() - 1];
+        let mut out = String::with_capacity(inner.len());
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some('t') => out.push('\t'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            }
+        }
+        return out;
+    }
+    if raw.len() >= 2 && raw.starts_with('\'') && raw.ends_with('\'') {
+        return raw[1..raw.len() - 1].replace("''", "'");
+    }
+    // A plain scalar ends at a comment
+    raw.split(" #").next().unwrap_or_default().trim().to_string()
+}
+
+/// Items of an inline YAML sequence ("[a, "b, c"]"), splitting on commas outside quotes
+fn parse_inline_list(raw: &str) -> Vec<String> {
+    let inner = raw.trim().trim_start_matches('[').trim_end_matches(']');
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    for c in inner.chars() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (',', None) => {
+                items.push(parse_scalar(&std::mem::take(&mut current)));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    items.push(parse_scalar(&current));
+    items.into_iter().filter(|item| !item.is_empty()).collect()
+}
+
+/// How a list-valued key was written
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ListStyle {
+    /// "key:" followed by "- item" lines
+    Block,
+    /// "key: [a, b]"
+    Inline,
+}
+
+/// One top-level key of a frontmatter block and the lines belonging to it
+struct FrontmatterEntry {
+    /// None for lines before the first key (comments, blank lines)
+    key: Option<String>,
+    lines: Vec<String>,
+}
+
+impl FrontmatterEntry {
+    /// Text after "key:" on the first line
+    fn inline_value(&self) -> &str {
+        self.lines.first()
+            .and_then(|line| split_key(line))
+            .map_or("", |(_, value)| value.trim())
+    }
+
+    /// The entry's value: scalars typed, inline arrays and block lists ("- item") as arrays of
+    /// strings, literal/folded block scalars ("|", ">") as one string, and one level of nested
+    /// keys as an object. None if a line doesn't fit.
+    fn value(&self) -> Option<Value> {
+        let rest = self.inline_value();
+        let under: Vec<&str> = self.lines[1..].iter().map(String::as_str).collect();
+        let block: Vec<&str> = under.iter().copied().filter(|l| !is_skippable(l)).collect();
+        // Lines under a key are indented, or "- item" lines (even at column 0) of a list
+        if !block.iter().all(|l| l.starts_with([' ', '\t']) || (rest.is_empty() && l.starts_with('-'))) {
+            return None;
+        }
+
+        if rest.starts_with('|') || rest.starts_with('>') {
+            let text: Vec<&str> = under.iter().map(|l| l.trim()).collect();
+            let joined = if rest.starts_with('|') { text.join("\n") } else { text.join(" ") };
+            return Some(Value::String(joined.trim().to_string()));
+        }
+        if !rest.is_empty() {
+            return if block.is_empty() { inline_value(rest) } else { None };
+        }
+        if block.is_empty() {
+            return Some(Value::Null);
+        }
+        if block.iter().all(|l| l.trim_start().starts_with('-')) {
+            return Some(Value::Array(block.iter()
+                .map(|l| parse_scalar(l.trim_start().trim_start_matches('-')))
+                .filter(|item| !item.is_empty())
+                .map(Value::String)
+                .collect()));
+        }
+        // One nested level: "  sub: value" lines, each with an inline value
+        let mut nested = Map::new();
+        let indent = block[0].len() - block[0].trim_start().len();
+        for sub in &block {
+            if sub.len() - sub.trim_start().len() != indent {
+                return None;
+            }
+            let (sub_key, sub_rest) = split_key(sub.trim_start())?;
+            nested.insert(sub_key, inline_value(sub_rest)?);
+        }
+        Some(Value::Object(nested))
+    }
+
+    /// List items, whether written as a block sequence, an inline array, or a comma separated
+    /// scalar. With `split_spaces` a scalar is also split on spaces ("tags: a b" is two tags).
+    /// None if the value can't be read.
+    fn list_values(&self, split_spaces: bool) -> Option<(Vec<String>, ListStyle)> {
+        let style = if self.inline_value().is_empty() { ListStyle::Block } else { ListStyle::Inline };
+        let items = match self.value()? {
+            Value::Array(items) => items.iter().filter_map(Value::as_str).map(String::from).collect(),
+            Value::Null | Value::Object(_) => Vec::new(),
+            scalar => {
+                let scalar = match scalar {
+                    Value::String(text) => text,
+                    other => other.to_string(),
+                };
+                let separator = if scalar.contains(',') || !split_spaces { ',' } else { ' ' };
+                scalar.split(separator).map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect()
+            }
+        };
+        Some((items, style))
+    }
+
+    /// Replace the entry's lines, keeping the comments and blank lines at its end, which belong
+    /// to the keys below
+    fn replace_lines(&mut self, lines: Vec<String>) {
+        let trailing = self.lines.iter().skip(1).rev()
+            .take_while(|line| line.trim().is_empty() || line.trim_start().starts_with('#'))
+            .count();
+        let trailing = self.lines.split_off(self.lines.len() - trailing);
+        self.lines = lines;
+        self.lines.extend(trailing);
+    }
+}
+
+fn is_skippable(line: &str) -> bool {
+    line.trim().is_empty() || line.trim_start().starts_with('#')
+}
+
+/// Split frontmatter YAML (without the --- lines) into top-level keys. Indented lines, list
+/// items, comments, blank lines and any other line that isn't "key: value" stay with the key
+/// above them.
+fn parse_entries(yaml: &str) -> Vec<FrontmatterEntry> {
+    let mut entries: Vec<FrontmatterEntry> = Vec::new();
+    for line in yaml.lines() {
+        let key = split_key(line).filter(|_| !line.starts_with([' ', '\t', '-', '#']));
+        if let Some((key, _)) = key {
+            entries.push(FrontmatterEntry { key: Some(key), lines: vec![line.to_string()] });
+        } else if let Some(last) = entries.last_mut() {
+            last.lines.push(line.to_string());
+        } else {
+            entries.push(FrontmatterEntry { key: None, lines: vec![line.to_string()] });
+        }
+    }
+    entries
+}
+
+/// The YAML inside a leading frontmatter block of `content` (without the --- lines), and the
+/// byte offset where the rest of the note starts
+pub fn split_frontmatter(content: &str) -> (Option<&str>, usize) {
+    let Some(rest) = content.strip_prefix("---\n").or_else(|| content.strip_prefix("---\r\n")) else {
+        return (None, 0);
+    };
+    let yaml_start = content.len() - rest.len();
+    let mut offset = yaml_start;
+    for line in rest.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            return (Some(&content[yaml_start..offset]), offset + line.len());
+        }
+        offset += line.len();
+    }
+    (None, 0)
+}
+
+/// Result of `parse_frontmatter`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ParsedFrontmatter {
+    /// Keys and values of the frontmatter, or None if there is none (or it couldn't be read)
+    pub frontmatter: Option<Map<String, Value>>,
+    /// Char offset where the body starts; 0 without frontmatter
+    pub body_start_offset: usize,
+    pub body: String,
+    /// The note opens with "---" but the block is unclosed or not YAML `parse_yaml_subset`
+    /// understands, so it was treated as body
+    pub malformed: bool,
+}
+
+/// Value of an unquoted scalar: booleans, null and numbers are typed, anything else is a string
+fn typed_scalar(raw: &str) -> Value {
+    let raw = raw.trim();
+    let quoted = raw.len() >= 2 && ((raw.starts_with('"') && raw.ends_with('"')) || (raw.starts_with('\'') && raw.ends_with('\'')));
+    if quoted {
+        return Value::String(parse_scalar(raw));
+    }
+    let plain = parse_scalar(raw);
+    match plain.as_str() {
+        "" | "~" | "null" | "Null" | "NULL" => Value::Null,
+        "true" | "True" | "TRUE" => Value::Bool(true),
+        "false" | "False" | "FALSE" => Value::Bool(false),
+        _ => plain.parse::<i64>().map(Value::from)
+            .ok()
+            .or_else(|| plain.parse::<f64>().ok().filter(|f| f.is_finite()).map(Value::from))
+            .unwrap_or(Value::String(plain)),
+    }
+}
+
+/// Value after "key:" on one line; None for an unterminated quote or inline list
+fn inline_value(raw: &str) -> Option<Value> {
+    let raw = raw.trim();
+    if raw.starts_with('[') {
+        return raw.ends_with(']').then(|| Value::Array(parse_inline_list(raw).into_iter().map(Value::String).collect()));
+    }
+    if let Some(quote) = raw.chars().next().filter(|&c| c == '"' || c == '\'') {
+        // A comment may follow the closing quote
+        let closed = raw.len() >= 2 && raw[1..].rfind(quote).is_some_and(|i| raw[i + 2..].trim().is_empty() || raw[i + 2..].trim_start().starts_with('#'));
+        if !closed {
+            return None;
+        }
+        let end = 1 + raw[1..].rfind(quote).unwrap_or(0) + 1;
+        return Some(typed_scalar(&raw[..end]));
+    }
+    Some(typed_scalar(raw))
+}
+
+/// Split "key: value" into its key and the text after the colon
+fn split_key(line: &str) -> Option<(String, &str)> {
+    let colon = line.char_indices()
+        .find(|&(i, c)| c == ':' && line[i + 1..].chars().next().is_none_or(char::is_whitespace))
+        .map(|(i, _)| i)?;
+    let key = line[..colon].trim().trim_matches(['"', '\'']).to_string();
+    (!key.is_empty()).then_some((key, &line[colon + 1..]))
+}
+
+/// Parse the small part of YAML notes use: "key: scalar", inline arrays, block lists ("- item"),
+/// literal/folded block scalars ("|", ">") and one level of nested keys (see
+/// `FrontmatterEntry::value`). List items are strings. None if a line doesn't fit, so callers
+/// can fall back to treating the block as text.
+pub fn parse_yaml_subset(yaml: &str) -> Option<Map<String, Value>> {
+    let mut map = Map::new();
+    for entry in parse_entries(yaml) {
+        match entry.key {
+            Some(ref key) => {
+                let value = entry.value()?;
+                map.insert(key.clone(), value);
+            }
+            // Only comments and blank lines may come before the first key
+            None if entry.lines.iter().all(|line| is_skippable(line)) => {}
+            None => return None,
+        }
+    }
+    Some(map)
+}
+
+/// Byte offset where the body of `content` starts, and its parsed frontmatter. A block that is
+/// unclosed or doesn't parse counts as body (offset 0, `malformed`).
+fn read_frontmatter(content: &str) -> (Option<Map<String, Value>>, usize, bool) {
+    let opens = content.starts_with("---\n") || content.starts_with("---\r\n");
+    match split_frontmatter(content) {
+        (Some(yaml), body_start) => match parse_yaml_subset(yaml) {
+            Some(map) => (Some(map), body_start, false),
+            None => (None, 0, true),
+        },
+        (None, _) => (None, 0, opens),
+    }
+}
+
+/// Split a note into its frontmatter (as an object) and body. Returns a `ParsedFrontmatter`;
+/// frontmatter that can't be read leaves the whole note as body and sets `malformed`.
+#[wasm_bindgen]
+pub fn parse_frontmatter(content: &str) -> Result<JsValue, JsValue> {
+    let (frontmatter, body_start, malformed) = read_frontmatter(content);
+    let parsed = ParsedFrontmatter {
+        frontmatter,
+        body_start_offset: content[..body_start].chars().count(),
+        body: content[body_start..].to_string(),
+        malformed,
+    };
+    // Objects rather than Maps, so the frontmatter reads like parsed YAML on the JS side
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    parsed.serialize(&serializer)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in parse_frontmatter: {}", e)))
+}
+
+/// The note without its frontmatter block (unchanged if it has none or it can't be read)
+#[wasm_bindgen]
+pub fn strip_frontmatter(content: &str) -> String {
+    note_body(content).to_string()
+}
+
+/// `content` after its frontmatter, as `strip_frontmatter` returns it
+pub(crate) fn note_body(content: &str) -> &str {
+    &content[read_frontmatter(content).1..]
+}
+
+/// Aliases declared in a note's frontmatter ("aliases" or "alias", list or inline)
+pub fn note_aliases(content: &str) -> Vec<String> {
+    let Some(yaml) = split_frontmatter(content).0 else {
+        return Vec::new();
+    };
+    parse_entries(yaml).iter()
+        .find(|e| e.key.as_deref().is_some_and(|k| k.eq_ignore_ascii_case("aliases") || k.eq_ignore_ascii_case("alias")))
+        .and_then(|e| e.list_values(false))
+        .map(|(aliases, _)| aliases)
+        .unwrap_or_default()
+}
+
+/// Frontmatter property a note opts out of Smart Vault with, unless another is configured
+pub const DEFAULT_OPT_OUT_PROPERTY: &str = "smart-vault";
+
+/// How a note opted out through its frontmatter
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OptOut {
+    /// "ignore": not processed, and any stored embeddings and keywords are dropped
+    Ignore,
+    /// "no-suggest": not processed, and never suggested as a link target
+    NoSuggest,
+}
+
+/// The opt-out declared by `property` in a note's frontmatter (matched ignoring case), as a
+/// value or a list of values. "ignore" wins if both are given; other values are not opt-outs.
+pub fn note_opt_out(content: &str, property: &str) -> Option<OptOut> {
+    let frontmatter = read_frontmatter(content).0?;
+    let value = frontmatter.iter().find(|(key, _)| key.eq_ignore_ascii_case(property.trim()))?.1;
+    let values: Vec<String> = match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(items) => items.iter().filter_map(Value::as_str).map(String::from).collect(),
+        _ => Vec::new(),
+    };
+    let opt_outs: Vec<OptOut> = values.iter()
+        .filter_map(|v| match v.trim().to_lowercase().replace('_', "-").as_str() {
+            "ignore" => Some(OptOut::Ignore),
+            "no-suggest" | "nosuggest" => Some(OptOut::NoSuggest),
+            _ => None,
+        })
+        .collect();
+    opt_outs.iter().copied().find(|&o| o == OptOut::Ignore).or(opt_outs.first().copied())
+}
+
+/// Tags declared in a note's frontmatter ("tags" or "tag"; a list, an inline array, or a comma
+/// or space separated scalar), cleaned with `clean_tag`
+pub fn frontmatter_tags(content: &str) -> Vec<String> {
+    let Some(yaml) = split_frontmatter(content).0 else {
+        return Vec::new();
+    };
+    parse_entries(yaml).iter()
+        .find(|e| e.key.as_deref().is_some_and(|k| k.eq_ignore_ascii_case("tags") || k.eq_ignore_ascii_case("tag")))
+        .and_then(|e| e.list_values(true))
+        .map(|(tags, _)| tags.iter().filter_map(|tag| clean_tag(tag)).collect())
+        .unwrap_or_default()
+}
+
+fn render_list(key: &str, items: &[String], style: ListStyle) -> Vec<String> {
+    match style {
+        ListStyle::Inline => {
+            let items: Vec<String> = items.iter().map(|item| {
+                // Inside [...] a comma also ends a plain item
+                if item.contains([',', '[', ']', '{', '}']) { format!("\"{}\"", item.replace('\\', "\\\\").replace('"', "\\\"")) } else { yaml_scalar(item) }
+            }).collect();
+            vec![format!("{}: [{}]", key, items.join(", "))]
+        }
+        ListStyle::Block => std::iter::once(format!("{}:", key))
+            .chain(items.iter().map(|item| format!("  - {}", yaml_scalar(item))))
+            .collect(),
+    }
+}
+
+/// Generated frontmatter values to write into a note
+pub struct FrontmatterValues<'a> {
+    pub summary: &'a str,
+    pub tags: &'a [String],
+    pub aliases: &'a [String],
+}
+
+/// Render a frontmatter block (with --- lines) holding `values`, merged into `existing` YAML when
+/// given: its other keys and comments are kept as written, tags and aliases are combined with the
+/// ones already there (in the same list style), and the summary replaces the old one. "tag" and
+/// "alias" keys are recognized as tags and aliases; one whose value can't be read is kept as is.
+pub fn merge_frontmatter(existing: Option<&str>, values: &FrontmatterValues) -> String {
+    let mut entries = existing.map(parse_entries).unwrap_or_default();
+    let find = |entries: &[FrontmatterEntry], keys: &[&str]| {
+        entries.iter().position(|e| e.key.as_deref().is_some_and(|k| keys.iter().any(|key| k.eq_ignore_ascii_case(key))))
+    };
+
+    if !values.summary.is_empty() {
+        let lines = vec![format!("summary: {}", yaml_scalar(values.summary))];
+        match find(&entries, &["summary"]) {
+            Some(i) => entries[i].replace_lines(lines),
+            None => entries.push(FrontmatterEntry { key: Some("summary".to_string()), lines }),
+        }
+    }
+
+    for (keys, new_items, is_tags) in [(["tags", "tag"], values.tags, true), (["aliases", "alias"], values.aliases, false)] {
+        let index = find(&entries, &keys);
+        let (mut items, style) = match index {
+            Some(i) => match entries[i].list_values(is_tags) {
+                Some(values) => values,
+                // A key that can't be read is left as written rather than rewritten
+                None => continue,
+            },
+            None => (Vec::new(), ListStyle::Block),
+        };
+        let key_of = |item: &str| if is_tags { tag_key(item) } else { item.trim().to_lowercase() };
+        let mut seen: HashSet<String> = items.iter().map(|item| key_of(item)).collect();
+        let before = items.len();
+        items.extend(new_items.iter().filter(|item| seen.insert(key_of(item))).cloned());
+        if items.is_empty() || (index.is_some() && items.len() == before) {
+            // Nothing to add: leave the key exactly as written
+            continue;
+        }
+        match index {
+            Some(i) => {
+                let key = entries[i].key.clone().unwrap_or_else(|| keys[0].to_string());
+                entries[i].replace_lines(render_list(&key, &items, style));
+            }
+            None => entries.push(FrontmatterEntry { key: Some(keys[0].to_string()), lines: render_list(keys[0], &items, style) }),
+        }
+    }
+
+    let mut yaml: Vec<String> = entries.into_iter().flat_map(|e| e.lines).collect();
+    while yaml.last().is_some_and(|line| line.trim().is_empty()) {
+        yaml.pop();
+    }
+    format!("---\n{}\n---\n", yaml.join("\n"))
+}
+
+/// Result of `generate_frontmatter_with_llm`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FrontmatterSuggestion {
+    pub summary: String,
+    /// Suggested tags the vault already uses
+    pub tags: Vec<String>,
+    /// Suggested tags new to the vault (at most MAX_NEW_TAGS)
+    pub new_tags: Vec<String>,
+    pub aliases: Vec<String>,
+    /// Complete frontmatter block (--- lines included), merged with the note's existing one
+    pub frontmatter: String,
+    /// Whether the note already had frontmatter that was merged into
+    pub merged: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn merges_into_list_style_tags() {
+        let existing = "title: Trip\ntags:\n  - travel\n  - \"#Japan\"\n# kept comment\ncreated: 2024-01-01";
+        let tags = strings(&["japan", "food", "Travel"]);
+        let aliases = strings(&["Tokyo trip"]);
+        let merged = merge_frontmatter(Some(existing), &FrontmatterValues { summary: "Notes: day one", tags: &tags, aliases: &aliases });
+        assert_eq!(merged, "---\ntitle: Trip\ntags:\n  - travel\n  - \"#Japan\"\n  - food\n# kept comment\ncreated: 2024-01-01\nsummary: \"Notes: day one\"\naliases:\n  - Tokyo trip\n---\n");
+    }
+
+    #[test]
+    fn merges_into_inline_array_tags() {
+        let existing = "tags: [travel, japan]\naliases: [Trip]\nsummary: old";
+        let tags = strings(&["food, drink", "JAPAN"]);
+        let aliases = strings(&["trip", "Journey"]);
+        let merged = merge_frontmatter(Some(existing), &FrontmatterValues { summary: "New summary", tags: &tags, aliases: &aliases });
+        assert_eq!(merged, "---\ntags: [travel, japan, \"food, drink\"]\naliases: [Trip, Journey]\nsummary: New summary\n---\n");
+    }
+
+    #[test]
+    fn leaves_keys_with_nothing_new_as_written() {
+        let existing = "tag: [ travel ,japan ]\nalias:\n  - Trip";
+        let tags = strings(&["travel"]);
+        let aliases = strings(&["TRIP"]);
+        let merged = merge_frontmatter(Some(existing), &FrontmatterValues { summary: "", tags: &tags, aliases: &aliases });
+        assert_eq!(merged, format!("---\n{}\n---\n", existing));
+
+        let fresh = merge_frontmatter(None, &FrontmatterValues { summary: "", tags: &tags, aliases: &[] });
+        assert_eq!(fresh, "---\ntags:\n  - travel\n---\n");
+    }
+
+    #[test]
+    fn tags_and_parsed_frontmatter_read_lists_alike() {
+        let notes = [
+            "---\ntags:\n- travel\n- \"food, drink\"\n---\n",
+            "---\ntags: [travel, \"food, drink\"]\n---\n",
+            "---\ntags:\n  - 'travel'\n  # a comment\n  - food, drink\n---\n",
+        ];
+        for note in notes {
+            let parsed = read_frontmatter(note).0.unwrap_or_else(|| panic!("unreadable: {:?}", note));
+            assert_eq!(parsed["tags"], serde_json::json!(["travel", "food, drink"]), "{:?}", note);
+            assert_eq!(frontmatter_tags(note), ["travel", "food-drink"], "{:?}", note);
+        }
+        assert_eq!(frontmatter_tags("---\ntags: travel food\n---\n"), ["travel", "food"]);
+        assert_eq!(note_aliases("---\naliases: Trip, Journey\n---\n"), ["Trip", "Journey"]);
+    }
+
+    #[test]
+    fn unreadable_keys_are_not_read_or_rewritten() {
+        let existing = "tags: [travel\nnot yaml";
+        assert_eq!(parse_yaml_subset(existing), None);
+        assert!(frontmatter_tags(&format!("---\n{}\n---\n", existing)).is_empty());
+        let tags = strings(&["food"]);
+        let merged = merge_frontmatter(Some(existing), &FrontmatterValues { summary: "", tags: &tags, aliases: &[] });
+        assert_eq!(merged, format!("---\n{}\n---\n", existing));
+    }
+}
@@ -0,0 +1,482 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use crate::links::{is_media_target, parse_all_links, LinkKind};
+use crate::frontmatter::note_aliases;
+use crate::vault::{is_canvas_path, read_canvas};
+use crate::extract_title_from_path;
+
+/// Closest existing names offered for a broken link
+const MAX_BROKEN_LINK_SUGGESTIONS: usize = 3;
+
+/// A note in the link graph; counts are distinct linking/linked notes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GraphNode {
+    pub path: String,
+    pub title: String,
+    pub outgoing: usize,
+    pub incoming: usize,
+}
+
+/// Links from `source` to `target` (both paths); `count` includes note embeds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub count: usize,
+}
+
+/// A link target with no matching note, and the notes linking to it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UnresolvedLink {
+    /// As first written in a link
+    pub target: String,
+    pub sources: Vec<String>,
+    pub count: usize,
+}
+
+/// A wiki link whose target matches no file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BrokenLink {
+    pub source_path: String,
+    /// 0-based line and char column of the link, for jumping to it in the editor
+    pub line: usize,
+    pub column: usize,
+    /// Byte and UTF-16 offsets of the link in the source note
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+    /// The whole link as written
+    pub raw_link: String,
+    /// Target without heading, block reference or alias
+    pub normalized_target: String,
+    /// Closest existing note titles (or file names, for embeds), best first
+    pub suggestions: Vec<String>,
+}
+
+/// Vault-wide link graph, sorted by path so repeated builds compare equal
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LinkGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+    pub unresolved: Vec<UnresolvedLink>,
+}
+
+/// Link target as written and how often a note links to it
+#[derive(Debug, Clone)]
+struct TargetCount {
+    written: String,
+    count: usize,
+}
+
+/// Comparison key for link targets and note paths: lowercase, no ".md", forward slashes
+pub(crate) fn link_key(target: &str) -> String {
+    let target = target.trim().replace('\\', "/").to_lowercase();
+    target.trim_start_matches('/').strip_suffix(".md").unwrap_or(target.trim_start_matches('/')).to_string()
+}
+
+/// A title shared by several notes, as listed by `SmartVault::get_ambiguous_titles`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AmbiguousTitle {
+    pub title: String,
+    /// Sorted
+    pub paths: Vec<String>,
+}
+
+fn folder_of(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(folder, _)| folder)
+}
+
+/// Join a "./x" or "../x" link to the source note's folder; None if it climbs above the vault
+fn relative_key(key: &str, source: &str) -> Option<String> {
+    let source_key = link_key(folder_of(source));
+    let mut parts: Vec<&str> = source_key.split('/').filter(|p| !p.is_empty()).collect();
+    for part in key.split('/') {
+        match part {
+            "." | "" => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Resolves link targets to file paths the way Obsidian does: an exact vault path first (or a
+/// "./"/"../" path relative to the linking note), then a file whose path ends with the target
+/// (any file with that name, for a bare name; notes without ".md"). Among several matches the
+/// one in the linking note's folder wins, then the shortest path. Aliases resolve last.
+/// Kept up to date as notes are added and removed.
+#[derive(Debug, Default)]
+pub struct TitleIndex {
+    /// Link key -> path
+    by_path: HashMap<String, String>,
+    /// Lowercase file name (no ".md") -> paths
+    by_name: HashMap<String, Vec<String>>,
+    aliases: HashMap<String, Vec<String>>,
+}
+
+impl TitleIndex {
+    pub fn new<'a>(paths: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut index = TitleIndex::default();
+        for path in paths {
+            index.insert(path);
+        }
+        index
+    }
+
+    pub fn insert(&mut self, path: &str) {
+        let key = link_key(path);
+        if self.by_path.insert(key.clone(), path.to_string()).is_none() {
+            let name = key.rsplit('/').next().unwrap_or(&key).to_string();
+            self.by_name.entry(name).or_default().push(path.to_string());
+        }
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        let key = link_key(path);
+        if self.by_path.remove(&key).is_none() {
+            return;
+        }
+        let name = key.rsplit('/').next().unwrap_or(&key);
+        if let Some(paths) = self.by_name.get_mut(name) {
+            paths.retain(|p| p != path);
+            if paths.is_empty() {
+                self.by_name.remove(name);
+            }
+        }
+        for paths in self.aliases.values_mut() {
+            paths.retain(|p| p != path);
+        }
+    }
+
+    /// Also resolve `[[alias]]` to the note declaring it in its frontmatter
+    pub fn add_alias(&mut self, alias: &str, path: &str) {
+        self.aliases.entry(link_key(alias)).or_default().push(path.to_string());
+    }
+
+    /// The candidate in `source`'s folder, else the one with the shortest path
+    fn choose<'a>(candidates: impl Iterator<Item = &'a String>, source: Option<&str>) -> Option<&'a str> {
+        let folder = source.map(|s| link_key(folder_of(s)));
+        candidates
+            .min_by_key(|p| (folder.as_ref().is_none_or(|f| link_key(folder_of(p)) != *f), p.matches('/').count(), p.len(), p.as_str()))
+            .map(String::as_str)
+    }
+
+    /// Path for a link target (without heading or block parts) written in the note at `source`
+    pub fn resolve(&self, target: &str, source: Option<&str>) -> Option<&str> {
+        let key = link_key(target);
+        if key.is_empty() {
+            return None;
+        }
+        if target.trim_start().starts_with("./") || target.trim_start().starts_with("../") {
+            return source
+                .and_then(|source| relative_key(&key, source))
+                .and_then(|key| self.by_path.get(&key))
+                .map(String::as_str);
+        }
+        if let Some(path) = self.by_path.get(&key) {
+            return Some(path);
+        }
+        let found = if key.contains('/') {
+            let suffix = format!("/{}", key);
+            Self::choose(self.by_path.iter().filter(|(k, _)| k.ends_with(&suffix)).map(|(_, path)| path), source)
+        } else {
+            self.by_name.get(&key).and_then(|paths| Self::choose(paths.iter(), source))
+        };
+        found.or_else(|| self.aliases.get(&key).and_then(|paths| Self::choose(paths.iter(), source)))
+    }
+
+    /// Shortest link target that resolves to `path` from the note at `source`: the file name
+    /// (without ".md" for notes) when that finds it, else the whole path
+    pub fn link_target(&self, path: &str, source: Option<&str>) -> String {
+        let written = path.strip_suffix(".md").unwrap_or(path);
+        let name = written.rsplit('/').next().unwrap_or(written);
+        if self.resolve(name, source) == Some(path) { name.to_string() } else { written.to_string() }
+    }
+
+    /// Paths whose title (see `extract_title_from_path`) is `title`, ignoring case, then paths
+    /// declaring it as an alias; shortest path first within each group
+    pub fn paths_for_title(&self, title: &str) -> Vec<String> {
+        let wanted = title.trim().to_lowercase();
+        if wanted.is_empty() {
+            return Vec::new();
+        }
+        let by_length = |paths: &mut Vec<String>| paths.sort_by(|a, b| (a.matches('/').count(), a.len(), a).cmp(&(b.matches('/').count(), b.len(), b)));
+        let mut titled: Vec<String> = self.by_path.values()
+            .filter(|path| extract_title_from_path(path).to_lowercase() == wanted)
+            .cloned()
+            .collect();
+        by_length(&mut titled);
+        let mut aliased: Vec<String> = self.aliases.get(&link_key(title)).cloned().unwrap_or_default();
+        aliased.retain(|path| !titled.contains(path));
+        aliased.dedup();
+        by_length(&mut aliased);
+        titled.extend(aliased);
+        titled
+    }
+
+    /// Note titles shared by more than one path, sorted by title
+    pub fn ambiguous_titles(&self) -> Vec<AmbiguousTitle> {
+        let mut titles: Vec<AmbiguousTitle> = self.by_name.values()
+            .filter(|paths| paths.len() > 1)
+            .map(|paths| {
+                let mut paths = paths.clone();
+                paths.sort();
+                AmbiguousTitle { title: extract_title_from_path(&paths[0]), paths }
+            })
+            .collect();
+        titles.sort_by_key(|t| t.title.to_lowercase());
+        titles
+    }
+}
+
+/// Targets of the links in `content` that belong in the graph, as written
+fn graph_link_targets(content: &str) -> Vec<String> {
+    parse_all_links(content, false).into_iter()
+        .filter(|link| link.kind != LinkKind::Url && !link.target.is_empty() && !(link.is_embed && is_media_target(&link.target)))
+        .map(|link| link.target)
+        .collect()
+}
+
+/// Outgoing links of every stored note, kept up to date as notes are added and removed. The
+/// resolved graph is built on demand and cached until the next change.
+#[derive(Debug, Default)]
+pub struct LinkIndex {
+    /// Source path -> target key -> count
+    outgoing: HashMap<String, HashMap<String, TargetCount>>,
+    graph: RefCell<Option<LinkGraph>>,
+}
+
+impl LinkIndex {
+    /// Parse a note's links, replacing what was recorded for it before. Links to same-note
+    /// headings and embedded media are not part of the graph; embedded notes are. For a canvas,
+    /// its file nodes (other than media) and the links in its text cards are its links.
+    pub fn update(&mut self, path: &str, content: &str) {
+        let written: Vec<String> = if is_canvas_path(path) {
+            let canvas = read_canvas(content);
+            canvas.files.into_iter()
+                .filter(|file| !is_media_target(file))
+                .chain(canvas.text_nodes.iter().flat_map(|node| graph_link_targets(&node.text)))
+                .collect()
+        } else {
+            graph_link_targets(content)
+        };
+        let mut targets: HashMap<String, TargetCount> = HashMap::new();
+        for target in written {
+            targets.entry(link_key(&target))
+                .or_insert_with(|| TargetCount { written: target.clone(), count: 0 })
+                .count += 1;
+        }
+        self.outgoing.insert(path.to_string(), targets);
+        self.graph.replace(None);
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        if self.outgoing.remove(path).is_some() {
+            self.graph.replace(None);
+        }
+    }
+
+    /// Run `f` on the resolved graph, building it first if anything changed since the last build
+    pub fn with_graph<R>(&self, f: impl FnOnce(&LinkGraph) -> R) -> R {
+        if self.graph.borrow().is_none() {
+            let graph = self.build();
+            self.graph.replace(Some(graph));
+        }
+        f(self.graph.borrow().as_ref().expect("graph was just built"))
+    }
+
+    /// Resolve every recorded link against the stored notes (see `TitleIndex`)
+    fn build(&self) -> LinkGraph {
+        let resolver = TitleIndex::new(self.outgoing.keys());
+
+        let mut edges: BTreeMap<(String, String), usize> = BTreeMap::new();
+        let mut unresolved: BTreeMap<String, UnresolvedLink> = BTreeMap::new();
+        for (source, targets) in &self.outgoing {
+            for (key, target) in targets {
+                match resolver.resolve(&target.written, Some(source)) {
+                    Some(path) if path == source => {}
+                    Some(path) => *edges.entry((source.clone(), path.to_string())).or_insert(0) += target.count,
+                    None => {
+                        let entry = unresolved.entry(key.clone()).or_insert_with(|| UnresolvedLink {
+                            target: target.written.clone(),
+                            sources: Vec::new(),
+                            count: 0,
+                        });
+                        entry.sources.push(source.clone());
+                        entry.count += target.count;
+                    }
+                }
+            }
+        }
+
+        let mut outgoing_counts: HashMap<&str, usize> = HashMap::new();
+        let mut incoming_counts: HashMap<&str, usize> = HashMap::new();
+        for (source, target) in edges.keys() {
+            *outgoing_counts.entry(source).or_insert(0) += 1;
+            *incoming_counts.entry(target).or_insert(0) += 1;
+        }
+
+        let mut nodes: Vec<GraphNode> = self.outgoing.keys()
+            .map(|path| GraphNode {
+                path: path.clone(),
+                title: extract_title_from_path(path),
+                outgoing: outgoing_counts.get(path.as_str()).copied().unwrap_or(0),
+                incoming: incoming_counts.get(path.as_str()).copied().unwrap_or(0),
+            })
+            .collect();
+        nodes.sort_by(|a, b| a.path.cmp(&b.path));
+
+        LinkGraph {
+            nodes,
+            edges: edges.into_iter()
+                .map(|((source, target), count)| GraphEdge { source, target, count })
+                .collect(),
+            unresolved: unresolved.into_values()
+                .map(|mut link| {
+                    link.sources.sort();
+                    link
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Levenshtein distance over chars
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            cur[j + 1] = (prev[j] + usize::from(ca != cb)).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+/// Names most like `target`: same name in another case or folder first, then by edit distance
+/// (at most a third of the name), then names containing it or contained in it (3+ chars)
+fn closest_names(target: &str, names: &[String]) -> Vec<String> {
+    let wanted = target.rsplit('/').next().unwrap_or(target).to_lowercase();
+    let max_distance = (wanted.chars().count() / 3).max(1);
+    let mut scored: Vec<(usize, &String)> = names.iter()
+        .filter_map(|name| {
+            let lower = name.to_lowercase();
+            let distance = edit_distance(&wanted, &lower);
+            if distance <= max_distance {
+                Some((distance, name))
+            } else if lower.chars().count().min(wanted.chars().count()) >= 3 && (lower.contains(&wanted) || wanted.contains(&lower)) {
+                Some((max_distance + 1, name))
+            } else {
+                None
+            }
+        })
+        .collect();
+    scored.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(b.1)));
+    let mut suggestions: Vec<String> = Vec::new();
+    for (_, name) in scored {
+        if !suggestions.contains(name) {
+            suggestions.push(name.clone());
+        }
+        if suggestions.len() == MAX_BROKEN_LINK_SUGGESTIONS {
+            break;
+        }
+    }
+    suggestions
+}
+
+/// Wiki links in `notes` (path -> content) that resolve to none of `known_paths` (every file in
+/// the vault, attachments included) by path, name or frontmatter alias. Sorted by source and position.
+pub(crate) fn find_broken_links(notes: &HashMap<String, String>, known_paths: &[String]) -> Vec<BrokenLink> {
+    let mut resolver = TitleIndex::new(known_paths);
+    let aliases: Vec<(&String, Vec<String>)> = notes.iter().map(|(path, content)| (path, note_aliases(content))).collect();
+    for (path, names) in &aliases {
+        for alias in names {
+            resolver.add_alias(alias, path);
+        }
+    }
+
+    let note_titles: Vec<String> = known_paths.iter()
+        .filter(|p| p.ends_with(".md"))
+        .map(|p| extract_title_from_path(p))
+        .collect();
+    let file_names: Vec<String> = known_paths.iter()
+        .filter(|p| !p.ends_with(".md"))
+        .map(|p| p.rsplit('/').next().unwrap_or(p).to_string())
+        .collect();
+
+    let mut broken = Vec::new();
+    let mut sources: Vec<&String> = notes.keys().collect();
+    sources.sort();
+    for source in sources {
+        for link in parse_all_links(&notes[source], false) {
+            if link.kind != LinkKind::Wiki || link.target.is_empty() || resolver.resolve(&link.target, Some(source)).is_some() {
+                continue;
+            }
+            let candidates = if is_media_target(&link.target) { &file_names } else { &note_titles };
+            broken.push(BrokenLink {
+                source_path: source.clone(),
+                line: link.line - 1,
+                column: link.column,
+                offset_bytes: link.offset_bytes,
+                end_bytes: link.end_bytes,
+                offset_utf16: link.offset_utf16,
+                end_utf16: link.end_utf16,
+                suggestions: closest_names(&link.target, candidates),
+                raw_link: link.raw,
+                normalized_target: link.target,
+            });
+        }
+    }
+    broken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(paths: &[&str]) -> TitleIndex {
+        let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+        TitleIndex::new(&paths)
+    }
+
+    #[test]
+    fn bare_names_prefer_the_linking_notes_folder() {
+        let titles = index(&["Archive/Note.md", "Work/Note.md", "Work/Deep/Note.md", "Home/Other.md"]);
+        assert_eq!(titles.resolve("Note", Some("Archive/Plan.md")), Some("Archive/Note.md"));
+        assert_eq!(titles.resolve("note", Some("Work/Deep/Plan.md")), Some("Work/Deep/Note.md"));
+        // No note of that name in the source's folder: the shortest path wins
+        assert_eq!(titles.resolve("Note", Some("Home/Other.md")), Some("Work/Note.md"));
+        assert_eq!(titles.resolve("Note", None), Some("Work/Note.md"));
+
+        assert_eq!(titles.link_target("Archive/Note.md", Some("Archive/Plan.md")), "Note");
+        assert_eq!(titles.link_target("Archive/Note.md", Some("Home/Other.md")), "Archive/Note");
+    }
+
+    #[test]
+    fn an_exact_vault_path_wins_over_the_same_folder() {
+        let titles = index(&["Note.md", "Work/Note.md"]);
+        assert_eq!(titles.resolve("Note", Some("Work/Plan.md")), Some("Note.md"));
+        assert_eq!(titles.resolve("./Note", Some("Work/Plan.md")), Some("Work/Note.md"));
+        assert_eq!(titles.link_target("Work/Note.md", Some("Work/Plan.md")), "Work/Note");
+    }
+
+    #[test]
+    fn folder_prefixes_match_whole_folder_names_only() {
+        let titles = index(&["Projects/Alpha/Note.md", "MyProjects/Note.md", "Archive/Alpha/Note.md"]);
+        assert_eq!(titles.resolve("Alpha/Note", Some("Projects/Alpha/Plan.md")), Some("Projects/Alpha/Note.md"));
+        assert_eq!(titles.resolve("Projects/Note", None), None);
+        assert_eq!(titles.resolve("jects/Alpha/Note", None), None);
+        assert_eq!(titles.resolve("Projects/Note", Some("MyProjects/Plan.md")), None);
+        assert_eq!(titles.resolve("projects/alpha/note.md", None), Some("Projects/Alpha/Note.md"));
+        assert_eq!(titles.resolve("Archive/Alpha/Note", None), Some("Archive/Alpha/Note.md"));
+        // Both end in "Alpha/Note"; the shorter, then alphabetically first path wins
+        assert_eq!(titles.resolve("Alpha/Note", None), Some("Archive/Alpha/Note.md"));
+    }
+}
@@ -0,0 +1,274 @@
+use futures::future::{self, Either};
+use gloo_net::http::{Request, Response};
+use gloo_timers::future::TimeoutFuture;
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{AbortController, AbortSignal, ReadableStreamDefaultReader, ReadableStreamReadResult};
+
+/// Retry settings for Ollama HTTP calls
+#[derive(Clone, Copy, Debug)]
+pub struct RetryConfig {
+    /// Total attempts including the first one (minimum 1)
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubles after each failed attempt
+    pub base_delay_ms: u32,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 3,
+            base_delay_ms: 500,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Build from optional JS parameters, falling back to the defaults
+    pub fn from_options(max_attempts: Option<u32>, base_delay_ms: Option<u32>) -> Self {
+        let default = RetryConfig::default();
+        RetryConfig {
+            max_attempts: max_attempts.unwrap_or(default.max_attempts).max(1),
+            base_delay_ms: base_delay_ms.unwrap_or(default.base_delay_ms),
+        }
+    }
+
+    fn delay_for_attempt(&self, attempt: u32) -> u32 {
+        // attempt is 1-based; the first retry waits base_delay_ms
+        self.base_delay_ms.saturating_mul(1u32 << (attempt - 1).min(16))
+    }
+}
+
+pub use crate::progress::ABORTED_ERROR;
+
+/// Per-call settings shared by every Ollama request
+#[derive(Clone, Debug, Default)]
+pub struct RequestSettings {
+    pub retry: RetryConfig,
+    /// Deadline for each call to the server: its request, any retries of it, and reading the
+    /// response body. A call that runs past it is cancelled and fails with `timeout_error`.
+    /// None = no timeout
+    pub timeout_ms: Option<u32>,
+    /// Signal from a JS AbortController; aborting cancels the in-flight fetch
+    pub abort_signal: Option<AbortSignal>,
+}
+
+impl RequestSettings {
+    /// Build from optional JS parameters
+    pub fn from_options(max_attempts: Option<u32>, retry_base_delay_ms: Option<u32>, timeout_ms: Option<u32>) -> Self {
+        RequestSettings {
+            retry: RetryConfig::from_options(max_attempts, retry_base_delay_ms),
+            timeout_ms,
+            abort_signal: None,
+        }
+    }
+
+    /// Default settings that can be cancelled through `abort_signal`
+    pub fn with_abort(abort_signal: Option<AbortSignal>) -> Self {
+        RequestSettings {
+            abort_signal,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.abort_signal.as_ref().is_some_and(|signal| signal.aborted())
+    }
+
+    /// Fail with the "aborted" error if the signal has fired
+    pub fn check_aborted(&self) -> Result<(), String> {
+        if self.is_aborted() {
+            Err(ABORTED_ERROR.to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// An options object passed from JS, e.g. `{timeout_ms: 30000, abort_signal}`. Fields are read
+/// through serde, except the AbortSignal in `abort_signal`, which serde can't carry.
+pub trait JsOptions: DeserializeOwned + Default {
+    fn set_abort_signal(&mut self, abort_signal: Option<AbortSignal>);
+
+    /// Read the object passed to `function`; undefined or null gives the defaults
+    fn from_js(options: Option<JsValue>, function: &str) -> Result<Self, JsValue> {
+        let Some(options) = options.filter(|o| !o.is_null()) else {
+            return Ok(Self::default());
+        };
+        let abort_signal = js_sys::Reflect::get(&options, &JsValue::from_str("abort_signal"))
+            .ok()
+            .and_then(|signal| signal.dyn_into::<AbortSignal>().ok());
+        let mut parsed: Self = serde_wasm_bindgen::from_value(options)
+            .map_err(|e| JsValue::from_str(&format!("Invalid options in {}: {}", function, e)))?;
+        parsed.set_abort_signal(abort_signal);
+        Ok(parsed)
+    }
+}
+
+/// Error message used when a call exceeds its deadline. Callers can match on the
+/// "timeout after" prefix to tell timeouts apart from other failures.
+pub fn timeout_error(timeout_ms: u32) -> String {
+    format!("timeout after {} ms", timeout_ms)
+}
+
+/// Run one call to the server under `settings.timeout_ms`. `call` gets the settings with an
+/// abort signal that also fires at the deadline, so requests still in flight then are cancelled
+/// instead of left open; the call fails with `timeout_error`. With no timeout `call` gets
+/// `settings` as they are and runs to completion.
+pub async fn with_timeout<T, E, F, Fut>(settings: &RequestSettings, call: F) -> Result<T, E>
+where
+    F: FnOnce(RequestSettings) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: From<String>,
+{
+    let Some(ms) = settings.timeout_ms else {
+        return call(settings.clone()).await;
+    };
+
+    let deadline = AbortController::new().ok();
+    let abort_signal = match (&deadline, &settings.abort_signal) {
+        (Some(deadline), Some(caller)) => Some(AbortSignal::any(&js_sys::Array::of2(caller, &deadline.signal()))),
+        (Some(deadline), None) => Some(deadline.signal()),
+        (None, caller) => caller.clone(),
+    };
+    let fut = std::pin::pin!(call(RequestSettings { abort_signal, ..settings.clone() }));
+    match future::select(fut, TimeoutFuture::new(ms)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => {
+            if let Some(deadline) = deadline {
+                deadline.abort();
+            }
+            Err(E::from(timeout_error(ms)))
+        }
+    }
+}
+
+/// Prefix of errors where the server could not be reached at all
+const NETWORK_ERROR_PREFIX: &str = "Network error";
+
+/// True if `error` (from post_json/get) means the server was unreachable rather than erroring
+pub fn is_network_error(error: &str) -> bool {
+    error.starts_with(NETWORK_ERROR_PREFIX) || error.starts_with("timeout after")
+}
+
+/// POST a JSON body, retrying on network errors and 5xx responses with exponential backoff.
+/// 2xx and 4xx responses are returned to the caller as-is (4xx is never retried).
+/// Aborted requests fail with `ABORTED_ERROR` and are never retried.
+pub async fn post_json(url: &str, body: &str, settings: &RequestSettings) -> Result<Response, String> {
+    post_json_with_headers(url, body, &[], settings).await
+}
+
+/// `post_json` with extra request headers (e.g. Authorization)
+pub async fn post_json_with_headers(url: &str, body: &str, headers: &[(&str, &str)], settings: &RequestSettings) -> Result<Response, String> {
+    send_with_retry(settings, || {
+        let mut builder = Request::post(url)
+            .header("Content-Type", "application/json")
+            .abort_signal(settings.abort_signal.as_ref());
+        for (name, value) in headers {
+            builder = builder.header(name, value);
+        }
+        builder.body(body.to_string())
+    }).await
+}
+
+/// GET a URL with the same retry and abort handling as `post_json`
+pub async fn get(url: &str, settings: &RequestSettings) -> Result<Response, String> {
+    send_with_retry(settings, || {
+        Request::get(url)
+            .abort_signal(settings.abort_signal.as_ref())
+            .build()
+    }).await
+}
+
+async fn send_with_retry<F>(settings: &RequestSettings, build: F) -> Result<Response, String>
+where
+    F: Fn() -> Result<Request, gloo_net::Error>,
+{
+    let retry = settings.retry;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        settings.check_aborted()?;
+
+        let request = build().map_err(|e| format!("Request error: {}", e))?;
+
+        let result = request.send().await;
+        settings.check_aborted()?;
+
+        let error = match result {
+            Ok(response) if response.status() < 500 => return Ok(response),
+            Ok(response) => {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                format!("HTTP {}: {}", status, error_text)
+            }
+            Err(e) => format!("{}: {}", NETWORK_ERROR_PREFIX, e),
+        };
+
+        if attempt >= retry.max_attempts {
+            return Err(if attempt > 1 {
+                format!("{} (after {} attempts)", error, attempt)
+            } else {
+                error
+            });
+        }
+
+        let delay = retry.delay_for_attempt(attempt);
+        log_warn!("{} - retrying in {}ms (attempt {}/{})",
+            error, delay, attempt + 1, retry.max_attempts);
+        TimeoutFuture::new(delay).await;
+    }
+}
+
+/// Read a newline-delimited JSON body incrementally, calling `on_line` for each complete line.
+/// Lines split across network chunks are reassembled. `on_line` returns Ok(true) on the final line.
+/// Errors from `on_line`, stream failures and aborts cancel the stream.
+pub async fn read_ndjson<F>(response: Response, settings: &RequestSettings, mut on_line: F) -> Result<(), String>
+where
+    F: FnMut(&str) -> Result<bool, String>,
+{
+    let body = response.body().ok_or_else(|| "Response has no body".to_string())?;
+    let reader: ReadableStreamDefaultReader = body.get_reader().unchecked_into();
+    let mut buffer: Vec<u8> = Vec::new();
+
+    let result = async {
+        loop {
+            settings.check_aborted()?;
+            let read = JsFuture::from(reader.read()).await;
+            settings.check_aborted()?;
+            let chunk: ReadableStreamReadResult = read
+                .map_err(|e| format!("Stream error: {:?}", e))?
+                .unchecked_into();
+
+            let done = chunk.get_done().unwrap_or(false);
+            if !done {
+                buffer.extend(js_sys::Uint8Array::new(&chunk.get_value()).to_vec());
+            }
+
+            // Split on the byte so multi-byte characters straddling chunks stay intact
+            while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                let line = std::str::from_utf8(&line).map_err(|e| format!("Stream decode error: {}", e))?;
+                if !line.trim().is_empty() && on_line(line.trim())? {
+                    return Ok(());
+                }
+            }
+
+            if done {
+                let rest = std::str::from_utf8(&buffer).map_err(|e| format!("Stream decode error: {}", e))?;
+                if !rest.trim().is_empty() && on_line(rest.trim())? {
+                    return Ok(());
+                }
+                return Err("Stream ended before the response was complete".to_string());
+            }
+        }
+    }.await;
+
+    if result.is_err() {
+        // Fire-and-forget; tells the server we've stopped reading
+        let _ = reader.cancel();
+    }
+    result
+}
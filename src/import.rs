@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Formats `SmartVault::import_external_embeddings` reads
+pub const EXTERNAL_EMBEDDING_FORMATS: [&str; 2] = ["smart-connections", "generic"];
+
+/// Counts returned by `SmartVault::import_external_embeddings`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ExternalImportResult {
+    pub imported: usize,
+    /// Entries without a usable vector: blocks, deleted entries, empty or non-numeric vectors
+    pub skipped: usize,
+    /// Vectors whose length isn't `expected_dim`
+    pub dimension_mismatch: usize,
+    /// Imported notes recorded as embedded at the mtime passed for them, so scans skip them
+    pub marked_fresh: usize,
+    pub expected_dim: Option<usize>,
+}
+
+/// Vault path with forward slashes and no leading "./" or "/"
+pub(crate) fn normalize_import_path(path: &str) -> String {
+    let path = path.trim().replace('\\', "/");
+    let path = path.trim_start_matches("./").trim_start_matches('/');
+    path.to_string()
+}
+
+/// A non-empty array of finite numbers
+fn vector(value: &Value) -> Option<Vec<f32>> {
+    let values = value.as_array().filter(|values| !values.is_empty())?;
+    values.iter()
+        .map(|v| v.as_f64().map(|v| v as f32).filter(|v| v.is_finite()))
+        .collect()
+}
+
+/// Smart Connections keeps its index as `.ajson` files: lines of `"key": {entry},` appended as
+/// notes change, so later lines replace earlier ones and `null` marks a deleted note. Wrapped in
+/// braces, that's a JSON object (serde_json keeps the last value of a repeated key). Older
+/// versions wrote a plain JSON object.
+fn smart_connections_entries(data: &str) -> Result<Map<String, Value>, String> {
+    if let Ok(Value::Object(entries)) = serde_json::from_str(data) {
+        return Ok(entries);
+    }
+    let lines = data.trim().trim_end_matches(',');
+    match serde_json::from_str(&format!("{{{}}}", lines)) {
+        Ok(Value::Object(entries)) => Ok(entries),
+        Ok(_) => Err("Smart Connections data is not an object".to_string()),
+        Err(e) => Err(format!("Invalid Smart Connections data: {}", e)),
+    }
+}
+
+/// Vector of a Smart Connections note entry: `vec` in older versions, else
+/// `embeddings.<model>.vec`, taking `preferred_model` when it's there and the first model
+/// (by name) otherwise
+fn smart_connections_vector(entry: &Value, preferred_model: Option<&str>) -> Option<Vec<f32>> {
+    if let Some(vec) = entry.get("vec") {
+        return vector(vec);
+    }
+    let models = entry.get("embeddings")?.as_object()?;
+    let chosen = preferred_model
+        .and_then(|model| models.get(model))
+        .or_else(|| models.values().next())?;
+    vector(chosen.get("vec")?)
+}
+
+/// Note paths and their vectors, in file order
+pub(crate) type ImportedEmbeddings = Vec<(String, Vec<f32>)>;
+
+/// Note paths and vectors from another plugin's embedding cache, with how many entries had no
+/// usable vector. Blocks (Smart Connections' per-heading embeddings) are skipped.
+pub(crate) fn parse_external_embeddings(format: &str, data: &str, preferred_model: Option<&str>) -> Result<(ImportedEmbeddings, usize), String> {
+    let mut found = Vec::new();
+    let mut skipped = 0;
+    match format {
+        "smart-connections" => {
+            for (key, entry) in smart_connections_entries(data)? {
+                let key = key.strip_prefix("smart_sources:").unwrap_or(&key);
+                if key.starts_with("smart_blocks:") || key.contains('#') || entry.is_null() {
+                    skipped += 1;
+                    continue;
+                }
+                let path = normalize_import_path(entry.get("path").and_then(Value::as_str).unwrap_or(key));
+                match smart_connections_vector(&entry, preferred_model) {
+                    Some(vector) if !path.is_empty() => found.push((path, vector)),
+                    _ => skipped += 1,
+                }
+            }
+        }
+        "generic" => {
+            let entries: Map<String, Value> = serde_json::from_str(data)
+                .map_err(|e| format!("Invalid embeddings JSON (expected {{path: [numbers]}}): {}", e))?;
+            for (path, value) in entries {
+                let path = normalize_import_path(&path);
+                match vector(&value) {
+                    Some(vector) if !path.is_empty() => found.push((path, vector)),
+                    _ => skipped += 1,
+                }
+            }
+        }
+        other => {
+            return Err(format!("Unknown embeddings format: {} (supported: {})", other, EXTERNAL_EMBEDDING_FORMATS.join(", ")));
+        }
+    }
+    Ok((found, skipped))
+}
@@ -0,0 +1,184 @@
+/// Escapes allowed after a backslash in a JSON string
+const JSON_ESCAPES: &[char] = &['"', '\\', '/', 'b', 'f', 'n', 'r', 't', 'u'];
+
+/// A quote ends a string only if what follows could come after a JSON string, so apostrophes
+/// in 'don't' and unescaped quotes in "say "hi" now" stay part of the text
+fn closes_string(chars: &[char], after: usize) -> bool {
+    chars[after..].iter()
+        .find(|c| !c.is_whitespace())
+        .is_none_or(|c| matches!(c, ',' | ':' | '}' | ']'))
+}
+
+/// Copy the string starting at `chars[start]` (a `"` or `'`) as a valid double-quoted JSON
+/// string, escaping raw control characters and stray quotes. Returns the index after it.
+fn copy_string(chars: &[char], start: usize, out: &mut String) -> usize {
+    let quote = chars[start];
+    out.push('"');
+    let mut i = start + 1;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' => match chars.get(i + 1) {
+                // \' is not a JSON escape
+                Some('\'') => {
+                    out.push('\'');
+                    i += 2;
+                    continue;
+                }
+                Some(&next) if JSON_ESCAPES.contains(&next) => {
+                    out.push('\\');
+                    out.push(next);
+                    i += 2;
+                    continue;
+                }
+                // A lone backslash, e.g. in a Windows path
+                _ => out.push_str("\\\\"),
+            },
+            c if c == quote => {
+                if closes_string(chars, i + 1) {
+                    out.push('"');
+                    return i + 1;
+                }
+                out.push_str(if quote == '"' { "\\\"" } else { "'" });
+            }
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    // Unterminated string (response cut off)
+    out.push('"');
+    chars.len()
+}
+
+/// Remove a comma left before a closing bracket or the end of the text
+fn strip_trailing_comma(out: &mut String) {
+    let end = out.trim_end().len();
+    if out[..end].ends_with(',') {
+        out.remove(end - 1);
+    }
+}
+
+/// Rewrite the value starting at the first bracket of `text`: quotes normalized, trailing
+/// commas dropped, stray closers skipped, and anything left open closed. Text after the
+/// value is complete (chatter, code fences) is ignored.
+fn repair_structure(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len() + 8);
+    let mut closers: Vec<char> = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '"' | '\'' => {
+                i = copy_string(&chars, i, &mut out);
+                continue;
+            }
+            '{' => {
+                closers.push('}');
+                out.push(c);
+            }
+            '[' => {
+                closers.push(']');
+                out.push(c);
+            }
+            '}' | ']' => {
+                // Close anything left open inside this bracket; a closer with no opener is dropped
+                if let Some(pos) = closers.iter().rposition(|&closer| closer == c) {
+                    for closer in closers.drain(pos..).rev() {
+                        strip_trailing_comma(&mut out);
+                        out.push(closer);
+                    }
+                    if closers.is_empty() {
+                        return out;
+                    }
+                }
+            }
+            c => out.push(c),
+        }
+        i += 1;
+    }
+
+    // Cut off mid-value: finish a dangling key and close what's open
+    strip_trailing_comma(&mut out);
+    if out.trim_end().ends_with(':') {
+        out.push_str(" null");
+    }
+    for closer in closers.into_iter().rev() {
+        strip_trailing_comma(&mut out);
+        out.push(closer);
+    }
+    out
+}
+
+/// Fix the usual defects in almost-JSON from small models: trailing commas, single-quoted keys
+/// and strings, raw newlines and stray quotes inside strings, missing closing brackets, and
+/// chatter around the value. Valid JSON is returned unchanged; None if the repair still doesn't parse.
+pub fn repair_json(text: &str) -> Option<String> {
+    if serde_json::from_str::<serde_json::Value>(text).is_ok() {
+        return Some(text.to_string());
+    }
+    let start = text.find(['{', '['])?;
+    let repaired = repair_structure(&text[start..]);
+    serde_json::from_str::<serde_json::Value>(&repaired).ok()?;
+    Some(repaired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repaired_value(text: &str) -> serde_json::Value {
+        let Some(repaired) = repair_json(text) else { panic!("not repaired: {}", text) };
+        serde_json::from_str(&repaired).unwrap()
+    }
+
+    #[test]
+    fn fixes_common_small_model_defects() {
+        let cases = [
+            (r#"{"a": 1, "b": [1, 2,],}"#, r#"{"a": 1, "b": [1, 2]}"#),
+            (r#"{'phrase': 'don't stop', 'ok': true}"#, r#"{"phrase": "don't stop", "ok": true}"#),
+            ("{\"reason\": \"line one\nline two\"}", r#"{"reason": "line one\nline two"}"#),
+            (r#"{"reason": "say "hi" now"}"#, r#"{"reason": "say \"hi\" now"}"#),
+            (r#"{"path": "C:\data\x"}"#, r#"{"path": "C:\\data\\x"}"#),
+            (r#"{"items": [{"a": 1}, {"b": "cut off"#, r#"{"items": [{"a": 1}, {"b": "cut off"}]}"#),
+            (r#"{"key":"#, r#"{"key": null}"#),
+            ("Here you go:\n```json\n[1, 2]\n```\nHope that helps!", "[1, 2]"),
+            (r#"{"a": 1}]}"#, r#"{"a": 1}"#),
+        ];
+        for (broken, expected) in cases {
+            assert_eq!(repaired_value(broken), serde_json::from_str::<serde_json::Value>(expected).unwrap(), "{}", broken);
+        }
+    }
+
+    #[test]
+    fn gives_up_without_a_value() {
+        assert_eq!(repair_json("no json here"), None);
+        assert_eq!(repair_json(""), None);
+    }
+
+    #[test]
+    fn valid_json_passes_through_unchanged() {
+        let corpus = [
+            "{}",
+            "[]",
+            "null",
+            "42",
+            r#""just a string""#,
+            r#"{"phrase": "see [[Note]]", "reason": "Mentions it", "confidence": 0.85}"#,
+            r#"{"rankings": [{"index": 1, "score": 7.5, "reason": "Close"}, {"index": 2, "score": 3, "reason": ""}]}"#,
+            "{\n  \"keywords\": [\"rust\", \"wasm\"],\n  \"nested\": {\"a\": [1, [2, [3]]]}\n}\n",
+            r#"{"escapes": "quote \" backslash \\ slash \/ tab \t unicode \u00e9", "trailing space": true}   "#,
+            r#"{"text": "Привет, 日本語, 👩‍👩‍👧 and 'single' quotes", "empty": "", "list": [true, false, null]}"#,
+            r#"[{"a": -1.5e3}, {"b": "]}"}, {"c": "{["}]"#,
+        ];
+        for json in corpus {
+            assert_eq!(repair_json(json).as_deref(), Some(json));
+        }
+    }
+}
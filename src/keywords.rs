@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+
+/// Keywords kept from one extraction unless the caller asks for a different limit
+pub const DEFAULT_MAX_KEYWORDS: usize = 15;
+
+/// Entries with more words than this are sentences, not keywords
+const MAX_KEYWORD_WORDS: usize = 6;
+
+/// Entries shorter than this (in chars) are dropped
+const MIN_KEYWORD_CHARS: usize = 2;
+
+/// Common English words that carry no meaning on their own
+const STOP_WORDS: &[&str] = &[
+    "a", "about", "after", "all", "also", "an", "and", "any", "are", "as", "at", "be", "because",
+    "been", "but", "by", "can", "could", "do", "does", "for", "from", "had", "has", "have", "he",
+    "her", "his", "how", "i", "if", "in", "into", "is", "it", "its", "keyword", "keywords", "more",
+    "most", "my", "no", "not", "of", "on", "or", "other", "our", "she", "so", "some", "such",
+    "than", "that", "the", "their", "them", "then", "there", "these", "they", "this", "those",
+    "to", "too", "up", "us", "very", "was", "we", "were", "what", "when", "where", "which", "who",
+    "why", "will", "with", "would", "you", "your",
+];
+
+/// Characters stripped from either end of a keyword (quotes, markdown emphasis, brackets, punctuation)
+fn is_edge_junk(c: char) -> bool {
+    matches!(c,
+        '"' | '\'' | '`' | '*' | '_' | '~' | '[' | ']' | '(' | ')' | '{' | '}' | '<' | '>'
+        | '.' | ',' | ';' | ':' | '!' | '?' | '|' | '•'
+        | '\u{2018}' | '\u{2019}' | '\u{201C}' | '\u{201D}' | '\u{00AB}' | '\u{00BB}')
+}
+
+/// Strip a leading list marker such as "- ", "* ", "3. " or "3) "
+pub(crate) fn strip_list_marker(text: &str) -> &str {
+    for marker in ["- ", "* ", "+ ", "• "] {
+        if let Some(rest) = text.strip_prefix(marker) {
+            return rest;
+        }
+    }
+    let digits = text.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 {
+        let rest = &text[digits..];
+        if let Some(rest) = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") ")) {
+            return rest;
+        }
+    }
+    text
+}
+
+/// Clean a single keyword, or None if nothing meaningful is left
+pub fn clean_keyword(raw: &str) -> Option<String> {
+    let text = strip_list_marker(raw.trim());
+    // Tags ("#topic") keep their name; trailing '#' is left alone for names like "C#"
+    let text = text.trim_start_matches('#');
+    let text = text.trim_matches(|c: char| c.is_whitespace() || is_edge_junk(c));
+    let cleaned = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let words = cleaned.split(' ').count();
+    if cleaned.chars().count() < MIN_KEYWORD_CHARS || words > MAX_KEYWORD_WORDS {
+        return None;
+    }
+    if STOP_WORDS.contains(&cleaned.to_lowercase().as_str()) {
+        return None;
+    }
+    Some(cleaned)
+}
+
+/// Clean a keyword list: strip quotes/markdown/punctuation, drop sentences, fragments and stop
+/// words, dedupe case-insensitively (keeping the first spelling), and keep at most `max_keywords`.
+pub fn normalize_keywords(raw: &[String], max_keywords: Option<usize>) -> Vec<String> {
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut keywords: Vec<String> = raw.iter()
+        .filter_map(|keyword| clean_keyword(keyword))
+        .filter(|keyword| seen.insert(keyword.to_lowercase()))
+        .collect();
+    if let Some(max) = max_keywords {
+        keywords.truncate(max);
+    }
+    keywords
+}
+
+/// Tokens shorter than this (in chars) are ignored by TF-IDF extraction
+const MIN_TFIDF_TOKEN_CHARS: usize = 3;
+
+/// Markup and URL fragments that survive tokenization but are never keywords
+const MARKUP_WORDS: &[&str] = &["http", "https", "www", "com", "org", "png", "jpg", "pdf", "md"];
+
+fn is_tfidf_stop_word(token: &str) -> bool {
+    token.chars().count() < MIN_TFIDF_TOKEN_CHARS
+        || token.chars().all(|c| c.is_ascii_digit())
+        || STOP_WORDS.contains(&token)
+        || MARKUP_WORDS.contains(&token)
+}
+
+/// End the current word: keep it in the run, or end the run if it's a stop word
+fn push_word(word: &mut String, run: &mut Vec<String>, runs: &mut Vec<Vec<String>>) {
+    if word.is_empty() {
+        return;
+    }
+    let token = std::mem::take(word).to_lowercase();
+    if is_tfidf_stop_word(&token) {
+        if !run.is_empty() {
+            runs.push(std::mem::take(run));
+        }
+    } else {
+        run.push(token);
+    }
+}
+
+/// Lowercased content words of a note, grouped into runs not broken by punctuation or
+/// stop words, so bigrams are only formed from words that actually sit next to each other.
+fn tokenize_runs(content: &str) -> Vec<Vec<String>> {
+    let mut runs: Vec<Vec<String>> = Vec::new();
+    let mut run: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut broken = false;
+
+    for c in content.chars() {
+        if c.is_alphanumeric() {
+            if broken && !run.is_empty() {
+                runs.push(std::mem::take(&mut run));
+            }
+            broken = false;
+            word.push(c);
+            continue;
+        }
+        push_word(&mut word, &mut run, &mut runs);
+        // Whitespace and hyphens join words; anything else (punctuation, markup) ends the run
+        if !c.is_whitespace() && c != '-' {
+            broken = true;
+        }
+    }
+    push_word(&mut word, &mut run, &mut runs);
+    if !run.is_empty() {
+        runs.push(run);
+    }
+    runs
+}
+
+/// Term counts for one note: unigrams plus adjacent-word bigrams ("neural network")
+fn term_counts(content: &str) -> HashMap<String, u32> {
+    let mut counts: HashMap<String, u32> = HashMap::new();
+    for run in tokenize_runs(content) {
+        for token in &run {
+            *counts.entry(token.clone()).or_insert(0) += 1;
+        }
+        for pair in run.windows(2) {
+            *counts.entry(format!("{} {}", pair[0], pair[1])).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// Number of notes each term appears in, kept up to date as notes are added and removed
+#[derive(Debug, Clone, Default)]
+pub struct DocumentFrequencies {
+    df: HashMap<String, u32>,
+    doc_count: u32,
+}
+
+impl DocumentFrequencies {
+    pub fn add_document(&mut self, content: &str) {
+        self.doc_count += 1;
+        for term in term_counts(content).into_keys() {
+            *self.df.entry(term).or_insert(0) += 1;
+        }
+    }
+
+    pub fn remove_document(&mut self, content: &str) {
+        self.doc_count = self.doc_count.saturating_sub(1);
+        for term in term_counts(content).into_keys() {
+            if let Some(count) = self.df.get_mut(&term) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    self.df.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Smoothed inverse document frequency
+    fn idf(&self, term: &str) -> f32 {
+        let df = self.df.get(term).copied().unwrap_or(0) as f32;
+        ((1.0 + self.doc_count as f32) / (1.0 + df)).ln() + 1.0
+    }
+
+    /// Top `max_keywords` terms of `content` by TF-IDF. Bigrams count only if they occur at
+    /// least twice in the note, and a chosen bigram replaces its individual words. Terms with
+    /// any of `stop_words` (e.g. those of the note's language) are left out.
+    pub fn top_terms(&self, content: &str, max_keywords: usize, stop_words: &[&str]) -> Vec<String> {
+        let mut scored: Vec<(String, f32)> = term_counts(content).into_iter()
+            .filter(|(term, count)| !term.contains(' ') || *count >= 2)
+            .filter(|(term, _)| !term.split(' ').any(|word| stop_words.contains(&word)))
+            .map(|(term, count)| {
+                let score = (1.0 + (count as f32).ln()) * self.idf(&term);
+                (term, score)
+            })
+            .collect();
+        // Ties broken alphabetically so results are stable
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let mut chosen: Vec<String> = Vec::new();
+        for (term, _) in scored {
+            if chosen.len() >= max_keywords {
+                break;
+            }
+            if term.contains(' ') {
+                // The phrase replaces its words if they were picked on their own
+                let words: Vec<&str> = term.split(' ').collect();
+                chosen.retain(|c| !words.contains(&c.as_str()));
+                chosen.push(term);
+            } else if !chosen.iter().any(|c| c.split(' ').any(|word| word == term)) {
+                chosen.push(term);
+            }
+        }
+        chosen
+    }
+}
@@ -0,0 +1,103 @@
+use wasm_bindgen::prelude::*;
+use crate::frontmatter::note_body;
+
+/// What detect_language returns when no language is clearly ahead
+pub const UNKNOWN_LANGUAGE: &str = "unknown";
+
+/// Words read from a note when detecting its language; the opening is enough to tell
+const MAX_DETECTION_WORDS: usize = 2000;
+
+/// Fewest stop words the winning language needs before it's trusted
+const MIN_STOP_WORD_HITS: usize = 3;
+
+/// Least share of a note's words that must be the winning language's stop words
+const MIN_STOP_WORD_SHARE: f32 = 0.05;
+
+/// Least lead of the winning language over the next, as a share of its own hits. Related
+/// languages share many function words ("de", "que", "la"), so only a clear lead counts.
+const MIN_LEAD: f32 = 0.2;
+
+/// The commonest function words of each language detect_language knows, by ISO 639-1 code
+const STOP_WORDS: &[(&str, &[&str])] = &[
+    ("en", &[
+        "the", "and", "of", "to", "is", "in", "that", "it", "for", "with", "as", "was", "on",
+        "are", "be", "this", "by", "not", "or", "have", "from", "but", "they", "which", "you",
+        "an", "at", "his", "her", "we", "there", "been", "were", "has", "would", "their", "what",
+        "can", "will", "if", "about", "when", "also",
+    ]),
+    ("es", &[
+        "el", "la", "los", "las", "de", "del", "que", "y", "en", "un", "una", "es", "por", "con",
+        "para", "no", "se", "su", "sus", "al", "lo", "como", "más", "pero", "este", "esta", "son",
+        "también", "muy", "hay", "ya", "entre", "cuando", "sobre", "fue", "ser", "tiene",
+        "porque", "donde", "ella",
+    ]),
+    ("fr", &[
+        "le", "la", "les", "de", "des", "du", "et", "est", "un", "une", "que", "qui", "dans",
+        "pour", "pas", "ne", "sur", "au", "aux", "avec", "ce", "cette", "il", "elle", "sont",
+        "par", "plus", "mais", "ou", "nous", "vous", "leur", "été", "être", "comme", "aussi",
+        "très", "tout", "ont",
+    ]),
+    ("de", &[
+        "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "zu", "den", "dem", "des",
+        "mit", "sich", "auf", "für", "von", "im", "auch", "es", "an", "als", "wie", "bei", "oder",
+        "aus", "wird", "sind", "wenn", "noch", "nach", "kann", "über", "einer", "werden", "hat",
+        "dass", "aber", "nur", "sie",
+    ]),
+    ("pt", &[
+        "o", "os", "as", "de", "do", "da", "dos", "das", "e", "que", "em", "um", "uma", "é",
+        "não", "com", "para", "por", "no", "na", "nos", "se", "mais", "mas", "ao", "como", "foi",
+        "são", "também", "muito", "já", "ele", "ela", "seu", "sua", "quando", "isso", "pelo",
+        "pela", "entre", "está",
+    ]),
+    ("it", &[
+        "il", "lo", "gli", "la", "le", "di", "del", "della", "che", "e", "è", "un", "una", "per",
+        "non", "con", "sono", "si", "da", "in", "al", "alla", "dei", "come", "anche", "ma", "più",
+        "questo", "questa", "nel", "nella", "essere", "ha", "molto", "perché", "quando", "tra",
+        "sul", "loro", "ci",
+    ]),
+];
+
+/// Language of a note's text as an ISO 639-1 code ("en", "es", "fr", "de", "pt" or "it"), by
+/// how many of each language's commonest function words it uses; frontmatter is ignored.
+/// "unknown" when the text is too short or no language is clearly ahead.
+#[wasm_bindgen]
+pub fn detect_language(content: &str) -> String {
+    let body = note_body(content).to_lowercase();
+    let words: Vec<&str> = body
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .take(MAX_DETECTION_WORDS)
+        .collect();
+
+    let mut hits: Vec<(&str, usize)> = STOP_WORDS.iter()
+        .map(|(language, stop_words)| (*language, words.iter().filter(|word| stop_words.contains(word)).count()))
+        .collect();
+    hits.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    let (language, best) = hits[0];
+    let second = hits[1].1;
+
+    let confident = best >= MIN_STOP_WORD_HITS
+        && best as f32 >= words.len() as f32 * MIN_STOP_WORD_SHARE
+        && (best - second) as f32 >= best as f32 * MIN_LEAD;
+    if confident { language } else { UNKNOWN_LANGUAGE }.to_string()
+}
+
+/// Stop words of a language detect_language returns; None for "unknown" and other codes
+pub(crate) fn stop_words(language: &str) -> Option<&'static [&'static str]> {
+    STOP_WORDS.iter().find(|(code, _)| *code == language).map(|(_, words)| *words)
+}
+
+/// English name of a language detect_language returns, for prompts; None for "unknown" and
+/// other codes
+#[cfg(feature = "wasm")]
+pub(crate) fn language_name(language: &str) -> Option<&'static str> {
+    match language {
+        "en" => Some("English"),
+        "es" => Some("Spanish"),
+        "fr" => Some("French"),
+        "de" => Some("German"),
+        "pt" => Some("Portuguese"),
+        "it" => Some("Italian"),
+        _ => None,
+    }
+}
@@ -1,32 +1,109 @@
+
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-
+use std::collections::{BTreeMap, HashMap, HashSet};
+use frontmatter::note_body;
+use progress::Progress;
+#[cfg(feature = "wasm")]
+use http::JsOptions;
+
+#[macro_use]
+mod logging;
+mod clock;
+#[cfg(feature = "wasm")]
+mod http;
 mod embeddings;
 mod vault;
 mod links;
+#[cfg(feature = "wasm")]
 mod llm;
 mod cache;
-
+#[cfg(feature = "wasm")]
+mod models;
+mod keywords;
+mod edits;
+mod tokens;
+mod json_repair;
+mod masking;
+#[cfg(feature = "wasm")]
+mod atomicity;
+mod frontmatter;
+mod graph;
+mod mentions;
+mod outline;
+mod exclusions;
+mod stats;
+mod report;
+mod import;
+mod progress;
+mod language;
+mod offsets;
+#[cfg(feature = "wasm")]
+mod digest;
+// Only the LLM calls use the response parsers, but they are built for native tests too
+#[cfg(any(feature = "wasm", test))]
+mod responses;
+
+pub use logging::*;
 pub use embeddings::*;
 pub use vault::*;
 pub use links::*;
+#[cfg(feature = "wasm")]
 pub use llm::*;
 pub use cache::*;
+#[cfg(feature = "wasm")]
+pub use models::*;
+pub use keywords::*;
+pub use edits::*;
+pub use tokens::*;
+pub use json_repair::*;
+pub use masking::*;
+#[cfg(feature = "wasm")]
+pub use atomicity::*;
+pub use frontmatter::*;
+pub use graph::*;
+pub use mentions::*;
+pub use outline::*;
+pub use exclusions::*;
+pub use stats::*;
+pub use report::*;
+pub use import::*;
+pub use progress::*;
+pub use language::*;
+pub use offsets::*;
+#[cfg(feature = "wasm")]
+pub use digest::*;
+#[cfg(any(feature = "wasm", test))]
+pub use responses::*;
 
-#[wasm_bindgen]
-extern "C" {
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(s: &str);
+#[wasm_bindgen(start)]
+pub fn init() {
+    console_error_panic_hook::set_once();
 }
 
-macro_rules! console_log {
-    ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
+/// Rerank cache key for a note's content, its candidate paths (any order) and the model
+#[wasm_bindgen]
+pub fn rerank_cache_key(document_content: &str, candidate_paths: Vec<String>, model: &str) -> String {
+    make_rerank_key(document_content, &candidate_paths, model)
 }
 
-#[wasm_bindgen(start)]
-pub fn init() {
-    console_error_panic_hook::set_once();
+/// Folders need this many embedded notes to be suggested by suggest_folder_by_similarity
+const DEFAULT_MIN_FOLDER_MEMBERS: usize = 2;
+
+/// Notes retrieved as context by chat_with_vault unless the caller asks for a different number
+#[cfg(feature = "wasm")]
+const DEFAULT_CHAT_TOP_K: usize = 5;
+
+/// Minimum similarity for a note to be used as chat_with_vault context
+#[cfg(feature = "wasm")]
+const DEFAULT_CHAT_THRESHOLD: f32 = 0.3;
+
+/// Vectors of one embedding model, set aside while another model is active
+#[derive(Default)]
+struct EmbeddingSpace {
+    embeddings: HashMap<String, Vec<f32>>,
+    chunk_embeddings: HashMap<String, Vec<ChunkEmbedding>>,
+    local_embeddings: HashSet<String>,
 }
 
 #[wasm_bindgen]
@@ -35,6 +112,25 @@ pub struct SmartVault {
     file_contents: HashMap<String, String>,
     keywords: HashMap<String, Vec<String>>,  // Document keywords for better cross-linking
     cache_index: CacheIndex,  // Unified cache management
+    embedding_model: Option<String>,  // Model that produced the current embeddings
+    embedding_spaces: HashMap<String, EmbeddingSpace>,  // Vectors of the other models used, by model
+    chunk_embeddings: HashMap<String, Vec<ChunkEmbedding>>,  // Per-chunk vectors for long notes
+    embedding_task: Option<EmbeddingTaskType>,  // Task prefix vault notes are embedded with
+    local_embeddings: HashSet<String>,  // Paths whose vector came from generate_local_embedding
+    document_frequencies: DocumentFrequencies,  // Term document counts over file_contents, for TF-IDF
+    link_index: LinkIndex,  // Outgoing links of each note in file_contents, for build_link_graph
+    title_index: TitleIndex,  // Paths, titles and aliases of file_contents, for resolving link targets
+    path_filter: PathFilter,  // Exclusions and inclusions applied by plan_scan
+    opt_out_property: String,  // Frontmatter property notes opt out with
+    opted_out: HashMap<String, OptOut>,  // Notes in file_contents that opted out
+    languages: HashMap<String, String>,  // detect_language of each note in file_contents
+    progress_callback: Option<js_sys::Function>,  // Receives ProgressEvents from long-running methods
+}
+
+impl Default for SmartVault {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
@@ -46,29 +142,319 @@ impl SmartVault {
             file_contents: HashMap::new(),
             keywords: HashMap::new(),
             cache_index: CacheIndex::new(),
+            embedding_model: None,
+            embedding_spaces: HashMap::new(),
+            chunk_embeddings: HashMap::new(),
+            embedding_task: None,
+            local_embeddings: HashSet::new(),
+            document_frequencies: DocumentFrequencies::default(),
+            link_index: LinkIndex::default(),
+            title_index: TitleIndex::default(),
+            path_filter: PathFilter::default(),
+            opt_out_property: DEFAULT_OPT_OUT_PROPERTY.to_string(),
+            opted_out: HashMap::new(),
+            languages: HashMap::new(),
+            progress_callback: None,
         }
     }
 
+    /// Send `callback(event)` a ProgressEvent `{operation, current, total, detail}` as the cache
+    /// deserializers, suggest_links_for_vault and generate_vault_report work; None stops the
+    /// events. Events come at most every 250 ms and every hundredth of the work, plus the first
+    /// and last. A callback that throws is logged and the operation carries on. The callback
+    /// runs in the middle of the call, so it mustn't call back into this SmartVault, but it may
+    /// abort the AbortSignal given to the operation, after which it gets no more events.
+    pub fn set_progress_callback(&mut self, callback: Option<js_sys::Function>) {
+        self.progress_callback = callback;
+    }
+
+    /// Store a note's keywords, cleaned with the same rules as extract_keywords_with_llm
+    /// so lists from older caches get deduped and stripped of junk. No length limit is applied.
     pub fn set_keywords(&mut self, path: String, keywords: Vec<String>) {
-        self.keywords.insert(path, keywords);
+        self.keywords.insert(path, normalize_keywords(&keywords, None));
     }
 
-    pub fn get_keywords(&self, path: &str) -> JsValue {
+    pub fn get_keywords(&self, path: &str) -> Result<JsValue, JsValue> {
         if let Some(keywords) = self.keywords.get(path) {
-            serde_wasm_bindgen::to_value(keywords).unwrap_or(JsValue::NULL)
+            serde_wasm_bindgen::to_value(keywords)
+                .map_err(|e| JsValue::from_str(&format!("Serialization error in get_keywords: {}", e)))
         } else {
-            JsValue::NULL
+            Ok(JsValue::NULL)
         }
     }
 
     pub fn add_file(&mut self, path: String, content: String) {
+        if let Some(previous) = self.file_contents.get(&path) {
+            self.document_frequencies.remove_document(previous);
+        }
+        self.document_frequencies.add_document(&content);
+        self.link_index.update(&path, &content);
+        self.title_index.remove(&path);
+        self.title_index.insert(&path);
+        for alias in note_aliases(&content) {
+            self.title_index.add_alias(&alias, &path);
+        }
+        self.record_opt_out(&path, &content);
+        self.languages.insert(path.clone(), detect_language(&content));
         self.file_contents.insert(path, content);
     }
 
+    /// Language detected for an added note when it was added (see detect_language), or null
+    /// if it hasn't been added. Pass it to extract_keywords_with_llm and
+    /// analyze_formatting_with_llm as their `language` hint.
+    pub fn get_note_language(&self, path: &str) -> Option<String> {
+        self.languages.get(path).cloned()
+    }
+
+    /// Frontmatter property notes opt out with ("smart-vault" by default): `ignore` leaves a
+    /// note out of scans and drops its embeddings and keywords, `no-suggest` leaves it out of
+    /// scans and link suggestions. Added notes are checked again.
+    pub fn set_opt_out_property(&mut self, property: String) {
+        let property = property.trim();
+        self.opt_out_property = if property.is_empty() { DEFAULT_OPT_OUT_PROPERTY.to_string() } else { property.to_string() };
+        let notes: Vec<(String, String)> = self.file_contents.iter().map(|(p, c)| (p.clone(), c.clone())).collect();
+        for (path, content) in notes {
+            self.record_opt_out(&path, &content);
+        }
+    }
+
+    /// Whether a note should be embedded and suggested: false if it opted out in its frontmatter
+    pub fn should_index(&self, content: &str) -> bool {
+        frontmatter::note_opt_out(content, &self.opt_out_property).is_none()
+    }
+
+    /// Paths of added notes that opted out, sorted
+    pub fn get_opted_out_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.opted_out.keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Add a `.canvas` file to the link graph: it links to its file nodes and to the links in
+    /// its text cards. Canvases aren't stored as notes; embed their text cards (see parse_canvas)
+    /// with set_embedding if they should be suggested. remove_file removes it again.
+    pub fn add_canvas_file(&mut self, path: String, content: &str) {
+        self.link_index.update(&path, content);
+        self.title_index.remove(&path);
+        self.title_index.insert(&path);
+    }
+
+    /// Forget a deleted note: its content, keywords and embeddings
+    pub fn remove_file(&mut self, path: &str) {
+        if let Some(previous) = self.file_contents.remove(path) {
+            self.document_frequencies.remove_document(&previous);
+        }
+        self.link_index.remove(path);
+        self.title_index.remove(path);
+        self.opted_out.remove(path);
+        self.languages.remove(path);
+        self.keywords.remove(path);
+        self.forget_embeddings(path);
+    }
+
+    /// Keywords for a stored note by TF-IDF against the rest of the vault, without an LLM.
+    /// Returns up to `max_keywords` lowercase terms (including repeated bigrams), best first,
+    /// or null if the note hasn't been added. Stop words of the note's language are left out
+    /// along with the English ones. Pass the result to set_keywords to use it for link
+    /// suggestions before an LLM pass has run.
+    pub fn extract_keywords_tfidf(&self, path: &str, max_keywords: usize) -> Result<JsValue, JsValue> {
+        match self.file_contents.get(path) {
+            Some(content) => {
+                let stop_words = self.languages.get(path).and_then(|language| language::stop_words(language)).unwrap_or(&[]);
+                let keywords = self.document_frequencies.top_terms(content, max_keywords, stop_words);
+                serde_wasm_bindgen::to_value(&keywords)
+                    .map_err(|e| JsValue::from_str(&format!("Serialization error in extract_keywords_tfidf: {}", e)))
+            }
+            None => Ok(JsValue::NULL),
+        }
+    }
+
+    /// Link graph of every added note and canvas: `{nodes, edges, unresolved}` (see LinkGraph).
+    /// Targets resolve to stored paths by path or title, the shortest path winning between notes
+    /// with the same title; links to missing notes are listed in `unresolved`. Rebuilt only after
+    /// notes are added or removed.
+    pub fn build_link_graph(&self) -> Result<JsValue, JsValue> {
+        self.link_index.with_graph(|graph| {
+            serde_wasm_bindgen::to_value(graph)
+                .map_err(|e| JsValue::from_str(&format!("Serialization error in build_link_graph: {}", e)))
+        })
+    }
+
+    /// Path an added note's link target resolves to, as Obsidian would resolve it from the note at
+    /// `source_path` (see TitleIndex): exact path, then the only note with that name or path
+    /// ending, then the one in the same folder, then the shortest path, then aliases. Any
+    /// "|alias", "#heading" or "#^block" part of `link_text` is ignored.
+    pub fn resolve_link_target(&self, link_text: &str, source_path: &str) -> Option<String> {
+        let target = link_text_target(link_text);
+        let source = (!source_path.is_empty()).then_some(source_path);
+        self.title_index.resolve(&target, source).map(str::to_string)
+    }
+
+    /// Paths of the added notes and canvases a title could refer to: those whose file name
+    /// (without extension) is `title`, ignoring case, then those with it as an alias. Shortest
+    /// path first.
+    pub fn title_to_path_candidates(&self, title: &str) -> Vec<String> {
+        self.title_index.paths_for_title(title)
+    }
+
+    /// Titles that more than one added note has, with their paths (AmbiguousTitles), so links to
+    /// them can be reviewed
+    pub fn get_ambiguous_titles(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.title_index.ambiguous_titles())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in get_ambiguous_titles: {}", e)))
+    }
+
+    /// Every wiki link in the added notes whose target isn't among `known_paths_json` (a JSON
+    /// array of all vault file paths, attachments included), matched by path, name or alias.
+    /// Returns a BrokenLink per link, with close existing names as suggestions.
+    pub fn find_broken_links(&self, known_paths_json: &str) -> Result<JsValue, JsValue> {
+        let known_paths: Vec<String> = serde_json::from_str(known_paths_json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid known paths JSON: {}", e)))?;
+        let broken = graph::find_broken_links(&self.file_contents, &known_paths);
+        serde_wasm_bindgen::to_value(&broken)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in find_broken_links: {}", e)))
+    }
+
+    /// Word, char, heading and link counts, reading time and age of every added note, with
+    /// vault totals, the longest and shortest notes, and the notes not modified for more than
+    /// `stale_after_days` days (VaultStats). Ages come from the processed mtimes in the cache
+    /// index, so notes that were never processed have none.
+    pub fn compute_note_stats(&self, stale_after_days: u32) -> Result<JsValue, JsValue> {
+        let now = clock::now_ms() as u64;
+        let index = &self.cache_index;
+        let notes = self.file_contents.iter()
+            .map(|(path, content)| {
+                let mtime = [&index.embedding_mtimes, &index.keyword_mtimes, &index.suggestion_mtimes].iter()
+                    .filter_map(|mtimes| mtimes.get(path).copied())
+                    .max();
+                stats::note_stats(path, content, mtime, now)
+            })
+            .collect();
+        serde_wasm_bindgen::to_value(&stats::vault_stats(notes, stale_after_days as u64))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in compute_note_stats: {}", e)))
+    }
+
+    /// Every tag used in the added notes (see LinkAnalyzer::extract_tags), most used first, as
+    /// VaultTags. The `tag` names are what analyze_formatting_with_llm expects in `all_vault_tags`.
+    pub fn get_all_tags(&self) -> Result<JsValue, JsValue> {
+        let mut tags: HashMap<String, VaultTag> = HashMap::new();
+        let mut paths: Vec<&String> = self.file_contents.keys().collect();
+        // Sorted so the spelling kept for a tag doesn't depend on hash order
+        paths.sort();
+        for path in paths {
+            for note_tag in parse_tags(&self.file_contents[path]) {
+                let entry = tags.entry(note_tag.tag.to_lowercase())
+                    .or_insert_with(|| VaultTag { tag: note_tag.tag.clone(), count: 0, notes: 0 });
+                entry.count += note_tag.count;
+                entry.notes += 1;
+            }
+        }
+        let mut tags: Vec<VaultTag> = tags.into_values().collect();
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+        serde_wasm_bindgen::to_value(&tags)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in get_all_tags: {}", e)))
+    }
+
+    /// After a note is renamed, point every stored note's wiki links to `old_title` at
+    /// `new_title` (see LinkAnalyzer::rewrite_links). Changed notes are stored with their new
+    /// content and returned as RewrittenNotes, sorted by path, for the plugin to write back.
+    pub fn rewrite_links_in_vault(&mut self, old_title: &str, new_title: &str) -> Result<JsValue, JsValue> {
+        let mut changed: Vec<RewrittenNote> = self.file_contents.iter()
+            .filter_map(|(path, content)| {
+                let rewrite = rewrite_note_links(content, old_title, new_title);
+                (rewrite.rewrites > 0).then(|| RewrittenNote { path: path.clone(), content: rewrite.content, rewrites: rewrite.rewrites })
+            })
+            .collect();
+        changed.sort_by(|a, b| a.path.cmp(&b.path));
+        for note in &changed {
+            self.add_file(note.path.clone(), note.content.clone());
+        }
+        serde_wasm_bindgen::to_value(&changed)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in rewrite_links_in_vault: {}", e)))
+    }
+
+    /// Places where the note at `target_path` is mentioned by title or alias as plain text (not
+    /// in a link, code or frontmatter) in the other added notes, skipping ignored suggestion pairs.
+    /// Returns an UnlinkedMention per occurrence.
+    pub fn find_unlinked_mentions(&self, target_path: &str) -> Result<JsValue, JsValue> {
+        let mut names: HashMap<String, Vec<String>> = HashMap::new();
+        for name in mentions::mention_names(target_path, self.file_contents.get(target_path).map(String::as_str), 1) {
+            names.insert(name.to_lowercase(), vec![target_path.to_string()]);
+        }
+        self.unlinked_mentions(&names)
+    }
+
+    /// `find_unlinked_mentions` for every added note at once, considering only titles and
+    /// aliases at least `min_title_len` chars long (short names match too much common text)
+    pub fn find_all_unlinked_mentions(&self, min_title_len: usize) -> Result<JsValue, JsValue> {
+        let mut names: HashMap<String, Vec<String>> = HashMap::new();
+        for (path, content) in &self.file_contents {
+            for name in mentions::mention_names(path, Some(content), min_title_len) {
+                let paths = names.entry(name.to_lowercase()).or_default();
+                if !paths.contains(path) {
+                    paths.push(path.clone());
+                }
+            }
+        }
+        for paths in names.values_mut() {
+            paths.sort();
+        }
+        self.unlinked_mentions(&names)
+    }
+
+    fn unlinked_mentions(&self, names: &HashMap<String, Vec<String>>) -> Result<JsValue, JsValue> {
+        let now = clock::now_ms() as u64;
+        let found = mentions::find_unlinked_mentions(&self.file_contents, names, |source, target| {
+            self.cache_index.is_suggestion_ignored(source, target, now)
+        });
+        serde_wasm_bindgen::to_value(&found)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in unlinked_mentions: {}", e)))
+    }
+
     pub fn set_embedding(&mut self, path: String, embedding: Vec<f32>) {
+        self.local_embeddings.remove(&path);
+        self.embeddings.insert(path, embedding);
+    }
+
+    /// Store an offline (generate_local_embedding) vector; it is only ever compared with other local vectors
+    pub fn set_local_embedding(&mut self, path: String, embedding: Vec<f32>) {
+        self.local_embeddings.insert(path.clone());
         self.embeddings.insert(path, embedding);
     }
 
+    pub fn is_local_embedding(&self, path: &str) -> bool {
+        self.local_embeddings.contains(path)
+    }
+
+    /// Paths whose embedding is local, e.g. to persist alongside the embeddings cache
+    pub fn get_local_embedding_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.local_embeddings.iter().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Restore local markers after loading embeddings from cache
+    pub fn mark_local_embeddings(&mut self, paths: Vec<String>) {
+        self.local_embeddings.extend(paths.into_iter().filter(|p| self.embeddings.contains_key(p)));
+    }
+
+    /// Store chunk embeddings for a long note (the `chunks` array from generate_document_embedding_ollama).
+    /// suggest_links_for_text matches against the best chunk when these are present; an empty array removes them.
+    pub fn set_chunk_embeddings(&mut self, path: String, chunks_json: &str) -> Result<(), JsValue> {
+        let chunks: Vec<ChunkEmbedding> = serde_json::from_str(chunks_json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid chunk embeddings: {}", e)))?;
+        if chunks.is_empty() {
+            self.chunk_embeddings.remove(&path);
+        } else {
+            self.chunk_embeddings.insert(path, chunks);
+        }
+        Ok(())
+    }
+
+    pub fn has_chunk_embeddings(&self, path: &str) -> bool {
+        self.chunk_embeddings.contains_key(path)
+    }
+
     pub fn get_file_count(&self) -> usize {
         self.file_contents.len()
     }
@@ -91,7 +477,7 @@ impl SmartVault {
     // Serialize embeddings to JSON string for persistence (legacy)
     pub fn serialize_embeddings(&self) -> Result<String, JsValue> {
         serde_json::to_string(&self.embeddings)
-            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in serialize_embeddings: {}", e)))
     }
 
     // Deserialize embeddings from JSON string (legacy)
@@ -109,189 +495,639 @@ impl SmartVault {
             .map_err(|e| JsValue::from_str(&format!("Binary serialization error: {}", e)))
     }
 
-    // Deserialize embeddings from binary MessagePack format with version detection
+    // Deserialize embeddings from binary MessagePack format with version detection.
+    // On failure the error is a CacheLoadError object describing both attempts.
+    // The loaded vectors replace the active space, dropping its chunk vectors and local marks.
     pub fn deserialize_embeddings_binary(&mut self, data: &[u8]) -> Result<(), JsValue> {
-        // Try to deserialize as versioned cache first
-        if let Ok(versioned) = VersionedCache::<EmbeddingsData>::from_msgpack(data) {
-            console_log!("[DEBUG] Loaded versioned cache: format={}, version={}",
-                versioned.header.format, versioned.header.version);
-            self.embeddings = versioned.data;
-            Ok(())
+        let callback = self.progress_callback.clone();
+        let mut progress = Progress::new(callback.as_ref(), "deserialize_embeddings_binary", data.len());
+        progress.update(0, || Some(format!("{} bytes", data.len())));
+        let (header, embeddings) = load_versioned_or_legacy::<EmbeddingsData>(data)?;
+        match header {
+            Some(header) => log_info!("Loaded versioned cache: format={}, version={}, verified={}",
+                header.format, header.version, header.checksum.is_some()),
+            None => log_info!("Loaded legacy format embeddings cache"),
+        }
+        self.replace_active_space(EmbeddingSpace { embeddings, ..Default::default() });
+        progress.finish(Some(format!("{} embeddings", self.embeddings.len())));
+        Ok(())
+    }
+
+    /// Import embeddings from another plugin's cache instead of re-embedding the vault.
+    /// `format` is "smart-connections" (its `.ajson` files or older JSON, preferring vectors
+    /// from the model set with set_embedding_model) or "generic" (`{path: [numbers]}`).
+    /// Vectors must be `expected_dim` long, by default the length of the stored embeddings,
+    /// or, with none stored, the most common length among those imported. `mtimes_json`
+    /// (`{path: mtime}`) marks the imported notes as embedded at those mtimes so scans treat
+    /// them as fresh. Returns an ExternalImportResult.
+    pub fn import_external_embeddings(&mut self, format: &str, data: &str, mtimes_json: Option<String>, expected_dim: Option<usize>) -> Result<JsValue, JsValue> {
+        let mtimes: HashMap<String, f64> = mtimes_json
+            .map(|json| serde_json::from_str(&json))
+            .transpose()
+            .map_err(|e| JsValue::from_str(&format!("Invalid mtimes JSON: {}", e)))?
+            .unwrap_or_default();
+        let mtimes: HashMap<String, f64> = mtimes.into_iter().map(|(path, mtime)| (import::normalize_import_path(&path), mtime)).collect();
+        let (found, skipped) = import::parse_external_embeddings(format, data, self.embedding_model.as_deref())
+            .map_err(|e| JsValue::from_str(&e))?;
+
+        let expected_dim = expected_dim
+            .or_else(|| self.embeddings.values().next().map(Vec::len))
+            .or_else(|| {
+                let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+                for (_, vector) in &found {
+                    *counts.entry(vector.len()).or_insert(0) += 1;
+                }
+                counts.into_iter().max_by_key(|&(dim, count)| (count, std::cmp::Reverse(dim))).map(|(dim, _)| dim)
+            });
+        let mut result = ExternalImportResult { skipped, expected_dim, ..ExternalImportResult::default() };
+        for (path, vector) in found {
+            if Some(vector.len()) != expected_dim {
+                result.dimension_mismatch += 1;
+                continue;
+            }
+            if let Some(&mtime) = mtimes.get(&path) {
+                self.cache_index.mark_embedding_processed(&path, normalize_mtime(mtime));
+                result.marked_fresh += 1;
+            }
+            self.set_embedding(path, vector);
+            result.imported += 1;
+        }
+        log_info!("Imported {} {} embeddings ({} skipped, {} with the wrong dimension)",
+            result.imported, format, result.skipped, result.dimension_mismatch);
+        serde_wasm_bindgen::to_value(&result)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in import_external_embeddings: {}", e)))
+    }
+
+    /// Set the embedding model that produced (or will produce) the current embeddings;
+    /// the same as set_active_embedding_model
+    pub fn set_embedding_model(&mut self, model: String) {
+        self.set_active_embedding_model(model);
+    }
+
+    /// Switch to `model`'s embedding space: set_embedding, get_embedding, find_similar_notes,
+    /// link suggestions and plan_scan work with its vectors and embedding mtimes from then on.
+    /// The vectors and mtimes of the model that was active are kept, so switching back to it
+    /// restores them without re-embedding. Vectors stored before any model was set are taken
+    /// to be `model`'s. Returns the number of vectors in the now active space.
+    pub fn set_active_embedding_model(&mut self, model: String) -> usize {
+        if self.embedding_model.as_deref() != Some(model.as_str()) {
+            let restored = self.embedding_spaces.remove(&model).unwrap_or_default();
+            match self.embedding_model.replace(model.clone()) {
+                Some(previous) => {
+                    let set_aside = self.replace_active_space(restored);
+                    if !set_aside.embeddings.is_empty() {
+                        self.embedding_spaces.insert(previous, set_aside);
+                    }
+                }
+                None if self.embeddings.is_empty() => {
+                    self.replace_active_space(restored);
+                }
+                None => {}
+            }
+            log_info!("Embedding model '{}' active with {} vectors", model, self.embeddings.len());
+        }
+        self.cache_index.switch_embedding_space(&model);
+        self.embeddings.len()
+    }
+
+    /// The active embedding model, if one was set
+    pub fn get_active_embedding_model(&self) -> Option<String> {
+        self.embedding_model.clone()
+    }
+
+    /// Every embedding space as EmbeddingSpaceInfo objects, the active one first
+    pub fn get_embedding_spaces(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.embedding_space_infos())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in get_embedding_spaces: {}", e)))
+    }
+
+    /// Delete the vectors and embedding mtimes of a model that isn't active.
+    /// Returns whether there was anything to delete.
+    pub fn drop_embedding_space(&mut self, model: &str) -> bool {
+        let had_vectors = self.embedding_model.as_deref() != Some(model) && self.embedding_spaces.remove(model).is_some();
+        let had_mtimes = self.cache_index.embedding_space.as_deref() != Some(model) && self.cache_index.drop_embedding_space(model);
+        had_vectors || had_mtimes
+    }
+
+    /// Serialize the vectors of every embedding model to binary MessagePack (EmbeddingSpacesData).
+    /// The header records the active model, its dimension and the task type, and lists every
+    /// space. Chunk embeddings aren't included.
+    pub fn serialize_embedding_spaces_binary(&self) -> Result<Vec<u8>, JsValue> {
+        let stored = |embeddings: &EmbeddingsData, local: &HashSet<String>| {
+            let mut local_paths: Vec<String> = local.iter().cloned().collect();
+            local_paths.sort();
+            StoredEmbeddingSpace { embeddings: embeddings.clone(), local_paths }
+        };
+        let data = EmbeddingSpacesData {
+            active: stored(&self.embeddings, &self.local_embeddings),
+            inactive: self.embedding_spaces.iter()
+                .map(|(model, space)| (model.clone(), stored(&space.embeddings, &space.local_embeddings)))
+                .collect(),
+        };
+        let mut versioned = VersionedCache::new(data, "msgpack");
+        versioned.header.embedding_model = self.embedding_model.clone();
+        versioned.header.embedding_dim = self.embeddings.values().next().map(Vec::len);
+        versioned.header.embedding_task = self.embedding_task.map(|t| t.as_str().to_string());
+        versioned.header.embedding_spaces = Some(self.embedding_space_infos());
+        versioned.to_msgpack()
+            .map_err(|e| JsValue::from_str(&format!("Binary serialization error: {}", e)))
+    }
+
+    /// Replace every embedding space with those in a serialize_embedding_spaces_binary cache.
+    /// A single-model embeddings cache (serialize_embeddings_binary*) loads as the space of the
+    /// model in its header. The active model stays active if one was set; otherwise the cache's
+    /// active model becomes active. Returns the spaces as get_embedding_spaces does.
+    /// On failure the error is a CacheLoadError object describing both attempts.
+    pub fn deserialize_embedding_spaces_binary(&mut self, data: &[u8]) -> Result<JsValue, JsValue> {
+        let callback = self.progress_callback.clone();
+        let mut progress = Progress::new(callback.as_ref(), "deserialize_embedding_spaces_binary", data.len());
+        progress.update(0, || Some(format!("{} bytes", data.len())));
+        let (header, spaces) = match load_versioned_or_legacy::<EmbeddingSpacesData>(data) {
+            Ok(loaded) => loaded,
+            Err(spaces_error) => match load_versioned_or_legacy::<EmbeddingsData>(data) {
+                Ok((header, embeddings)) => {
+                    log_info!("Loaded a single-model embeddings cache as an embedding space");
+                    let active = StoredEmbeddingSpace { embeddings, local_paths: Vec::new() };
+                    (header, EmbeddingSpacesData { active, inactive: BTreeMap::new() })
+                }
+                Err(_) => return Err(spaces_error.into()),
+            },
+        };
+        let space = |stored: StoredEmbeddingSpace| {
+            let local_embeddings = stored.local_paths.into_iter().filter(|p| stored.embeddings.contains_key(p)).collect();
+            EmbeddingSpace { embeddings: stored.embeddings, chunk_embeddings: HashMap::new(), local_embeddings }
+        };
+
+        let wanted = self.embedding_model.take();
+        let cached_model = header.and_then(|header| header.embedding_model);
+        self.embedding_spaces = spaces.inactive.into_iter()
+            .filter(|(model, stored)| cached_model.as_ref() != Some(model) && !stored.embeddings.is_empty())
+            .map(|(model, stored)| (model, space(stored)))
+            .collect();
+        self.replace_active_space(space(spaces.active));
+        self.embedding_model = cached_model.clone();
+        if let Some(model) = wanted.or(cached_model) {
+            self.set_active_embedding_model(model);
+        }
+        log_info!("Loaded {} embedding spaces", self.embedding_spaces.len() + 1);
+        progress.finish(Some(format!("{} embedding spaces", self.embedding_spaces.len() + 1)));
+        self.get_embedding_spaces()
+    }
+
+    /// Set the task type vault notes are embedded with (normally Document), recorded in cache headers
+    pub fn set_embedding_task_type(&mut self, task_type: EmbeddingTaskType) {
+        self.embedding_task = Some(task_type);
+    }
+
+    /// Serialize embeddings to binary MessagePack, recording the model, dimension and task type in the header
+    pub fn serialize_embeddings_binary_with_meta(&self, model: &str, dim: usize) -> Result<Vec<u8>, JsValue> {
+        let mut versioned = VersionedCache::new(self.embeddings.clone(), "msgpack")
+            .with_embedding_meta(model, dim);
+        if let Some(task) = self.embedding_task {
+            versioned = versioned.with_embedding_task(task.as_str());
+        }
+        versioned.to_msgpack()
+            .map_err(|e| JsValue::from_str(&format!("Binary serialization error: {}", e)))
+    }
+
+    /// Deserialize embeddings, checking them against the expected model and dimension.
+    /// A model mismatch rejects the cache entirely, as does a task type that differs from the one
+    /// set with set_embedding_task_type (prefixed and unprefixed vectors don't compare well).
+    /// Vectors of the wrong dimension are dropped. Accepted vectors replace the active space,
+    /// dropping its chunk vectors and local marks.
+    /// Returns an EmbeddingCacheLoadResult describing what happened.
+    pub fn deserialize_embeddings_binary_checked(&mut self, data: &[u8], expected_model: &str, expected_dim: usize) -> Result<JsValue, JsValue> {
+        let callback = self.progress_callback.clone();
+        let mut progress = Progress::new(callback.as_ref(), "deserialize_embeddings_binary_checked", data.len());
+        progress.update(0, || Some(format!("{} bytes", data.len())));
+        let (embeddings, cached_model, cached_dim, cached_task) = match load_versioned_or_legacy::<EmbeddingsData>(data)? {
+            (Some(header), embeddings) => (embeddings, header.embedding_model, header.embedding_dim, header.embedding_task),
+            (None, embeddings) => (embeddings, None, None, None),
+        };
+
+        let missing_metadata = cached_model.is_none();
+        let model_mismatch = cached_model.as_deref().is_some_and(|m| m != expected_model);
+
+        let expected_task = self.embedding_task.map(|t| t.as_str());
+        let task_mismatch = matches!((cached_task.as_deref(), expected_task), (Some(cached), Some(expected)) if cached != expected);
+
+        let result = if model_mismatch || task_mismatch {
+            if model_mismatch {
+                log_warn!("Embedding cache was built with '{}' but '{}' is active - rejecting cache",
+                    cached_model.as_deref().unwrap_or(""), expected_model);
+            } else {
+                log_warn!("Embedding cache uses task type '{}' but '{}' is active - rejecting cache",
+                    cached_task.as_deref().unwrap_or(""), expected_task.unwrap_or(""));
+            }
+            EmbeddingCacheLoadResult {
+                status: EmbeddingCacheStatus::Rejected,
+                cached_model,
+                cached_dim,
+                loaded: 0,
+                dropped: embeddings.len(),
+                missing_metadata,
+                cached_task,
+            }
         } else {
-            // Fallback: try to deserialize as raw HashMap (legacy format)
-            console_log!("[DEBUG] Attempting legacy format deserialization");
-            let embeddings: HashMap<String, Vec<f32>> = rmp_serde::from_slice(data)
-                .map_err(|e| JsValue::from_str(&format!("Binary deserialization error: {}", e)))?;
-            self.embeddings = embeddings;
-            Ok(())
+            let total = embeddings.len();
+            let kept: EmbeddingsData = embeddings.into_iter()
+                .filter(|(_, v)| v.len() == expected_dim)
+                .collect();
+            let dropped = total - kept.len();
+            if missing_metadata {
+                log_warn!("Embedding cache has no model metadata - accepting without model check");
+            }
+
+            self.set_active_embedding_model(expected_model.to_string());
+            self.replace_active_space(EmbeddingSpace { embeddings: kept, ..Default::default() });
+            EmbeddingCacheLoadResult {
+                status: if dropped > 0 { EmbeddingCacheStatus::Filtered } else { EmbeddingCacheStatus::Accepted },
+                cached_model,
+                cached_dim,
+                loaded: total - dropped,
+                dropped,
+                missing_metadata,
+                cached_task,
+            }
+        };
+        progress.finish(Some(format!("{} embeddings", result.loaded)));
+
+        serde_wasm_bindgen::to_value(&result)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in deserialize_embeddings_binary_checked: {}", e)))
+    }
+
+    /// Get a summary of cache state, including the active embedding model
+    pub fn get_cache_stats(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.cache_stats())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in get_cache_stats: {}", e)))
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            embedding_count: self.embeddings.len(),
+            file_count: self.file_contents.len(),
+            keyword_count: self.keywords.len(),
+            ignored_count: self.cache_index.ignored_suggestions.len() + self.cache_index.ignored_target_prefixes.len(),
+            insertion_cache_count: self.cache_index.insertion_cache.len(),
+            active_model: self.embedding_model.clone(),
+            embedding_dim: self.embeddings.values().next().map(|v| v.len()),
         }
     }
 
-    pub fn find_similar_notes(&self, path: &str, top_k: usize) -> JsValue {
+    /// The `top_k` notes most similar to the one at `path` as [path, similarity] pairs, best
+    /// first; equal scores are ordered by path. Null if the note has no embedding.
+    pub fn find_similar_notes(&self, path: &str, top_k: usize) -> Result<JsValue, JsValue> {
         if let Some(query_embedding) = self.embeddings.get(path) {
+            let query_is_local = self.is_local_embedding(path);
             let mut similarities: Vec<(String, f32)> = self.embeddings
                 .iter()
-                .filter(|(p, _)| p.as_str() != path)
+                .filter(|(p, _)| p.as_str() != path && self.is_local_embedding(p) == query_is_local)
                 .map(|(p, emb)| {
                     let similarity = cosine_similarity(query_embedding, emb);
                     (p.clone(), similarity)
                 })
                 .collect();
+            let scored = similarities.len();
+            similarities.retain(|(_, similarity)| similarity.is_finite());
+            if similarities.len() < scored {
+                log_warn!("find_similar_notes: dropped {} non-finite similarity scores", scored - similarities.len());
+            }
 
-            similarities.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+            similarities.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
             similarities.truncate(top_k);
 
-            serde_wasm_bindgen::to_value(&similarities).unwrap()
+            serde_wasm_bindgen::to_value(&similarities)
+                .map_err(|e| JsValue::from_str(&format!("Serialization error in find_similar_notes: {}", e)))
         } else {
-            JsValue::NULL
+            Ok(JsValue::NULL)
         }
     }
 
-    pub fn find_similar(&self, query_embedding: Vec<f32>, threshold: f32) -> JsValue {
-        let mut matches: Vec<SimilarityMatch> = self.embeddings
-            .iter()
-            .map(|(p, emb)| {
-                let score = cosine_similarity(&query_embedding, emb);
-                SimilarityMatch { path: p.clone(), score }
+    /// Rank folders for a note by similarity to each folder's centroid embedding, without an LLM.
+    /// Folders with fewer than `min_members` embedded notes (default 2; the note itself doesn't
+    /// count) are skipped. Returns up to `top_k` {folder, score, member_count, nearest_member},
+    /// best first (equal scores by folder); root-level notes form the folder "/". Null if the
+    /// note has no embedding.
+    pub fn suggest_folder_by_similarity(&self, path: &str, top_k: usize, min_members: Option<usize>) -> Result<JsValue, JsValue> {
+        let Some(query_embedding) = self.embeddings.get(path) else {
+            return Ok(JsValue::NULL);
+        };
+        let min_members = min_members.unwrap_or(DEFAULT_MIN_FOLDER_MEMBERS).max(1);
+        let query_is_local = self.is_local_embedding(path);
+
+        // folder -> (vector sum, members)
+        let mut folders: HashMap<&str, (Vec<f32>, Vec<&String>)> = HashMap::new();
+        for (p, emb) in &self.embeddings {
+            if p == path || emb.len() != query_embedding.len() || self.is_local_embedding(p) != query_is_local {
+                continue;
+            }
+            let folder = p.rsplit_once('/').map_or("/", |(folder, _)| folder);
+            let (sum, members) = folders.entry(folder)
+                .or_insert_with(|| (vec![0.0; emb.len()], Vec::new()));
+            for (total, value) in sum.iter_mut().zip(emb) {
+                *total += value;
+            }
+            members.push(p);
+        }
+
+        let mut suggestions: Vec<FolderSuggestion> = folders.into_iter()
+            .filter(|(_, (_, members))| members.len() >= min_members)
+            .map(|(folder, (sum, members))| {
+                let centroid: Vec<f32> = sum.iter().map(|total| total / members.len() as f32).collect();
+                let nearest_member = members.iter()
+                    .map(|member| (member, cosine_similarity(query_embedding, &self.embeddings[*member])))
+                    .max_by(|a, b| a.1.total_cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+                    .map(|(member, _)| (*member).clone())
+                    .unwrap_or_default();
+                FolderSuggestion {
+                    folder: folder.to_string(),
+                    score: cosine_similarity(query_embedding, &centroid),
+                    member_count: members.len(),
+                    nearest_member,
+                }
             })
-            .filter(|m| m.score >= threshold)
             .collect();
 
-        matches.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
-        
-        serde_wasm_bindgen::to_value(&matches).unwrap_or(JsValue::NULL)
+        suggestions.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.folder.cmp(&b.folder)));
+        suggestions.truncate(top_k);
+        serde_wasm_bindgen::to_value(&suggestions)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in suggest_folder_by_similarity: {}", e)))
+    }
+
+    /// Notes above `threshold` similarity to the query, best first (equal scores by path). Set
+    /// `query_is_local` when the query came from generate_local_embedding so it is matched
+    /// against local vectors only.
+    pub fn find_similar(&self, query_embedding: Vec<f32>, threshold: f32, query_is_local: Option<bool>) -> Result<JsValue, JsValue> {
+        let matches = self.similarity_matches(&query_embedding, threshold, query_is_local.unwrap_or(false));
+        serde_wasm_bindgen::to_value(&matches)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in find_similar: {}", e)))
+    }
+
+    /// Link suggestions for `text`. Notes whose title appears in the text are force-included
+    /// and kept regardless of score, up to `max_forced` (default `top_k`), ahead of the best
+    /// `top_k` minus that many semantic matches; both groups are sorted by score, then path.
+    ///
+    /// `existing_links_json` is a JSON array of the link targets the current document already
+    /// has ("Note", "folder/Note#Heading|alias"), for when `text` is only part of it. Notes
+    /// whose contents aren't loaded are still suggested, with an empty context; without
+    /// `existing_links_json` they're marked `unverified`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn suggest_links_for_text(&self, text: &str, query_embedding: Vec<f32>, threshold: f32, current_file_path: &str, top_k: usize, query_is_local: Option<bool>, max_forced: Option<usize>, existing_links_json: Option<String>) -> Result<JsValue, JsValue> {
+        let existing_links: Option<Vec<String>> = existing_links_json
+            .map(|json| serde_json::from_str(&json))
+            .transpose()
+            .map_err(|e| JsValue::from_str(&format!("Invalid existing_links_json: {}", e)))?;
+        let suggestions = self.link_suggestions(
+            text, &query_embedding, threshold, current_file_path, top_k, query_is_local.unwrap_or(false),
+            max_forced.unwrap_or(top_k), existing_links.as_deref(),
+        );
+        serde_wasm_bindgen::to_value(&suggestions)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in suggest_links_for_text: {}", e)))
+    }
+
+    /// Why `target_path` is or isn't suggested as a link for `source_path`: a
+    /// SuggestionExplanation with the raw similarity, title, keyword and title-relation boosts,
+    /// the final score, and the ignore, opt-out, exclusion and existing-link checks, computed as
+    /// suggest_links_for_text does with the source's embedding as the query. Ignored and
+    /// excluded pairs are reported, but as suggest_links_for_text doesn't drop them they don't
+    /// change `would_suggest`. Works for any pair,
+    /// including ones below the threshold. `text` defaults to the source's loaded contents;
+    /// `threshold` to 0.7.
+    pub fn explain_suggestion(&self, source_path: &str, target_path: &str, text: Option<String>, threshold: Option<f32>) -> Result<JsValue, JsValue> {
+        let text = match text.or_else(|| self.file_contents.get(source_path).cloned()) {
+            Some(text) => text,
+            None => return Err(JsValue::from_str(&format!("No text given and {} is not loaded", source_path))),
+        };
+        self.suggestion_explanation(source_path, target_path, &text, threshold.unwrap_or(DEFAULT_SUGGESTION_THRESHOLD))
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in explain_suggestion: {}", e)))
+    }
+
+    /// Link suggestions for every note with an embedding and loaded contents, for a
+    /// vault-wide report: each note's contents are the text and its embedding the query, scored
+    /// as suggest_links_for_text would. Excluded and opted-out notes, and ignored suggestions,
+    /// are left out. Keeps up to `top_k_per_note` per note and `max_total` overall, highest
+    /// scores first.
+    ///
+    /// Notes are visited in path order. To keep the UI responsive on large vaults, pass
+    /// `max_notes` and call again with `start_after` set to the returned `next` (null once every
+    /// note has been checked), yielding in between; `max_total` applies per call. Progress is
+    /// reported per note checked. Aborting `abort_signal` (from the progress callback) stops
+    /// after the current note, with `next` set to resume from it.
+    pub fn suggest_links_for_vault(&self, threshold: f32, top_k_per_note: usize, max_total: usize, start_after: Option<String>, max_notes: Option<usize>, abort_signal: Option<web_sys::AbortSignal>) -> Result<JsValue, JsValue> {
+        let mut progress = Progress::new(self.progress_callback.as_ref(), "suggest_links_for_vault", 0)
+            .with_abort(abort_signal.as_ref());
+        self.vault_link_report(threshold, top_k_per_note, max_total, start_after, max_notes, &mut progress)
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in suggest_links_for_vault: {}", e)))
+    }
+
+    /// A markdown report for reviewing the vault, with sections for link suggestions per note
+    /// (as checkboxes), orphan notes (no links in or out), broken links, near-duplicate notes
+    /// and cache statistics. `options_json` is a VaultReportOptions object; every field is
+    /// optional. Links are written so they resolve from `report_path`. The same vault state
+    /// always gives the same report, so weekly reports can be diffed. Progress is reported for
+    /// the suggestions and near-duplicates sections, counting notes from 0 for each, with the
+    /// section as `detail`. Aborting `abort_signal` rejects with "aborted".
+    pub fn generate_vault_report(&self, options_json: &str, abort_signal: Option<web_sys::AbortSignal>) -> Result<String, JsValue> {
+        let options: VaultReportOptions = if options_json.trim().is_empty() {
+            VaultReportOptions::default()
+        } else {
+            serde_json::from_str(options_json)
+                .map_err(|e| JsValue::from_str(&format!("Invalid report options: {}", e)))?
+        };
+        let scanned = |path: &str| self.path_filter.allows(path) && !self.opted_out.contains_key(path);
+
+        let mut progress = Progress::new(self.progress_callback.as_ref(), "generate_vault_report", 0)
+            .with_abort(abort_signal.as_ref());
+        let aborted = || JsValue::from_str(progress::ABORTED_ERROR);
+        let mut data = report::VaultReportData::default();
+        if options.suggestions {
+            data.suggestions = Some(self.vault_link_report(options.threshold, options.top_k_per_note, options.max_suggestions, None, None, &mut progress).suggestions);
+            if progress.is_aborted() {
+                return Err(aborted());
+            }
+        }
+        if options.orphans {
+            data.orphans = Some(self.link_index.with_graph(|graph| {
+                graph.nodes.iter()
+                    .filter(|node| node.incoming == 0 && node.outgoing == 0)
+                    .filter(|node| !is_canvas_path(&node.path) && scanned(&node.path))
+                    .map(|node| node.path.clone())
+                    .collect()
+            }));
+        }
+        if options.broken_links {
+            let mut known_paths = options.known_paths.clone().unwrap_or_else(|| self.file_contents.keys().cloned().collect());
+            known_paths.sort();
+            data.broken_links = Some(graph::find_broken_links(&self.file_contents, &known_paths));
+        }
+        if options.near_duplicates {
+            let mut paths: Vec<&String> = self.embeddings.keys().filter(|path| scanned(path)).collect();
+            paths.sort();
+            progress.restart(paths.len());
+            let mut pairs: Vec<NearDuplicate> = Vec::new();
+            for (i, first) in paths.iter().enumerate() {
+                if progress.is_aborted() {
+                    return Err(aborted());
+                }
+                progress.update(i, || Some("near-duplicates".to_string()));
+                for second in &paths[i + 1..] {
+                    if self.is_local_embedding(first) != self.is_local_embedding(second) {
+                        continue;
+                    }
+                    let similarity = cosine_similarity(&self.embeddings[*first], &self.embeddings[*second]);
+                    if similarity >= options.near_duplicate_threshold {
+                        pairs.push(NearDuplicate { first: (*first).clone(), second: (*second).clone(), similarity });
+                    }
+                }
+            }
+            pairs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity).then_with(|| (&a.first, &a.second).cmp(&(&b.first, &b.second))));
+            pairs.truncate(options.max_near_duplicates);
+            data.near_duplicates = Some(pairs);
+            progress.finish(Some("near-duplicates".to_string()));
+        }
+        if options.cache_stats {
+            data.cache_stats = Some(self.cache_stats());
+        }
+
+        let source = options.report_path.as_deref();
+        let link = |path: &str| format!("[[{}]]", self.title_index.link_target(path, source));
+        let link_title = |title: &str| self.title_index.resolve(title, source).map(link);
+        Ok(report::render_vault_report(&options.title, &data, link, link_title))
+    }
+
+    /// Suggestions gathered by suggest_links_for_vault
+    fn vault_link_report(&self, threshold: f32, top_k_per_note: usize, max_total: usize, start_after: Option<String>, max_notes: Option<usize>, progress: &mut Progress) -> VaultLinkReport {
+        let now = clock::now_ms() as u64;
+        let mut sources: Vec<&String> = self.embeddings.keys()
+            .filter(|path| self.file_contents.contains_key(*path))
+            .filter(|path| self.path_filter.allows(path) && !self.opted_out.contains_key(*path))
+            .filter(|path| start_after.as_ref().is_none_or(|after| path.as_str() > after.as_str()))
+            .collect();
+        sources.sort();
+        let max_notes = max_notes.unwrap_or(usize::MAX).max(1);
+        let mut next = (sources.len() > max_notes).then(|| sources[max_notes - 1].clone());
+        sources.truncate(max_notes);
+
+        progress.restart(sources.len());
+        let mut aborted = false;
+        let mut found: Vec<(&String, LinkSuggestion)> = Vec::new();
+        for (i, source) in sources.iter().enumerate() {
+            if progress.is_aborted() {
+                next = i.checked_sub(1).map(|last| sources[last].clone()).or(start_after.clone());
+                aborted = true;
+                sources.truncate(i);
+                break;
+            }
+            progress.update(i, || Some("link suggestions".to_string()));
+            let suggestions = self.link_suggestions(
+                &self.file_contents[*source], &self.embeddings[*source], threshold, source, top_k_per_note,
+                self.is_local_embedding(source), top_k_per_note, None,
+            );
+            found.extend(suggestions.into_iter()
+                .filter(|s| self.path_filter.allows(&s.path) && !self.cache_index.is_suggestion_ignored(source, &s.path, now))
+                .map(|s| (*source, s)));
+        }
+        found.sort_by(|(a_source, a), (b_source, b)| {
+            b.similarity.total_cmp(&a.similarity).then_with(|| (a_source, &a.path).cmp(&(b_source, &b.path)))
+        });
+        found.truncate(max_total);
+        progress.finish(Some("link suggestions".to_string()));
+
+        let mut report = VaultLinkReport { notes_checked: sources.len(), total: found.len(), next, aborted, ..VaultLinkReport::default() };
+        for (source, suggestion) in found {
+            report.suggestions.entry(source.clone()).or_default().push(suggestion);
+        }
+        report
     }
 
-    pub fn suggest_links_for_text(&self, text: &str, query_embedding: Vec<f32>, threshold: f32, current_file_path: &str, top_k: usize) -> JsValue {
+    /// Scoring behind suggest_links_for_text and suggest_links_for_vault
+    #[allow(clippy::too_many_arguments)]
+    fn link_suggestions(&self, text: &str, query_embedding: &[f32], threshold: f32, current_file_path: &str, top_k: usize, query_is_local: bool, max_forced: usize, existing_links: Option<&[String]>) -> Vec<LinkSuggestion> {
         let mut suggestions: Vec<LinkSuggestion> = Vec::new();
-        let text_lower = text.to_lowercase();
         let mut self_link_skipped = false;
         let mut candidates_above_threshold = 0;
-        let effective_threshold = threshold * 0.85;
+        let mut non_finite_scores = 0;
+        let effective_threshold = threshold * EFFECTIVE_THRESHOLD_FACTOR;
+        let query = self.suggestion_query(text, current_file_path, existing_links);
 
-        web_sys::console::log_1(&format!("[DEBUG] suggest_links_for_text: threshold={}, effective={}, current_file={}, total_files={}",
-            threshold, effective_threshold, current_file_path, self.embeddings.len()).into());
+        log_debug!("suggest_links_for_text: threshold={}, effective={}, current_file={}, total_files={}, language={}",
+            threshold, effective_threshold, current_file_path, self.embeddings.len(), query.language);
 
         for (path, embedding) in &self.embeddings {
             // Skip the current file
             if path == current_file_path {
                 self_link_skipped = true;
-                web_sys::console::log_1(&format!("[DEBUG] Skipped self-link: {}", path).into());
+                log_debug!("Skipped self-link: {}", path);
                 continue;
             }
 
-            let mut similarity = cosine_similarity(&query_embedding, embedding);
-            let mut force_include = false;  // Flag for mandatory inclusion
-
-            let note_title = extract_title_from_path(path);
-            let note_title_lower = note_title.to_lowercase();
-
-            // PRIORITY 0: MANDATORY INCLUSION - Exact title match in text (as standalone word/phrase)
-            // If text contains "turbulence" as a standalone word and note is named "turbulence",
-            // ALWAYS include it regardless of embedding similarity
-            let title_words: Vec<&str> = note_title_lower.split_whitespace().collect();
-            let is_single_word_title = title_words.len() == 1;
-
-            if is_single_word_title {
-                // For single-word titles, check for exact word match with word boundaries
-                let word = title_words[0];
-                let word_regex_pattern = format!(r"\b{}\b", regex::escape(word));
-                if let Ok(word_regex) = regex::Regex::new(&word_regex_pattern) {
-                    if word_regex.is_match(&text_lower) {
-                        // Exact word match: FORCE INCLUDE + huge boost
-                        force_include = true;
-                        similarity += 0.50;
-                        web_sys::console::log_1(&format!("[DEBUG] MANDATORY: Exact title word '{}' found in text - forcing inclusion", note_title).into());
-                    }
-                }
-            } else {
-                // For multi-word titles, check if full title appears as a phrase
-                if text_lower.contains(&note_title_lower) {
-                    // Full phrase match: FORCE INCLUDE + moderate boost
-                    force_include = true;
-                    similarity += 0.30;
-                    web_sys::console::log_1(&format!("[DEBUG] MANDATORY: Full phrase '{}' found in text - forcing inclusion", note_title).into());
-                }
-            }
-
-            // PRIORITY 2: Boost similarity if document keywords appear in the text
-            if let Some(keywords) = self.keywords.get(path) {
-                let mut keyword_match_count = 0;
-                for keyword in keywords {
-                    if text_lower.contains(&keyword.to_lowercase()) {
-                        keyword_match_count += 1;
-                    }
-                }
-                // Boost by up to 0.2 based on keyword matches
-                if keyword_match_count > 0 {
-                    let boost = (keyword_match_count as f32 * 0.05).min(0.2);
-                    similarity += boost;
-                }
+            // Local and model vectors live in different spaces
+            if self.is_local_embedding(path) != query_is_local {
+                continue;
             }
 
-            // PRIORITY 3: Bidirectional title relationship boosting for parent/child topics
-            // Example: "turbulence" <-> "strong turbulence", "weak turbulence"
-            // But with lower boost than exact matches
-            let current_title_lower = extract_title_from_path(current_file_path).to_lowercase();
-
-            // Check if current title is contained in candidate title (parent -> child)
-            // e.g., current="turbulence", candidate="strong turbulence"
-            if note_title_lower.contains(&current_title_lower) && note_title_lower != current_title_lower {
-                similarity += 0.10;  // Reduced boost for child topics (was 0.15)
+            // Opted out of being suggested
+            if self.opted_out.contains_key(path) {
+                continue;
             }
 
-            // Check if candidate title is contained in current title (child -> parent)
-            // e.g., current="strong turbulence", candidate="turbulence"
-            if current_title_lower.contains(&note_title_lower) && note_title_lower != current_title_lower {
-                similarity += 0.10;  // Reduced boost for parent topics (was 0.15)
+            let (similarity, _) = self.candidate_similarity(query_embedding, path, embedding, query_is_local);
+            if !similarity.is_finite() {
+                non_finite_scores += 1;
+                continue;
             }
+            let note_title = extract_title_from_path(path);
+            let score = self.score_candidate(&query, path, &note_title);
+            let force_include = score.forced;
+            let similarity = score.total(similarity);
 
             // Include if EITHER:
             // 1. Force include (title found in text) - ALWAYS include these
             // 2. Similarity above threshold (semantic match)
             if force_include || similarity > effective_threshold {
                 candidates_above_threshold += 1;
-                if let Some(content) = self.file_contents.get(path) {
-                    // note_title already extracted above, reuse it
-                    let link_pattern = format!("[[{}]]", note_title);
-                    let link_exists = text.contains(&link_pattern);
-
-                    web_sys::console::log_1(&format!("[DEBUG] Checking '{}': link_pattern='{}', exists={}, similarity={:.3}, forced={}",
-                        note_title, link_pattern, link_exists, similarity, force_include).into());
-
-                    if !link_exists {
-                        suggestions.push(LinkSuggestion {
-                            path: path.clone(),
-                            title: note_title,
-                            similarity,
-                            context: extract_context(content, 100),
-                        });
-                    } else if force_include {
-                        web_sys::console::log_1(&format!("[DEBUG] Skipping '{}' - link already exists despite force_include", note_title).into());
-                    }
-                } else {
-                    // No file content loaded - this candidate is lost! Log a warning.
-                    web_sys::console::warn_1(&format!("⚠️ No file content for '{}' - cannot check for existing links. Load file contents first!", note_title).into());
+                let content = self.file_contents.get(path);
+                let link_exists = query.links_to(path, &note_title);
+
+                log_debug!("Checking '{}': exists={}, similarity={:.3}, forced={}, content_loaded={}",
+                    note_title, link_exists, similarity, force_include, content.is_some());
+
+                if !link_exists {
+                    suggestions.push(LinkSuggestion {
+                        path: path.clone(),
+                        title: note_title,
+                        similarity,
+                        context: content.map(|content| extract_context(content, 100)).unwrap_or_default(),
+                        forced: force_include,
+                        unverified: content.is_none() && existing_links.is_none(),
+                    });
+                } else if force_include {
+                    log_debug!("Skipping '{}' - link already exists despite force_include", note_title);
                 }
             }
         }
 
-        web_sys::console::log_1(&format!("[DEBUG] Candidates above threshold: {}, after dedup: {}, after truncate: {}",
-            candidates_above_threshold, suggestions.len(), suggestions.len().min(top_k)).into());
 
-        // Sort by similarity and take top K
-        suggestions.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
-        suggestions.truncate(top_k);
+        if non_finite_scores > 0 {
+            log_warn!("suggest_links_for_text: skipped {} notes with non-finite similarity", non_finite_scores);
+        }
+
+        let found = suggestions.len();
+        let suggestions = keep_top_suggestions(suggestions, top_k, max_forced);
+        log_debug!("Candidates above threshold: {}, after dedup: {}, after truncate: {}",
+            candidates_above_threshold, found, suggestions.len());
 
         // Debug: log if self-link was NOT found (potential path mismatch)
         if !self_link_skipped && !current_file_path.is_empty() {
-            web_sys::console::warn_1(&format!("⚠️ Self-link filtering may have failed! Current file '{}' not found in embeddings. Available paths: {:?}",
+            log_warn!("Self-link filtering may have failed! Current file '{}' not found in embeddings. Available paths: {:?}",
                 current_file_path,
                 self.embeddings.keys().take(3).collect::<Vec<_>>()
-            ).into());
+            );
         }
 
-        serde_wasm_bindgen::to_value(&suggestions).unwrap()
+        suggestions
     }
 
     // ============================================================
@@ -300,32 +1136,32 @@ impl SmartVault {
 
     /// Check if a file's embedding is fresh (mtime unchanged)
     pub fn is_embedding_fresh(&self, path: &str, current_mtime: f64) -> bool {
-        self.cache_index.is_embedding_fresh(path, current_mtime as u64)
+        self.cache_index.is_embedding_fresh(path, normalize_mtime(current_mtime))
     }
 
     /// Check if a file's keywords are fresh
     pub fn is_keyword_fresh(&self, path: &str, current_mtime: f64) -> bool {
-        self.cache_index.is_keyword_fresh(path, current_mtime as u64)
+        self.cache_index.is_keyword_fresh(path, normalize_mtime(current_mtime))
     }
 
     /// Check if a file's suggestions are fresh
     pub fn is_suggestion_fresh(&self, path: &str, current_mtime: f64) -> bool {
-        self.cache_index.is_suggestion_fresh(path, current_mtime as u64)
+        self.cache_index.is_suggestion_fresh(path, normalize_mtime(current_mtime))
     }
 
     /// Mark a file's embedding as processed
     pub fn mark_embedding_processed(&mut self, path: &str, mtime: f64) {
-        self.cache_index.mark_embedding_processed(path, mtime as u64);
+        self.cache_index.mark_embedding_processed(path, normalize_mtime(mtime));
     }
 
     /// Mark a file's keywords as processed
     pub fn mark_keyword_processed(&mut self, path: &str, mtime: f64) {
-        self.cache_index.mark_keyword_processed(path, mtime as u64);
+        self.cache_index.mark_keyword_processed(path, normalize_mtime(mtime));
     }
 
     /// Mark a file's suggestions as processed
     pub fn mark_suggestion_processed(&mut self, path: &str, mtime: f64) {
-        self.cache_index.mark_suggestion_processed(path, mtime as u64);
+        self.cache_index.mark_suggestion_processed(path, normalize_mtime(mtime));
     }
 
     /// Invalidate all caches for a specific file
@@ -338,11 +1174,31 @@ impl SmartVault {
         self.cache_index.clear();
     }
 
+    // --- Failure Tracking ---
+
+    /// Record a failed processing attempt ("embedding", "keyword" or "suggestion") for retry backoff
+    pub fn record_processing_failure(&mut self, path: &str, kind: &str, now_ms: Option<f64>) -> Result<(), JsValue> {
+        let kind = ProcessingKind::parse(kind)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown processing kind: {}", kind)))?;
+        let now = now_ms.unwrap_or_else(clock::now_ms) as u64;
+        self.cache_index.record_processing_failure(path, kind, now);
+        Ok(())
+    }
+
+    /// Check whether a previously failed file may be retried yet
+    pub fn should_retry(&self, path: &str, kind: &str, now_ms: Option<f64>) -> bool {
+        let now = now_ms.unwrap_or_else(clock::now_ms) as u64;
+        ProcessingKind::parse(kind)
+            .is_none_or(|kind| self.cache_index.should_retry(path, kind, now))
+    }
+
     // --- Ignored Suggestions ---
 
-    /// Check if a suggestion is ignored
-    pub fn is_suggestion_ignored(&self, source_file: &str, target_file: &str) -> bool {
-        self.cache_index.is_suggestion_ignored(source_file, target_file)
+    /// Check if a suggestion is ignored. Expired temporary ignores no longer apply;
+    /// `now_ms` defaults to the current time when omitted.
+    pub fn is_suggestion_ignored(&self, source_file: &str, target_file: &str, now_ms: Option<f64>) -> bool {
+        let now = now_ms.unwrap_or_else(clock::now_ms) as u64;
+        self.cache_index.is_suggestion_ignored(source_file, target_file, now)
     }
 
     /// Ignore a suggestion
@@ -350,15 +1206,37 @@ impl SmartVault {
         self.cache_index.ignore_suggestion(source_file, target_file);
     }
 
+    /// Ignore a suggestion until the given time (ms since epoch), e.g. "snooze for 30 days"
+    pub fn ignore_suggestion_until(&mut self, source_file: &str, target_file: &str, expires_at_ms: f64) {
+        self.cache_index.ignore_suggestion_until(source_file, target_file, expires_at_ms as u64);
+    }
+
     /// Unignore a suggestion
     pub fn unignore_suggestion(&mut self, source_file: &str, target_file: &str) {
         self.cache_index.unignore_suggestion(source_file, target_file);
     }
 
-    /// Get all ignored suggestions
-    pub fn get_ignored_suggestions(&self) -> JsValue {
+    /// Never suggest targets under the given path prefix (e.g. "Templates/")
+    pub fn ignore_target_prefix(&mut self, prefix: &str) {
+        self.cache_index.ignore_target_prefix(prefix);
+    }
+
+    /// Remove a target prefix ignore. Returns true if it existed.
+    pub fn unignore_target_prefix(&mut self, prefix: &str) -> bool {
+        self.cache_index.unignore_target_prefix(prefix)
+    }
+
+    /// Remove expired temporary ignores. Returns the number removed.
+    pub fn prune_expired_ignores(&mut self, now_ms: Option<f64>) -> usize {
+        let now = now_ms.unwrap_or_else(clock::now_ms) as u64;
+        self.cache_index.prune_expired_ignores(now)
+    }
+
+    /// Get all ignored suggestions as [{source_file, target_file, timestamp, kind, expires_at}]
+    pub fn get_ignored_suggestions(&self) -> Result<JsValue, JsValue> {
         let ignored = self.cache_index.get_ignored_suggestions();
-        serde_wasm_bindgen::to_value(&ignored).unwrap_or(JsValue::NULL)
+        serde_wasm_bindgen::to_value(&ignored)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in get_ignored_suggestions: {}", e)))
     }
 
     /// Clear all ignored suggestions
@@ -368,23 +1246,44 @@ impl SmartVault {
 
     // --- Insertion Cache ---
 
-    /// Get a cached insertion result
-    pub fn get_cached_insertion(&self, file_path: &str, link_title: &str) -> JsValue {
+    /// Get a cached insertion result as an object ({phrase, reason, confidence, created_at, model})
+    pub fn get_cached_insertion(&self, file_path: &str, link_title: &str) -> Result<JsValue, JsValue> {
         match self.cache_index.get_cached_insertion(file_path, link_title) {
-            Some(json_str) => {
-                // Parse the JSON string and return as JsValue
-                match serde_json::from_str::<serde_json::Value>(json_str) {
-                    Ok(value) => serde_wasm_bindgen::to_value(&value).unwrap_or(JsValue::NULL),
-                    Err(_) => JsValue::NULL,
-                }
-            }
-            None => JsValue::NULL,
+            Some(result) => serde_wasm_bindgen::to_value(result)
+                .map_err(|e| JsValue::from_str(&format!("Serialization error in get_cached_insertion: {}", e))),
+            None => Ok(JsValue::NULL),
         }
     }
 
-    /// Cache an insertion result
-    pub fn cache_insertion(&mut self, file_path: &str, link_title: &str, result_json: &str) {
-        self.cache_index.cache_insertion(file_path, link_title, result_json);
+    /// Get a cached insertion result as a JSON string (compatibility shim)
+    pub fn get_cached_insertion_json(&self, file_path: &str, link_title: &str) -> Option<String> {
+        self.cache_index.get_cached_insertion(file_path, link_title)
+            .and_then(|result| serde_json::to_string(result).ok())
+    }
+
+    /// Cache an insertion result given as JSON. Rejects malformed JSON instead of storing it.
+    pub fn cache_insertion(&mut self, file_path: &str, link_title: &str, result_json: &str) -> Result<(), JsValue> {
+        self.cache_index.cache_insertion_json(file_path, link_title, result_json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid insertion result: {}", e)))
+    }
+
+    /// Cache a typed insertion result
+    pub fn cache_insertion_result(&mut self, file_path: &str, link_title: &str, phrase: Option<String>, reason: String, confidence: f32, model: String) {
+        self.cache_index.cache_insertion(file_path, link_title, InsertionResult {
+            phrase,
+            reason,
+            confidence,
+            created_at: 0,
+            model,
+        });
+    }
+
+    /// Cache the object returned by suggest_insertion_points_batch_with_llm (title -> result or null)
+    /// under the same per-link keys as cache_insertion. Returns the number of entries written.
+    pub fn cache_insertion_batch(&mut self, file_path: &str, results: JsValue, model: String) -> Result<usize, JsValue> {
+        let results: HashMap<String, Option<InsertionResult>> = serde_wasm_bindgen::from_value(results)
+            .map_err(|e| JsValue::from_str(&format!("Invalid insertion results: {}", e)))?;
+        Ok(self.cache_index.cache_insertion_batch(file_path, results, &model))
     }
 
     /// Invalidate insertion cache entries for a specific file
@@ -397,6 +1296,33 @@ impl SmartVault {
         self.cache_index.clear_insertion_cache();
     }
 
+    // --- Rerank Cache ---
+
+    /// Cached rerank_suggestions_with_llm result (RerankResult) for a key from rerank_cache_key,
+    /// or null. Entries older than `max_age_ms` (at most a week) are treated as missing.
+    pub fn get_cached_rerank(&self, key: &str, max_age_ms: Option<f64>) -> Result<JsValue, JsValue> {
+        let now = clock::now_ms() as u64;
+        match self.cache_index.get_cached_rerank(key, max_age_ms.map(|ms| ms as u64), now) {
+            Some(entry) => serde_wasm_bindgen::to_value(&entry.result)
+                .map_err(|e| JsValue::from_str(&format!("Serialization error in get_cached_rerank: {}", e))),
+            None => Ok(JsValue::NULL),
+        }
+    }
+
+    /// Cache a RerankResult, as rerank_suggestions_with_llm returns it, for `source_path`.
+    /// The entry is dropped when the source file is invalidated; entries older than a week, then
+    /// the oldest beyond 500, are evicted.
+    pub fn cache_rerank(&mut self, key: &str, source_path: &str, result: JsValue) -> Result<(), JsValue> {
+        let result: RerankResult = serde_wasm_bindgen::from_value(result)
+            .map_err(|e| JsValue::from_str(&format!("Invalid rerank results: {}", e)))?;
+        self.cache_index.cache_rerank(key, source_path, result, clock::now_ms() as u64);
+        Ok(())
+    }
+
+    pub fn clear_rerank_cache(&mut self) {
+        self.cache_index.clear_rerank_cache();
+    }
+
     // --- Unified Cache Serialization ---
 
     /// Serialize the entire cache index to binary MessagePack format
@@ -406,42 +1332,103 @@ impl SmartVault {
             .map_err(|e| JsValue::from_str(&format!("Cache index serialization error: {}", e)))
     }
 
-    /// Deserialize the cache index from binary MessagePack format
+    /// Deserialize the cache index from binary MessagePack format (versioned or legacy).
+    /// On failure the error is a CacheLoadError object describing both attempts.
     pub fn deserialize_cache_index(&mut self, data: &[u8]) -> Result<(), JsValue> {
-        match VersionedCache::<CacheIndex>::from_msgpack(data) {
-            Ok(versioned) => {
-                console_log!("[DEBUG] Loaded cache index: format={}, version={}",
-                    versioned.header.format, versioned.header.version);
-                self.cache_index = versioned.data;
-                Ok(())
-            }
-            Err(e) => {
-                // Try legacy format (raw CacheIndex without versioning)
-                console_log!("[DEBUG] Attempting legacy cache index format");
-                match rmp_serde::from_slice::<CacheIndex>(data) {
-                    Ok(index) => {
-                        self.cache_index = index;
-                        Ok(())
-                    }
-                    Err(_) => Err(JsValue::from_str(&format!("Cache index deserialization error: {}", e)))
-                }
-            }
+        let callback = self.progress_callback.clone();
+        let mut progress = Progress::new(callback.as_ref(), "deserialize_cache_index", data.len());
+        progress.update(0, || Some(format!("{} bytes", data.len())));
+        let (header, index) = load_versioned_or_legacy::<CacheIndex>(data)?;
+        match header {
+            Some(header) => log_info!("Loaded cache index: format={}, version={}, verified={}",
+                header.format, header.version, header.checksum.is_some()),
+            None => log_info!("Loaded legacy format cache index"),
+        }
+        self.cache_index = index;
+        if let Some(model) = &self.embedding_model {
+            self.cache_index.switch_embedding_space(model);
         }
+        progress.finish(None);
+        Ok(())
+    }
+
+    /// Compare two serialized cache indexes (versioned or legacy) and report per-path
+    /// differences across the embedding/keyword/suggestion maps plus ignored suggestions.
+    pub fn diff_cache_indexes(a: &[u8], b: &[u8]) -> Result<JsValue, JsValue> {
+        let index_a = CacheIndex::from_msgpack_any(a)
+            .map_err(|e| JsValue::from_str(&format!("Cache index A deserialization error: {}", e)))?;
+        let index_b = CacheIndex::from_msgpack_any(b)
+            .map_err(|e| JsValue::from_str(&format!("Cache index B deserialization error: {}", e)))?;
+
+        serde_wasm_bindgen::to_value(&CacheIndex::diff(&index_a, &index_b))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in diff_cache_indexes: {}", e)))
+    }
+
+    /// Merge a serialized cache index (e.g. synced from another device) into this one.
+    /// strategy: "newest_wins" (default), "prefer_other" or "prefer_local". Ignores are always unioned.
+    pub fn merge_cache_index(&mut self, other: &[u8], strategy: Option<String>) -> Result<JsValue, JsValue> {
+        let strategy = match strategy.as_deref() {
+            None => MergeStrategy::NewestWins,
+            Some(s) => MergeStrategy::parse(s)
+                .ok_or_else(|| JsValue::from_str(&format!("Unknown merge strategy: {}", s)))?,
+        };
+        let other_index = CacheIndex::from_msgpack_any(other)
+            .map_err(|e| JsValue::from_str(&format!("Cache index deserialization error: {}", e)))?;
+
+        let summary = self.cache_index.merge(&other_index, strategy);
+        serde_wasm_bindgen::to_value(&summary)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in merge_cache_index: {}", e)))
     }
 
     // --- Content Utilities (Phase 4) ---
 
-    /// Truncate content to a maximum length
+    /// Truncate content to at most `max_length` bytes, without splitting a character or an
+    /// emoji sequence
     pub fn truncate_content(&self, content: &str, max_length: usize) -> String {
-        if content.len() <= max_length {
-            content.to_string()
-        } else {
-            content[..max_length].to_string()
-        }
+        content[..tokens::cut_boundary(content, max_length)].to_string()
     }
 
     // --- Scan Planning (Phase 2) ---
 
+    /// Remember that a full vault scan finished at `now_ms`; stored in the cache index
+    pub fn record_scan_completed(&mut self, now_ms: f64) {
+        self.cache_index.record_scan_completed(normalize_mtime(now_ms));
+    }
+
+    /// When the last full scan finished (ms), or None if none has been recorded
+    pub fn get_last_scan_time(&self) -> Option<f64> {
+        self.cache_index.get_last_scan_time().map(|ms| ms as f64)
+    }
+
+    /// The files in `files_json` (a JSON array of FileInfo objects, like plan_scan takes) that
+    /// need a look after `since_ms`: modified after it, or never embedded and processed. Files
+    /// left out by exclusions or a frontmatter opt-out are only returned when modified.
+    pub fn changed_files_since(&self, files_json: &str, since_ms: f64) -> Result<JsValue, JsValue> {
+        let files: Vec<FileInfo> = match serde_json::from_str(files_json) {
+            Ok(f) => f,
+            Err(e) => {
+                log_error!("changed_files_since: Failed to parse files JSON: {}", e);
+                return Ok(JsValue::NULL);
+            }
+        };
+        let changed: Vec<&FileInfo> = files.iter().filter(|file| self.changed_since(file, since_ms)).collect();
+        serde_wasm_bindgen::to_value(&changed)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in changed_files_since: {}", e)))
+    }
+
+    /// Leave paths matching any of these patterns out of plan_scan and
+    /// count_files_needing_processing: folders ("Templates/") or globs ("*.excalidraw.md",
+    /// "Daily/**"). Matching is case-sensitive. Replaces the previous exclusions.
+    pub fn set_exclusions(&mut self, patterns: Vec<String>) {
+        self.path_filter.set_exclusions(patterns);
+    }
+
+    /// Only scan paths matching one of these patterns (same syntax as set_exclusions); an empty
+    /// list scans everything again
+    pub fn set_inclusions(&mut self, patterns: Vec<String>) {
+        self.path_filter.set_inclusions(patterns);
+    }
+
     /// Plan a vault scan: determine which files need processing and in what order.
     /// Returns a ScanPlan with files sorted optimally (current file first, then by mtime desc).
     ///
@@ -449,28 +1436,144 @@ impl SmartVault {
     /// - files_json: JSON array of FileInfo objects [{path, mtime}, ...]
     /// - current_file: Optional path of the currently open file (will be prioritized)
     /// - check_suggestions: Whether to check if suggestions need regeneration
-    pub fn plan_scan(&self, files_json: &str, current_file: Option<String>, check_suggestions: bool) -> JsValue {
+    /// - since_ms: Optional time of the last scan; only files changed_files_since would return are planned
+    pub fn plan_scan(&self, files_json: &str, current_file: Option<String>, check_suggestions: bool, since_ms: Option<f64>) -> Result<JsValue, JsValue> {
         let files: Vec<FileInfo> = match serde_json::from_str(files_json) {
             Ok(f) => f,
             Err(e) => {
-                web_sys::console::error_1(&format!("[ERROR] plan_scan: Failed to parse files JSON: {}", e).into());
-                return JsValue::NULL;
+                log_error!("plan_scan: Failed to parse files JSON: {}", e);
+                return Ok(JsValue::NULL);
             }
         };
+        let plan = self.scan_plan(files, current_file, check_suggestions, since_ms);
+        serde_wasm_bindgen::to_value(&plan)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in plan_scan: {}", e)))
+    }
+
+    /// Get the number of files that need processing (quick check)
+    pub fn count_files_needing_processing(&self, files_json: &str) -> usize {
+        let files: Vec<FileInfo> = match serde_json::from_str(files_json) {
+            Ok(f) => f,
+            Err(_) => return 0,
+        };
+
+        let now = clock::now_ms() as u64;
+        files.iter().filter(|file| self.path_filter.allows(&file.path) && !self.opted_out.contains_key(&file.path)).filter(|file| {
+            let mtime = normalize_mtime(file.mtime);
+            let has_embedding = self.embeddings.contains_key(&file.path);
+            let embedding_fresh = self.cache_index.is_embedding_fresh(&file.path, mtime);
+            (!has_embedding || !embedding_fresh)
+                && self.cache_index.should_retry(&file.path, ProcessingKind::Embedding, now)
+        }).count()
+    }
+}
+
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+impl SmartVault {
+    /// Chat about the vault with retrieval done here: embed `user_message` with `embedding_model`
+    /// (as a query if the vault was embedded with task prefixes), take the `top_k` (default 5) notes
+    /// at or above `threshold` (default 0.3) similarity, and fit their contents with [Source N]
+    /// markers plus the prior turns (`history`) into `context_window` tokens (default 4096) before
+    /// asking `chat_model`. `options` is a VaultChatOptions object; notes in `exclude_paths` or
+    /// under `exclude_folders` are never used. Returns {answer, sources: [paths of notes included]}.
+    /// The vault stays borrowed until the reply arrives, so don't call mutating methods meanwhile.
+    pub async fn chat_with_vault(
+        &self,
+        endpoint: String,
+        embedding_model: String,
+        chat_model: String,
+        system_prompt: String,
+        user_message: String,
+        options: Option<JsValue>,
+    ) -> Result<JsValue, JsValue> {
+        let mut options = llm::VaultChatOptions::from_js(options, "chat_with_vault")?;
+        let history = options.take_history();
+        let settings = options.request.settings();
+
+        let query_text = match self.embedding_task.and_then(|_| embeddings::task_prefix(&embedding_model, EmbeddingTaskType::Query, None)) {
+            Some(prefix) => format!("{}{}", prefix, user_message),
+            None => user_message.clone(),
+        };
+        let query_embedding = embeddings::fetch_embedding(&endpoint, &embedding_model, query_text, None, &settings)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to embed chat message: {}", e)))?;
+
+        let exclude_paths: HashSet<&str> = options.exclude_paths.iter().map(String::as_str).collect();
+        let exclude_folders: Vec<String> = options.exclude_folders.iter()
+            .map(|folder| folder.trim_matches('/'))
+            .filter(|folder| !folder.is_empty())
+            .map(|folder| format!("{}/", folder))
+            .collect();
+        let is_excluded = |path: &str| exclude_paths.contains(path)
+            || exclude_folders.iter().any(|folder| path.starts_with(folder.as_str()));
+
+        let notes: Vec<ContextNote> = self.similarity_matches(&query_embedding, options.threshold.unwrap_or(DEFAULT_CHAT_THRESHOLD), false)
+            .into_iter()
+            .filter(|m| !is_excluded(&m.path))
+            .filter_map(|m| self.file_contents.get(&m.path).map(|content| ContextNote {
+                title: extract_title_from_path(&m.path),
+                content: content.clone(),
+                path: m.path,
+            }))
+            .take(options.top_k.unwrap_or(DEFAULT_CHAT_TOP_K))
+            .collect();
+
+        let reply = llm::chat_with_context_notes(
+            endpoint, chat_model, system_prompt, user_message, history, &notes, &options.request,
+        ).await?;
+        serde_wasm_bindgen::to_value(&reply)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in chat_with_vault: {}", e)))
+    }
+}
+
+impl SmartVault {
+    /// The plan behind plan_scan for already parsed `files`
+    fn scan_plan(&self, mut files: Vec<FileInfo>, current_file: Option<String>, check_suggestions: bool, since_ms: Option<f64>) -> ScanPlan {
+        if let Some(since_ms) = since_ms {
+            files.retain(|file| self.changed_since(file, since_ms));
+        }
 
         let mut to_process: Vec<FileToProcess> = Vec::new();
-        let mut to_skip: Vec<String> = Vec::new();
+        let mut to_skip: Vec<SkippedFile> = Vec::new();
+        let mut deferred: Vec<String> = Vec::new();
+        let mut local_only: Vec<String> = Vec::new();
+        let mut opted_out = 0;
+        let now = clock::now_ms() as u64;
 
         for file in &files {
-            let mtime = file.mtime as u64;
+            if let Some((reason, pattern)) = self.path_filter.skip_reason(&file.path) {
+                to_skip.push(SkippedFile { path: file.path.clone(), reason, pattern });
+                continue;
+            }
+            if self.opted_out.contains_key(&file.path) {
+                to_skip.push(SkippedFile { path: file.path.clone(), reason: SkipReason::OptedOut, pattern: None });
+                opted_out += 1;
+                continue;
+            }
+            if self.local_embeddings.contains(&file.path) {
+                local_only.push(file.path.clone());
+            }
+
+            let mtime = normalize_mtime(file.mtime);
             let has_embedding = self.embeddings.contains_key(&file.path);
             let embedding_fresh = self.cache_index.is_embedding_fresh(&file.path, mtime);
             let keyword_fresh = self.cache_index.is_keyword_fresh(&file.path, mtime);
             let suggestion_fresh = self.cache_index.is_suggestion_fresh(&file.path, mtime);
 
             let needs_embedding = !has_embedding || !embedding_fresh;
-            let needs_keywords = needs_embedding || !keyword_fresh;
-            let needs_suggestions = check_suggestions && (needs_embedding || !suggestion_fresh);
+            let wants_keywords = needs_embedding || !keyword_fresh;
+            let wants_suggestions = check_suggestions && (needs_embedding || !suggestion_fresh);
+
+            // Files that recently failed are deferred until their backoff expires.
+            // Keywords and suggestions depend on the embedding, so an embedding backoff defers everything.
+            let retry = |kind| self.cache_index.should_retry(&file.path, kind, now);
+            if needs_embedding && !retry(ProcessingKind::Embedding) {
+                deferred.push(file.path.clone());
+                continue;
+            }
+            let needs_keywords = wants_keywords && retry(ProcessingKind::Keyword);
+            let needs_suggestions = wants_suggestions && retry(ProcessingKind::Suggestion);
 
             if needs_embedding || needs_keywords || needs_suggestions {
                 to_process.push(FileToProcess {
@@ -480,25 +1583,27 @@ impl SmartVault {
                     needs_keywords,
                     needs_suggestions,
                 });
+            } else if wants_keywords || wants_suggestions {
+                deferred.push(file.path.clone());
             } else {
-                to_skip.push(file.path.clone());
+                to_skip.push(SkippedFile { path: file.path.clone(), reason: SkipReason::Unchanged, pattern: None });
             }
         }
 
-        // Sort: current file first, then by mtime descending (most recent first)
+        // Sort: current file first, then by mtime descending (most recent first), then path
         let current_file_ref = current_file.as_ref();
         to_process.sort_by(|a, b| {
             // Current file always first
-            let a_is_current = current_file_ref.map_or(false, |cf| &a.path == cf);
-            let b_is_current = current_file_ref.map_or(false, |cf| &b.path == cf);
+            let a_is_current = current_file_ref.is_some_and(|cf| &a.path == cf);
+            let b_is_current = current_file_ref.is_some_and(|cf| &b.path == cf);
 
             if a_is_current && !b_is_current {
                 std::cmp::Ordering::Less
             } else if !a_is_current && b_is_current {
                 std::cmp::Ordering::Greater
             } else {
-                // Then by mtime descending
-                b.mtime.partial_cmp(&a.mtime).unwrap_or(std::cmp::Ordering::Equal)
+                // Then by mtime descending, then path
+                b.mtime.total_cmp(&a.mtime).then_with(|| a.path.cmp(&b.path))
             }
         });
 
@@ -507,28 +1612,293 @@ impl SmartVault {
             to_process.iter().position(|f| &f.path == cf)
         });
 
-        let plan = ScanPlan {
+        ScanPlan {
             to_process,
             to_skip,
             current_file_index,
+            deferred,
+            local_only,
+            opted_out,
+        }
+    }
+
+    /// Remember whether a note opted out; an ignored note loses its stored embeddings and keywords
+    fn record_opt_out(&mut self, path: &str, content: &str) {
+        match frontmatter::note_opt_out(content, &self.opt_out_property) {
+            Some(opt_out) => {
+                if opt_out == OptOut::Ignore {
+                    self.forget_embeddings(path);
+                    self.keywords.remove(path);
+                }
+                self.opted_out.insert(path.to_string(), opt_out);
+            }
+            None => {
+                self.opted_out.remove(path);
+            }
+        }
+    }
+
+    /// Drop a note's vectors from every embedding space
+    fn forget_embeddings(&mut self, path: &str) {
+        self.embeddings.remove(path);
+        self.chunk_embeddings.remove(path);
+        self.local_embeddings.remove(path);
+        for space in self.embedding_spaces.values_mut() {
+            space.embeddings.remove(path);
+            space.chunk_embeddings.remove(path);
+            space.local_embeddings.remove(path);
+        }
+    }
+
+    /// Put `space` in place of the active vectors, returning those
+    fn replace_active_space(&mut self, space: EmbeddingSpace) -> EmbeddingSpace {
+        EmbeddingSpace {
+            embeddings: std::mem::replace(&mut self.embeddings, space.embeddings),
+            chunk_embeddings: std::mem::replace(&mut self.chunk_embeddings, space.chunk_embeddings),
+            local_embeddings: std::mem::replace(&mut self.local_embeddings, space.local_embeddings),
+        }
+    }
+
+    /// Model, dimension and size of every embedding space, the active one first, then by model
+    fn embedding_space_infos(&self) -> Vec<EmbeddingSpaceInfo> {
+        let info = |model: Option<&String>, embeddings: &HashMap<String, Vec<f32>>, active| EmbeddingSpaceInfo {
+            model: model.cloned(),
+            dim: embeddings.values().next().map(Vec::len),
+            count: embeddings.len(),
+            active,
         };
+        let mut inactive: Vec<(&String, &EmbeddingSpace)> = self.embedding_spaces.iter().collect();
+        inactive.sort_by(|a, b| a.0.cmp(b.0));
+        std::iter::once(info(self.embedding_model.as_ref(), &self.embeddings, true))
+            .chain(inactive.into_iter().map(|(model, space)| info(Some(model), &space.embeddings, false)))
+            .collect()
+    }
 
-        serde_wasm_bindgen::to_value(&plan).unwrap_or(JsValue::NULL)
+    /// Whether a file was modified after `since_ms`, or is due for processing but has never been
+    /// embedded (or its embedding was never recorded in the cache index)
+    fn changed_since(&self, file: &FileInfo, since_ms: f64) -> bool {
+        if normalize_mtime(file.mtime) > normalize_mtime(since_ms) {
+            return true;
+        }
+        let scanned = self.path_filter.allows(&file.path) && !self.opted_out.contains_key(&file.path);
+        scanned && (!self.embeddings.contains_key(&file.path) || !self.cache_index.embedding_mtimes.contains_key(&file.path))
     }
 
-    /// Get the number of files that need processing (quick check)
-    pub fn count_files_needing_processing(&self, files_json: &str) -> usize {
-        let files: Vec<FileInfo> = match serde_json::from_str(files_json) {
-            Ok(f) => f,
-            Err(_) => return 0,
+    /// Notes at or above `threshold` similarity to the query, best first, equal scores by path.
+    /// Local and model vectors live in different spaces, so only the query's kind is searched.
+    fn similarity_matches(&self, query_embedding: &[f32], threshold: f32, query_is_local: bool) -> Vec<SimilarityMatch> {
+        let mut matches: Vec<SimilarityMatch> = self.embeddings
+            .iter()
+            .filter(|(p, _)| self.is_local_embedding(p) == query_is_local)
+            .map(|(p, emb)| {
+                let score = cosine_similarity(query_embedding, emb);
+                SimilarityMatch { path: p.clone(), score }
+            })
+            .filter(|m| m.score.is_finite() && m.score >= threshold)
+            .collect();
+
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+        matches
+    }
+
+    /// See explain_suggestion
+    fn suggestion_explanation(&self, source_path: &str, target_path: &str, text: &str, threshold: f32) -> SuggestionExplanation {
+        let query = self.suggestion_query(text, source_path, None);
+        let target_title = extract_title_from_path(target_path);
+        let candidate = self.score_candidate(&query, target_path, &target_title);
+        let query_is_local = self.is_local_embedding(source_path);
+
+        let mut reasons = Vec::new();
+        let similarity = match (self.embeddings.get(source_path), self.embeddings.get(target_path)) {
+            (None, _) => {
+                reasons.push("the source note has no embedding".to_string());
+                None
+            }
+            (_, None) => {
+                reasons.push("the target note has no embedding".to_string());
+                None
+            }
+            (Some(_), Some(_)) if self.is_local_embedding(target_path) != query_is_local => {
+                reasons.push("one note has a local embedding and the other a model one".to_string());
+                None
+            }
+            (Some(query_embedding), Some(embedding)) => Some(self.candidate_similarity(query_embedding, target_path, embedding, query_is_local)),
         };
+        let chunk_match = similarity.is_some_and(|(_, chunk)| chunk);
+        let similarity = similarity.map(|(similarity, _)| similarity);
+        let score = similarity.map(|similarity| candidate.total(similarity));
+        let effective_threshold = threshold * EFFECTIVE_THRESHOLD_FACTOR;
 
-        files.iter().filter(|file| {
-            let mtime = file.mtime as u64;
-            let has_embedding = self.embeddings.contains_key(&file.path);
-            let embedding_fresh = self.cache_index.is_embedding_fresh(&file.path, mtime);
-            !has_embedding || !embedding_fresh
-        }).count()
+        if source_path == target_path {
+            reasons.push("the source and target are the same note".to_string());
+        }
+        match score {
+            Some(score) if !score.is_finite() => reasons.push("the similarity is not a number".to_string()),
+            Some(score) if !candidate.forced && score <= effective_threshold => reasons.push(format!(
+                "the score {:.3} is not above the effective threshold {:.3} and the title isn't in the text", score, effective_threshold,
+            )),
+            _ => {}
+        }
+        let opted_out = self.opted_out.contains_key(target_path);
+        if opted_out {
+            reasons.push("the target note opted out of suggestions".to_string());
+        }
+        let link_exists = query.links_to(target_path, &target_title);
+        if link_exists {
+            reasons.push("the text already links to the target".to_string());
+        }
+        // suggest_links_for_vault drops these too, but link_suggestions doesn't, so they're
+        // reported without counting against would_suggest
+        let ignored = self.cache_index.is_suggestion_ignored(source_path, target_path, clock::now_ms() as u64);
+        let excluded = !self.path_filter.allows(target_path);
+
+        let names = mention_names(target_path, self.file_contents.get(target_path).map(String::as_str), 1);
+        let options = PositionOptions { include_code: false, max_per_keyword: usize::MAX, allow_line_wrap: true };
+        SuggestionExplanation {
+            source_path: source_path.to_string(),
+            target_path: target_path.to_string(),
+            target_title,
+            similarity,
+            chunk_match,
+            language: query.language.clone(),
+            title_matches: potential_link_positions(text, &names, &options),
+            forced: candidate.forced,
+            title_is_stop_word: candidate.title_is_stop_word,
+            title_boost: candidate.title_boost,
+            keyword_matches: candidate.keyword_matches,
+            keyword_boost: candidate.keyword_boost,
+            title_relation: candidate.title_relation,
+            title_relation_boost: candidate.title_relation_boost,
+            score,
+            threshold,
+            effective_threshold,
+            ignored,
+            opted_out,
+            excluded,
+            link_exists,
+            would_suggest: reasons.is_empty(),
+            reasons,
+        }
+    }
+
+    /// What link_suggestions checks every candidate against: the text, the notes it already
+    /// links to and its language
+    fn suggestion_query(&self, text: &str, current_file_path: &str, existing_links: Option<&[String]>) -> SuggestionQuery<'_> {
+        let text_lower = text.to_lowercase();
+        // Words separated by single spaces, so multi-word titles match across line breaks
+        let text_words = text_lower.split_whitespace().collect::<Vec<_>>().join(" ");
+        // Notes the text already links to, by wiki or markdown link: the paths the links resolve
+        // to, and their names, which also rule out other notes sharing an already linked title
+        let mut linked_names = linked_note_names(text);
+        let source = (!current_file_path.is_empty()).then_some(current_file_path);
+        let mut linked_paths: HashSet<&str> = parse_all_links(text, false).iter()
+            .filter(|link| link.kind != LinkKind::Url && !(link.is_embed && is_media_target(&link.target)))
+            .filter_map(|link| self.title_index.resolve(&link.target, source))
+            .collect();
+        for link in existing_links.into_iter().flatten() {
+            let target = link_text_target(link);
+            if target.is_empty() {
+                continue;
+            }
+            linked_paths.extend(self.title_index.resolve(&target, source));
+            add_linked_name(&mut linked_names, &target);
+        }
+
+        // A note named after a function word of the text's language ("the", "de") isn't forced
+        // in wherever that word appears; with the language unknown every title can be
+        let language = self.languages.get(current_file_path).cloned().unwrap_or_else(|| detect_language(text));
+        SuggestionQuery {
+            text_lower,
+            text_words,
+            current_title_lower: extract_title_from_path(current_file_path).to_lowercase(),
+            title_stop_words: language::stop_words(&language).unwrap_or(&[]),
+            language,
+            linked_paths,
+            linked_names,
+        }
+    }
+
+    /// Similarity of a candidate to the query, and whether it came from one of its chunks: long
+    /// notes match on their best chunk instead of the diluted whole-note average
+    fn candidate_similarity(&self, query_embedding: &[f32], path: &str, embedding: &[f32], query_is_local: bool) -> (f32, bool) {
+        let chunks = if query_is_local { None } else { self.chunk_embeddings.get(path) };
+        match chunks {
+            Some(chunks) => {
+                let best = chunks
+                    .iter()
+                    .map(|c| cosine_similarity(query_embedding, &c.embedding))
+                    .fold(f32::MIN, f32::max);
+                (best, true)
+            }
+            None => (cosine_similarity(query_embedding, embedding), false),
+        }
+    }
+
+    /// Boosts link_suggestions adds to a candidate's similarity
+    fn score_candidate(&self, query: &SuggestionQuery, path: &str, note_title: &str) -> CandidateScore {
+        let mut score = CandidateScore::default();
+        let note_title_lower = note_title.to_lowercase();
+
+        // PRIORITY 0: MANDATORY INCLUSION - Exact title match in text (as standalone word/phrase)
+        // If text contains "turbulence" as a standalone word and note is named "turbulence",
+        // ALWAYS include it regardless of embedding similarity
+        let title_words: Vec<&str> = note_title_lower.split_whitespace().collect();
+        let is_single_word_title = title_words.len() == 1;
+
+        if is_single_word_title {
+            // For single-word titles, check for exact word match with word boundaries
+            if contains_whole_word(&query.text_lower, title_words[0]) {
+                if query.title_stop_words.contains(&title_words[0]) {
+                    score.title_is_stop_word = true;
+                } else {
+                    // Exact word match: FORCE INCLUDE + huge boost
+                    score.forced = true;
+                    score.title_boost = 0.50;
+                    log_debug!("MANDATORY: Exact title word '{}' found in text - forcing inclusion", note_title);
+                }
+            }
+        } else {
+            // For multi-word titles, check if full title appears as a phrase, with the same
+            // word boundaries at its edges as single-word titles
+            if contains_whole_word(&query.text_words, &title_words.join(" ")) {
+                // Full phrase match: FORCE INCLUDE + moderate boost
+                score.forced = true;
+                score.title_boost = 0.30;
+                log_debug!("MANDATORY: Full phrase '{}' found in text - forcing inclusion", note_title);
+            }
+        }
+
+        // PRIORITY 2: Boost similarity if document keywords appear in the text
+        if let Some(keywords) = self.keywords.get(path) {
+            score.keyword_matches = keywords.iter()
+                .filter(|keyword| query.text_lower.contains(&keyword.to_lowercase()))
+                .cloned()
+                .collect();
+            // Boost by up to 0.2 based on keyword matches
+            if !score.keyword_matches.is_empty() {
+                score.keyword_boost = (score.keyword_matches.len() as f32 * 0.05).min(0.2);
+            }
+        }
+
+        // PRIORITY 3: Bidirectional title relationship boosting for parent/child topics
+        // Example: "turbulence" <-> "strong turbulence", "weak turbulence"
+        // But with lower boost than exact matches
+        let current_title_lower = &query.current_title_lower;
+
+        // Check if current title is contained in candidate title (parent -> child)
+        // e.g., current="turbulence", candidate="strong turbulence"
+        if note_title_lower.contains(current_title_lower.as_str()) && note_title_lower != *current_title_lower {
+            score.title_relation = Some(TitleRelation::Child);
+            score.title_relation_boost = 0.10;  // Reduced boost for child topics (was 0.15)
+        }
+
+        // Check if candidate title is contained in current title (child -> parent)
+        // e.g., current="strong turbulence", candidate="turbulence"
+        if current_title_lower.contains(&note_title_lower) && note_title_lower != *current_title_lower {
+            score.title_relation = Some(TitleRelation::Parent);
+            score.title_relation_boost = 0.10;  // Reduced boost for parent topics (was 0.15)
+        }
+        score
     }
 }
 
@@ -536,6 +1906,7 @@ impl SmartVault {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FileInfo {
     pub path: String,
+    /// As Obsidian reports it; compared with the cache index after normalize_mtime
     pub mtime: f64,
 }
 
@@ -553,8 +1924,15 @@ pub struct FileToProcess {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ScanPlan {
     pub to_process: Vec<FileToProcess>,
-    pub to_skip: Vec<String>,
+    /// Unchanged files, and files filtered out by set_exclusions/set_inclusions
+    pub to_skip: Vec<SkippedFile>,
     pub current_file_index: Option<usize>,
+    /// Files that need work but are waiting out a retry backoff after failures
+    pub deferred: Vec<String>,
+    /// Files that only have an offline embedding and should be re-embedded once Ollama is back
+    pub local_only: Vec<String>,
+    /// How many files in `to_skip` opted out in their frontmatter
+    pub opted_out: usize,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -563,16 +1941,175 @@ pub struct SimilarityMatch {
     pub score: f32,
 }
 
+/// A folder ranked by how close its notes are to a query note
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FolderSuggestion {
+    pub folder: String,
+    /// Cosine similarity between the query note and the folder's centroid
+    pub score: f32,
+    pub member_count: usize,
+    /// The folder's note most similar to the query
+    pub nearest_member: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct LinkSuggestion {
     pub path: String,
     pub title: String,
     pub similarity: f32,
     pub context: String,
+    /// The note's title appears in the text; kept even when it scores below other candidates
+    pub forced: bool,
+    /// Neither the note's contents nor the document's existing links were given, so a link to
+    /// it may already exist outside the text that was checked
+    pub unverified: bool,
+}
+
+/// What suggest_links_for_vault returns
+#[derive(Serialize, Deserialize, Default)]
+pub struct VaultLinkReport {
+    /// Source path -> its suggestions, highest score first
+    pub suggestions: BTreeMap<String, Vec<LinkSuggestion>>,
+    pub notes_checked: usize,
+    /// Suggestions across all notes
+    pub total: usize,
+    /// Last path checked, to pass as `start_after` for the next page; None when done
+    pub next: Option<String>,
+    /// True if the call was aborted before checking every note of the page; `next` is then
+    /// where to resume (None if no note was checked and there was no `start_after`)
+    pub aborted: bool,
+}
+
+/// Share of the caller's threshold a candidate's boosted score must exceed in link_suggestions
+const EFFECTIVE_THRESHOLD_FACTOR: f32 = 0.85;
+
+/// Threshold explain_suggestion checks against when none is given, as generate_vault_report's
+const DEFAULT_SUGGESTION_THRESHOLD: f32 = 0.7;
+
+/// What link_suggestions checks each candidate against, worked out once per query
+struct SuggestionQuery<'a> {
+    text_lower: String,
+    /// Words separated by single spaces
+    text_words: String,
+    current_title_lower: String,
+    /// detect_language of the text, or the note's stored language
+    language: String,
+    title_stop_words: &'static [&'static str],
+    linked_paths: HashSet<&'a str>,
+    linked_names: HashSet<String>,
+}
+
+impl SuggestionQuery<'_> {
+    /// Whether the text already links to the note at `path`, or to a note of the same name
+    fn links_to(&self, path: &str, note_title: &str) -> bool {
+        let note_path_lower = path.trim_end_matches(".md").to_lowercase();
+        self.linked_paths.contains(path)
+            || self.linked_names.contains(&note_title.to_lowercase())
+            || self.linked_names.contains(&note_path_lower)
+    }
+}
+
+/// How a candidate's title relates to the title of the note links are suggested for
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TitleRelation {
+    /// The candidate's title contains the note's ("Strong turbulence" for "Turbulence")
+    Child,
+    /// The note's title contains the candidate's ("Turbulence" for "Strong turbulence")
+    Parent,
+}
+
+/// Boosts link_suggestions adds to a candidate's similarity
+#[derive(Default)]
+struct CandidateScore {
+    /// The title appears in the text as a whole word or phrase
+    forced: bool,
+    /// A one-word title found in the text that is a stop word of its language, so not forced
+    title_is_stop_word: bool,
+    title_boost: f32,
+    keyword_matches: Vec<String>,
+    keyword_boost: f32,
+    title_relation: Option<TitleRelation>,
+    title_relation_boost: f32,
+}
+
+impl CandidateScore {
+    /// `similarity` with the boosts added, in the order link_suggestions always added them
+    fn total(&self, similarity: f32) -> f32 {
+        similarity + self.title_boost + self.keyword_boost + self.title_relation_boost
+    }
+}
+
+/// Every input to the link suggestion score of a pair of notes, as returned by
+/// `SmartVault::explain_suggestion`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SuggestionExplanation {
+    pub source_path: String,
+    pub target_path: String,
+    pub target_title: String,
+    /// Cosine similarity of the two notes' embeddings; None when either has none
+    pub similarity: Option<f32>,
+    /// The similarity is that of the target's best chunk rather than the whole note
+    pub chunk_match: bool,
+    /// Language of the text; a one-word title that is one of its stop words isn't forced in
+    pub language: String,
+    /// Where the target's title or aliases appear in the text, outside links and code
+    /// (`keyword` is the name found)
+    pub title_matches: Vec<PotentialLinkPosition>,
+    /// The title appears in the text as a whole word or phrase, so the target is suggested
+    /// whatever its score
+    pub forced: bool,
+    pub title_is_stop_word: bool,
+    pub title_boost: f32,
+    /// The target's keywords found in the text
+    pub keyword_matches: Vec<String>,
+    pub keyword_boost: f32,
+    pub title_relation: Option<TitleRelation>,
+    pub title_relation_boost: f32,
+    /// Similarity plus the boosts, as the suggestion's `similarity`; None without a similarity
+    pub score: Option<f32>,
+    pub threshold: f32,
+    /// What a score must exceed unless forced: a share of `threshold`
+    pub effective_threshold: f32,
+    /// The pair was ignored with ignore_suggestion. suggest_links_for_vault leaves it out;
+    /// suggest_links_for_text doesn't, so it doesn't affect `would_suggest`.
+    pub ignored: bool,
+    pub opted_out: bool,
+    /// Left out by the path exclusions. Like `ignored`, only suggest_links_for_vault applies
+    /// this, so it doesn't affect `would_suggest`.
+    pub excluded: bool,
+    pub link_exists: bool,
+    /// Whether suggest_links_for_text would suggest the target for the source, not counting
+    /// the `top_k` cut
+    pub would_suggest: bool,
+    /// Why it wouldn't be; empty when it would
+    pub reasons: Vec<String>,
+}
+
+/// Forced suggestions first (at most `max_forced`), then the best others to fill `top_k`,
+/// each group sorted by score, then path
+fn keep_top_suggestions(suggestions: Vec<LinkSuggestion>, top_k: usize, max_forced: usize) -> Vec<LinkSuggestion> {
+    let by_score = |a: &LinkSuggestion, b: &LinkSuggestion| b.similarity.total_cmp(&a.similarity).then_with(|| a.path.cmp(&b.path));
+    let (mut forced, mut others): (Vec<_>, Vec<_>) = suggestions.into_iter().partition(|s| s.forced);
+    forced.sort_by(by_score);
+    forced.truncate(max_forced);
+    others.sort_by(by_score);
+    others.truncate(top_k.saturating_sub(forced.len()));
+    forced.extend(others);
+    forced
 }
 
+/// Note part of a link as written ("[[folder/Note#Heading|alias]]", "Note^block"), without
+/// the brackets, alias, heading or block
+fn link_text_target(link_text: &str) -> String {
+    let link_text = link_text.trim().trim_start_matches('!').trim_start_matches("[[").trim_end_matches("]]");
+    split_link_target(link_text.split('|').next().unwrap_or_default()).0
+}
+
+/// Cosine similarity of two vectors; 0.0 when their lengths differ, either has no magnitude,
+/// or either holds a NaN or infinity (e.g. from a corrupted cache)
 fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
-    if a.len() != b.len() {
+    if a.len() != b.len() || !a.iter().chain(b).all(|x| x.is_finite()) {
         return 0.0;
     }
 
@@ -584,24 +2121,317 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
         return 0.0;
     }
 
-    dot_product / (magnitude_a * magnitude_b)
+    let similarity = dot_product / (magnitude_a * magnitude_b);
+    // Squares of huge components can overflow to infinity
+    if similarity.is_finite() { similarity } else { 0.0 }
 }
 
-fn extract_title_from_path(path: &str) -> String {
-    path.rsplit('/')
-        .next()
-        .unwrap_or(path)
-        .trim_end_matches(".md")
-        .to_string()
+/// Drawings made with the Excalidraw plugin are notes with a double extension
+const EXCALIDRAW_EXTENSION: &str = ".excalidraw.md";
+
+/// Note title for a vault path: the file name without its folders ('/' or '\\' separated) or
+/// extension ("Drawing.excalidraw.md" -> "Drawing"), trimmed. A trailing ".5" in "Version 1.5"
+/// isn't an extension and stays.
+#[wasm_bindgen]
+pub fn extract_title_from_path(path: &str) -> String {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path).trim();
+    let split = name.len().checked_sub(EXCALIDRAW_EXTENSION.len()).filter(|&at| name.is_char_boundary(at));
+    let stem = match split {
+        Some(at) if at > 0 && name[at..].eq_ignore_ascii_case(EXCALIDRAW_EXTENSION) => &name[..at],
+        _ => match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.trim().is_empty()
+                && (1..=8).contains(&ext.len())
+                && ext.chars().all(|c| c.is_ascii_alphanumeric())
+                && ext.chars().any(|c| c.is_ascii_alphabetic()) => stem,
+            _ => name,
+        },
+    };
+    stem.trim().to_string()
 }
 
+/// Whether `word` (or a phrase) occurs in `text` with no letter or digit (in any script) right
+/// before or after it. Scripts without spaces, like Chinese, need punctuation or spacing around
+/// the word too.
+fn contains_whole_word(text: &str, word: &str) -> bool {
+    if word.is_empty() {
+        return false;
+    }
+    text.match_indices(word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+/// First non-blank lines of a note's body (frontmatter skipped), cut to `max_chars` chars
+/// without splitting an emoji sequence
 fn extract_context(content: &str, max_chars: usize) -> String {
-    let lines: Vec<&str> = content.lines().take(5).collect();
+    let lines: Vec<&str> = note_body(content).lines().filter(|line| !line.trim().is_empty()).take(5).collect();
     let context = lines.join(" ");
 
-    if context.len() > max_chars {
-        format!("{}...", &context[..max_chars])
-    } else {
-        context
+    match context.char_indices().nth(max_chars) {
+        Some((byte, _)) => format!("{}...", &context[..tokens::cut_boundary(&context, byte)]),
+        None => context,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, mtime: f64) -> FileInfo {
+        FileInfo { path: path.to_string(), mtime }
+    }
+
+    fn processed(vault: &mut SmartVault, path: &str, mtime: f64) {
+        vault.set_embedding(path.to_string(), vec![1.0, 0.0]);
+        vault.mark_embedding_processed(path, mtime);
+        vault.mark_keyword_processed(path, mtime);
+        vault.mark_suggestion_processed(path, mtime);
+    }
+
+    #[test]
+    fn cosine_similarity_of_parallel_orthogonal_and_opposite_vectors() {
+        assert!((cosine_similarity(&[1.0, 2.0, 3.0], &[2.0, 4.0, 6.0]) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]), 0.0);
+        assert!((cosine_similarity(&[1.0, -1.0], &[-1.0, 1.0]) + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cosine_similarity_is_zero_for_unusable_vectors() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[1.0, 0.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[], &[]), 0.0);
+        assert_eq!(cosine_similarity(&[f32::NAN, 1.0], &[1.0, 1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[f32::INFINITY, 1.0], &[1.0, 1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[f32::MAX, f32::MAX], &[f32::MAX, f32::MAX]), 0.0);
+    }
+
+    #[test]
+    fn scan_plan_puts_the_current_file_first_then_newest() {
+        let vault = SmartVault::new();
+        let files = vec![file("Old.md", 1.0), file("New.md", 3.0), file("Current.md", 2.0), file("Also new.md", 3.0)];
+        let plan = vault.scan_plan(files, Some("Current.md".to_string()), true, None);
+        let order: Vec<&str> = plan.to_process.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(order, ["Current.md", "Also new.md", "New.md", "Old.md"]);
+        assert_eq!(plan.current_file_index, Some(0));
+        assert!(plan.to_process.iter().all(|f| f.needs_embedding && f.needs_keywords && f.needs_suggestions));
+    }
+
+    #[test]
+    fn scan_plan_skips_unchanged_and_excluded_files() {
+        let mut vault = SmartVault::new();
+        processed(&mut vault, "Done.md", 10.4);
+        processed(&mut vault, "Edited.md", 10.0);
+        vault.set_exclusions(vec!["Templates/".to_string()]);
+        let files = vec![file("Done.md", 10.0), file("Edited.md", 20.0), file("Templates/Daily.md", 5.0)];
+        let plan = vault.scan_plan(files, None, false, None);
+
+        let skipped: Vec<(&str, SkipReason)> = plan.to_skip.iter().map(|f| (f.path.as_str(), f.reason)).collect();
+        assert_eq!(skipped, [("Done.md", SkipReason::Unchanged), ("Templates/Daily.md", SkipReason::Excluded)]);
+        assert_eq!(plan.to_process.len(), 1);
+        assert_eq!(plan.to_process[0].path, "Edited.md");
+        assert!(!plan.to_process[0].needs_suggestions);
+        assert_eq!(plan.current_file_index, None);
+    }
+
+    #[test]
+    fn scan_plan_defers_files_in_backoff() {
+        let mut vault = SmartVault::new();
+        vault.cache_index.record_processing_failure("Failing.md", ProcessingKind::Embedding, clock::now_ms() as u64);
+        let plan = vault.scan_plan(vec![file("Failing.md", 1.0), file("Fresh.md", 1.0)], None, true, None);
+        assert_eq!(plan.deferred, ["Failing.md"]);
+        assert_eq!(plan.to_process.len(), 1);
+    }
+
+    #[test]
+    fn nan_embeddings_are_ignored_and_the_rest_still_suggested() {
+        let mut vault = SmartVault::new();
+        vault.set_embedding("Notes/Apples.md".to_string(), vec![1.0, 0.0]);
+        vault.set_embedding("Notes/Broken.md".to_string(), vec![f32::NAN, 1.0]);
+        vault.set_embedding("Notes/Pears.md".to_string(), vec![0.9, 0.1]);
+
+        let suggestions = vault.link_suggestions("Some text", &[1.0, 0.0], 0.5, "Current.md", 5, false, 5, None);
+        let paths: Vec<&str> = suggestions.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(paths, ["Notes/Apples.md", "Notes/Pears.md"]);
+        assert!(suggestions.iter().all(|s| s.similarity.is_finite()));
+
+        let matches = vault.similarity_matches(&[1.0, 0.0], 0.0, false);
+        assert!(matches.iter().all(|m| m.score.is_finite()));
+        assert_eq!(matches[0].path, "Notes/Apples.md");
+
+        let nan_query = vault.link_suggestions("Some text", &[f32::NAN, 0.0], 0.5, "Current.md", 5, false, 5, None);
+        assert!(nan_query.is_empty());
+    }
+
+    #[test]
+    fn notes_linked_through_a_heading_and_alias_are_not_suggested_again() {
+        let mut vault = SmartVault::new();
+        vault.set_embedding("Notes/Apples.md".to_string(), vec![1.0, 0.0]);
+        vault.set_embedding("Notes/Pears.md".to_string(), vec![0.9, 0.1]);
+
+        let text = "See [[Apples#Varieties|the apple list]] and [[#Intro]].";
+        let suggestions = vault.link_suggestions(text, &[1.0, 0.0], 0.5, "Current.md", 5, false, 5, None);
+        let paths: Vec<&str> = suggestions.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(paths, ["Notes/Pears.md"]);
+    }
+
+    #[test]
+    fn every_link_form_marks_its_note_as_linked() {
+        let mut vault = SmartVault::new();
+        for path in ["Apples.md", "Plums.md", "Figs.md", "Fruit/Lemons.md", "Cherries.md"] {
+            vault.add_file(path.to_string(), "Some fruit.".to_string());
+            vault.set_embedding(path.to_string(), vec![1.0, 0.0]);
+        }
+        let suggested = |text: &str| {
+            let mut paths: Vec<String> = vault.link_suggestions(text, &[1.0, 0.0], 0.5, "Current.md", 10, false, 10, None)
+                .into_iter()
+                .map(|s| s.path)
+                .collect();
+            paths.sort();
+            paths
+        };
+
+        let cases = [
+            ("See [[Apples|the red ones]].", "Apples.md"),
+            ("See [[Plums#Varieties]].", "Plums.md"),
+            ("![[Figs]]", "Figs.md"),
+            ("See [[Fruit/Lemons]].", "Fruit/Lemons.md"),
+            ("See [Cherries](Cherries.md).", "Cherries.md"),
+        ];
+        for (text, linked) in cases {
+            let paths = suggested(text);
+            assert!(!paths.iter().any(|p| p == linked), "{} still suggested for {:?}", linked, text);
+            assert_eq!(paths.len(), 4, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn an_alias_equal_to_another_title_does_not_hide_its_note() {
+        let mut vault = SmartVault::new();
+        vault.add_file("Pears.md".to_string(), "Pears.".to_string());
+        vault.add_file("Quince.md".to_string(), "---\naliases: [Pears]\n---\nQuince.".to_string());
+        for path in ["Pears.md", "Quince.md"] {
+            vault.set_embedding(path.to_string(), vec![1.0, 0.0]);
+        }
+        let suggested = |text: &str| {
+            vault.link_suggestions(text, &[1.0, 0.0], 0.5, "Current.md", 10, false, 10, None)
+                .into_iter()
+                .map(|s| s.path)
+                .collect::<Vec<_>>()
+        };
+
+        // [[Pears]] resolves to the note named Pears, not to the one with that alias
+        assert_eq!(suggested("See [[Pears]]."), ["Quince.md"]);
+        assert_eq!(suggested("See [[Quince]]."), ["Pears.md"]);
+    }
+
+    fn suggestion(path: &str, similarity: f32, forced: bool) -> LinkSuggestion {
+        LinkSuggestion {
+            path: path.to_string(),
+            title: extract_title_from_path(path),
+            similarity,
+            context: String::new(),
+            forced,
+            unverified: false,
+        }
+    }
+
+    #[test]
+    fn keep_top_suggestions_keeps_forced_matches_ahead_of_better_scores() {
+        let candidates = || {
+            let forced = (0..5).map(|i| suggestion(&format!("Forced {}.md", i), 0.1 + i as f32 * 0.05, true));
+            let related = (0..10).map(|i| suggestion(&format!("Related {}.md", i), 0.9 + i as f32 * 0.005, false));
+            forced.chain(related).collect::<Vec<_>>()
+        };
+        let paths = |kept: Vec<LinkSuggestion>| kept.into_iter().map(|s| s.path).collect::<Vec<_>>();
+
+        assert_eq!(paths(keep_top_suggestions(candidates(), 3, 3)), ["Forced 4.md", "Forced 3.md", "Forced 2.md"]);
+        assert_eq!(paths(keep_top_suggestions(candidates(), 3, 5)).len(), 5);
+        assert_eq!(paths(keep_top_suggestions(candidates(), 3, 1)), ["Forced 4.md", "Related 9.md", "Related 8.md"]);
+        assert_eq!(paths(keep_top_suggestions(candidates(), 3, 0)), ["Related 9.md", "Related 8.md", "Related 7.md"]);
+    }
+
+    #[test]
+    fn contains_whole_word_needs_non_letter_neighbours_in_cjk() {
+        assert!(contains_whole_word("我喜欢「東京」的夜景", "東京"));
+        assert!(contains_whole_word("東京。", "東京"));
+        assert!(contains_whole_word("see 東京 tonight", "東京"));
+        assert!(!contains_whole_word("我喜欢東京的夜景", "東京"));
+        assert!(!contains_whole_word("東京都", "東京"));
+    }
+
+    #[test]
+    fn contains_whole_word_treats_accents_and_cyrillic_as_letters() {
+        assert!(contains_whole_word("meet at the café, then home", "café"));
+        assert!(contains_whole_word("café", "café"));
+        assert!(!contains_whole_word("cafés nearby", "café"));
+        assert!(!contains_whole_word("décafé", "café"));
+        assert!(!contains_whole_word("the cafe", "café"));
+
+        assert!(contains_whole_word("заметка о москве.", "москве"));
+        assert!(!contains_whole_word("подмосквье", "москв"));
+        assert!(!contains_whole_word("москвею", "москве"));
+        assert!(!contains_whole_word("anything", ""));
+    }
+
+    #[test]
+    fn single_word_titles_are_forced_only_as_whole_words() {
+        let mut vault = SmartVault::new();
+        for path in ["Café.md", "東京.md", "Москва.md"] {
+            vault.set_embedding(path.to_string(), vec![0.0, 1.0]);
+        }
+        let forced = |text: &str| {
+            let mut paths: Vec<String> = vault.link_suggestions(text, &[1.0, 0.0], 0.9, "Current.md", 10, false, 10, None)
+                .into_iter()
+                .filter(|s| s.forced)
+                .map(|s| s.path)
+                .collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(forced("Lunch at the Café, then 「東京」 and Москва."), ["Café.md", "Москва.md", "東京.md"]);
+        assert!(forced("Decafé in 東京都 near Москвабад").is_empty());
+    }
+
+    #[test]
+    fn multi_word_titles_match_whole_phrases_across_lines() {
+        let mut vault = SmartVault::new();
+        vault.set_embedding("Art History.md".to_string(), vec![0.0, 1.0]);
+        let forced = |text: &str| vault.link_suggestions(text, &[1.0, 0.0], 0.9, "Current.md", 10, false, 10, None)
+            .iter()
+            .any(|s| s.forced && s.path == "Art History.md");
+
+        assert!(forced("Art history starts here.\nMore later."));
+        assert!(forced("# Notes\nart history"));
+        assert!(forced("I studied art\nhistory today"));
+        assert!(forced("I studied art  \t\n  history today"));
+        assert!(!forced("A smart historyography of things"));
+        assert!(!forced("We start history class at noon"));
+        assert!(!forced("art histories"));
+    }
+
+    #[test]
+    fn identical_embeddings_rank_the_same_on_every_call() {
+        let paths = ["Notes/Echo.md", "Notes/Alpha.md", "Notes/Delta.md", "Notes/Charlie.md", "Notes/Bravo.md"];
+        let ranked = || {
+            let mut vault = SmartVault::new();
+            for path in paths {
+                vault.set_embedding(path.to_string(), vec![0.6, 0.8]);
+            }
+            let suggestions = vault.link_suggestions("Unrelated text", &[0.6, 0.8], 0.5, "Current.md", 10, false, 10, None);
+            let matches = vault.similarity_matches(&[0.6, 0.8], 0.5, false);
+            (serde_json::to_string(&suggestions).unwrap(), serde_json::to_string(&matches).unwrap())
+        };
+
+        let first = ranked();
+        for _ in 0..10 {
+            assert_eq!(ranked(), first);
+        }
+        let mut sorted = paths;
+        sorted.sort();
+        let order: Vec<SimilarityMatch> = serde_json::from_str(&first.1).unwrap();
+        assert!(order.iter().map(|m| m.path.as_str()).eq(sorted));
     }
 }
@@ -1,17 +1,187 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::OnceLock;
+use crate::frontmatter::{frontmatter_tags, split_frontmatter};
+use crate::masking::terms_pattern;
+use crate::graph::link_key;
+use crate::offsets::OffsetCounter;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DetectedLink {
     pub source: String,
+    /// Linked note, without any heading or block reference; for same-file links ([[#Heading]])
+    /// this is `source`
     pub target: String,
+    pub heading: Option<String>,
+    /// Block id without the '^'
+    pub block_ref: Option<String>,
+    /// A transclusion (![[Note]]) rather than a plain link
+    pub is_embed: bool,
     pub line: usize,
+    /// Byte and UTF-16 offsets of the whole link in the content; use the UTF-16 ones with the
+    /// editor API
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+}
+
+/// Position and parts of a [[wiki link]], as returned by `LinkAnalyzer::extract_links_detailed`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WikiLinkSpan {
+    /// 0-based line
+    pub line: usize,
+    /// Char offset of the link within its line
+    pub column: usize,
+    /// Char offsets of the whole "[[...]]" (including a leading '!') in the content
+    pub start: usize,
+    pub end: usize,
+    /// The same range in bytes and UTF-16 units (see `offsets`)
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+    /// Text between the brackets, as written
+    pub inner: String,
+    pub target: String,
+    pub heading: Option<String>,
+    pub block_ref: Option<String>,
+    pub alias: Option<String>,
+    pub is_embed: bool,
+}
+
+/// A tag used in a note. Tags compare without case; `tag` is the first spelling found.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NoteTag {
+    /// Without the '#'; nested tags keep their '/' ("project/active")
+    pub tag: String,
+    /// Uses in the body plus one if the frontmatter declares it
+    pub count: usize,
+    pub in_body: bool,
+    pub in_frontmatter: bool,
+}
+
+/// A tag's use across the vault, as returned by `SmartVault::get_all_tags`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VaultTag {
+    pub tag: String,
+    /// Sum of the per-note counts
+    pub count: usize,
+    /// Notes using the tag
+    pub notes: usize,
+}
+
+/// Text around one or more nearby links or mentions, for previews. Offsets are chars unless
+/// named otherwise.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ContextSnippet {
+    /// 0-based line of the first link in the snippet
+    pub line: usize,
+    /// Range of `snippet` in the note
+    pub start: usize,
+    pub end: usize,
+    /// The same range in bytes and UTF-16 units (see `offsets`)
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+    pub snippet: String,
+    /// Ranges of the links within `snippet`
+    pub highlights: Vec<(usize, usize)>,
+    /// The same ranges in bytes and UTF-16 units, from the start of `snippet`
+    pub highlights_bytes: Vec<(usize, usize)>,
+    pub highlights_utf16: Vec<(usize, usize)>,
+    /// The snippet was cut mid-sentence at its start or end (show an ellipsis there)
+    pub truncated_start: bool,
+    pub truncated_end: bool,
+}
+
+/// How a keyword matched at a `PotentialLinkPosition`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeywordMatchKind {
+    /// The whole keyword
+    Exact,
+    /// The longest run of the keyword's leading words (at least two) found in the text
+    Prefix,
+}
+
+/// Where a keyword could become a link. Columns are within `line`; `length`s cover the matched
+/// text, which may run onto `end_line` when line wraps are allowed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PotentialLinkPosition {
+    /// 1-based
+    pub line: usize,
+    pub end_line: usize,
+    /// Chars before the match in its line
+    pub column: usize,
+    pub byte_column: usize,
+    /// UTF-16 units (see `offsets`)
+    pub utf16_column: usize,
+    pub length: usize,
+    pub utf16_length: usize,
+    /// Byte and UTF-16 offsets of the match in the content
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+    /// The keyword as given (lowercased)
+    pub keyword: String,
+    pub match_kind: KeywordMatchKind,
+    pub matched_text: String,
+    /// The trimmed line the match starts on
+    pub context: String,
+}
+
+pub struct PositionOptions {
+    pub include_code: bool,
+    pub max_per_keyword: usize,
+    pub allow_line_wrap: bool,
+}
+
+/// Why `insert_wiki_link` left the content unchanged
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkInsertionError {
+    EmptyPhrase,
+    /// No (or fewer than `occurrence`) whole-word matches outside links, code and frontmatter
+    NotFound,
+    /// `occurrence` was 0 but the phrase appears more than once
+    Ambiguous,
+}
+
+/// Result of `LinkAnalyzer::insert_wiki_link`. On failure `content` is the input unchanged.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LinkInsertion {
+    pub success: bool,
+    pub content: String,
+    /// Char range of the inserted link in the new content
+    pub start: usize,
+    pub end: usize,
+    /// The same range in bytes and UTF-16 units (see `offsets`); the replaced text starts at the
+    /// same offsets in the old content
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+    /// Text that was replaced, as written in the note
+    pub original_text: String,
+    pub link: String,
+    /// Matches of the phrase that could be linked
+    pub occurrences: usize,
+    pub error: Option<LinkInsertionError>,
 }
 
 #[wasm_bindgen]
 pub struct LinkAnalyzer;
 
+impl Default for LinkAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[wasm_bindgen]
 impl LinkAnalyzer {
     #[wasm_bindgen(constructor)]
@@ -19,101 +189,1444 @@ impl LinkAnalyzer {
         LinkAnalyzer
     }
 
-    pub fn extract_links(&self, content: &str) -> JsValue {
-        let mut links = Vec::new();
+    /// A `DetectedLink` for every link to a note (wiki or markdown), outside the frontmatter and
+    /// (unless `include_code`) code. Embedded notes count as links; embedded media (images, audio,
+    /// video, PDFs) don't. `source` is the path of the note `content` belongs to, if known.
+    pub fn extract_links(&self, content: &str, source: Option<String>, include_code: Option<bool>) -> Result<JsValue, JsValue> {
+        let source = source.unwrap_or_default();
+        let links: Vec<DetectedLink> = parse_all_links(content, include_code.unwrap_or(false))
+            .into_iter()
+            .filter(|link| link.kind != LinkKind::Url && !(link.is_embed && is_media_target(&link.target)))
+            .map(|link| DetectedLink {
+                source: source.clone(),
+                target: if link.target.is_empty() { source.clone() } else { link.target },
+                heading: link.heading,
+                block_ref: link.block_ref,
+                is_embed: link.is_embed,
+                line: link.line,
+                offset_bytes: link.offset_bytes,
+                end_bytes: link.end_bytes,
+                offset_utf16: link.offset_utf16,
+                end_utf16: link.end_utf16,
+            })
+            .collect();
 
-        for (line_num, line) in content.lines().enumerate() {
-            let mut chars = line.chars().peekable();
-            let mut current_pos = 0;
+        serde_wasm_bindgen::to_value(&links)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in extract_links: {}", e)))
+    }
 
-            while current_pos < line.len() {
-                if let Some(link) = self.try_parse_wiki_link(&mut chars, line, &mut current_pos) {
-                    links.push((link, line_num + 1));
-                }
-                if chars.peek().is_some() {
-                    chars.next();
-                    current_pos += 1;
+    /// Every [[wiki link]] outside the frontmatter and code with its exact position, for highlighting or editing it:
+    /// a `WikiLinkSpan` per link, with a 0-based line and char offsets
+    pub fn extract_links_detailed(&self, content: &str) -> Result<JsValue, JsValue> {
+        let spans: Vec<WikiLinkSpan> = parse_all_links(content, false)
+            .into_iter()
+            .filter(|link| link.kind == LinkKind::Wiki)
+            .map(|link| WikiLinkSpan {
+                line: link.line - 1,
+                column: link.column,
+                start: link.start,
+                end: link.end,
+                offset_bytes: link.offset_bytes,
+                end_bytes: link.end_bytes,
+                offset_utf16: link.offset_utf16,
+                end_utf16: link.end_utf16,
+                inner: link.raw.trim_start_matches('!')
+                    .strip_prefix("[[")
+                    .and_then(|inner| inner.strip_suffix("]]"))
+                    .unwrap_or_default()
+                    .to_string(),
+                target: link.target,
+                heading: link.heading,
+                block_ref: link.block_ref,
+                alias: link.display_text,
+                is_embed: link.is_embed,
+            })
+            .collect();
+
+        serde_wasm_bindgen::to_value(&spans)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in extract_links_detailed: {}", e)))
+    }
+
+    /// Wiki links, markdown links and bare URLs outside the frontmatter and code, as `ExtractedLink`s
+    pub fn extract_all_links(&self, content: &str) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&parse_all_links(content, false))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in extract_all_links: {}", e)))
+    }
+
+    /// Every whole-word, case-insensitive occurrence of each keyword (see `potential_link_positions`),
+    /// skipping frontmatter, existing [[links]] and (unless `include_code`) code, at most
+    /// `max_per_keyword` per keyword. With `allow_line_wrap` a phrase may continue on the next line.
+    pub fn find_potential_link_positions(&self, content: &str, keywords: Vec<String>, include_code: Option<bool>, max_per_keyword: Option<usize>, allow_line_wrap: Option<bool>) -> Result<JsValue, JsValue> {
+        let options = PositionOptions {
+            include_code: include_code.unwrap_or(false),
+            max_per_keyword: max_per_keyword.unwrap_or(usize::MAX),
+            allow_line_wrap: allow_line_wrap.unwrap_or(false),
+        };
+        serde_wasm_bindgen::to_value(&potential_link_positions(content, &keywords, &options))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in find_potential_link_positions: {}", e)))
+    }
+
+    /// Footnote markers ([^label]) outside code, each a reference or the start of a definition,
+    /// as `FootnoteMarker`s
+    pub fn extract_footnote_markers(&self, content: &str) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&parse_footnotes(content))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in extract_footnote_markers: {}", e)))
+    }
+
+    /// Footnote definitions as an object of label -> text
+    pub fn extract_footnotes(&self, content: &str) -> Result<JsValue, JsValue> {
+        let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+        footnote_definitions(content).serialize(&serializer)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in extract_footnotes: {}", e)))
+    }
+
+    /// Tags used in a note, from its frontmatter and inline #tags outside code, as `NoteTag`s
+    pub fn extract_tags(&self, content: &str) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&parse_tags(content))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in extract_tags: {}", e)))
+    }
+
+    /// Snippets of about `window_chars` chars around every link to `target_title` (wiki or
+    /// markdown, with or without an alias, heading or block reference, embeds included), as
+    /// `ContextSnippet`s. Nearby links share one snippet.
+    pub fn extract_link_contexts(&self, content: &str, target_title: &str, window_chars: usize) -> Result<JsValue, JsValue> {
+        let target = link_key(target_title);
+        let spans: Vec<(usize, usize)> = find_links(content, false).into_iter()
+            .filter(|(_, _, link)| link.kind != LinkKind::Url && !link.target.is_empty())
+            .filter(|(_, _, link)| {
+                let key = link_key(&link.target);
+                key == target || key.rsplit('/').next() == Some(target.as_str())
+            })
+            .map(|(start, end, _)| (start, end))
+            .collect();
+        serde_wasm_bindgen::to_value(&context_snippets(content, &spans, window_chars))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in extract_link_contexts: {}", e)))
+    }
+
+    /// Replace one plain-text occurrence of `phrase` (whole word, any case, outside links, code
+    /// and frontmatter) with a link to `target_title`: `[[Target|original text]]`, or `[[Target]]`
+    /// when the text already reads as the title (ignoring case unless `preserve_case`).
+    /// `occurrence` is 1-based; 0 links the phrase only if it occurs exactly once. Returns a
+    /// `LinkInsertion`, with `error` set instead of guessing when the match isn't certain.
+    pub fn insert_wiki_link(&self, content: &str, phrase: &str, target_title: &str, occurrence: usize, preserve_case: bool) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&link_phrase(content, phrase, target_title, occurrence, preserve_case))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in insert_wiki_link: {}", e)))
+    }
+
+    /// Point wiki links to `old_title` at `new_title`: `[[Old]]`, `[[Folder/Old]]`,
+    /// `[[Old|alias]]`, `[[Old#Heading]]`, `[[Old#^block]]` and `![[Old]]`, keeping folders,
+    /// headings, block references and aliases. Links in code are left alone, and so are links
+    /// whose name differs from `new_title` only by case. Returns `{content, rewrites}`.
+    pub fn rewrite_links(&self, content: &str, old_title: &str, new_title: &str) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&rewrite_note_links(content, old_title, new_title))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in rewrite_links: {}", e)))
+    }
+
+    fn is_inside_link(&self, line: &str, pos: usize) -> bool {
+        let before = &line[..pos];
+        let after = &line[pos..];
+
+        let open_brackets = before.rfind("[[");
+        let close_brackets = before.rfind("]]");
+
+        match (open_brackets, close_brackets) {
+            (Some(open), Some(close)) => open > close && after.contains("]]"),
+            (Some(_), None) => after.contains("]]"),
+            _ => false,
+        }
+    }
+}
+
+/// A [[wiki link]] found in text. `start`/`end` are the byte range of the whole link.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WikiLink {
+    pub start: usize,
+    pub end: usize,
+    /// Linked note (text before any '#' or '|'); empty for same-file links like [[#Heading]]
+    pub target: String,
+    /// "Section" in [[Note#Section]]; nested headings keep their '#' ("H1#H2")
+    pub heading: Option<String>,
+    /// "id" in [[Note#^id]]
+    pub block_ref: Option<String>,
+    pub alias: Option<String>,
+    /// Preceded by '!' (an embed such as ![[image.png]])
+    pub embed: bool,
+    /// Size of an embed ("300" in ![[image.png|300]], "640x480"), which is not an alias
+    pub embed_size: Option<String>,
+}
+
+/// "300" or "640x480"
+fn is_embed_size(text: &str) -> bool {
+    let mut parts = text.split('x');
+    let width = parts.next().unwrap_or_default();
+    let height = parts.next();
+    !width.is_empty()
+        && width.chars().all(|c| c.is_ascii_digit())
+        && height.is_none_or(|h| !h.is_empty() && h.chars().all(|c| c.is_ascii_digit()))
+        && parts.next().is_none()
+}
+
+/// File extensions Obsidian embeds as media rather than as a note
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "bmp", "svg", "webp", "avif",
+    "mp3", "wav", "m4a", "ogg", "flac", "3gp", "webm", "mp4", "mkv", "mov", "ogv", "pdf",
+];
+
+/// Whether a link target is an image, audio, video or PDF file (by extension)
+pub fn is_media_target(target: &str) -> bool {
+    target.rsplit_once('.')
+        .is_some_and(|(_, ext)| MEDIA_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Split "Note#Heading", "Note#^block" or "#Heading" into the note (trimmed, empty for the
+/// same file), heading and block id
+pub fn split_link_target(target: &str) -> (String, Option<String>, Option<String>) {
+    let Some((note, reference)) = target.split_once('#') else {
+        return (target.trim().to_string(), None, None);
+    };
+    let mut sections: Vec<&str> = reference.split('#').map(str::trim).collect();
+    let block_ref = sections.last()
+        .and_then(|last| last.strip_prefix('^'))
+        .map(|id| id.trim().to_string());
+    if block_ref.is_some() {
+        sections.pop();
+    }
+    let heading = sections.join("#");
+    (
+        note.trim().to_string(),
+        (!heading.is_empty()).then_some(heading),
+        block_ref.filter(|id| !id.is_empty()),
+    )
+}
+
+/// All closed [[...]] links in `text`, in order. An unclosed "[[" ends the search.
+pub fn parse_wiki_links(text: &str) -> Vec<WikiLink> {
+    let mut links = Vec::new();
+    let mut cursor = 0;
+
+    while let Some(open) = text[cursor..].find("[[").map(|i| cursor + i) {
+        let Some(close) = text[open + 2..].find("]]").map(|i| open + 2 + i) else {
+            break;
+        };
+        let inner = &text[open + 2..close];
+        let (target, mut alias) = match inner.split_once('|') {
+            Some((target, alias)) => (target, Some(alias.to_string())),
+            None => (inner, None),
+        };
+        let embed = text[..open].ends_with('!');
+        // ![[image.png|300]] and ![[image.png|caption|300]] end in a size, not an alias
+        let mut embed_size = None;
+        if let Some(params) = alias.as_deref().filter(|_| embed) {
+            let (rest, last) = params.rsplit_once('|').map_or((None, params), |(rest, last)| (Some(rest), last));
+            if is_embed_size(last.trim()) {
+                embed_size = Some(last.trim().to_string());
+                alias = rest.map(str::to_string);
+            }
+        }
+        let (target, heading, block_ref) = split_link_target(target);
+        links.push(WikiLink {
+            start: if embed { open - 1 } else { open },
+            end: close + 2,
+            target,
+            heading,
+            block_ref,
+            alias,
+            embed,
+            embed_size,
+        });
+        cursor = close + 2;
+    }
+
+    links
+}
+
+/// What kind of link an `ExtractedLink` is
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    /// [[Note]] or [[Note|text]]
+    Wiki,
+    /// [text](Note%20Name.md)
+    Markdown,
+    /// A bare URL, or a markdown link to one
+    Url,
+}
+
+/// A link found by `parse_all_links`. `start`/`end` are char offsets of the whole link in the content.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExtractedLink {
+    pub kind: LinkKind,
+    /// Note path or title (markdown targets URL-decoded, without anchor or ".md"), or the URL.
+    /// Empty for links within the same note ([[#Heading]], [text](#Heading)).
+    pub target: String,
+    pub heading: Option<String>,
+    pub block_ref: Option<String>,
+    pub display_text: Option<String>,
+    /// ![[...]] or ![...](...): the target is shown inline
+    pub is_embed: bool,
+    /// Size suffix of an embed ("300" in ![[image.png|300]])
+    pub embed_size: Option<String>,
+    /// Label of the definition a reference-style link ([text][label]) was resolved through
+    pub reference: Option<String>,
+    /// The whole link as written, e.g. "![[Note#Part|text]]"
+    pub raw: String,
+    /// 1-based
+    pub line: usize,
+    /// Char offset of the link within its line
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+    /// The same range in bytes and UTF-16 units (see `offsets`)
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+}
+
+/// [text](target) or [text](<target with spaces>), with an optional "title"
+const MARKDOWN_LINK_PATTERN: &str = r#"!?\[([^\]\n]*)\]\(\s*(<[^>\n]*>|[^)\s]+)(?:\s+"[^"\n]*")?\s*\)"#;
+
+/// Bare URLs, not counting trailing punctuation
+const BARE_URL_PATTERN: &str = r#"https?://[^\s<>\[\]()"]*[^\s<>\[\]()".,;:!?']"#;
+
+/// A reference-style link definition line: `[label]: destination "optional title"`
+const LINK_DEFINITION_PATTERN: &str = r#"(?m)^ {0,3}\[([^\]\n^][^\]\n]*)\]:[ \t]*(<[^>\n]*>|\S+)(?:[ \t]+(?:"[^"\n]*"|'[^'\n]*'|\([^)\n]*\)))?[ \t]*$"#;
+
+/// Reference-style link uses: [text][label], [label][] and [label]
+const REFERENCE_LINK_PATTERN: &str = r"!?\[([^\]\n]+)\](?:\[([^\]\n]*)\])?";
+
+/// A footnote marker ([^label]); followed by ':' at the start of a line it begins the definition
+const FOOTNOTE_PATTERN: &str = r"\[\^([^\]\s]+)\](:?)";
+
+fn footnote_regex() -> &'static regex::Regex {
+    static FOOTNOTE: OnceLock<regex::Regex> = OnceLock::new();
+    FOOTNOTE.get_or_init(|| regex::Regex::new(FOOTNOTE_PATTERN).expect("built-in footnote pattern is valid"))
+}
+
+/// Whether a footnote marker uses a footnote or starts its definition
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FootnoteKind {
+    Reference,
+    Definition,
+}
+
+/// A footnote marker found by `parse_footnotes`. Offsets are chars of the marker (`[^1]`, or
+/// `[^1]:` for a definition).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FootnoteMarker {
+    pub label: String,
+    pub kind: FootnoteKind,
+    /// 1-based
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+    /// The same range in bytes and UTF-16 units (see `offsets`)
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+}
+
+/// Link definition labels match ignoring case and whitespace differences
+fn reference_label_key(label: &str) -> String {
+    label.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Byte ranges of link definition lines and footnote markers in the body, outside code, and
+/// the definitions by label key (destination as written). Definition lines in code are ignored.
+fn reference_ranges(content: &str) -> (Vec<(usize, usize)>, HashMap<String, String>) {
+    let body_start = split_frontmatter(content).1;
+    let code = code_ranges(content, body_start);
+    let outside_code = |start: usize, end: usize| start >= body_start && !code.iter().any(|&(s, e)| start < e && s < end);
+
+    let mut ranges = Vec::new();
+    let mut definitions = HashMap::new();
+    static DEFINITION: OnceLock<regex::Regex> = OnceLock::new();
+    let definition = DEFINITION.get_or_init(|| regex::Regex::new(LINK_DEFINITION_PATTERN).expect("built-in definition pattern is valid"));
+    for caps in definition.captures_iter(content) {
+        let whole = caps.get(0).expect("group 0 always matches");
+        if outside_code(whole.start(), whole.end()) {
+            ranges.push((whole.start(), whole.end()));
+            let destination = caps[2].trim_start_matches('<').trim_end_matches('>').trim().to_string();
+            // The first definition of a label wins, as in CommonMark
+            definitions.entry(reference_label_key(&caps[1])).or_insert(destination);
+        }
+    }
+    let footnote = footnote_regex();
+    for m in footnote.find_iter(content) {
+        if outside_code(m.start(), m.end()) && !ranges.iter().any(|&(s, e)| m.start() < e && s < m.end()) {
+            ranges.push((m.start(), m.end()));
+        }
+    }
+    ranges.sort_unstable();
+    (ranges, definitions)
+}
+
+/// Footnote markers outside the frontmatter and code, in document order. A marker followed by
+/// ':' at the start of a line (up to three spaces in) is a definition, any other a reference.
+pub fn parse_footnotes(content: &str) -> Vec<FootnoteMarker> {
+    let footnote = footnote_regex();
+    let (ranges, _) = reference_ranges(content);
+    let mut markers = Vec::new();
+    let mut offsets = OffsetCounter::new(content);
+    let (mut byte, mut line, mut line_start) = (0usize, 1usize, 0usize);
+    for caps in footnote.captures_iter(content) {
+        let whole = caps.get(0).expect("group 0 always matches");
+        if !ranges.contains(&(whole.start(), whole.end())) {
+            continue;
+        }
+        let before_on_line = &content[content[..whole.start()].rfind('\n').map_or(0, |i| i + 1)..whole.start()];
+        let is_definition = !caps[2].is_empty() && before_on_line.len() <= 3 && before_on_line.trim().is_empty();
+        let end = if is_definition { whole.end() } else { caps.get(1).map_or(whole.end(), |m| m.end() + 1) };
+
+        let between = &content[byte..whole.start()];
+        if let Some(newline) = between.rfind('\n') {
+            line += between.matches('\n').count();
+            line_start = offsets.at(byte + newline + 1).chars;
+        }
+        let (from, to) = (offsets.at(whole.start()), offsets.at(end));
+        byte = whole.start();
+        markers.push(FootnoteMarker {
+            label: caps[1].to_string(),
+            kind: if is_definition { FootnoteKind::Definition } else { FootnoteKind::Reference },
+            line,
+            column: from.chars - line_start,
+            start: from.chars,
+            end: to.chars,
+            offset_bytes: whole.start(),
+            end_bytes: end,
+            offset_utf16: from.utf16,
+            end_utf16: to.utf16,
+        });
+    }
+    markers
+}
+
+/// Footnote definitions by label: the text after `[^label]:` plus any indented continuation
+/// lines, joined with spaces. The first definition of a label wins.
+pub fn footnote_definitions(content: &str) -> BTreeMap<String, String> {
+    let lines: Vec<(usize, &str)> = content.split_inclusive('\n')
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some((start, line))
+        })
+        .collect();
+    let char_to_byte: Vec<usize> = content.char_indices().map(|(i, _)| i).chain(std::iter::once(content.len())).collect();
+
+    let mut definitions = BTreeMap::new();
+    for marker in parse_footnotes(content).into_iter().filter(|m| m.kind == FootnoteKind::Definition) {
+        let end = char_to_byte[marker.end];
+        let Some(index) = lines.iter().rposition(|&(start, _)| start <= end) else {
+            continue;
+        };
+        let (line_start, line) = lines[index];
+        let mut text = vec![line[end - line_start..].trim().to_string()];
+        for &(_, next) in &lines[index + 1..] {
+            if next.trim().is_empty() || !next.starts_with([' ', '\t']) {
+                break;
+            }
+            text.push(next.trim().to_string());
+        }
+        definitions.entry(marker.label).or_insert_with(|| text.join(" ").trim().to_string());
+    }
+    definitions
+}
+
+/// Decode %XX escapes (as UTF-8); malformed escapes are kept as written
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).filter(|h| h.iter().all(u8::is_ascii_hexdigit));
+        if let (b'%', Some(hex)) = (bytes[i], hex) {
+            let digits = std::str::from_utf8(hex).expect("hex digits are ASCII");
+            out.push(u8::from_str_radix(digits, 16).expect("checked hex digits"));
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn has_url_scheme(target: &str) -> bool {
+    let scheme_end = target.find(':').unwrap_or(0);
+    scheme_end > 1 && target[..scheme_end].chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Byte ranges that link and keyword scanning skips: a leading YAML frontmatter block and,
+/// unless `include_code`, fenced code blocks (see `FenceTracker`) and `inline code`. Unclosed
+/// fences run to the end.
+fn skipped_ranges(content: &str, include_code: bool) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let (frontmatter, body_start) = split_frontmatter(content);
+    if frontmatter.is_some() {
+        ranges.push((0, body_start));
+    }
+    if !include_code {
+        ranges.extend(code_ranges(content, body_start));
+    }
+    ranges.extend(reference_ranges(content).0);
+    ranges.sort_unstable();
+    ranges
+}
+
+/// Where a line stands in relation to fenced code, as read by `FenceTracker`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FenceLine {
+    Text,
+    /// Opens a block ("```rust", "~~~~")
+    Open,
+    /// Inside a block
+    Code,
+    /// Closes the block it's in
+    Close,
+}
+
+/// Follows fenced code blocks through a note, line by line. Fences may be indented; a block
+/// closes only on a bare run of its fence char at least as long as the opening one, so a ````
+/// block can contain ``` lines and "```python" never closes one.
+#[derive(Debug, Default)]
+pub(crate) struct FenceTracker {
+    /// Char and length of the open block's fence
+    open: Option<(char, usize)>,
+}
+
+impl FenceTracker {
+    pub(crate) fn line(&mut self, line: &str) -> FenceLine {
+        let trimmed = line.trim();
+        let marker_char = trimmed.chars().next().filter(|&c| c == '`' || c == '~');
+        let marker_len = marker_char.map_or(0, |m| trimmed.chars().take_while(|&c| c == m).count());
+        match self.open {
+            Some((open_char, open_len)) => {
+                if marker_char == Some(open_char) && marker_len >= open_len && marker_len == trimmed.chars().count() {
+                    self.open = None;
+                    FenceLine::Close
+                } else {
+                    FenceLine::Code
                 }
             }
+            None if marker_len >= 3 => {
+                self.open = marker_char.map(|m| (m, marker_len));
+                FenceLine::Open
+            }
+            None => FenceLine::Text,
         }
+    }
 
-        serde_wasm_bindgen::to_value(&links).unwrap()
+    /// Inside a block (after its opening line, before its closing one)
+    pub(crate) fn is_open(&self) -> bool {
+        self.open.is_some()
     }
+}
 
-    fn try_parse_wiki_link(
-        &self,
-        chars: &mut std::iter::Peekable<std::str::Chars>,
-        line: &str,
-        pos: &mut usize,
-    ) -> Option<String> {
-        if line[*pos..].starts_with("[[") {
-            *pos += 2;
-            let start = *pos;
+/// Byte ranges of fenced code blocks and `inline code` in `content[body_start..]`, in order
+fn code_ranges(content: &str, body_start: usize) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    static INLINE_CODE: OnceLock<regex::Regex> = OnceLock::new();
+    let inline = INLINE_CODE.get_or_init(|| regex::Regex::new(r"``[^\n]+?``|`[^`\n]+`").expect("built-in code pattern is valid"));
+    let mut fence = FenceTracker::default();
+    let mut block_start = body_start;
+    let mut offset = body_start;
+    for line in content[body_start..].split_inclusive('\n') {
+        match fence.line(line) {
+            FenceLine::Open => block_start = offset,
+            FenceLine::Close => ranges.push((block_start, offset + line.len())),
+            FenceLine::Code => {}
+            FenceLine::Text => ranges.extend(inline.find_iter(line).map(|m| (offset + m.start(), offset + m.end()))),
+        }
+        offset += line.len();
+    }
+    if fence.is_open() {
+        ranges.push((block_start, content.len()));
+    }
+    ranges
+}
 
-            while *pos < line.len() && !line[*pos..].starts_with("]]") {
-                *pos += 1;
+/// `content` with every char inside `ranges` replaced by spaces (as many as its UTF-8 bytes, so
+/// byte offsets are unchanged); newlines are kept so lines still line up
+fn blank_ranges(content: &str, ranges: &[(usize, usize)]) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut cursor = 0;
+    for &(start, end) in ranges {
+        out.push_str(&content[cursor..start]);
+        for c in content[start..end].chars() {
+            if c == '\n' {
+                out.push(c);
+            } else {
+                out.extend(std::iter::repeat_n(' ', c.len_utf8()));
             }
+        }
+        cursor = end;
+    }
+    out.push_str(&content[cursor..]);
+    out
+}
+
+/// Every wiki link, markdown link and bare URL in `content`, in document order. Links in the
+/// frontmatter are skipped, and so are links in code unless `include_code`.
+pub fn parse_all_links(content: &str, include_code: bool) -> Vec<ExtractedLink> {
+    let mut links = Vec::new();
+    let mut offsets = OffsetCounter::new(content);
+    let (mut byte, mut line, mut line_start) = (0usize, 1usize, 0usize);
+    for (start, end, mut link) in find_links(content, include_code) {
+        let between = &content[byte..start];
+        if let Some(newline) = between.rfind('\n') {
+            line += between.matches('\n').count();
+            line_start = offsets.at(byte + newline + 1).chars;
+        }
+        let (from, to) = (offsets.at(start), offsets.at(end));
+        link.raw = content[start..end].to_string();
+        link.line = line;
+        link.column = from.chars - line_start;
+        link.start = from.chars;
+        link.end = to.chars;
+        link.offset_bytes = start;
+        link.end_bytes = end;
+        link.offset_utf16 = from.utf16;
+        link.end_utf16 = to.utf16;
+        links.push(link);
+        byte = start;
+    }
+    links
+}
 
-            if *pos < line.len() {
-                let link_text = &line[start..*pos];
-                *pos += 2;
+/// Links in `content` with their byte range, sorted by start; positions and `raw` are left unset
+fn find_links(content: &str, include_code: bool) -> Vec<(usize, usize, ExtractedLink)> {
+    static MARKDOWN: OnceLock<regex::Regex> = OnceLock::new();
+    static BARE_URL: OnceLock<regex::Regex> = OnceLock::new();
+    let markdown = MARKDOWN.get_or_init(|| regex::Regex::new(MARKDOWN_LINK_PATTERN).expect("built-in link pattern is valid"));
+    let bare_url = BARE_URL.get_or_init(|| regex::Regex::new(BARE_URL_PATTERN).expect("built-in URL pattern is valid"));
+    let skipped = skipped_ranges(content, include_code);
+    let not_skipped = |start: usize, end: usize| !skipped.iter().any(|&(s, e)| start < e && s < end);
 
-                let link = link_text.split('|').next().unwrap_or(link_text);
-                return Some(link.to_string());
+    // Byte range of each link, with its char offsets and line filled in below
+    let mut found: Vec<(usize, usize, ExtractedLink)> = Vec::new();
+    let overlaps_found = |found: &[(usize, usize, ExtractedLink)], start: usize, end: usize| {
+        found.iter().any(|&(s, e, _)| start < e && s < end)
+    };
+    let link = |kind: LinkKind,
+                (target, heading, block_ref): (String, Option<String>, Option<String>),
+                display_text: Option<String>,
+                (is_embed, embed_size): (bool, Option<String>)| {
+        ExtractedLink { kind, target, heading, block_ref, display_text, is_embed, embed_size, reference: None, raw: String::new(), line: 0, column: 0, start: 0, end: 0, offset_bytes: 0, end_bytes: 0, offset_utf16: 0, end_utf16: 0 }
+    };
+
+    for wiki in parse_wiki_links(content) {
+        let inner = &content[wiki.start..wiki.end];
+        if not_skipped(wiki.start, wiki.end) && !inner.contains('\n') {
+            found.push((wiki.start, wiki.end, link(LinkKind::Wiki, (wiki.target, wiki.heading, wiki.block_ref), wiki.alias, (wiki.embed, wiki.embed_size))));
+        }
+    }
+
+    for caps in markdown.captures_iter(content) {
+        let whole = caps.get(0).expect("group 0 always matches");
+        if !not_skipped(whole.start(), whole.end()) || overlaps_found(&found, whole.start(), whole.end()) {
+            continue;
+        }
+        let raw_target = caps[2].trim_start_matches('<').trim_end_matches('>').trim();
+        let is_embed = whole.as_str().starts_with('!');
+        let (display_text, embed_size) = caption_and_size(&caps[1], is_embed);
+        if let Some((kind, target)) = markdown_target(raw_target) {
+            found.push((whole.start(), whole.end(), link(kind, target, display_text, (is_embed, embed_size))));
+        }
+    }
+
+    // Reference-style links, where their label is defined
+    let (_, definitions) = reference_ranges(content);
+    if !definitions.is_empty() {
+        static REFERENCE: OnceLock<regex::Regex> = OnceLock::new();
+        let reference = REFERENCE.get_or_init(|| regex::Regex::new(REFERENCE_LINK_PATTERN).expect("built-in reference pattern is valid"));
+        for caps in reference.captures_iter(content) {
+            let whole = caps.get(0).expect("group 0 always matches");
+            if !not_skipped(whole.start(), whole.end()) || overlaps_found(&found, whole.start(), whole.end()) {
+                continue;
+            }
+            // A shortcut [label] followed by '(' or ':' is something else
+            if caps.get(2).is_none() && content[whole.end()..].starts_with(['(', ':']) {
+                continue;
             }
+            let label = caps.get(2).map(|m| m.as_str()).filter(|l| !l.trim().is_empty()).unwrap_or(&caps[1]);
+            let Some(destination) = definitions.get(&reference_label_key(label)) else {
+                continue;
+            };
+            let is_embed = whole.as_str().starts_with('!');
+            let (display_text, embed_size) = caption_and_size(&caps[1], is_embed);
+            if let Some((kind, target)) = markdown_target(destination) {
+                let mut reference_link = link(kind, target, display_text, (is_embed, embed_size));
+                reference_link.reference = Some(label.trim().to_string());
+                found.push((whole.start(), whole.end(), reference_link));
+            }
+        }
+    }
+
+    for m in bare_url.find_iter(content) {
+        if not_skipped(m.start(), m.end()) && !overlaps_found(&found, m.start(), m.end()) {
+            found.push((m.start(), m.end(), link(LinkKind::Url, (m.as_str().to_string(), None, None), None, (false, None))));
         }
-        None
     }
 
-    pub fn find_potential_link_positions(&self, content: &str, keywords: Vec<String>) -> JsValue {
-        let mut positions = Vec::new();
-        let keywords_set: HashSet<String> = keywords.iter().map(|k| k.to_lowercase()).collect();
+    found.sort_by_key(|&(start, ..)| start);
+    found
+}
 
-        for (line_num, line) in content.lines().enumerate() {
-            let line_lower = line.to_lowercase();
+/// `content` with the frontmatter, code and every link blanked out (byte offsets and lines
+/// unchanged), leaving only the prose where a plain-text mention could be linked
+pub(crate) fn unlinked_text(content: &str) -> String {
+    let mut ranges = skipped_ranges(content, false);
+    ranges.extend(find_links(content, false).into_iter().map(|(start, end, _)| (start, end)));
+    ranges.sort_unstable();
+    // Links never overlap skipped spans, but merge anyway so blanking sees ordered, disjoint ranges
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start < last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    blank_ranges(content, &merged)
+}
 
-            for keyword in &keywords_set {
-                // Find all occurrences of the keyword in the line
-                let mut search_from = 0;
-                while let Some(pos) = line_lower[search_from..].find(keyword) {
-                    let actual_pos = search_from + pos;
+/// Pattern for a keyword's words `words`, separated in the text by spaces, tabs or a hyphen,
+/// or also a single line break when `allow_line_wrap`
+fn phrase_pattern(words: &[&str], allow_line_wrap: bool) -> Option<regex::Regex> {
+    let separator = if allow_line_wrap { r"(?:[ \t]*\r?\n[ \t]*|[ \t]*-[ \t]*|[ \t]+)" } else { r"(?:[ \t]*-[ \t]*|[ \t]+)" };
+    let pattern = words.iter().map(|w| regex::escape(w)).collect::<Vec<_>>().join(separator);
+    regex::RegexBuilder::new(&pattern).case_insensitive(true).build().ok()
+}
 
-                    // Check word boundaries to avoid matching partial words
-                    // e.g., "number" should not match inside "Reynolds number"
-                    let is_word_start = actual_pos == 0 ||
-                        !line_lower.chars().nth(actual_pos - 1).map_or(false, |c| c.is_alphanumeric());
-                    let is_word_end = actual_pos + keyword.len() >= line_lower.len() ||
-                        !line_lower.chars().nth(actual_pos + keyword.len()).map_or(false, |c| c.is_alphanumeric());
+/// Whole-word occurrences of `keywords` outside frontmatter, existing links and (unless
+/// `include_code`) code, in document order. Keywords are matched word by word, so a phrase still
+/// matches with other spacing or hyphens. A multi-word keyword whose whole phrase doesn't occur
+/// somewhere can match by its leading words instead (`Prefix`). Where matches overlap, the
+/// longer one wins, exact before prefix, so "strong turbulence" beats "turbulence".
+pub fn potential_link_positions(content: &str, keywords: &[String], options: &PositionOptions) -> Vec<PotentialLinkPosition> {
+    let mut seen: HashSet<String> = HashSet::new();
+    let keywords: Vec<String> = keywords.iter()
+        .map(|k| k.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase())
+        .filter(|k| !k.is_empty() && seen.insert(k.clone()))
+        .collect();
 
-                    if is_word_start && is_word_end && !self.is_inside_link(line, actual_pos) {
-                        positions.push(serde_json::json!({
-                            "line": line_num + 1,
-                            "column": actual_pos,
-                            "keyword": keyword,
-                            "context": line.trim(),
-                        }));
-                        break; // Only add first occurrence per line
-                    }
+    // Skipped spans are blanked out, keeping byte offsets, so matches line up with `content`
+    let masked = blank_ranges(content, &skipped_ranges(content, options.include_code));
+    let line_starts: Vec<usize> = std::iter::once(0).chain(masked.match_indices('\n').map(|(i, _)| i + 1)).collect();
+    let line_of = |byte: usize| line_starts.partition_point(|&start| start <= byte) - 1;
+    let is_whole_word = |start: usize, end: usize| {
+        !masked[..start].chars().next_back().is_some_and(char::is_alphanumeric)
+            && !masked[end..].chars().next().is_some_and(char::is_alphanumeric)
+    };
+    let analyzer = LinkAnalyzer;
 
-                    search_from = actual_pos + 1;
+    // (start, end, keyword index, kind)
+    let mut candidates: Vec<(usize, usize, usize, KeywordMatchKind)> = Vec::new();
+    for (index, keyword) in keywords.iter().enumerate() {
+        let words: Vec<&str> = keyword.split([' ', '-']).filter(|w| !w.is_empty()).collect();
+        for take in (2.min(words.len())..=words.len()).rev() {
+            let kind = if take == words.len() { KeywordMatchKind::Exact } else { KeywordMatchKind::Prefix };
+            let Some(pattern) = phrase_pattern(&words[..take], options.allow_line_wrap) else {
+                continue;
+            };
+            let before = candidates.len();
+            for m in pattern.find_iter(&masked) {
+                let line_start = line_starts[line_of(m.start())];
+                let line = &masked[line_start..masked[line_start..].find('\n').map_or(masked.len(), |i| line_start + i)];
+                if is_whole_word(m.start(), m.end()) && !analyzer.is_inside_link(line, m.start() - line_start) {
+                    candidates.push((m.start(), m.end(), index, kind));
                 }
             }
+            // Shorter prefixes only when the longer form is nowhere in the note
+            if candidates.len() > before {
+                break;
+            }
         }
+    }
 
-        serde_wasm_bindgen::to_value(&positions).unwrap()
+    candidates.sort_by_key(|&(start, end, _, kind)| (std::cmp::Reverse(end - start), kind != KeywordMatchKind::Exact, start));
+    let mut kept: Vec<(usize, usize, usize, KeywordMatchKind)> = Vec::new();
+    for candidate in candidates {
+        if !kept.iter().any(|&(start, end, ..)| candidate.0 < end && start < candidate.1) {
+            kept.push(candidate);
+        }
     }
+    kept.sort_by_key(|&(start, ..)| start);
 
-    fn is_inside_link(&self, line: &str, pos: usize) -> bool {
-        let before = &line[..pos];
-        let after = &line[pos..];
+    let mut counts = vec![0usize; keywords.len()];
+    let mut positions = Vec::new();
+    let mut offsets = OffsetCounter::new(content);
+    for (start, end, index, match_kind) in kept {
+        if counts[index] >= options.max_per_keyword {
+            continue;
+        }
+        counts[index] += 1;
+        let line = line_of(start);
+        let line_start = line_starts[line];
+        let line_text = &content[line_start..content[line_start..].find('\n').map_or(content.len(), |i| line_start + i)];
+        let before = &content[line_start..start];
+        let matched = &content[start..end];
+        let (from, to) = (offsets.at(start), offsets.at(end));
+        positions.push(PotentialLinkPosition {
+            line: line + 1,
+            end_line: line_of(end) + 1,
+            column: before.chars().count(),
+            byte_column: start - line_start,
+            utf16_column: before.encode_utf16().count(),
+            length: matched.chars().count(),
+            utf16_length: matched.encode_utf16().count(),
+            offset_bytes: start,
+            end_bytes: end,
+            offset_utf16: from.utf16,
+            end_utf16: to.utf16,
+            keyword: keywords[index].clone(),
+            match_kind,
+            matched_text: matched.to_string(),
+            context: line_text.trim().to_string(),
+        });
+    }
+    positions
+}
 
-        let open_brackets = before.rfind("[[");
-        let close_brackets = before.rfind("]]");
+/// Inline #tags as Obsidian reads them: after whitespace or an opening parenthesis, made of
+/// letters, digits, '_', '-' and '/'
+const INLINE_TAG_PATTERN: &str = r"(?:^|[\s(])#([\p{L}\p{N}_/-]+)";
 
-        match (open_brackets, close_brackets) {
-            (Some(open), Some(close)) => open > close && after.contains("]]"),
-            (Some(_), None) => after.contains("]]"),
-            _ => false,
+/// Tags in the frontmatter, then inline tags in the body outside code and links, in order of
+/// first use. All-digit tags ("#123") are ignored, as in Obsidian.
+pub fn parse_tags(content: &str) -> Vec<NoteTag> {
+    let mut tags: Vec<NoteTag> = Vec::new();
+    let mut add = |tag: &str, from_frontmatter: bool| {
+        let tag = tag.trim_matches('/');
+        if tag.is_empty() || tag.chars().all(|c| c.is_ascii_digit()) {
+            return;
+        }
+        let index = match tags.iter().position(|t| t.tag.to_lowercase() == tag.to_lowercase()) {
+            Some(index) => index,
+            None => {
+                tags.push(NoteTag { tag: tag.to_string(), count: 0, in_body: false, in_frontmatter: false });
+                tags.len() - 1
+            }
+        };
+        let entry = &mut tags[index];
+        if from_frontmatter {
+            // A tag listed twice in the frontmatter is still one declaration
+            if entry.in_frontmatter {
+                return;
+            }
+            entry.in_frontmatter = true;
+        } else {
+            entry.in_body = true;
+        }
+        entry.count += 1;
+    };
+
+    for tag in frontmatter_tags(content) {
+        add(&tag, true);
+    }
+    static INLINE_TAG: OnceLock<regex::Regex> = OnceLock::new();
+    let pattern = INLINE_TAG.get_or_init(|| regex::Regex::new(INLINE_TAG_PATTERN).expect("built-in tag pattern is valid"));
+    for caps in pattern.captures_iter(&unlinked_text(content)) {
+        add(&caps[1], false);
+    }
+    tags
+}
+
+/// Whether a sentence starts at char `i`: after a line break, or after ". ", "! " or "? "
+fn is_sentence_start(chars: &[char], i: usize) -> bool {
+    i == 0 || chars[i - 1] == '\n' || (i >= 2 && chars[i - 1].is_whitespace() && matches!(chars[i - 2], '.' | '!' | '?'))
+}
+
+/// Context of about `window_chars` chars around each of `spans` (byte ranges in `content`, in
+/// order), within the span's line. Windows are widened to the enclosing sentences when that adds
+/// at most half a window on each side, otherwise cut at a word break. Windows that overlap are merged into one snippet.
+pub(crate) fn context_snippets(content: &str, spans: &[(usize, usize)], window_chars: usize) -> Vec<ContextSnippet> {
+    let chars: Vec<char> = content.chars().collect();
+    let byte_to_char = |byte: usize| content[..byte].chars().count();
+    let slack = window_chars / 2;
+
+    // Highlights hold absolute char ranges until the snippet text is cut
+    let mut windows: Vec<ContextSnippet> = Vec::new();
+    for &(byte_start, byte_end) in spans {
+        let (link_start, link_end) = (byte_to_char(byte_start), byte_to_char(byte_end));
+        let room = window_chars.saturating_sub(link_end - link_start) / 2;
+        // Snippets stay within the link's line
+        let line_start = (0..link_start).rev().find(|&i| chars[i] == '\n').map_or(0, |i| i + 1);
+        let (mut start, mut end) = (link_start.saturating_sub(room).max(line_start), (link_end + room).min(chars.len()));
+
+        let mut truncated_start = false;
+        match (start.saturating_sub(slack).max(line_start)..=start).rev().find(|&i| is_sentence_start(&chars, i)) {
+            Some(sentence_start) => start = sentence_start,
+            None => {
+                // Cut after a space so the snippet doesn't open mid-word
+                start = (start..=link_start).find(|&i| i > 0 && chars[i - 1].is_whitespace()).unwrap_or(start);
+                truncated_start = true;
+            }
+        }
+        let mut truncated_end = false;
+        let limit = (end + slack).min(chars.len());
+        match (end..limit).find(|&i| chars[i] == '\n' || (matches!(chars[i], '.' | '!' | '?') && chars.get(i + 1).is_none_or(|c| c.is_whitespace()))) {
+            Some(i) => end = if chars[i] == '\n' { i } else { i + 1 },
+            None if end >= chars.len() => {}
+            None => {
+                end = (link_end..end).rev().find(|&i| chars[i].is_whitespace()).unwrap_or(end);
+                truncated_end = true;
+            }
+        }
+
+        match windows.last_mut() {
+            Some(last) if start <= last.end => {
+                last.end = last.end.max(end);
+                last.truncated_end = truncated_end;
+                last.highlights.push((link_start, link_end));
+            }
+            _ => windows.push(ContextSnippet {
+                line: chars[..link_start].iter().filter(|&&c| c == '\n').count(),
+                start,
+                end,
+                offset_bytes: 0,
+                end_bytes: 0,
+                offset_utf16: 0,
+                end_utf16: 0,
+                snippet: String::new(),
+                highlights: vec![(link_start, link_end)],
+                highlights_bytes: Vec::new(),
+                highlights_utf16: Vec::new(),
+                truncated_start,
+                truncated_end,
+            }),
+        }
+    }
+
+    // Byte offset of every char, and of the end
+    let char_bytes: Vec<usize> = content.char_indices().map(|(byte, _)| byte).chain(std::iter::once(content.len())).collect();
+    let mut offsets = OffsetCounter::new(content);
+    for window in &mut windows {
+        // Leave out surrounding whitespace, keeping offsets exact
+        let (start, end) = (window.start, window.end);
+        window.start = (start..end).find(|&i| !chars[i].is_whitespace()).unwrap_or(start);
+        window.end = (window.start..end).rev().find(|&i| !chars[i].is_whitespace()).map_or(window.start, |i| i + 1);
+        window.snippet = chars[window.start..window.end].iter().collect();
+        window.offset_bytes = char_bytes[window.start];
+        window.offset_utf16 = offsets.at(window.offset_bytes).utf16;
+        for highlight in &mut window.highlights {
+            *highlight = (highlight.0 - window.start, highlight.1.min(window.end) - window.start);
+            let (start_byte, end_byte) = (char_bytes[window.start + highlight.0], char_bytes[window.start + highlight.1]);
+            window.highlights_bytes.push((start_byte - window.offset_bytes, end_byte - window.offset_bytes));
+            let (start_utf16, end_utf16) = (offsets.at(start_byte).utf16, offsets.at(end_byte).utf16);
+            window.highlights_utf16.push((start_utf16 - window.offset_utf16, end_utf16 - window.offset_utf16));
+        }
+        window.end_bytes = char_bytes[window.end];
+        window.end_utf16 = offsets.at(window.end_bytes).utf16;
+    }
+    windows
+}
+
+/// See `LinkAnalyzer::insert_wiki_link`
+pub fn link_phrase(content: &str, phrase: &str, target_title: &str, occurrence: usize, preserve_case: bool) -> LinkInsertion {
+    let mut result = LinkInsertion {
+        success: false,
+        content: content.to_string(),
+        start: 0,
+        end: 0,
+        offset_bytes: 0,
+        end_bytes: 0,
+        offset_utf16: 0,
+        end_utf16: 0,
+        original_text: String::new(),
+        link: String::new(),
+        occurrences: 0,
+        error: None,
+    };
+    let phrase = phrase.trim();
+    let target = target_title.trim();
+    let Some(pattern) = (!phrase.is_empty() && !target.is_empty())
+        .then(|| terms_pattern(&[phrase.to_string()]))
+        .flatten()
+        .and_then(|p| regex::RegexBuilder::new(&p).case_insensitive(true).build().ok())
+    else {
+        result.error = Some(LinkInsertionError::EmptyPhrase);
+        return result;
+    };
+
+    let masked = unlinked_text(content);
+    let matches: Vec<regex::Match> = pattern.find_iter(&masked).collect();
+    result.occurrences = matches.len();
+    let chosen = match occurrence {
+        0 if matches.len() > 1 => {
+            result.error = Some(LinkInsertionError::Ambiguous);
+            return result;
+        }
+        0 => matches.first(),
+        n => matches.get(n - 1),
+    };
+    let Some(m) = chosen else {
+        result.error = Some(LinkInsertionError::NotFound);
+        return result;
+    };
+
+    let original = &content[m.start()..m.end()];
+    let same_as_title = if preserve_case { original == target } else { original.to_lowercase() == target.to_lowercase() };
+    // A '|' inside a table row would split the cell, so it's escaped there
+    let line_start = content[..m.start()].rfind('\n').map_or(0, |i| i + 1);
+    let separator = if content[line_start..].trim_start().starts_with('|') { "\\|" } else { "|" };
+    let link = if same_as_title { format!("[[{}]]", target) } else { format!("[[{}{}{}]]", target, separator, original) };
+
+    let before = &content[..m.start()];
+    result.start = before.chars().count();
+    result.end = result.start + link.chars().count();
+    result.offset_bytes = m.start();
+    result.end_bytes = m.start() + link.len();
+    result.offset_utf16 = before.encode_utf16().count();
+    result.end_utf16 = result.offset_utf16 + link.encode_utf16().count();
+    result.content = format!("{}{}{}", &content[..m.start()], link, &content[m.end()..]);
+    result.original_text = original.to_string();
+    result.link = link;
+    result.success = true;
+    result
+}
+
+/// Result of `LinkAnalyzer::rewrite_links`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LinkRewrite {
+    pub content: String,
+    pub rewrites: usize,
+}
+
+/// A stored note changed by `SmartVault::rewrite_links_in_vault`, to be written back
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RewrittenNote {
+    pub path: String,
+    pub content: String,
+    pub rewrites: usize,
+}
+
+/// See `LinkAnalyzer::rewrite_links`
+pub fn rewrite_note_links(content: &str, old_title: &str, new_title: &str) -> LinkRewrite {
+    let old = old_title.trim().trim_end_matches(".md").to_lowercase();
+    let new = new_title.trim().trim_end_matches(".md");
+    if old.is_empty() || new.is_empty() {
+        return LinkRewrite { content: content.to_string(), rewrites: 0 };
+    }
+    let code = code_ranges(content, split_frontmatter(content).1);
+
+    let mut out = String::with_capacity(content.len());
+    let mut cursor = 0;
+    let mut rewrites = 0;
+    for link in parse_wiki_links(content) {
+        let raw = &content[link.start..link.end];
+        if raw.contains('\n') || code.iter().any(|&(s, e)| link.start < e && s < link.end) {
+            continue;
+        }
+        // Byte range of the note name inside the brackets, leaving any folder, ".md", heading,
+        // block reference and alias as written
+        let inner_start = link.start + if link.embed { 3 } else { 2 };
+        let note_end = content[inner_start..link.end - 2].find(['#', '|']).map_or(link.end - 2, |i| inner_start + i);
+        let note = content[inner_start..note_end].trim();
+        let note_start = inner_start + content[inner_start..note_end].find(note).unwrap_or(0);
+        let folder_len = note.rfind('/').map_or(0, |i| i + 1);
+        let name = &note[folder_len..];
+        let name = match name.len().checked_sub(3).filter(|&i| name.is_char_boundary(i) && name[i..].eq_ignore_ascii_case(".md")) {
+            Some(i) => &name[..i],
+            None => name,
+        };
+        // A rename that only changes case already resolves, so the link is kept as written
+        if name.to_lowercase() != old || name.eq_ignore_ascii_case(new) {
+            continue;
+        }
+        let name_start = note_start + folder_len;
+        out.push_str(&content[cursor..name_start]);
+        out.push_str(new);
+        cursor = name_start + name.len();
+        rewrites += 1;
+    }
+    out.push_str(&content[cursor..]);
+    LinkRewrite { content: out, rewrites }
+}
+
+/// Display text of a markdown link, and for an embed the size after the caption
+/// (![caption|300](image.png))
+fn caption_and_size(text: &str, is_embed: bool) -> (Option<String>, Option<String>) {
+    let mut text = text.trim();
+    let mut embed_size = None;
+    if is_embed {
+        let (rest, last) = text.rsplit_once('|').map_or(("", text), |(rest, last)| (rest, last));
+        if is_embed_size(last.trim()) {
+            embed_size = Some(last.trim().to_string());
+            text = rest.trim();
+        }
+    }
+    ((!text.is_empty()).then(|| text.to_string()), embed_size)
+}
+
+/// Target, heading and block ref of a link
+type LinkParts = (String, Option<String>, Option<String>);
+
+/// Kind and parts of a markdown link destination; None if it's empty
+fn markdown_target(raw_target: &str) -> Option<(LinkKind, LinkParts)> {
+    if has_url_scheme(raw_target) {
+        return Some((LinkKind::Url, (raw_target.to_string(), None, None)));
+    }
+    // Split before decoding so an escaped "%23" stays part of the name
+    let (note, heading, block_ref) = split_link_target(raw_target);
+    let note = percent_decode(&note);
+    let note = note.strip_suffix(".md").unwrap_or(&note).to_string();
+    let heading = heading.map(|h| percent_decode(&h));
+    (!note.is_empty() || heading.is_some() || block_ref.is_some()).then_some((LinkKind::Markdown, (note, heading, block_ref)))
+}
+
+/// Lowercased names of the notes `text` already links to or embeds (wiki or markdown, with or
+/// without a heading or block reference): each target as written and its file name without folders
+pub(crate) fn linked_note_names(text: &str) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for link in parse_all_links(text, false) {
+        // Same-file links ([[#Heading]]) don't link another note; embedded media isn't a note
+        if link.kind == LinkKind::Url || link.target.is_empty() || (link.is_embed && is_media_target(&link.target)) {
+            continue;
+        }
+        add_linked_name(&mut names, &link.target);
+    }
+    names
+}
+
+/// Add a link target to a `linked_note_names` set: its lowercase path without ".md", and its
+/// last segment
+pub(crate) fn add_linked_name(names: &mut HashSet<String>, target: &str) {
+    let target = target.to_lowercase();
+    let target = target.strip_suffix(".md").unwrap_or(&target).to_string();
+    if let Some(name) = target.rsplit('/').next() {
+        names.insert(name.to_string());
+    }
+    names.insert(target);
+}
+
+/// How a phrase was found in the document
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PhraseMatchKind {
+    Exact,
+    /// Matched after normalizing case, quotes, dashes and whitespace
+    Normalized,
+    /// Closest substring by edit distance
+    Fuzzy,
+}
+
+/// Where a phrase sits in a document; `text` is the document's own wording
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PhraseMatch {
+    pub text: String,
+    pub start_byte: usize,
+    pub end_byte: usize,
+    pub start_char: usize,
+    pub end_char: usize,
+    /// UTF-16 units (see `offsets`)
+    pub start_utf16: usize,
+    pub end_utf16: usize,
+    pub kind: PhraseMatchKind,
+    /// 1.0 for exact/normalized matches, 1 - edit distance / phrase length for fuzzy ones
+    pub similarity: f32,
+}
+
+/// Minimum similarity for a fuzzy phrase match to be accepted
+pub const FUZZY_PHRASE_CUTOFF: f32 = 0.8;
+
+/// One normalized char and the byte range of the original text it came from
+struct NormalizedChar {
+    ch: char,
+    start: usize,
+    end: usize,
+}
+
+/// Lowercase, straighten typographic quotes and dashes, and collapse whitespace runs to one space
+fn normalize_for_matching(text: &str) -> Vec<NormalizedChar> {
+    let mut out: Vec<NormalizedChar> = Vec::with_capacity(text.len());
+    for (start, c) in text.char_indices() {
+        let end = start + c.len_utf8();
+        if c.is_whitespace() {
+            match out.last_mut() {
+                Some(prev) if prev.ch == ' ' => prev.end = end,
+                _ => out.push(NormalizedChar { ch: ' ', start, end }),
+            }
+            continue;
+        }
+        let mapped = match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}' => '\'',
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{2033}' => '"',
+            '\u{2010}'..='\u{2015}' => '-',
+            _ => c,
+        };
+        for lower in mapped.to_lowercase() {
+            out.push(NormalizedChar { ch: lower, start, end });
+        }
+    }
+    out
+}
+
+/// Best approximate occurrence of `needle` anywhere in `haystack` (Sellers' algorithm).
+/// Returns (edit distance, start index, end index) in haystack positions.
+fn best_fuzzy_window(haystack: &[char], needle: &[char]) -> Option<(usize, usize, usize)> {
+    let m = needle.len();
+    if m == 0 || haystack.is_empty() {
+        return None;
+    }
+
+    // (cost, start of the alignment in haystack)
+    let mut prev: Vec<(usize, usize)> = (0..=m).map(|j| (j, 0)).collect();
+    let mut cur: Vec<(usize, usize)> = vec![(0, 0); m + 1];
+    let mut best: Option<(usize, usize, usize)> = None;
+
+    for (i, &h) in haystack.iter().enumerate() {
+        // A match may start at any haystack position for free
+        cur[0] = (0, i + 1);
+        for j in 1..=m {
+            let substitute = (prev[j - 1].0 + usize::from(h != needle[j - 1]), prev[j - 1].1);
+            let skip_haystack = (prev[j].0 + 1, prev[j].1);
+            let skip_needle = (cur[j - 1].0 + 1, cur[j - 1].1);
+            cur[j] = [substitute, skip_haystack, skip_needle].into_iter().min_by_key(|&(cost, _)| cost).unwrap_or(substitute);
+        }
+        let (cost, start) = cur[m];
+        if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+            best = Some((cost, start, i + 1));
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    best
+}
+
+/// Locate `phrase` in `document`: verbatim first, then ignoring case/quote/whitespace
+/// differences, then by edit distance if at least `FUZZY_PHRASE_CUTOFF` similar.
+pub fn locate_phrase(document: &str, phrase: &str) -> Option<PhraseMatch> {
+    let phrase = phrase.trim();
+    if phrase.is_empty() {
+        return None;
+    }
+
+    let build = |start_byte: usize, end_byte: usize, kind: PhraseMatchKind, similarity: f32| {
+        let mut offsets = OffsetCounter::new(document);
+        let (start, end) = (offsets.at(start_byte), offsets.at(end_byte));
+        PhraseMatch {
+            text: document[start_byte..end_byte].to_string(),
+            start_char: start.chars,
+            end_char: end.chars,
+            start_utf16: start.utf16,
+            end_utf16: end.utf16,
+            start_byte,
+            end_byte,
+            kind,
+            similarity,
+        }
+    };
+
+    if let Some(start) = document.find(phrase) {
+        return Some(build(start, start + phrase.len(), PhraseMatchKind::Exact, 1.0));
+    }
+
+    let doc_norm = normalize_for_matching(document);
+    let phrase_norm: Vec<char> = normalize_for_matching(phrase).into_iter().map(|n| n.ch).collect();
+    let doc_chars: Vec<char> = doc_norm.iter().map(|n| n.ch).collect();
+
+    // Byte range in the original document covered by normalized chars [start, end)
+    let byte_range = |start: usize, end: usize| (doc_norm[start].start, doc_norm[end - 1].end);
+
+    if let Some(start) = doc_chars.windows(phrase_norm.len()).position(|w| w == phrase_norm.as_slice()) {
+        let (start_byte, end_byte) = byte_range(start, start + phrase_norm.len());
+        return Some(build(start_byte, end_byte, PhraseMatchKind::Normalized, 1.0));
+    }
+
+    let (distance, start, end) = best_fuzzy_window(&doc_chars, &phrase_norm)?;
+    let similarity = 1.0 - distance as f32 / phrase_norm.len() as f32;
+    if similarity < FUZZY_PHRASE_CUTOFF || end <= start {
+        return None;
+    }
+
+    // Don't let the match begin or end on collapsed whitespace
+    let (mut start, mut end) = (start, end);
+    while start < end && doc_chars[start] == ' ' {
+        start += 1;
+    }
+    while end > start && doc_chars[end - 1] == ' ' {
+        end -= 1;
+    }
+    if start == end {
+        return None;
+    }
+
+    let (start_byte, end_byte) = byte_range(start, end);
+    Some(build(start_byte, end_byte, PhraseMatchKind::Fuzzy, similarity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16_slice(text: &str, (start, end): (usize, usize)) -> String {
+        String::from_utf16(&text.encode_utf16().collect::<Vec<u16>>()[start..end]).unwrap()
+    }
+
+    #[test]
+    fn context_snippet_offsets_in_bytes_and_utf16() {
+        let content = "Intro line\n😀 Über 日本 links to [[Target]] and 🎉 [[Target|again]]. Rest.";
+        let spans: Vec<(usize, usize)> = find_links(content, false).into_iter().map(|(start, end, _)| (start, end)).collect();
+        let snippets = context_snippets(content, &spans, 200);
+        assert_eq!(snippets.len(), 1);
+        let snippet = &snippets[0];
+
+        assert_eq!(&content[snippet.offset_bytes..snippet.end_bytes], snippet.snippet);
+        assert_eq!(utf16_slice(content, (snippet.offset_utf16, snippet.end_utf16)), snippet.snippet);
+        let expected = ["[[Target]]", "[[Target|again]]"];
+        for (i, expected) in expected.iter().enumerate() {
+            let (start, end) = snippet.highlights_bytes[i];
+            assert_eq!(&snippet.snippet[start..end], *expected);
+            assert_eq!(utf16_slice(&snippet.snippet, snippet.highlights_utf16[i]), *expected);
+            let (start, end) = snippet.highlights[i];
+            assert_eq!(snippet.snippet.chars().skip(start).take(end - start).collect::<String>(), *expected);
+        }
+    }
+
+    fn located(document: &str, phrase: &str) -> PhraseMatch {
+        let Some(found) = locate_phrase(document, phrase) else { panic!("{:?} not found", phrase) };
+        assert_eq!(&document[found.start_byte..found.end_byte], found.text);
+        assert_eq!(document.chars().skip(found.start_char).take(found.end_char - found.start_char).collect::<String>(), found.text);
+        assert_eq!(utf16_slice(document, (found.start_utf16, found.end_utf16)), found.text);
+        found
+    }
+
+    #[test]
+    fn locate_phrase_straightens_smart_quotes() {
+        let document = "😀 She said \u{201C}don\u{2019}t panic\u{201D} and left.";
+        let found = located(document, "said \"don't panic\"");
+        assert_eq!(found.kind, PhraseMatchKind::Normalized);
+        assert_eq!(found.text, "said \u{201C}don\u{2019}t panic\u{201D}");
+
+        let straight = "It's the \"best\" one";
+        let found = located(straight, "it\u{2019}s the \u{201C}best\u{201D} one");
+        assert_eq!((found.kind, found.text.as_str()), (PhraseMatchKind::Normalized, straight));
+    }
+
+    #[test]
+    fn locate_phrase_matches_across_newline_differences() {
+        let document = "Intro.\r\nThe quick brown\r\n   fox jumps over\n\nthe lazy dog.";
+        let found = located(document, "quick brown fox jumps");
+        assert_eq!(found.kind, PhraseMatchKind::Normalized);
+        assert_eq!(found.text, "quick brown\r\n   fox jumps");
+
+        let found = located(document, "over\nthe lazy dog");
+        assert_eq!(found.text, "over\n\nthe lazy dog");
+
+        let found = located("One line of text here", "line of\ntext");
+        assert_eq!(found.text, "line of text");
+    }
+
+    #[test]
+    fn locate_phrase_falls_back_to_close_wording_only() {
+        let document = "We walked along the “riverside path”\nuntil dusk.";
+        let found = located(document, "walked along the riverside paths until");
+        assert_eq!(found.kind, PhraseMatchKind::Fuzzy);
+        assert!(found.similarity >= FUZZY_PHRASE_CUTOFF && found.similarity < 1.0);
+        assert!(found.text.starts_with("walked") && !found.text.ends_with(char::is_whitespace));
+
+        assert!(locate_phrase(document, "something else entirely").is_none());
+        assert!(locate_phrase(document, "   ").is_none());
+        assert_eq!(located(document, " until dusk ").kind, PhraseMatchKind::Exact);
+    }
+
+    #[test]
+    fn wiki_links_split_heading_block_and_alias() {
+        let links = parse_wiki_links("See [[Note#Section|shown text]], [[Note#^abc123]], [[Note#H1#H2]] and [[#Local]].");
+        let parts: Vec<_> = links.iter()
+            .map(|link| (link.target.as_str(), link.heading.as_deref(), link.block_ref.as_deref(), link.alias.as_deref()))
+            .collect();
+        assert_eq!(parts, vec![
+            ("Note", Some("Section"), None, Some("shown text")),
+            ("Note", None, Some("abc123"), None),
+            ("Note", Some("H1#H2"), None, None),
+            ("", Some("Local"), None, None),
+        ]);
+    }
+
+    #[test]
+    fn heading_links_count_as_linking_their_note() {
+        let names = linked_note_names("[[Note#Section|shown text]] and [[Other#^id]] and [[#Local]]");
+        assert!(names.contains("note"));
+        assert!(names.contains("other"));
+        assert!(!names.contains(""));
+        assert!(!names.iter().any(|name| name.contains('#') || name.contains('|')));
+    }
+
+    #[test]
+    fn links_with_emoji_and_cjk_titles_have_consistent_offsets() {
+        let content = "# 日記 🎉\n\n今日は [[東京タワー|タワー 🗼]] を見た。\n👩‍👩‍👧 ![[😀 Smile#Ünïcode]] and [[Café ☕]]";
+        let links: Vec<ExtractedLink> = parse_all_links(content, false).into_iter().filter(|link| link.kind == LinkKind::Wiki).collect();
+        let parts: Vec<_> = links.iter()
+            .map(|link| (link.target.as_str(), link.heading.as_deref(), link.display_text.as_deref(), link.line, link.column))
+            .collect();
+        assert_eq!(parts, vec![
+            ("東京タワー", None, Some("タワー 🗼"), 3, 4),
+            ("😀 Smile", Some("Ünïcode"), None, 4, 6),
+            ("Café ☕", None, None, 4, 31),
+        ]);
+
+        for link in &links {
+            assert_eq!(&content[link.offset_bytes..link.end_bytes], link.raw);
+            assert_eq!(content.chars().skip(link.start).take(link.end - link.start).collect::<String>(), link.raw);
+            assert_eq!(utf16_slice(content, (link.offset_utf16, link.end_utf16)), link.raw);
+            let line = content.lines().nth(link.line - 1).unwrap();
+            assert!(line.chars().skip(link.column).collect::<String>().starts_with(&link.raw));
+        }
+        assert_eq!(links[1].raw, "![[😀 Smile#Ünïcode]]");
+        assert!(links[1].is_embed);
+    }
+
+    fn wiki_targets(content: &str) -> Vec<String> {
+        parse_all_links(content, false).into_iter().filter(|link| link.kind == LinkKind::Wiki).map(|link| link.target).collect()
+    }
+
+    #[test]
+    fn nested_and_indented_fences_hide_their_links() {
+        let content = "[[Before]]\n````markdown\n```\n[[Inner]]\n```\n[[Still code]]\n````\n[[Between]]\n  ~~~\n  [[Indented]]\n  ~~~\n- item\n    ```\n    [[In list]]\n    ```\n[[After]] `[[inline]]`";
+        assert_eq!(wiki_targets(content), ["Before", "Between", "After"]);
+        let with_code: Vec<String> = parse_all_links(content, true).into_iter().map(|link| link.target).collect();
+        assert_eq!(with_code, ["Before", "Inner", "Still code", "Between", "Indented", "In list", "After", "inline"]);
+    }
+
+    #[test]
+    fn fences_close_only_on_a_matching_bare_marker() {
+        // "~~~" doesn't close a ``` fence, and neither does "``` text"
+        let content = "```\n~~~\n[[A]]\n``` not a close\n[[B]]\n```\n[[C]]\n```\n[[Unclosed]]";
+        assert_eq!(wiki_targets(content), ["C"]);
+    }
+
+    #[test]
+    fn frontmatter_with_bracketed_text_is_skipped() {
+        let content = "---\ntags: [project, \"[[Not a link]]\"]\nrelated:\n  - \"[[Other]]\"\nnote: see [docs](Docs.md) and [[Hidden]]\n---\nBody links [[Real]] and [Guide](Guide.md).";
+        let links = parse_all_links(content, false);
+        let targets: Vec<&str> = links.iter().map(|link| link.target.as_str()).collect();
+        assert_eq!(targets, ["Real", "Guide"]);
+        assert_eq!(links[0].line, 7);
+
+        let options = PositionOptions { include_code: false, max_per_keyword: usize::MAX, allow_line_wrap: false };
+        let positions = potential_link_positions(content, &["docs".to_string(), "body".to_string()], &options);
+        let found: Vec<&str> = positions.iter().map(|p| p.keyword.as_str()).collect();
+        assert_eq!(found, ["body"]);
+    }
+
+    #[test]
+    fn accented_letters_before_a_keyword_join_its_word() {
+        let content = "Crème apple, éapple, Éapple and 😀apple.\nÉMILE ZOLA wrote it; naïveüber, but Über alles.";
+        let keywords = ["apple", "émile zola", "über"].map(String::from);
+        let options = PositionOptions { include_code: false, max_per_keyword: usize::MAX, allow_line_wrap: false };
+        let positions = potential_link_positions(content, &keywords, &options);
+        let found: Vec<(&str, usize, usize)> = positions.iter().map(|p| (p.matched_text.as_str(), p.line, p.column)).collect();
+        assert_eq!(found, [("apple", 1, 6), ("apple", 1, 33), ("ÉMILE ZOLA", 2, 0), ("Über", 2, 36)]);
+
+        for position in &positions {
+            assert_eq!(&content[position.offset_bytes..position.end_bytes], position.matched_text);
+            assert_eq!(utf16_slice(content, (position.offset_utf16, position.end_utf16)), position.matched_text);
         }
+        // è, é and É are two bytes each; 😀 is four bytes and two UTF-16 units
+        assert_eq!((positions[1].utf16_column, positions[1].byte_column), (34, 39));
     }
 }
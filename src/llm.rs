@@ -1,7 +1,22 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Deserialize)]
+use std::collections::{HashMap, HashSet};
+use crate::http::{self, JsOptions, RequestSettings};
+use crate::keywords::{normalize_keywords, strip_list_marker, DEFAULT_MAX_KEYWORDS};
+use crate::links::{locate_phrase, parse_wiki_links, PhraseMatchKind};
+use crate::tokens::{cut_boundary, estimate_tokens, fit_to_budget, natural_cut, prompt_budget};
+use crate::json_repair::repair_json;
+use crate::responses::{answer_only, combined_score, extract_json_array, extract_json_object, locate_grammar_issues, parse_natural_rankings, Flashcard, FormattingAnalysis, LLMRankingItem};
+use crate::cache::{RankedSuggestion, RerankResult};
+use crate::graph::edit_distance;
+use crate::atomicity::{resolve_atomicity, ProposedAtomicity};
+use crate::digest::{build_digest_prompt, digest_batches, fit_digest_entries, parse_digest_notes, with_digest_title, DigestEntry, DigestResult, DigestStage, DIGEST_MISSING_HEADING, DIGEST_OUTPUT_TOKENS};
+use crate::frontmatter::{match_vault_tags, merge_frontmatter, sanitize_aliases, split_frontmatter, FrontmatterSuggestion, FrontmatterValues, MAX_NEW_TAGS};
+use crate::masking::{mask_protected, placeholder, placeholder_indices, placeholder_regex, restore_protected};
+use crate::extract_title_from_path;
+use gloo_net::http::Response;
+
+#[derive(Serialize, Deserialize, Default)]
 pub struct OllamaOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub num_ctx: Option<u32>,
@@ -9,6 +24,130 @@ pub struct OllamaOptions {
     pub num_predict: Option<i32>, // -1 for infinite, otherwise positive integer
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_thread: Option<u32>,
+}
+
+/// Model options supplied by the plugin as JSON, e.g. `{"top_p": 0.9, "seed": 42}`.
+/// Unset fields leave Ollama's defaults (and our built-in num_ctx) alone.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct ModelOptions {
+    pub num_ctx: Option<u32>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<u32>,
+    pub seed: Option<i64>,
+    pub num_thread: Option<u32>,
+}
+
+impl ModelOptions {
+    fn to_ollama(&self, temperature: Option<f32>) -> OllamaOptions {
+        OllamaOptions {
+            num_ctx: Some(self.num_ctx.unwrap_or(4096)),
+            num_predict: Some(-1), // Infinite generation
+            temperature,
+            top_p: self.top_p,
+            top_k: self.top_k,
+            seed: self.seed,
+            num_thread: self.num_thread,
+        }
+    }
+}
+
+/// Settings shared by the LLM exports, passed from the plugin as one object, e.g.
+/// `{provider: "openai", api_key, timeout_ms: 60000, debug: true, abort_signal}`.
+/// Every field is optional; features ignore the ones they have no use for.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct RequestOptions {
+    /// "ollama" (default) or "openai" for OpenAI-compatible servers
+    pub provider: Option<String>,
+    /// Sent as a Bearer token to OpenAI-compatible servers
+    pub api_key: Option<String>,
+    /// Transient failures (network errors, 5xx) are retried `max_attempts` times (default 3)
+    /// with exponential backoff starting at `retry_base_delay_ms` (default 500)
+    pub max_attempts: Option<u32>,
+    pub retry_base_delay_ms: Option<u32>,
+    /// Deadline for each call to the server (see `RequestSettings::timeout_ms`); unset waits
+    /// indefinitely, since long generations can take minutes
+    pub timeout_ms: Option<u32>,
+    /// How long Ollama keeps the model loaded after the call (e.g. "30m", "-1" for forever)
+    pub keep_alive: Option<String>,
+    /// Sampling temperature; unset leaves the model's default
+    pub temperature: Option<f32>,
+    /// {num_ctx, top_p, top_k, seed, num_thread} passed through to Ollama
+    pub model_options: ModelOptions,
+    /// Constrain the answer with the feature's JSON schema (Ollama only)
+    pub use_schema: bool,
+    /// The model's context length in tokens (e.g. from show_ollama_model); prompts are
+    /// budgeted against it, default 4096
+    pub context_window: Option<u32>,
+    /// Language to answer in, for features that support it
+    pub language: Option<String>,
+    pub enable_thinking: bool,
+    pub debug: bool,
+    /// Aborting cancels the request and rejects with "aborted"
+    #[serde(skip)]
+    pub abort_signal: Option<web_sys::AbortSignal>,
+}
+
+impl JsOptions for RequestOptions {
+    fn set_abort_signal(&mut self, abort_signal: Option<web_sys::AbortSignal>) {
+        self.abort_signal = abort_signal;
+    }
+}
+
+impl RequestOptions {
+    pub fn settings(&self) -> RequestSettings {
+        RequestSettings {
+            abort_signal: self.abort_signal.clone(),
+            ..RequestSettings::from_options(self.max_attempts, self.retry_base_delay_ms, self.timeout_ms)
+        }
+    }
+
+    pub fn backend(&self) -> Result<LlmBackend, JsValue> {
+        LlmBackend::from_options(self.provider.as_deref(), self.api_key.clone())
+    }
+
+    /// `model_options` with num_ctx sized to `context_window` when it is known
+    fn sized_model_options(&self) -> ModelOptions {
+        ModelOptions {
+            num_ctx: self.context_window.filter(|&w| w > 0).or(self.model_options.num_ctx),
+            ..self.model_options.clone()
+        }
+    }
+}
+
+/// Ollama `format` field: the string "json", or a JSON schema the output must match
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResponseFormat {
+    Json,
+    Schema(serde_json::Value),
+}
+
+impl Serialize for ResponseFormat {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ResponseFormat::Json => serializer.serialize_str("json"),
+            ResponseFormat::Schema(schema) => schema.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ResponseFormat {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        Ok(match value.as_str() {
+            Some("json") => ResponseFormat::Json,
+            _ => ResponseFormat::Schema(value),
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -17,11 +156,14 @@ pub struct OllamaGenerateRequest {
     pub prompt: String,
     pub stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub format: Option<String>,
+    pub format: Option<ResponseFormat>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub images: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub options: Option<OllamaOptions>,
+    /// How long Ollama keeps the model loaded after the call (e.g. "30m", "-1" for forever)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -30,17 +172,11 @@ pub struct OllamaGenerateResponse {
     pub done: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct RankedSuggestion {
-    pub path: String,
-    pub title: String,
-    pub similarity: f32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub llm_score: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub llm_reason: Option<String>,
-    pub context: String,
-}
+/// Below this many candidates, reranking isn't worth an LLM round trip
+const DEFAULT_MIN_CANDIDATES_FOR_LLM: usize = 3;
+
+/// Default weight of the LLM score in the blended rerank score
+const DEFAULT_RERANK_ALPHA: f32 = 0.7;
 
 // Input suggestion without LLM data
 #[derive(Serialize, Deserialize, Clone)]
@@ -51,129 +187,152 @@ struct BaseSuggestion {
     pub context: String,
 }
 
-#[derive(Serialize, Deserialize)]
-struct LLMRankingItem {
-    index: usize,
-    score: f32,
-    reason: String,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct GrammarIssue {
-    pub original: String,
-    pub corrected: String,
-    pub reason: String,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct StructureChange {
-    pub title: String,
-    pub description: String,
-    pub markdown_to_insert: String, // The actual text to insert
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct Flashcard {
-    pub question: String,
-    pub answer: String,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct FormattingAnalysis {
-    pub grammar: Vec<GrammarIssue>,
-    pub structure_suggestions: Vec<StructureChange>,
-    pub flashcards: Vec<Flashcard>,
-    pub existing_tags: Vec<String>,
-    pub new_tags: Vec<String>,
-}
-
 #[derive(Serialize, Deserialize)]
 pub struct OrganizationCandidate {
     pub folder: String,
+    #[serde(default)]
     pub confidence: f32, // 0.0 - 1.0
+    #[serde(default)]
     pub reason: String,
+    #[serde(default)]
     pub is_new_path: bool,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct OrganizationResult {
     pub suggestions: Vec<OrganizationCandidate>,
+    /// Folder names rewritten to match the vault (filled in after parsing, not by the model)
+    #[serde(default)]
+    pub corrections: Vec<FolderCorrection>,
 }
 
-/// Extract JSON array from text that might have extra content
-/// Looks for the first `[` and last `]` to extract a JSON array
-fn extract_json_array(text: &str) -> Option<String> {
-    let first_bracket = text.find('[')?;
-    let last_bracket = text.rfind(']')?;
+/// How a suggested folder was corrected against the vault's folder list
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderCorrectionKind {
+    /// Separators or surrounding slashes/whitespace fixed
+    Normalized,
+    /// Matched an existing folder ignoring case
+    Case,
+    /// Matched an existing folder within a small edit distance
+    Fuzzy,
+    /// Not in the vault, so marked as a new path
+    NewPath,
+}
 
-    if last_bracket > first_bracket {
-        Some(text[first_bracket..=last_bracket].to_string())
-    } else {
-        None
-    }
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FolderCorrection {
+    pub original: String,
+    pub corrected: String,
+    pub kind: FolderCorrectionKind,
 }
 
-/// Extract JSON object from text that might have extra content
-/// Looks for the first `{` and last `}` to extract a JSON object
-fn extract_json_object(text: &str) -> Option<String> {
-    let first_brace = text.find('{')?;
-    let last_brace = text.rfind('}')?;
+/// Send a non-streaming /api/generate request and return the response text
+/// The timeout covers the request, its retries and reading the body.
+async fn send_generate_request(
+    endpoint: &str,
+    request_json: &str,
+    settings: &RequestSettings,
+) -> Result<String, JsValue> {
+    http::with_timeout(settings, |settings| async move {
+        send_generate_request_inner(endpoint, request_json, &settings).await
+    }).await
+}
 
-    if last_brace > first_brace {
-        Some(text[first_brace..=last_brace].to_string())
-    } else {
-        None
+async fn send_generate_request_inner(
+    endpoint: &str,
+    request_json: &str,
+    settings: &RequestSettings,
+) -> Result<String, JsValue> {
+    let response = http::post_json(&format!("{}/api/generate", endpoint), request_json, settings)
+        .await
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    settings.check_aborted().map_err(|e| JsValue::from_str(&e))?;
+
+    if !response.ok() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(JsValue::from_str(&format!("HTTP {}: {}", status, error_text)));
     }
+
+    let generate_response: OllamaGenerateResponse = response
+        .json()
+        .await
+        .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
+
+    Ok(generate_response.response)
 }
 
-/// Generate text completion using Ollama
+/// Generate text completion using Ollama.
+/// `options` is a RequestOptions object: `temperature`, retries, `timeout_ms`, `abort_signal`,
+/// `keep_alive` and `model_options` apply; with no timeout the call waits indefinitely (long MOC
+/// generations can take minutes).
+/// `<think>` reasoning blocks are removed unless `strip_think` is false.
 #[wasm_bindgen]
 pub async fn generate_text_ollama(
+    endpoint: String,
+    model: String,
+    prompt: String,
+    json_format: bool,
+    options: Option<JsValue>,
+    strip_think: Option<bool>,
+) -> Result<String, JsValue> {
+    let options = RequestOptions::from_js(options, "generate_text_ollama")?;
+    let format = json_format.then_some(ResponseFormat::Json);
+    let response = generate_text_with_format(endpoint, model, prompt, options.temperature, format, &options).await?;
+
+    Ok(if strip_think.unwrap_or(true) { answer_only(&response) } else { response })
+}
+
+/// Generate text completion (internal entry point for the LLM features).
+/// Reasoning blocks are stripped so callers only ever parse the answer.
+pub(crate) async fn generate_text(
     endpoint: String,
     model: String,
     prompt: String,
     temperature: Option<f32>,
     json_format: bool,
+    request: &RequestOptions,
+) -> Result<String, JsValue> {
+    let response = match request.backend()? {
+        LlmBackend::Ollama => {
+            let format = json_format.then_some(ResponseFormat::Json);
+            generate_text_with_format(endpoint, model, prompt, temperature, format, request).await
+        }
+        LlmBackend::OpenAICompatible { api_key } => {
+            let messages = vec![openai_text_message("user", &prompt)];
+            openai_chat(&endpoint, &model, messages, temperature, json_format, api_key.as_deref(), request).await
+        }
+    }?;
+
+    Ok(answer_only(&response))
+}
+
+/// Ollama /api/generate with an explicit `format` (plain JSON or a schema)
+pub(crate) async fn generate_text_with_format(
+    endpoint: String,
+    model: String,
+    prompt: String,
+    temperature: Option<f32>,
+    format: Option<ResponseFormat>,
+    request: &RequestOptions,
 ) -> Result<String, JsValue> {
-    let request = OllamaGenerateRequest {
+    let request_body = OllamaGenerateRequest {
         model,
         prompt,
         stream: false,
-        format: if json_format { Some("json".to_string()) } else { None },
+        format,
         images: None,
-        options: Some(OllamaOptions {
-            num_ctx: Some(4096),
-            num_predict: Some(-1), // Infinite generation
-            temperature,
-        }),
+        options: Some(request.sized_model_options().to_ollama(temperature)),
+        keep_alive: request.keep_alive.clone(),
     };
 
-    let request_json = serde_json::to_string(&request).map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))?;
-
-    web_sys::console::log_1(&format!("[Rust] generate_text_ollama Request: {}", request_json).into());
-
-    let client = gloo_net::http::Request::post(&format!("{}/api/generate", endpoint))
-        .header("Content-Type", "application/json")
-        .body(request_json)
-        .map_err(|e| JsValue::from_str(&format!("Request error: {}", e)))?;
-
-    let response = client
-        .send()
-        .await
-        .map_err(|e| JsValue::from_str(&format!("Network error: {}", e)))?;
-
-    if !response.ok() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(JsValue::from_str(&format!("HTTP {}: {}", status, error_text)));
-    }
+    let request_json = serde_json::to_string(&request_body).map_err(|e| JsValue::from_str(&format!("Serialization error in generate_text_with_format: {}", e)))?;
 
-    let generate_response: OllamaGenerateResponse = response
-        .json()
-        .await
-        .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
+    log_debug!("[Rust] generate_text_ollama Request: {}", request_json);
 
-    Ok(generate_response.response)
+    send_generate_request(&endpoint, &request_json, &request.settings()).await
 }
 
 pub async fn generate_text_with_images_ollama(
@@ -182,54 +341,396 @@ pub async fn generate_text_with_images_ollama(
     prompt: String,
     images: Vec<String>,
     temperature: Option<f32>,
+    request: &RequestOptions,
 ) -> Result<String, JsValue> {
-    let request = OllamaGenerateRequest {
+    if let LlmBackend::OpenAICompatible { api_key } = request.backend()? {
+        let mut content = vec![serde_json::json!({ "type": "text", "text": prompt })];
+        content.extend(images.iter().map(|image| serde_json::json!({
+            "type": "image_url",
+            "image_url": { "url": format!("data:image/png;base64,{}", image) },
+        })));
+        let messages = vec![serde_json::json!({ "role": "user", "content": content })];
+        let response = openai_chat(&endpoint, &model, messages, temperature, false, api_key.as_deref(), request).await?;
+        return Ok(answer_only(&response));
+    }
+
+    let request_body = OllamaGenerateRequest {
         model,
         prompt,
         stream: false,
         format: None,
         images: Some(images),
-        options: Some(OllamaOptions {
-            num_ctx: Some(4096),
-            num_predict: Some(-1),
-            temperature,
-        }),
+        options: Some(request.sized_model_options().to_ollama(temperature)),
+        keep_alive: request.keep_alive.clone(),
     };
 
-    let request_json = serde_json::to_string(&request).map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))?;
-    
-    web_sys::console::log_1(&format!("[Rust] Sending request to Ollama: {}/api/generate", endpoint).into());
-    web_sys::console::log_1(&format!("[Rust] Request Body: {}", request_json).into());
+    let request_json = serde_json::to_string(&request_body).map_err(|e| JsValue::from_str(&format!("Serialization error in generate_text_with_images_ollama: {}", e)))?;
 
-    let client = gloo_net::http::Request::post(&format!("{}/api/generate", endpoint))
-        .header("Content-Type", "application/json")
-        .body(request_json)
-        .map_err(|e| JsValue::from_str(&format!("Request error: {}", e)))?;
+    log_debug!("[Rust] Sending request to Ollama: {}/api/generate", endpoint);
+    log_debug!("[Rust] Request Body: {}", request_json);
 
-    web_sys::console::log_1(&"[Rust] Request built, sending...".into());
+    let response = send_generate_request(&endpoint, &request_json, &request.settings()).await?;
+    Ok(answer_only(&response))
+}
 
-    let response = client
-        .send()
-        .await
-        .map_err(|e| JsValue::from_str(&format!("Network error: {}", e)))?;
+/// API flavour spoken by the LLM endpoint
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum LlmBackend {
+    #[default]
+    Ollama,
+    /// `/v1/chat/completions` servers (LM Studio, OpenRouter, vLLM, ...)
+    OpenAICompatible { api_key: Option<String> },
+}
 
-    web_sys::console::log_1(&"[Rust] Response received, parsing...".into());
+impl LlmBackend {
+    /// Build from the plugin's provider setting: None or "ollama", or "openai" for OpenAI-compatible servers
+    pub fn from_options(provider: Option<&str>, api_key: Option<String>) -> Result<Self, JsValue> {
+        match provider.map(|p| p.trim().to_lowercase()).as_deref() {
+            None | Some("") | Some("ollama") => Ok(LlmBackend::Ollama),
+            Some("openai") | Some("openai_compatible") | Some("openai-compatible") => {
+                Ok(LlmBackend::OpenAICompatible { api_key: api_key.filter(|k| !k.is_empty()) })
+            }
+            Some(other) => Err(JsValue::from_str(&format!("Unknown LLM provider: {}", other))),
+        }
+    }
+}
 
-    if !response.ok() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(JsValue::from_str(&format!("HTTP {}: {}", status, error_text)));
+#[derive(Serialize)]
+struct OpenAIChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [serde_json::Value],
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct OpenAIChatResponse {
+    choices: Vec<OpenAIChoice>,
+}
+
+#[derive(Deserialize)]
+struct OpenAIChoice {
+    message: OpenAIResponseMessage,
+}
+
+#[derive(Deserialize)]
+struct OpenAIResponseMessage {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+fn openai_text_message(role: &str, content: &str) -> serde_json::Value {
+    serde_json::json!({ "role": role, "content": content })
+}
+
+/// Chat completions URL for a base endpoint, with or without a trailing /v1
+fn openai_chat_url(endpoint: &str) -> String {
+    let base = endpoint.trim_end_matches('/');
+    if base.ends_with("/v1") {
+        format!("{}/chat/completions", base)
+    } else {
+        format!("{}/v1/chat/completions", base)
     }
+}
 
-    let generate_response: OllamaGenerateResponse = response
-        .json()
-        .await
-        .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
+/// Send messages to an OpenAI-compatible /v1/chat/completions endpoint and return the reply text.
+/// `json_format` asks for `response_format: {type: "json_object"}`; servers that reject it are retried without.
+/// max_tokens is left unset, matching Ollama's unlimited num_predict.
+async fn openai_chat(
+    endpoint: &str,
+    model: &str,
+    messages: Vec<serde_json::Value>,
+    temperature: Option<f32>,
+    json_format: bool,
+    api_key: Option<&str>,
+    request: &RequestOptions,
+) -> Result<String, JsValue> {
+    let options = &request.model_options;
+    http::with_timeout(&request.settings(), |settings| async move {
+        let authorization = api_key.map(|key| format!("Bearer {}", key));
+        let headers: Vec<(&str, &str)> = authorization.iter().map(|a| ("Authorization", a.as_str())).collect();
+        let url = openai_chat_url(endpoint);
+
+        let mut use_json_format = json_format;
+        loop {
+            let request = OpenAIChatRequest {
+                model,
+                messages: &messages,
+                stream: false,
+                temperature,
+                top_p: options.top_p,
+                seed: options.seed,
+                response_format: use_json_format.then(|| serde_json::json!({ "type": "json_object" })),
+            };
+            let request_json = serde_json::to_string(&request).map_err(|e| format!("Serialization error in openai_chat: {}", e))?;
+
+            let response = http::post_json_with_headers(&url, &request_json, &headers, &settings).await?;
+            settings.check_aborted()?;
+
+            if !response.ok() {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                // Not every server implements response_format; the prompt still asks for JSON
+                if use_json_format && (status == 400 || status == 422) {
+                    log_warn!("Server rejected response_format ({}), retrying without it", error_text);
+                    use_json_format = false;
+                    continue;
+                }
+                return Err(format!("HTTP {}: {}", status, error_text));
+            }
 
-    Ok(generate_response.response)
+            let chat_response: OpenAIChatResponse = response
+                .json()
+                .await
+                .map_err(|e| format!("Parse error: {}", e))?;
+
+            return chat_response.choices.into_iter().next()
+                .and_then(|choice| choice.message.content)
+                .ok_or_else(|| "Response contained no message content".to_string());
+        }
+    })
+    .await
+    .map_err(|e: String| JsValue::from_str(&e))
+}
+
+/// Generate text with either backend (`provider` and `api_key` in `options` pick it).
+/// Otherwise behaves like generate_text_ollama.
+#[wasm_bindgen]
+pub async fn generate_text_llm(
+    endpoint: String,
+    model: String,
+    prompt: String,
+    json_format: bool,
+    options: Option<JsValue>,
+) -> Result<String, JsValue> {
+    let options = RequestOptions::from_js(options, "generate_text_llm")?;
+    generate_text(endpoint, model, prompt, options.temperature, json_format, &options).await
+}
+
+/// JSON schema for extract_keywords_with_llm: an array of keyword strings
+pub const KEYWORDS_SCHEMA: &str = r#"{
+  "type": "array",
+  "items": { "type": "string" }
+}"#;
+
+/// JSON schema for suggest_title_with_llm
+pub const TITLES_SCHEMA: &str = r#"{
+  "type": "object",
+  "properties": {
+    "titles": { "type": "array", "items": { "type": "string" } }
+  },
+  "required": ["titles"]
+}"#;
+
+/// JSON schema for generate_flashcards_with_llm
+pub const FLASHCARDS_SCHEMA: &str = r#"{
+  "type": "object",
+  "properties": {
+    "flashcards": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "properties": {
+          "question": { "type": "string" },
+          "answer": { "type": "string" }
+        },
+        "required": ["question", "answer"]
+      }
+    }
+  },
+  "required": ["flashcards"]
+}"#;
+
+/// JSON schema for rerank_suggestions_with_llm: one {index, score, reason} per candidate
+pub const RANKING_SCHEMA: &str = r#"{
+  "type": "array",
+  "items": {
+    "type": "object",
+    "properties": {
+      "index": { "type": "integer" },
+      "score": { "type": "number" },
+      "reason": { "type": "string" }
+    },
+    "required": ["index", "score", "reason"]
+  }
+}"#;
+
+/// JSON schema for FormattingAnalysis
+pub const FORMATTING_SCHEMA: &str = r#"{
+  "type": "object",
+  "properties": {
+    "grammar": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "properties": {
+          "original": { "type": "string" },
+          "corrected": { "type": "string" },
+          "reason": { "type": "string" },
+          "context": { "type": "string" }
+        },
+        "required": ["original", "corrected", "reason", "context"]
+      }
+    },
+    "structure_suggestions": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "properties": {
+          "title": { "type": "string" },
+          "description": { "type": "string" },
+          "markdown_to_insert": { "type": "string" }
+        },
+        "required": ["title", "description", "markdown_to_insert"]
+      }
+    },
+    "flashcards": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "properties": {
+          "question": { "type": "string" },
+          "answer": { "type": "string" }
+        },
+        "required": ["question", "answer"]
+      }
+    },
+    "existing_tags": { "type": "array", "items": { "type": "string" } },
+    "new_tags": { "type": "array", "items": { "type": "string" } }
+  },
+  "required": ["grammar", "structure_suggestions", "flashcards", "existing_tags", "new_tags"]
+}"#;
+
+/// JSON schema for analyze_atomicity_with_llm's proposal
+pub const ATOMICITY_SCHEMA: &str = r#"{
+  "type": "object",
+  "properties": {
+    "score": { "type": "number" },
+    "reason": { "type": "string" },
+    "children": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "properties": {
+          "title": { "type": "string" },
+          "reason": { "type": "string" },
+          "heading": { "type": ["string", "null"] },
+          "start_quote": { "type": "string" },
+          "end_quote": { "type": "string" },
+          "links": { "type": "array", "items": { "type": "string" } }
+        },
+        "required": ["title", "reason", "heading", "start_quote", "end_quote", "links"]
+      }
+    }
+  },
+  "required": ["score", "reason", "children"]
+}"#;
+
+/// JSON schema for generate_frontmatter_with_llm
+pub const FRONTMATTER_SCHEMA: &str = r#"{
+  "type": "object",
+  "properties": {
+    "summary": { "type": "string" },
+    "existing_tags": { "type": "array", "items": { "type": "string" } },
+    "new_tags": { "type": "array", "items": { "type": "string" } },
+    "aliases": { "type": "array", "items": { "type": "string" } }
+  },
+  "required": ["summary", "existing_tags", "new_tags", "aliases"]
+}"#;
+
+/// JSON schema for OrganizationResult
+pub const ORGANIZATION_SCHEMA: &str = r#"{
+  "type": "object",
+  "properties": {
+    "suggestions": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "properties": {
+          "folder": { "type": "string" },
+          "confidence": { "type": "number" },
+          "reason": { "type": "string" },
+          "is_new_path": { "type": "boolean" }
+        },
+        "required": ["folder", "confidence", "reason", "is_new_path"]
+      }
+    }
+  },
+  "required": ["suggestions"]
+}"#;
+
+fn parse_schema(schema: &str) -> serde_json::Value {
+    serde_json::from_str(schema).expect("built-in schema constants are valid JSON")
+}
+
+/// Generate constrained by `schema` when set. Older Ollama versions reject non-string formats
+/// with HTTP 400; then (and for non-Ollama backends) this falls back to plain `fallback_json` mode.
+pub(crate) async fn generate_structured(
+    endpoint: String,
+    model: String,
+    prompt: String,
+    temperature: Option<f32>,
+    schema: Option<&str>,
+    fallback_json: bool,
+    request: &RequestOptions,
+) -> Result<String, JsValue> {
+    let backend = request.backend()?;
+    if let (Some(schema), LlmBackend::Ollama) = (schema, &backend) {
+        let format = Some(ResponseFormat::Schema(parse_schema(schema)));
+        match generate_text_with_format(endpoint.clone(), model.clone(), prompt.clone(), temperature, format, request).await {
+            Ok(response) => return Ok(answer_only(&response)),
+            Err(e) if e.as_string().is_some_and(|e| e.starts_with("HTTP 400")) => {
+                log_warn!("Server rejected JSON schema format ({:?}), retrying with plain JSON mode", e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    generate_text(endpoint, model, prompt, temperature, fallback_json, request).await
+}
+
+/// Tokens left for the model's answer when budgeting prompts
+const RERANK_OUTPUT_TOKENS: usize = 1024;
+const INSERTION_OUTPUT_TOKENS: usize = 512;
+const FORMATTING_OUTPUT_TOKENS: usize = 2048;
+const ATOMICITY_OUTPUT_TOKENS: usize = 1024;
+const FRONTMATTER_OUTPUT_TOKENS: usize = 512;
+/// Extra answer tokens reserved when thinking mode is on
+const THINKING_OUTPUT_TOKENS: usize = 1024;
+
+/// Options of rerank_suggestions_with_llm: the shared RequestOptions plus how candidates are
+/// scored
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct RerankOptions {
+    #[serde(flatten)]
+    pub request: RequestOptions,
+    /// Weight of the LLM score in the blended score, 0.0 - 1.0 (default 0.7)
+    pub alpha: Option<f32>,
+    /// Path of the note being edited; it is never suggested as a link to itself
+    pub current_doc_path: Option<String>,
+    /// Below this many candidates the LLM is skipped (default 3)
+    pub min_candidates_for_llm: Option<usize>,
+}
+
+impl JsOptions for RerankOptions {
+    fn set_abort_signal(&mut self, abort_signal: Option<web_sys::AbortSignal>) {
+        self.request.abort_signal = abort_signal;
+    }
 }
 
-/// Rerank link suggestions using LLM analysis
+/// Rerank link suggestions using LLM analysis. `options` is a RerankOptions object; prompts
+/// are budgeted against its `context_window`.
+/// Returns {suggestions, skipped_llm, ranking_coverage}, sorted by blended score, equal scores
+/// by path. With fewer than `min_candidates_for_llm` candidates (default 3) or an empty
+/// document, the LLM is skipped and suggestions are ranked on similarity alone.
 #[wasm_bindgen]
 pub async fn rerank_suggestions_with_llm(
     endpoint: String,
@@ -237,72 +738,123 @@ pub async fn rerank_suggestions_with_llm(
     current_doc_title: String,
     current_doc_content: String,
     suggestions_json: String,
-    temperature: f32,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let RerankOptions { request: options, alpha, current_doc_path, min_candidates_for_llm } =
+        RerankOptions::from_js(options, "rerank_suggestions_with_llm")?;
+    let RequestOptions { temperature, enable_thinking, debug, use_schema, context_window, .. } = options;
+    let request = RerankRequest {
+        model: model.clone(),
+        current_doc_title,
+        current_doc_content,
+        suggestions_json,
+        temperature,
+        enable_thinking,
+        debug,
+        use_schema,
+        alpha: alpha.unwrap_or(DEFAULT_RERANK_ALPHA).clamp(0.0, 1.0),
+        current_doc_path,
+        context_window,
+        min_candidates_for_llm: min_candidates_for_llm.unwrap_or(DEFAULT_MIN_CANDIDATES_FOR_LLM),
+    };
+    // CRITICAL: Use json_format=false for natural language responses
+    // If true, the model will try to structure the prompt itself as JSON!
+    let send = |prompt: String| generate_structured(
+        endpoint,
+        model,
+        prompt,
+        temperature,
+        use_schema.then_some(RANKING_SCHEMA),
+        false, // Natural language output, not JSON
+        &options,
+    );
+    let result = rerank_candidates(request, send).await?;
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in rerank_suggestions_with_llm: {}", e)))
+}
+
+/// The arguments of rerank_suggestions_with_llm that shape the prompt and the ranking, with the
+/// defaults applied
+struct RerankRequest {
+    /// Only logged; the sender talks to the model
+    model: String,
+    current_doc_title: String,
+    current_doc_content: String,
+    suggestions_json: String,
+    temperature: Option<f32>,
     enable_thinking: bool,
     debug: bool,
-) -> Result<JsValue, JsValue> {
+    use_schema: bool,
+    alpha: f32,
+    current_doc_path: Option<String>,
+    context_window: Option<u32>,
+    min_candidates_for_llm: usize,
+}
+
+/// See rerank_suggestions_with_llm. `send` asks the model and returns its answer; it isn't
+/// called when the LLM is skipped.
+async fn rerank_candidates<F, Fut>(request: RerankRequest, send: F) -> Result<RerankResult, JsValue>
+where
+    F: FnOnce(String) -> Fut,
+    Fut: std::future::Future<Output = Result<String, JsValue>>,
+{
+    let RerankRequest {
+        model,
+        current_doc_title,
+        current_doc_content,
+        suggestions_json,
+        temperature,
+        enable_thinking,
+        debug,
+        use_schema,
+        alpha,
+        current_doc_path,
+        context_window,
+        min_candidates_for_llm,
+    } = request;
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] rerank_suggestions_with_llm called with {} suggestions",
-            suggestions_json.matches("\"path\"").count()).into());
+        log_debug!("rerank_suggestions_with_llm called with {} suggestions",
+            suggestions_json.matches("\"path\"").count());
     }
 
     // Parse incoming suggestions (they don't have LLM scores yet)
     let base_suggestions: Vec<BaseSuggestion> = serde_json::from_str(&suggestions_json)
         .map_err(|e| {
-            web_sys::console::log_1(&format!("[ERROR] Parse suggestions error: {}", e).into());
-            web_sys::console::log_1(&format!("[ERROR] Suggestions JSON: {}", suggestions_json).into());
+            log_error!("Parse suggestions error: {}", e);
+            log_error!("Suggestions JSON: {}", suggestions_json);
             JsValue::from_str(&format!("Parse suggestions error: {}", e))
         })?;
 
-    if base_suggestions.is_empty() {
+    let base_suggestions = clean_candidates(base_suggestions, current_doc_path.as_deref());
+
+    // Nothing for the LLM to add: return the candidates ranked by similarity right away
+    if base_suggestions.len() < min_candidates_for_llm || current_doc_content.trim().is_empty() {
         if debug {
-            web_sys::console::log_1(&"[DEBUG] No suggestions to rerank".into());
+            log_debug!("Skipping LLM rerank: {} candidates (minimum {}), document empty: {}",
+                base_suggestions.len(), min_candidates_for_llm, current_doc_content.trim().is_empty());
         }
-        return serde_wasm_bindgen::to_value(&Vec::<RankedSuggestion>::new())
-            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)));
+        let mut suggestions: Vec<RankedSuggestion> = base_suggestions.iter()
+            .map(|s| embedding_only_suggestion(s, alpha))
+            .collect();
+        suggestions.sort_by(|a, b| b.combined_score.total_cmp(&a.combined_score).then_with(|| a.path.cmp(&b.path)));
+        return Ok(RerankResult { suggestions, skipped_llm: true, ranking_coverage: 0 });
     }
 
     if debug {
-        web_sys::console::log_1(&"[DEBUG] ========== INPUT SUGGESTIONS ==========".into());
-        web_sys::console::log_1(&format!("[DEBUG] Successfully parsed {} base suggestions:", base_suggestions.len()).into());
+        log_debug!("========== INPUT SUGGESTIONS ==========");
+        log_debug!("Successfully parsed {} base suggestions:", base_suggestions.len());
         for (i, sugg) in base_suggestions.iter().enumerate() {
-            web_sys::console::log_1(&format!(
-                "[DEBUG]   {}. \"{}\" (similarity: {:.3}, path: {})",
+            log_debug!(
+                "  {}. \"{}\" (similarity: {:.3}, path: {})",
                 i + 1,
                 sugg.title,
                 sugg.similarity,
                 sugg.path
-            ).into());
+            );
         }
-        web_sys::console::log_1(&"[DEBUG] ========== END INPUT SUGGESTIONS ==========".into());
-    }
-
-    // Truncate document content for LLM context (first 800 chars)
-    let doc_preview = if current_doc_content.len() > 800 {
-        format!("{}...", &current_doc_content[..800])
-    } else {
-        current_doc_content.clone()
-    };
-
-    if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Document preview length: {} chars", doc_preview.len()).into());
+        log_debug!("========== END INPUT SUGGESTIONS ==========");
     }
 
-    // Build candidate list for prompt
-    let candidates_text: Vec<String> = base_suggestions
-        .iter()
-        .enumerate()
-        .map(|(i, s)| {
-            format!(
-                "{}. Title: \"{}\"\n   Embedding Similarity: {:.2}\n   Context: {}",
-                i + 1,
-                s.title,
-                s.similarity,
-                s.context
-            )
-        })
-        .collect();
-
     let thinking_instructions = if enable_thinking {
         r#"
 
@@ -317,20 +869,26 @@ After thinking, provide your final rankings as a JSON array."#
         ""
     };
 
-    // Build the exact template the LLM should fill in
-    let template_entries: Vec<String> = (1..=base_suggestions.len())
-        .map(|i| format!(
-            "  {{\"index\": {}, \"score\": <score>, \"reason\": \"<reason>\"}}{}",
-            i,
-            if i < base_suggestions.len() { "," } else { "" }
-        ))
-        .collect();
+    let format_rules = if use_schema {
+        r#"Respond with a JSON array containing one object per document:
+[{"index": 1, "score": 8.5, "reason": "..."}, ...]"#
+    } else {
+        r#"STRICT FORMATTING RULES:
+- Output ONLY the rankings in the exact format below.
+- Do NOT use Markdown formatting (no bolding, no italics).
+- Do NOT surround text with asterisks (e.g. **Document 1** is FORBIDDEN).
+- Do NOT include the document title in the output line.
+- Keep each ranking on a SINGLE line.
 
-    let template = format!("[\n{}\n]", template_entries.join("\n"));
+Required Format:
+Document 1: [score] - [reason]
+Document 2: [score] - [reason]
+..."#
+    };
 
     // CREATIVE APPROACH: Ask for reasoning first, then structured output
     // This works better with smaller models that prefer natural language
-    let prompt = format!(
+    let build_prompt = |doc_text: &str, candidates_text: &str| format!(
         r#"You are ranking {} documents for relevance to the current document.
 
 Current Document: "{}"
@@ -345,177 +903,117 @@ TASK: For EACH of the {} documents above, provide:
 1. A relevance score (0.0 to 10.0, where 10 is most relevant)
 2. A brief reason (max 15 words)
 
-STRICT FORMATTING RULES:
-- Output ONLY the rankings in the exact format below.
-- Do NOT use Markdown formatting (no bolding, no italics).
-- Do NOT surround text with asterisks (e.g. **Document 1** is FORBIDDEN).
-- Do NOT include the document title in the output line.
-- Keep each ranking on a SINGLE line.
-
-Required Format:
-Document 1: [score] - [reason]
-Document 2: [score] - [reason]
-...
+{}
 
 Make sure you analyze ALL {} documents. Do not skip any!"#,
         base_suggestions.len(),
         current_doc_title,
-        doc_preview,
-        candidates_text.join("\n\n"),
+        doc_text,
+        candidates_text,
         thinking_instructions,
         base_suggestions.len(),
+        format_rules,
         base_suggestions.len()
     );
 
-    // Call LLM
+    // Budget the document and candidate contexts against the context window; the document
+    // is trimmed before the contexts the model is actually ranking
+    let format_candidate = |i: usize, s: &BaseSuggestion, context: &str| format!(
+        "{}. Title: \"{}\"\n   Embedding Similarity: {:.2}\n   Context: {}",
+        i + 1,
+        s.title,
+        s.similarity,
+        context
+    );
+    let skeleton: Vec<String> = base_suggestions.iter().enumerate()
+        .map(|(i, s)| format_candidate(i, s, ""))
+        .collect();
+    let overhead = estimate_tokens(&build_prompt("", &skeleton.join("\n\n")));
+    let output_tokens = RERANK_OUTPUT_TOKENS + if enable_thinking { THINKING_OUTPUT_TOKENS } else { 0 };
+    let budget = prompt_budget(context_window, output_tokens, overhead);
+
+    let mut parts: Vec<(&str, u8)> = vec![(current_doc_content.as_str(), 0)];
+    parts.extend(base_suggestions.iter().map(|s| (s.context.as_str(), 1)));
+    let mut fitted = fit_to_budget(parts, budget).into_iter();
+    let doc_preview = fitted.next().unwrap_or_default();
+    let candidates_text: Vec<String> = base_suggestions.iter().zip(fitted).enumerate()
+        .map(|(i, (s, context))| format_candidate(i, s, &context))
+        .collect();
+
     if debug {
-        web_sys::console::log_1(&"[DEBUG] ========== LLM RERANKING REQUEST ==========".into());
-        web_sys::console::log_1(&format!("[DEBUG] Model: {}", model).into());
-        web_sys::console::log_1(&format!("[DEBUG] Temperature: {}", temperature).into());
-        web_sys::console::log_1(&format!("[DEBUG] Candidates sent: {}", base_suggestions.len()).into());
-        web_sys::console::log_1(&"[DEBUG] ========== FULL PROMPT ==========".into());
-        web_sys::console::log_1(&prompt.clone().into());
-        web_sys::console::log_1(&"[DEBUG] ========== END PROMPT ==========".into());
+        log_debug!("Prompt budget: {} tokens, document preview: {} of {} tokens",
+            budget, estimate_tokens(&doc_preview), estimate_tokens(&current_doc_content));
     }
 
-    // CRITICAL: Use json_format=false for natural language responses
-    // If true, the model will try to structure the prompt itself as JSON!
-    let response_text = generate_text_ollama(
-        endpoint,
-        model,
-        prompt,
-        Some(temperature),
-        false,  // Natural language output, not JSON
-    )
-    .await?;
+    let prompt = build_prompt(&doc_preview, &candidates_text.join("\n\n"));
 
+    // Call LLM
     if debug {
-        web_sys::console::log_1(&"[DEBUG] ========== LLM RESPONSE ==========".into());
-        web_sys::console::log_1(&format!("[DEBUG] Response length: {} chars", response_text.len()).into());
-        web_sys::console::log_1(&response_text.clone().into());
-        web_sys::console::log_1(&"[DEBUG] ========== END RESPONSE ==========".into());
+        log_debug!("========== LLM RERANKING REQUEST ==========");
+        log_debug!("Model: {}", model);
+        log_debug!("Temperature: {:?}", temperature);
+        log_debug!("Candidates sent: {}", base_suggestions.len());
+        log_debug!("========== FULL PROMPT ==========");
+        log_trace!("{}", prompt);
+        log_debug!("========== END PROMPT ==========");
     }
 
-    // Parse natural language response: "Document 1: 8.5 - Directly related to..."
-    // Format: Document N: [score] - [reason]
-    let mut llm_rankings: Vec<LLMRankingItem> = Vec::new();
+    let response_text = send(prompt).await?;
 
-    for line in response_text.lines() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
+    if debug {
+        log_debug!("========== LLM RESPONSE ==========");
+        log_debug!("Response length: {} chars", response_text.len());
+        log_trace!("{}", response_text);
+        log_debug!("========== END RESPONSE ==========");
+    }
 
-        // Match patterns:
-        // "Document 1: 8.5 - Reason"
-        // "Doc 1: 8.5 - Reason"
-        // "1. 8.5 - Reason"
-        // "Source 1: 8.5"
-        
-        let lower = trimmed.to_lowercase();
-        let mut index_part = "";
-        let mut rest_part = "";
-
-        // Try to identify the start
-        if let Some(idx) = lower.find("document ") {
-            let after = &trimmed[idx + 9..];
-            if let Some(colon) = after.find(':') {
-                index_part = &after[..colon];
-                rest_part = &after[colon+1..];
-            }
-        } else if let Some(idx) = lower.find("doc ") {
-            let after = &trimmed[idx + 4..];
-            if let Some(colon) = after.find(':') {
-                index_part = &after[..colon];
-                rest_part = &after[colon+1..];
-            }
-        } else if let Some(idx) = lower.find("source ") {
-            let after = &trimmed[idx + 7..];
-            if let Some(colon) = after.find(':') {
-                index_part = &after[..colon];
-                rest_part = &after[colon+1..];
-            }
-        } else {
-             // Try "1. 8.5" format
-             if let Some(dot) = trimmed.find('.') {
-                 let potential_idx = &trimmed[..dot];
-                 if potential_idx.trim().parse::<usize>().is_ok() {
-                     index_part = potential_idx;
-                     rest_part = &trimmed[dot+1..];
-                 }
-             }
-        }
-
-        if !index_part.is_empty() {
-            let index_str = index_part.trim().replace("#", "").replace("*", "");
-             if let Ok(index) = index_str.parse::<usize>() {
-                // Parse score/reason from rest_part
-                let content = rest_part.trim();
-                
-                // Expect "8.5 - Reason" or "8.5: Reason" or just "8.5"
-                // Find separator (dash or colon or space)
-                let mut score_str = "";
-                let mut reason = "";
-                
-                if let Some(dash) = content.find(" - ") {
-                    score_str = &content[..dash];
-                    reason = &content[dash+3..];
-                } else if let Some(dash) = content.find('-') {
-                     // check if it's a negative number or separator? 
-                     // usually score is positive. assume separator.
-                     score_str = &content[..dash];
-                     reason = &content[dash+1..];
-                } else if let Some(colon) = content.find(':') {
-                     score_str = &content[..colon];
-                     reason = &content[colon+1..];
-                } else {
-                    // Maybe just score?
-                    score_str = content;
-                    reason = "Relevant";
-                }
+    // Schema-constrained responses are a plain JSON array; anything else goes through the parsers below
+    let mut llm_rankings: Vec<LLMRankingItem> = if use_schema {
+        serde_json::from_str(response_text.trim()).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
 
-                if let Ok(score) = score_str.trim().replace("*", "").parse::<f32>() {
-                      llm_rankings.push(LLMRankingItem {
-                            index,
-                            score,
-                            reason: reason.trim().to_string(),
-                        });
-                }
-             }
-        }
+    let parsed_as_schema = !llm_rankings.is_empty();
+
+    // Parse natural language response: "Document 1: 8.5 - Directly related to..."
+    // Format: Document N: [score] - [reason]
+    if !parsed_as_schema {
+        llm_rankings = parse_natural_rankings(&response_text, base_suggestions.len());
     }
 
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Parsed {} rankings from natural language response", llm_rankings.len()).into());
+        log_debug!("Parsed {} rankings from natural language response", llm_rankings.len());
     }
 
     // Fallback: try JSON parsing if natural language failed
     if llm_rankings.is_empty() {
         if debug {
-            web_sys::console::log_1(&"[DEBUG] Natural language parsing failed, trying JSON...".into());
+            log_debug!("Natural language parsing failed, trying JSON...");
         }
 
         let json_text = extract_json_array(&response_text).unwrap_or(response_text.clone());
+        let json_text = repair_json(&json_text).unwrap_or(json_text);
         llm_rankings = match serde_json::from_str::<Vec<LLMRankingItem>>(&json_text) {
         Ok(rankings) => rankings,
         Err(array_err) => {
             if debug {
-                web_sys::console::log_1(&format!("[DEBUG] Failed to parse as plain array: {}", array_err).into());
-                web_sys::console::log_1(&"[DEBUG] Attempting to parse as single object...".into());
+                log_debug!("Failed to parse as plain array: {}", array_err);
+                log_debug!("Attempting to parse as single object...");
             }
 
             // Try parsing as a single LLMRankingItem (LLM returned just one object instead of array)
             match serde_json::from_str::<LLMRankingItem>(&json_text) {
                 Ok(single_item) => {
                     if debug {
-                        web_sys::console::log_1(&format!("[DEBUG] LLM returned single object instead of array - wrapping in array").into());
+                        log_debug!("LLM returned single object instead of array - wrapping in array");
                     }
                     vec![single_item]
                 },
                 Err(single_err) => {
                     if debug {
-                        web_sys::console::log_1(&format!("[DEBUG] Failed to parse as single object: {}", single_err).into());
-                        web_sys::console::log_1(&"[DEBUG] Attempting to parse as wrapped object...".into());
+                        log_debug!("Failed to parse as single object: {}", single_err);
+                        log_debug!("Attempting to parse as wrapped object...");
                     }
 
                     // Try parsing as object with "candidates" field
@@ -533,30 +1031,30 @@ Make sure you analyze ALL {} documents. Do not skip any!"#,
                     match serde_json::from_str::<WrappedResponseCandidates>(&json_text) {
                         Ok(wrapped) => {
                             if debug {
-                                web_sys::console::log_1(&format!("[DEBUG] Successfully parsed as wrapped object with {} candidates", wrapped.candidates.len()).into());
+                                log_debug!("Successfully parsed as wrapped object with {} candidates", wrapped.candidates.len());
                             }
                             wrapped.candidates
                         },
                         Err(wrapped_err) => {
                             if debug {
-                                web_sys::console::log_1(&format!("[DEBUG] Failed to parse with 'candidates' field: {}", wrapped_err).into());
-                                web_sys::console::log_1(&"[DEBUG] Attempting to parse with 'indexes' field...".into());
+                                log_debug!("Failed to parse with 'candidates' field: {}", wrapped_err);
+                                log_debug!("Attempting to parse with 'indexes' field...");
                             }
 
                             // Try parsing with "indexes" field
                             match serde_json::from_str::<WrappedResponseIndexes>(&json_text) {
                                 Ok(wrapped) => {
                                     if debug {
-                                        web_sys::console::log_1(&format!("[DEBUG] Successfully parsed as wrapped object with {} indexes", wrapped.indexes.len()).into());
+                                        log_debug!("Successfully parsed as wrapped object with {} indexes", wrapped.indexes.len());
                                     }
                                     wrapped.indexes
                                 },
                                 Err(_indexes_err) => {
                                     // All formats failed - fall back to embedding-only suggestions
                                     if debug {
-                                        web_sys::console::log_1(&format!("[WARNING] LLM reranking failed - invalid JSON format. Response: {}",
-                                            if response_text.len() > 200 { &response_text[..200] } else { &response_text }).into());
-                                        web_sys::console::log_1(&format!("[WARNING] Expected array of {} items, falling back to embedding-only", base_suggestions.len()).into());
+                                        log_warn!("LLM reranking failed - invalid JSON format. Response: {}",
+                                            &response_text[..cut_boundary(&response_text, 200)]);
+                                        log_warn!("Expected array of {} items, falling back to embedding-only", base_suggestions.len());
                                     }
                                     return Err(JsValue::from_str("LLM returned invalid format"));
                                 }
@@ -570,13 +1068,13 @@ Make sure you analyze ALL {} documents. Do not skip any!"#,
     }
 
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Successfully parsed {} LLM rankings (total)", llm_rankings.len()).into());
+        log_debug!("Successfully parsed {} LLM rankings (total)", llm_rankings.len());
     }
 
     // Validate that LLM returned correct number of items
     if llm_rankings.len() != base_suggestions.len() {
-        web_sys::console::log_1(&format!("[WARNING] LLM returned {} items but expected {}. Will include unranked items with embedding scores.",
-            llm_rankings.len(), base_suggestions.len()).into());
+        log_warn!("LLM returned {} items but expected {}. Will include unranked items with embedding scores.",
+            llm_rankings.len(), base_suggestions.len());
     }
 
     // Track which indices were ranked by LLM
@@ -584,110 +1082,131 @@ Make sure you analyze ALL {} documents. Do not skip any!"#,
 
     // Create reranked suggestions from LLM rankings
     let mut reranked: Vec<RankedSuggestion> = Vec::new();
+    let (mut out_of_range, mut duplicates) = (0, 0);
 
     for ranking in llm_rankings {
-        let idx = ranking.index - 1; // Convert 1-indexed to 0-indexed
-        if idx < base_suggestions.len() {
-            ranked_indices.insert(idx);
-            let original = &base_suggestions[idx];
-            reranked.push(RankedSuggestion {
-                path: original.path.clone(),
-                title: original.title.clone(),
-                similarity: original.similarity,
-                llm_score: Some(ranking.score),
-                llm_reason: Some(ranking.reason.clone()),
-                context: original.context.clone(),
-            });
+        // Indices are 1-based, so 0 is out of range too; only the first ranking of an index counts
+        let Some(idx) = ranking.index.checked_sub(1).filter(|&i| i < base_suggestions.len()) else {
+            out_of_range += 1;
+            log_warn!("Ignoring LLM ranking with out-of-range index {} (valid: 1-{})",
+                ranking.index, base_suggestions.len());
+            continue;
+        };
+        if !ranked_indices.insert(idx) {
+            duplicates += 1;
+            log_warn!("Ignoring duplicate LLM ranking for index {}", ranking.index);
+            continue;
         }
+        let original = &base_suggestions[idx];
+        reranked.push(RankedSuggestion {
+            path: original.path.clone(),
+            title: original.title.clone(),
+            similarity: original.similarity,
+            llm_score: Some(ranking.score.clamp(0.0, 10.0)),
+            llm_reason: Some(ranking.reason.clone()),
+            context: original.context.clone(),
+            combined_score: combined_score(Some(ranking.score.clamp(0.0, 10.0)), original.similarity, alpha),
+        });
+    }
+
+    let ranking_coverage = ranked_indices.len();
+    if out_of_range + duplicates > 0 || ranking_coverage < base_suggestions.len() {
+        log_warn!("LLM ranking coverage {}/{} ({} out-of-range, {} duplicate rankings ignored)",
+            ranking_coverage, base_suggestions.len(), out_of_range, duplicates);
     }
 
     // Add unranked suggestions (LLM didn't process them) with embedding scores only
     for (idx, suggestion) in base_suggestions.iter().enumerate() {
         if !ranked_indices.contains(&idx) {
             if debug {
-                web_sys::console::log_1(&format!("[DEBUG] Adding unranked suggestion '{}' with embedding score {:.3}",
-                    suggestion.title, suggestion.similarity).into());
+                log_debug!("Adding unranked suggestion '{}' with embedding score {:.3}",
+                    suggestion.title, suggestion.similarity);
             }
-            reranked.push(RankedSuggestion {
-                path: suggestion.path.clone(),
-                title: suggestion.title.clone(),
-                similarity: suggestion.similarity,
-                llm_score: None,  // No LLM score
-                llm_reason: None,
-                context: suggestion.context.clone(),
-            });
+            reranked.push(embedding_only_suggestion(suggestion, alpha));
         }
     }
 
     if debug {
-        web_sys::console::log_1(&"[DEBUG] ========== OUTPUT SUGGESTIONS ==========".into());
-        web_sys::console::log_1(&format!("[DEBUG] Created {} reranked suggestions ({} from LLM, {} from embeddings only)",
-            reranked.len(), ranked_indices.len(), reranked.len() - ranked_indices.len()).into());
+        log_debug!("========== OUTPUT SUGGESTIONS ==========");
+        log_debug!("Created {} reranked suggestions ({} from LLM, {} from embeddings only), alpha={:.2}",
+            reranked.len(), ranking_coverage, reranked.len() - ranking_coverage, alpha);
 
-        web_sys::console::log_1(&"[DEBUG] Before sorting:".into());
+        log_debug!("Before sorting:");
         for (i, sugg) in reranked.iter().enumerate() {
-            if let Some(llm_score) = sugg.llm_score {
-                web_sys::console::log_1(&format!(
-                    "[DEBUG]   {}. \"{}\" - LLM score: {:.2}, reason: {:?}",
-                    i + 1,
-                    sugg.title,
-                    llm_score,
-                    sugg.llm_reason.as_ref().unwrap_or(&"N/A".to_string())
-                ).into());
-            } else {
-                web_sys::console::log_1(&format!(
-                    "[DEBUG]   {}. \"{}\" - embedding only (similarity: {:.3})",
-                    i + 1,
-                    sugg.title,
-                    sugg.similarity
-                ).into());
-            }
+            log_ranked_suggestion(i, sugg);
         }
     }
 
-    // Sort: LLM-ranked items first (by score), then embedding-only items (by similarity)
-    reranked.sort_by(|a, b| {
-        match (a.llm_score, b.llm_score) {
-            // Both have LLM scores - compare by score
-            (Some(score_a), Some(score_b)) => {
-                score_b.partial_cmp(&score_a).unwrap_or(std::cmp::Ordering::Equal)
-            },
-            // Only a has LLM score - a comes first
-            (Some(_), None) => std::cmp::Ordering::Less,
-            // Only b has LLM score - b comes first
-            (None, Some(_)) => std::cmp::Ordering::Greater,
-            // Neither has LLM score - compare by embedding similarity
-            (None, None) => {
-                b.similarity.partial_cmp(&a.similarity).unwrap_or(std::cmp::Ordering::Equal)
-            }
-        }
-    });
+    // Sort by the blended score; a low LLM score no longer beats a strong embedding-only match
+    reranked.sort_by(|a, b| b.combined_score.total_cmp(&a.combined_score).then_with(|| a.path.cmp(&b.path)));
 
     if debug {
-        web_sys::console::log_1(&"[DEBUG] After sorting (final output):".into());
+        log_debug!("After sorting (final output):");
         for (i, sugg) in reranked.iter().enumerate() {
-            if let Some(llm_score) = sugg.llm_score {
-                web_sys::console::log_1(&format!(
-                    "[DEBUG]   {}. \"{}\" - LLM score: {:.2}, reason: {:?}",
-                    i + 1,
-                    sugg.title,
-                    llm_score,
-                    sugg.llm_reason.as_ref().unwrap_or(&"N/A".to_string())
-                ).into());
-            } else {
-                web_sys::console::log_1(&format!(
-                    "[DEBUG]   {}. \"{}\" - embedding only (similarity: {:.3})",
-                    i + 1,
-                    sugg.title,
-                    sugg.similarity
-                ).into());
+            log_ranked_suggestion(i, sugg);
+        }
+        log_debug!("========== END OUTPUT SUGGESTIONS ==========");
+    }
+
+    Ok(RerankResult { suggestions: reranked, skipped_llm: false, ranking_coverage })
+}
+
+/// A candidate the LLM didn't score, ranked on embedding similarity alone
+fn embedding_only_suggestion(suggestion: &BaseSuggestion, alpha: f32) -> RankedSuggestion {
+    RankedSuggestion {
+        path: suggestion.path.clone(),
+        title: suggestion.title.clone(),
+        similarity: suggestion.similarity,
+        llm_score: None,
+        llm_reason: None,
+        context: suggestion.context.clone(),
+        combined_score: combined_score(None, suggestion.similarity, alpha),
+    }
+}
+
+/// Drop candidates that would confuse the LLM or render twice: duplicate paths (keeping the
+/// higher similarity, at the first position), the current document, and empty titles
+fn clean_candidates(candidates: Vec<BaseSuggestion>, current_doc_path: Option<&str>) -> Vec<BaseSuggestion> {
+    let mut cleaned: Vec<BaseSuggestion> = Vec::with_capacity(candidates.len());
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for candidate in candidates {
+        if current_doc_path.is_some_and(|current| candidate.path == current) {
+            log_warn!("Dropping rerank candidate '{}': it is the current document", candidate.path);
+            continue;
+        }
+        if candidate.title.trim().is_empty() {
+            log_warn!("Dropping rerank candidate '{}': empty title", candidate.path);
+            continue;
+        }
+        match positions.get(&candidate.path) {
+            Some(&pos) => {
+                log_warn!("Dropping duplicate rerank candidate '{}'", candidate.path);
+                if candidate.similarity > cleaned[pos].similarity {
+                    cleaned[pos] = candidate;
+                }
+            }
+            None => {
+                positions.insert(candidate.path.clone(), cleaned.len());
+                cleaned.push(candidate);
             }
         }
-        web_sys::console::log_1(&"[DEBUG] ========== END OUTPUT SUGGESTIONS ==========".into());
     }
 
-    serde_wasm_bindgen::to_value(&reranked)
-        .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    cleaned
+}
+
+fn log_ranked_suggestion(i: usize, sugg: &RankedSuggestion) {
+    let llm_score = sugg.llm_score.map_or("n/a".to_string(), |score| format!("{:.2}", score));
+    log_debug!(
+        "  {}. \"{}\" - combined: {:.3}, LLM: {}, similarity: {:.3}, reason: {:?}",
+        i + 1,
+        sugg.title,
+        sugg.combined_score,
+        llm_score,
+        sugg.similarity,
+        sugg.llm_reason.as_deref().unwrap_or("N/A")
+    );
 }
 
 /// Generate smart insertion suggestions using LLM
@@ -698,22 +1217,15 @@ pub async fn suggest_insertion_points_with_llm(
     document_content: String,
     link_title: String,
     link_context: String,
-    temperature: f32,
-    enable_thinking: bool,
-    debug: bool,
+    options: Option<JsValue>,
 ) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "suggest_insertion_points_with_llm")?;
+    let RequestOptions { temperature, enable_thinking, debug, context_window, .. } = options;
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] suggest_insertion_points_with_llm called for link: {}", link_title).into());
-        web_sys::console::log_1(&format!("[DEBUG] Document length: {} chars", document_content.len()).into());
+        log_debug!("suggest_insertion_points_with_llm called for link: {}", link_title);
+        log_debug!("Document length: {} chars", document_content.len());
     }
 
-    // Truncate document if too long
-    let doc_text = if document_content.len() > 2000 {
-        format!("{}...\n\n[Content truncated]", &document_content[..2000])
-    } else {
-        document_content.clone()
-    };
-
     let thinking_instructions = if enable_thinking {
         r#"
 
@@ -727,7 +1239,7 @@ Then provide your answer as JSON."#
         ""
     };
 
-    let prompt = format!(
+    let build_prompt = |doc_text: &str, link_context: &str| format!(
         r#"Find the best place to insert a link to "{}" in this document.
 
 Document Content:
@@ -757,17 +1269,26 @@ If no good insertion point exists, return: {{"phrase": null, "reason": "No natur
         thinking_instructions
     );
 
+    // The document is trimmed before the (usually short) link context
+    let overhead = estimate_tokens(&build_prompt("", ""));
+    let output_tokens = INSERTION_OUTPUT_TOKENS + if enable_thinking { THINKING_OUTPUT_TOKENS } else { 0 };
+    let budget = prompt_budget(context_window, output_tokens, overhead);
+    let fitted = fit_to_budget(vec![(document_content.as_str(), 0), (link_context.as_str(), 1)], budget);
+    let prompt = build_prompt(&fitted[0], &fitted[1]);
+
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Sending insertion request to LLM (model: {})", model).into());
-        web_sys::console::log_1(&format!("[DEBUG] Prompt length: {} chars", prompt.len()).into());
+        log_debug!("Sending insertion request to LLM (model: {})", model);
+        log_debug!("Prompt: ~{} tokens (budget {} for content)", estimate_tokens(&prompt), budget);
     }
 
-    let response_text = generate_text_ollama(
+    let response_text = generate_structured(
         endpoint,
         model,
         prompt,
-        Some(temperature),
+        temperature,
+        None,
         true,
+        &options,
     )
     .await?;
 
@@ -775,46 +1296,278 @@ If no good insertion point exists, return: {{"phrase": null, "reason": "No natur
     let response_text = response_text.trim();
 
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] LLM insertion response length: {} chars", response_text.len()).into());
-        web_sys::console::log_1(&format!("[DEBUG] LLM insertion response: {}", response_text).into());
+        log_debug!("LLM insertion response length: {} chars", response_text.len());
+        log_debug!("LLM insertion response: {}", response_text);
     }
 
     // Parse and return the response
-    let parsed: serde_json::Value = serde_json::from_str(response_text)
+    let mut parsed: serde_json::Value = serde_json::from_str(response_text)
         .map_err(|e| {
-            web_sys::console::log_1(&format!("[ERROR] LLM Insertion Response: {}", response_text).into());
-            web_sys::console::log_1(&format!("[ERROR] Parse error: {}", e).into());
+            log_error!("LLM Insertion Response: {}", response_text);
+            log_error!("Parse error: {}", e);
             JsValue::from_str(&format!("Failed to parse LLM insertion response: {}", e))
         })?;
 
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Successfully parsed insertion response").into());
+        log_debug!("Successfully parsed insertion response");
     }
 
+    resolve_insertion_phrase(&mut parsed, &document_content, debug);
+
     serde_wasm_bindgen::to_value(&parsed)
-        .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in suggest_insertion_points_with_llm: {}", e)))
+}
+/// Maximum document length (bytes) sent in insertion-point prompts
+const INSERTION_DOC_LIMIT: usize = 2000;
+
+/// Cut `text` to at most `limit` bytes, marking it as truncated. The cut never splits a
+/// character and falls after the last paragraph, line or sentence end when that keeps at least
+/// half the text, else after the last word.
+fn truncate_for_prompt(text: &str, limit: usize) -> String {
+    if text.len() <= limit {
+        return text.to_string();
+    }
+    let prefix = &text[..cut_boundary(text, limit)];
+    format!("{}...\n\n[Content truncated]", prefix[..natural_cut(prefix)].trim_end())
+}
+
+/// Document text for insertion prompts, truncated if too long
+fn insertion_doc_text(document: &str) -> String {
+    truncate_for_prompt(document, INSERTION_DOC_LIMIT)
+}
+
+/// A link to place in `suggest_insertion_points_batch_with_llm`
+#[derive(Deserialize, Debug, Clone)]
+pub struct InsertionLink {
+    pub title: String,
+    #[serde(default)]
+    pub context: String,
+}
+
+/// Suggest insertion points for several links in one LLM call, sending the document once.
+/// `links_json` is an array of {title, context}. Returns an object mapping each title to an
+/// insertion result (as from suggest_insertion_points_with_llm) or null if the model gave
+/// no usable answer for that link.
+#[wasm_bindgen]
+pub async fn suggest_insertion_points_batch_with_llm(
+    endpoint: String,
+    model: String,
+    document_content: String,
+    links_json: String,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "suggest_insertion_points_batch_with_llm")?;
+    let RequestOptions { temperature, enable_thinking, debug, .. } = options;
+    let links: Vec<InsertionLink> = serde_json::from_str(&links_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse links JSON: {}", e)))?;
+
+    // Titles key the response, so each may only appear once
+    let mut seen = HashSet::new();
+    let links: Vec<InsertionLink> = links.into_iter()
+        .filter(|link| !link.title.trim().is_empty() && seen.insert(link.title.clone()))
+        .collect();
+
+    let mut results: HashMap<String, Option<serde_json::Value>> = HashMap::new();
+    if links.is_empty() {
+        return insertion_batch_to_js(&results);
+    }
+
+    if debug {
+        log_debug!("suggest_insertion_points_batch_with_llm called for {} links", links.len());
+    }
+
+    let links_list = links.iter()
+        .map(|link| format!("- \"{}\": {}", link.title, link.context))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let thinking_instructions = if enable_thinking {
+        r#"
+
+THINKING MODE: For each link, first consider where its topic is most relevant and which phrase connects to it naturally.
+Then provide your answer as JSON."#
+    } else {
+        ""
+    };
+
+    let prompt = format!(
+        r#"Find the best place to insert each of the following links in this document.
+
+Document Content:
+{}
+
+Links (title: what the linked document is about):
+{}{}
+
+Task: For each link, identify the specific phrase or sentence where that link would add most value.
+Use a different phrase for each link where possible.
+
+IMPORTANT: Return ONLY valid JSON, no other text.
+
+Respond with one entry per link, keyed by the exact link title:
+{{
+  "Link Title": {{
+    "phrase": "exact text from document to replace",
+    "reason": "why this is the best insertion point",
+    "confidence": 0.85
+  }}
+}}
+
+If no good insertion point exists for a link, use: {{"phrase": null, "reason": "No natural insertion point found", "confidence": 0.0}}"#,
+        insertion_doc_text(&document_content),
+        links_list,
+        thinking_instructions
+    );
+
+    let response_text = generate_text(
+        endpoint,
+        model,
+        prompt,
+        temperature,
+        true,
+        &options,
+    )
+    .await?;
+    let response_text = response_text.trim();
+
+    if debug {
+        log_debug!("LLM batch insertion response: {}", response_text);
+    }
+
+    let parsed: serde_json::Value = serde_json::from_str(response_text)
+        .map_err(|e| {
+            log_error!("LLM Batch Insertion Response: {}", response_text);
+            JsValue::from_str(&format!("Failed to parse LLM batch insertion response: {}", e))
+        })?;
+    let Some(entries) = parsed.as_object() else {
+        return Err(JsValue::from_str("LLM batch insertion response is not a JSON object"));
+    };
+
+    for (i, link) in links.iter().enumerate() {
+        // Models sometimes key by position instead of title
+        let entry = entries.get(&link.title)
+            .or_else(|| entries.get(&(i + 1).to_string()))
+            .filter(|entry| entry.is_object());
+
+        let result = match entry {
+            Some(entry) => {
+                let mut entry = entry.clone();
+                resolve_insertion_phrase(&mut entry, &document_content, debug);
+                Some(entry)
+            }
+            None => {
+                log_warn!("No usable insertion result for link '{}'", link.title);
+                None
+            }
+        };
+        results.insert(link.title.clone(), result);
+    }
+
+    insertion_batch_to_js(&results)
+}
+
+fn insertion_batch_to_js(results: &HashMap<String, Option<serde_json::Value>>) -> Result<JsValue, JsValue> {
+    let serializer = serde_wasm_bindgen::Serializer::new().serialize_maps_as_objects(true);
+    results.serialize(&serializer)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in insertion_batch_to_js: {}", e)))
+}
+
+/// Replace the LLM's "phrase" with the text it actually matches in the document, adding
+/// `start`/`end` (char offsets), `start_byte`/`end_byte`, the same offsets under the names other
+/// positions use (`offset_bytes`/`end_bytes`, and `offset_utf16`/`end_utf16` for the editor API)
+/// and `match` ("exact" | "normalized" | "fuzzy").
+/// A phrase that can't be located becomes `null` with `failure: "not_found"`.
+fn resolve_insertion_phrase(parsed: &mut serde_json::Value, document: &str, debug: bool) {
+    let Some(obj) = parsed.as_object_mut() else {
+        return;
+    };
+    let Some(phrase) = obj.get("phrase").and_then(|p| p.as_str()).map(str::to_string) else {
+        return;
+    };
+
+    match locate_phrase(document, &phrase) {
+        Some(found) => {
+            if debug && found.kind != PhraseMatchKind::Exact {
+                log_debug!("Insertion phrase \"{}\" matched as \"{}\" ({:?}, similarity {:.2})",
+                    phrase, found.text, found.kind, found.similarity);
+            }
+            obj.insert("phrase".to_string(), serde_json::Value::from(found.text));
+            obj.insert("start".to_string(), serde_json::Value::from(found.start_char));
+            obj.insert("end".to_string(), serde_json::Value::from(found.end_char));
+            obj.insert("start_byte".to_string(), serde_json::Value::from(found.start_byte));
+            obj.insert("end_byte".to_string(), serde_json::Value::from(found.end_byte));
+            obj.insert("offset_bytes".to_string(), serde_json::Value::from(found.start_byte));
+            obj.insert("end_bytes".to_string(), serde_json::Value::from(found.end_byte));
+            obj.insert("offset_utf16".to_string(), serde_json::Value::from(found.start_utf16));
+            obj.insert("end_utf16".to_string(), serde_json::Value::from(found.end_utf16));
+            obj.insert("match".to_string(), serde_json::to_value(found.kind).unwrap_or(serde_json::Value::Null));
+        }
+        None => {
+            log_warn!("Insertion phrase not found in document: \"{}\"", phrase);
+            obj.insert("phrase".to_string(), serde_json::Value::Null);
+            obj.insert("failure".to_string(), serde_json::Value::from("not_found"));
+        }
+    }
+}
+
+/// Keywords with the model's unprocessed list, returned when `include_raw` is set
+#[derive(Serialize)]
+pub struct KeywordExtraction {
+    pub keywords: Vec<String>,
+    pub raw: Vec<String>,
+}
+
+/// Prompt line asking for `instruction`, naming the language, when `language` is one
+/// detect_language knows; empty otherwise so the prompt is unchanged
+fn language_instruction(language: Option<&str>, instruction: &str) -> String {
+    match language.and_then(crate::language::language_name) {
+        Some(name) => format!("{} ({}).\n\n", instruction, name),
+        None => String::new(),
+    }
 }
-/// Extract keywords and key concepts from a document using LLM
+
+/// Maximum document length (bytes) sent in keyword extraction prompts
+const KEYWORD_DOC_LIMIT: usize = 3000;
+
+/// Options of extract_keywords_with_llm: the shared RequestOptions plus the list's shape
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct KeywordOptions {
+    #[serde(flatten)]
+    pub request: RequestOptions,
+    /// Most keywords returned (default 15)
+    pub max_keywords: Option<u32>,
+    /// Return {keywords, raw} with the model's unprocessed list instead of the bare array
+    pub include_raw: bool,
+}
+
+impl JsOptions for KeywordOptions {
+    fn set_abort_signal(&mut self, abort_signal: Option<web_sys::AbortSignal>) {
+        self.request.abort_signal = abort_signal;
+    }
+}
+
+/// Extract keywords and key concepts from a document using LLM. `options` is a KeywordOptions
+/// object. The model's list is cleaned with normalize_keywords and capped at `max_keywords`
+/// (default 15). With `include_raw`, returns {keywords, raw} instead of the bare array.
+/// `language` (from detect_language or SmartVault::get_note_language) asks for keywords in the
+/// document's language; "unknown" or None leaves the prompt as it was.
 #[wasm_bindgen]
 pub async fn extract_keywords_with_llm(
     endpoint: String,
     model: String,
     document_title: String,
     document_content: String,
-    temperature: f32,
-    enable_thinking: bool,
-    debug: bool,
+    options: Option<JsValue>,
 ) -> Result<JsValue, JsValue> {
+    let KeywordOptions { request: options, max_keywords, include_raw } = KeywordOptions::from_js(options, "extract_keywords_with_llm")?;
+    let RequestOptions { temperature, enable_thinking, debug, use_schema, .. } = options;
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] extract_keywords_with_llm called for: {}", document_title).into());
+        log_debug!("extract_keywords_with_llm called for: {}", document_title);
     }
 
     // Truncate document if too long
-    let doc_text = if document_content.len() > 3000 {
-        format!("{}...\n\n[Content truncated]", &document_content[..3000])
-    } else {
-        document_content.clone()
-    };
+    let doc_text = truncate_for_prompt(&document_content, KEYWORD_DOC_LIMIT);
 
     let thinking_instructions = if enable_thinking {
         r#"
@@ -842,35 +1595,41 @@ Task: Identify 5-15 key terms that represent the main concepts discussed in this
 - Important topics or themes
 - Terms that other related documents might reference
 
-Return ONLY a JSON array of strings (no explanations):
+{}Return ONLY a JSON array of strings (no explanations):
 ["keyword1", "keyword2", "keyword3", ...]
 
 Keywords:"#,
         document_title,
         doc_text,
-        thinking_instructions
+        thinking_instructions,
+        language_instruction(options.language.as_deref(), "Respond with keywords in the document's language")
     );
 
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Keyword extraction prompt length: {} chars", prompt.len()).into());
+        log_debug!("Keyword extraction prompt length: {} chars", prompt.len());
     }
 
     // Call LLM
-    let response_text = generate_text_ollama(
+    let response_text = generate_structured(
         endpoint,
         model,
         prompt,
-        Some(temperature),
+        temperature,
+        use_schema.then_some(KEYWORDS_SCHEMA),
         true, // JSON format
+        &options,
     ).await?;
 
     // Trim whitespace - LLM sometimes adds trailing newlines that break JSON parsing
     let response_text = response_text.trim().to_string();
 
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] LLM keyword response: {}", response_text).into());
+        log_debug!("LLM keyword response: {}", response_text);
     }
 
+    // Fix almost-JSON (trailing commas, single quotes, cut-off arrays); valid JSON passes through
+    let response_text = repair_json(&response_text).unwrap_or(response_text);
+
     // Parse the response - try as array first, then extract from object with "keywords" key
     let keywords: Vec<String> = match serde_json::from_str::<Vec<String>>(&response_text) {
         Ok(arr) => arr,
@@ -902,7 +1661,7 @@ Keywords:"#,
                         if found_keywords.is_empty() {
                             // No array found - return error instead of extracting keys
                             if debug {
-                                web_sys::console::log_1(&format!("[WARNING] No keyword array found in object, keys were: {:?}", obj.keys().collect::<Vec<_>>()).into());
+                                log_warn!("No keyword array found in object, keys were: {:?}", obj.keys().collect::<Vec<_>>());
                             }
                             return Err(JsValue::from_str("LLM returned object without keywords array"));
                         }
@@ -910,394 +1669,2879 @@ Keywords:"#,
                     }
                 },
                 Err(e) => {
-                    web_sys::console::log_1(&format!("[ERROR] Failed to parse keywords: {}", e).into());
-                    web_sys::console::log_1(&format!("[ERROR] Response was: {}", response_text).into());
+                    log_error!("Failed to parse keywords: {}", e);
+                    log_error!("Response was: {}", response_text);
                     return Err(JsValue::from_str(&format!("Failed to parse keyword response: {}", e)));
                 }
             }
         }
     };
 
+    let max_keywords = max_keywords.map_or(DEFAULT_MAX_KEYWORDS, |max| max as usize);
+    let cleaned = normalize_keywords(&keywords, Some(max_keywords));
+
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Extracted {} keywords", keywords.len()).into());
+        log_debug!("Extracted {} keywords ({} after cleanup)", keywords.len(), cleaned.len());
     }
 
-    serde_wasm_bindgen::to_value(&keywords)
-        .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    if include_raw {
+        serde_wasm_bindgen::to_value(&KeywordExtraction { keywords: cleaned, raw: keywords })
+    } else {
+        serde_wasm_bindgen::to_value(&cleaned)
+    }
+    .map_err(|e| JsValue::from_str(&format!("Serialization error in extract_keywords_with_llm: {}", e)))
 }
 
-/// Chat with LLM (Context-Aware)
-#[wasm_bindgen]
-pub async fn chat_with_llm(
-    endpoint: String,
-    model: String,
-    system_prompt: String,
-    user_message: String,
-    context: String,
-    temperature: f32,
-) -> Result<String, JsValue> {
-    let full_prompt = format!(
-        "{}\n\nContext:\n{}\n\nUser: {}",
-        system_prompt, context, user_message
-    );
+/// Most title candidates returned by suggest_title_with_llm
+const MAX_TITLE_SUGGESTIONS: usize = 5;
+
+/// Maximum note length (bytes) sent for title suggestions; the opening is what names a note
+const TITLE_DOC_LIMIT: usize = 3000;
+
+/// Longest title kept, in chars
+const MAX_TITLE_CHARS: usize = 100;
+
+/// Items of a JSON array, or of the first array inside a JSON object ({"items": [...]})
+fn parse_json_list(text: &str) -> Option<Vec<serde_json::Value>> {
+    let value: serde_json::Value = serde_json::from_str(text)
+        .ok()
+        .or_else(|| extract_json_array(text).and_then(|arr| serde_json::from_str(&arr).ok()))?;
+    match value {
+        serde_json::Value::Array(arr) => Some(arr),
+        serde_json::Value::Object(obj) => obj.into_iter().find_map(|(_, v)| match v {
+            serde_json::Value::Array(arr) => Some(arr),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
 
-    // Reuse existing generation function
-    generate_text_ollama(
-        endpoint,
-        model,
-        full_prompt,
-        Some(temperature),
-        false, // Not forcing JSON for chat
-    )
-    .await
+/// Strings from a JSON array, or from the first array inside a JSON object
+fn parse_string_list(text: &str) -> Option<Vec<String>> {
+    let array = parse_json_list(text)?;
+    Some(array.iter().filter_map(|v| v.as_str().map(str::to_string)).collect())
+}
+
+/// Make a title usable as a filename and wikilink: drop characters the filesystem rejects
+/// (/ \ : * ? " < > |) and those Obsidian links can't contain (# ^ [ ]), then tidy whitespace.
+pub fn sanitize_title(raw: &str) -> Option<String> {
+    let text = strip_list_marker(raw.trim());
+    let text = text.strip_suffix(".md").unwrap_or(text);
+    let cleaned: String = text.chars()
+        .filter_map(|c| match c {
+            // Separators become spaces so "A/B" doesn't turn into "AB"
+            '/' | '\\' | ':' | '|' => Some(' '),
+            '*' | '?' | '"' | '<' | '>' | '#' | '^' | '[' | ']' => None,
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect();
+    let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+    // Leading/trailing dots and quotes make hidden or awkward filenames
+    let cleaned = cleaned.trim_matches(|c: char| matches!(c, '.' | '\'' | '`' | '\u{201C}' | '\u{201D}') || c.is_whitespace());
+    let cleaned: String = cleaned.chars().take(MAX_TITLE_CHARS).collect();
+    let cleaned = cleaned.trim_end();
+    (!cleaned.is_empty()).then(|| cleaned.to_string())
 }
 
-/// Analyze formatting, grammar, structure, and generate flashcards
+/// Suggest 3-5 titles for a note with a placeholder or poor name (e.g. "Untitled 17" or a URL).
+/// `existing_titles_json` is a JSON array of titles already in the vault; the model is told to
+/// avoid them and any that come back anyway are removed. Returns a JSON array of titles.
 #[wasm_bindgen]
-pub async fn analyze_formatting_with_llm(
+pub async fn suggest_title_with_llm(
     endpoint: String,
     model: String,
     content: String,
-    all_vault_tags: Vec<String>,
-    temperature: f32,
-    enable_thinking: bool,
-    debug: bool,
+    existing_titles_json: String,
+    options: Option<JsValue>,
 ) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "suggest_title_with_llm")?;
+    let RequestOptions { temperature, debug, use_schema, .. } = options;
+    let existing_titles: Vec<String> = serde_json::from_str(&existing_titles_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse existing titles JSON: {}", e)))?;
+
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] analyze_formatting called. Content len: {}", content.len()).into());
+        log_debug!("suggest_title_with_llm called ({} existing titles)", existing_titles.len());
     }
 
-    let doc_text = if content.len() > 2000 {
-        format!("{}...\n\n[Content truncated]", &content[..2000])
+    // Only the closest-sounding titles matter for collisions; cap the list to keep the prompt small
+    let avoid_list = existing_titles.iter()
+        .take(200)
+        .map(|title| format!("- {}", title))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let avoid_section = if avoid_list.is_empty() {
+        String::new()
     } else {
-        content.clone()
+        format!("\n\nTitles already used in the vault (do not suggest these):\n{}", avoid_list)
     };
 
-    let existing_tags_str = all_vault_tags.join(", ");
-    
-    let thinking_part = if enable_thinking {
-        r#"
-Thinking Process:
-1. Scan for grammar/spelling errors.
-2. Evaluate Zettelkasten atomic structure (is it focused? is it connected?).
-3. Identify facts suitable for spaced repetition flashcards.
-4. Select relevant tags from the existing list, and identify any necessary new tags.
-"#
-    } else { "" };
-
-    // Use replace() to avoid format! macro escaping issues with JSON braces
-    let template = r###"Analyze this note content for improvements.
-        
-Existing Vault Tags: [PLACEHOLDER_TAGS]
+    let prompt = format!(
+        r#"Suggest 3 to 5 titles for the following note.
 
-Content:
-PLACEHOLDER_CONTENT
+Note Content:
+{}{}
 
-Tasks:
-1. Grammar: Identify typos or grammar mistakes.
-2. Structure: Suggest concrete improvements.
-   - For each suggestion, provide the specific Markdown text to insert/append.
-3. Flashcards: Generate 1-3 flashcards ("Question::Answer").
-4. Tags: Suggest tags (existing vs new).
-   - "new_tags": Limit to top 1-3 most relevant new tags.
+Guidelines:
+- Short and descriptive (2-8 words), naming the note's main subject
+- Title case, no trailing punctuation, no dates unless the note is about a date
+- Do not use the characters / \ : * ? " < > | # ^ [ ]
 
-Response Format (JSON ONLY, NO DUPLICATE KEYS):
-{
-  "grammar": [
-    {"original": "typo", "corrected": "correction", "reason": "spelling"}
-  ],
-  "structure_suggestions": [
-     {
-        "title": "Add Summary", 
-        "description": "Add a summary section at the end.", 
-        "markdown_to_insert": "## Summary\n\nThis note discusses..."
-     }
-  ],
-  "flashcards": [
-     { "question": "What is the mitochondria?", "answer": "The powerhouse of the cell" }
-  ],
-  "existing_tags": ["existing-tag-1"],
-  "new_tags": ["top-new-tag"]
-}
-PLACEHOLDER_THINKING
-"###;
+Return ONLY valid JSON, no other text:
+{{"titles": ["First Title", "Second Title", "Third Title"]}}"#,
+        truncate_for_prompt(&content, TITLE_DOC_LIMIT),
+        avoid_section
+    );
 
-    let prompt = template
-        .replace("PLACEHOLDER_TAGS", &existing_tags_str)
-        .replace("PLACEHOLDER_CONTENT", &doc_text)
-        .replace("PLACEHOLDER_THINKING", thinking_part);
+    let response_text = generate_structured(
+        endpoint,
+        model,
+        prompt,
+        temperature,
+        use_schema.then_some(TITLES_SCHEMA),
+        true,
+        &options,
+    ).await?;
+    let response_text = response_text.trim();
 
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Formatting Prompt len: {}", prompt.len()).into());
+        log_debug!("LLM title response: {}", response_text);
     }
 
-    let response = generate_text_ollama(
-        endpoint, 
-        model, 
-        prompt, 
-        Some(temperature), 
-        true
-    ).await?;
+    let raw_titles = parse_string_list(response_text).unwrap_or_else(|| {
+        log_warn!("Title response was not a JSON list; extracting lines instead");
+        response_text.lines()
+            .map(str::trim)
+            // Skip blank lines and preambles like "Here are some titles:"
+            .filter(|line| !line.is_empty() && !line.ends_with(':'))
+            .map(str::to_string)
+            .collect()
+    });
+
+    let mut seen: HashSet<String> = existing_titles.iter().map(|t| t.trim().to_lowercase()).collect();
+    let titles: Vec<String> = raw_titles.iter()
+        .filter_map(|title| sanitize_title(title))
+        .filter(|title| seen.insert(title.to_lowercase()))
+        .take(MAX_TITLE_SUGGESTIONS)
+        .collect();
 
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Formatting Response: {}", response).into());
+        log_debug!("{} title suggestions after filtering: {:?}", titles.len(), titles);
     }
 
-    // Attempt to parse
-    match serde_json::from_str::<FormattingAnalysis>(&response) {
-        Ok(analysis) => serde_wasm_bindgen::to_value(&analysis)
-            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e))),
-        Err(e) => {
-             // Fallback: try to extract JSON object if LLM ignored strictness
-             let json_text = extract_json_object(&response).unwrap_or(response.clone());
-             match serde_json::from_str::<FormattingAnalysis>(&json_text) {
-                 Ok(analysis) => serde_wasm_bindgen::to_value(&analysis)
-                    .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e))),
-                 Err(e2) => Err(JsValue::from_str(&format!("Failed to parse formatting analysis: {}. Response: {}", e2, response)))
-             }
+    serde_wasm_bindgen::to_value(&titles)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in suggest_title_with_llm: {}", e)))
+}
+
+/// Card syntax for generate_flashcards_with_llm, as read by the Spaced Repetition plugin
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashcardFormat {
+    /// Single-line "Question::Answer"
+    QuestionAnswer,
+    /// Sentence with the answer hidden as {{c1::answer}}
+    Cloze,
+    /// Multi-line card: question, a line with "?", then the answer
+    BasicMarkdown,
+}
+
+/// Most cards generated in one call
+const MAX_FLASHCARDS: u32 = 50;
+
+/// Questions sharing at least this fraction of their words count as duplicates
+const DUPLICATE_QUESTION_OVERLAP: f32 = 0.8;
+
+/// Generated cards plus the markdown block to append to the note
+#[derive(Serialize, Debug, Clone)]
+pub struct FlashcardSet {
+    pub flashcards: Vec<Flashcard>,
+    pub markdown: String,
+}
+
+fn question_words(question: &str) -> HashSet<String> {
+    question.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Drop empty cards and cards whose question nearly repeats an earlier one (word-set Jaccard)
+fn dedupe_flashcards(cards: Vec<Flashcard>) -> Vec<Flashcard> {
+    let mut kept: Vec<(HashSet<String>, Flashcard)> = Vec::new();
+    for card in cards {
+        let words = question_words(&card.question);
+        if words.is_empty() || card.answer.trim().is_empty() {
+            continue;
+        }
+        let duplicate = kept.iter().any(|(other, _)| {
+            let shared = words.intersection(other).count() as f32;
+            let total = words.union(other).count() as f32;
+            shared / total >= DUPLICATE_QUESTION_OVERLAP
+        });
+        if !duplicate {
+            kept.push((words, card));
         }
     }
+    kept.into_iter().map(|(_, card)| card).collect()
 }
 
-/// Analyze organization and suggest placement
+/// Single-line card text; the Spaced Repetition plugin reads one card per line
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn format_flashcard(card: &Flashcard, format: FlashcardFormat) -> String {
+    let question = one_line(&card.question);
+    let answer = one_line(&card.answer);
+    match format {
+        FlashcardFormat::QuestionAnswer => format!("{}::{}", question, answer),
+        FlashcardFormat::Cloze => match question.find(&answer) {
+            // The "question" is the full sentence; hide the answer where it appears
+            Some(pos) if !answer.is_empty() => format!("{}{{{{c1::{}}}}}{}",
+                &question[..pos], answer, &question[pos + answer.len()..]),
+            _ => format!("{} {{{{c1::{}}}}}", question, answer),
+        },
+        FlashcardFormat::BasicMarkdown => format!("{}\n?\n{}", card.question.trim(), card.answer.trim()),
+    }
+}
+
+/// Cards as a block ready to append to a note, tagged so the Spaced Repetition plugin finds them
+pub fn flashcards_to_markdown(cards: &[Flashcard], format: FlashcardFormat) -> String {
+    let separator = match format {
+        FlashcardFormat::BasicMarkdown => "\n\n",
+        _ => "\n",
+    };
+    let body = cards.iter()
+        .map(|card| format_flashcard(card, format))
+        .collect::<Vec<_>>()
+        .join(separator);
+    format!("#flashcards\n\n{}\n", body)
+}
+
+/// Generate `count` flashcards from a note in the given format.
+/// Returns {flashcards: [{question, answer}], markdown}; `markdown` is ready to append to the note.
+/// For Cloze, each card's question is the full sentence and its answer the hidden part.
 #[wasm_bindgen]
-pub async fn analyze_organization_with_llm(
+pub async fn generate_flashcards_with_llm(
     endpoint: String,
     model: String,
-    file_name: String,
+    title: String,
     content: String,
-    vault_folders: Vec<String>,
-    temperature: f32,
-    enable_thinking: bool,
-    debug: bool,
+    count: u32,
+    format: FlashcardFormat,
+    options: Option<JsValue>,
 ) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "generate_flashcards_with_llm")?;
+    let RequestOptions { temperature, debug, use_schema, .. } = options;
+    let count = count.clamp(1, MAX_FLASHCARDS);
+
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] analyze_organization called for {}", file_name).into());
+        log_debug!("generate_flashcards_with_llm called for: {} ({} cards, {:?})", title, count, format);
     }
 
-    let doc_text = if content.len() > 1000 {
-        format!("{}...", &content[..1000])
-    } else {
-        content.clone()
+    let card_instructions = match format {
+        FlashcardFormat::Cloze => r#"Each card is a cloze deletion:
+- "question": one complete, self-contained sentence stating a fact from the note
+- "answer": the key term from that sentence to hide, copied exactly as it appears in the sentence"#,
+        _ => r#"Each card is a question and answer:
+- "question": a specific question that tests one fact or concept from the note
+- "answer": a short, accurate answer"#,
     };
 
-    let folders_list = vault_folders.join("\n- ");
+    let prompt = format!(
+        r#"Create {} flashcards for studying the note titled "{}".
 
-    let thinking_part = if enable_thinking {
-        "Think about the semantic meaning of the note and how it aligns with the existing folder taxonomy."
-    } else { "" };
+Note Content:
+{}
 
-    let template = r#"Suggest the best folder(s) for this note.
+{}
 
-Note Title: "PLACEHOLDER_TITLE"
-Content Snippet:
-PLACEHOLDER_CONTENT
+Guidelines:
+- Cover the most important ideas; do not ask the same thing twice
+- Use only information from the note
 
-Existing Folders:
-- PLACEHOLDER_FOLDERS
+Return ONLY valid JSON, no other text:
+{{"flashcards": [{{"question": "...", "answer": "..."}}]}}"#,
+        count,
+        title,
+        truncate_for_prompt(&content, SUMMARY_DOC_LIMIT),
+        card_instructions
+    );
 
-Task:
-1. Identify the BEST existing folder.
-2. Suggest an alternative or NEW folder path if appropriate.
-3. Provide a confidence score (0.0 - 1.0) for each.
+    let response_text = generate_structured(
+        endpoint,
+        model,
+        prompt,
+        temperature,
+        use_schema.then_some(FLASHCARDS_SCHEMA),
+        true,
+        &options,
+    ).await?;
+    let response_text = response_text.trim();
 
-Response Format (JSON ONLY):
-{
-  "suggestions": [
-     {
-       "folder": "Selected/Folder",
-       "is_new_path": false,
-       "confidence": 0.9,
-       "reason": "Matches gardening theme."
-     },
-     {
-       "folder": "Another/Option",
-       "is_new_path": true,
-       "confidence": 0.6,
-       "reason": "Alternative categorization."
-     }
-  ]
-}
-PLACEHOLDER_THINKING
-"#;
+    if debug {
+        log_debug!("LLM flashcard response: {}", response_text);
+    }
 
-    let prompt = template
-        .replace("PLACEHOLDER_TITLE", &file_name)
-        .replace("PLACEHOLDER_CONTENT", &doc_text)
-        .replace("PLACEHOLDER_FOLDERS", &folders_list)
-        .replace("PLACEHOLDER_THINKING", thinking_part);
+    let items = parse_json_list(response_text).ok_or_else(|| {
+        log_error!("Failed to parse flashcards: {}", response_text);
+        JsValue::from_str("Failed to parse flashcard response: no card array found")
+    })?;
+    // Malformed items are skipped rather than failing the whole set
+    let cards: Vec<Flashcard> = items.into_iter()
+        .filter_map(|item| serde_json::from_value(item).ok())
+        .collect();
+    let parsed_count = cards.len();
+
+    let mut cards = dedupe_flashcards(cards);
+    cards.truncate(count as usize);
 
     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Organization Prompt len: {}", prompt.len()).into());
+        log_debug!("{} flashcards parsed, {} after dedupe", parsed_count, cards.len());
     }
 
-    let response = generate_text_ollama(
-        endpoint,
-        model,
-        prompt,
-        Some(temperature),
-        true
-    ).await?;
+    let result = FlashcardSet {
+        markdown: flashcards_to_markdown(&cards, format),
+        flashcards: cards,
+    };
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in generate_flashcards_with_llm: {}", e)))
+}
 
-    if debug {
-        web_sys::console::log_1(&format!("[DEBUG] Organization Response: {}", response).into());
+/// One chat turn. Fields other than role/content (e.g. images) are passed through untouched.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: String) -> Self {
+        ChatMessage { role: role.to_string(), content, extra: serde_json::Map::new() }
+    }
+}
+
+#[derive(Serialize)]
+struct OllamaChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct OllamaChatResponse {
+    message: ChatMessage,
+}
+
+/// POST to /api/chat; Ok(None) means the server has no chat route (older Ollama)
+async fn send_chat_request(endpoint: &str, request_json: &str, settings: &RequestSettings) -> Result<Option<String>, JsValue> {
+    http::with_timeout(settings, |settings| async move {
+        send_chat_request_inner(endpoint, request_json, &settings).await
+    }).await
+}
+
+async fn send_chat_request_inner(endpoint: &str, request_json: &str, settings: &RequestSettings) -> Result<Option<String>, JsValue> {
+    let response = http::post_json(&format!("{}/api/chat", endpoint), request_json, settings)
+        .await
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    settings.check_aborted().map_err(|e| JsValue::from_str(&e))?;
+
+    if response.status() == 404 {
+        return Ok(None);
     }
+    if !response.ok() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(JsValue::from_str(&format!("HTTP {}: {}", status, error_text)));
+    }
+
+    let chat_response: OllamaChatResponse = response
+        .json()
+        .await
+        .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
 
-    // Try to find JSON in the response (LLMs sometimes add chatter)
-    // Prioritize object extraction since OrganizationResult is a struct
-    let json_str = extract_json_object(&response)
-        .or_else(|| extract_json_array(&response))
-        .unwrap_or(response.clone());
+    Ok(Some(chat_response.message.content))
+}
 
-    match serde_json::from_str::<serde_json::Value>(&json_str) {
-        Ok(val) => serde_wasm_bindgen::to_value(&val)
-            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e))),
-        Err(e) => {
-            // Fallback: try to fix common JSON errors?
-            // For now just error out
-             Err(JsValue::from_str(&format!("Failed to parse organization output: {}. Response was: {}", e, response)))
+/// Flatten a conversation into a single /api/generate prompt
+fn chat_messages_to_prompt(messages: &[ChatMessage]) -> String {
+    let mut prompt = String::new();
+    for message in messages {
+        match message.role.as_str() {
+            "system" => prompt.push_str(&message.content),
+            "assistant" => prompt.push_str(&format!("Assistant: {}", message.content)),
+            _ => prompt.push_str(&format!("User: {}", message.content)),
         }
+        prompt.push_str("\n\n");
     }
+    prompt.push_str("Assistant:");
+    prompt
 }
 
-/// Generate a Map of Content (MOC)
-#[wasm_bindgen]
-pub async fn generate_moc_with_llm(
+/// Chat via /api/chat, falling back to /api/generate with a flattened prompt when the route is missing.
+/// OpenAI-compatible backends send the messages to /v1/chat/completions instead.
+pub(crate) async fn chat(
     endpoint: String,
     model: String,
-    topic: String,
-    related_notes_json: String,
-    temperature: f32,
-    enable_thinking: bool,
-    debug: bool,
+    messages: Vec<ChatMessage>,
+    temperature: Option<f32>,
+    request: &RequestOptions,
 ) -> Result<String, JsValue> {
-     if debug {
-        web_sys::console::log_1(&format!("[DEBUG] generate_moc called for topic: {}", topic).into());
+    if let LlmBackend::OpenAICompatible { api_key } = request.backend()? {
+        let messages = messages.iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in chat: {}", e)))?;
+        let response = openai_chat(&endpoint, &model, messages, temperature, false, api_key.as_deref(), request).await?;
+        return Ok(answer_only(&response));
     }
 
-    let notes: Vec<serde_json::Value> = serde_json::from_str(&related_notes_json)
-        .map_err(|e| JsValue::from_str(&format!("Failed to parse related notes JSON: {}", e)))?;
+    let request_body = OllamaChatRequest {
+        model: &model,
+        messages: &messages,
+        stream: false,
+        options: Some(request.sized_model_options().to_ollama(temperature)),
+        keep_alive: request.keep_alive.as_deref(),
+    };
+    let request_json = serde_json::to_string(&request_body).map_err(|e| JsValue::from_str(&format!("Serialization error in chat: {}", e)))?;
 
-    // Create a summarized list of notes for the prompt
-    let mut notes_list = String::new();
-    for note in notes {
-        if let (Some(title), Some(path)) = (note["title"].as_str(), note["path"].as_str()) {
-             // Optional: Include snippet if available?
-             // For MOC, title is usually enough, maybe a tiny snippet.
-             // let snippet = note["context"].as_str().unwrap_or("");
-             notes_list.push_str(&format!("- [[{}]] (Path: {})\n", title, path));
-        }
+    if let Some(content) = send_chat_request(&endpoint, &request_json, &request.settings()).await? {
+        return Ok(answer_only(&content));
     }
 
-    let thinking_part = if enable_thinking {
-        "THINKING PROCESS: Analyze the list of notes. Identify 3-5 main sub-themes or categories. Group the notes accordingly."
-    } else { "" };
+    log_debug!("/api/chat not available, falling back to /api/generate");
+    let prompt = chat_messages_to_prompt(&messages);
+    let response = generate_text_with_format(endpoint, model, prompt, temperature, None, request).await?;
+    Ok(answer_only(&response))
+}
 
-    let prompt = format!(
-        r#"You are an expert Knowledge Manager. Your task is to organize a chaotic list of notes into a structured "Map of Content" (MOC) for the topic: "{}"
+/// Multi-turn chat via Ollama's /api/chat.
+/// `messages_json` is `[{role, content, ...}]` with role "system", "user" or "assistant";
+/// extra fields on each message are sent as-is. Returns the assistant's reply text.
+/// `options` is as in generate_text_ollama. Servers without /api/chat get a flattened /api/generate prompt.
+#[wasm_bindgen]
+pub async fn chat_ollama(
+    endpoint: String,
+    model: String,
+    messages_json: String,
+    options: Option<JsValue>,
+) -> Result<String, JsValue> {
+    let options = RequestOptions::from_js(options, "chat_ollama")?;
+    let messages: Vec<ChatMessage> = serde_json::from_str(&messages_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid messages JSON: {}", e)))?;
 
-List of Notes to Organize:
-{}
+    chat(endpoint, model, messages, options.temperature, &options).await
+}
 
-Instructions:
-1. Create a structured Markdown output.
-2. Group the notes into logical categories/sub-headings (e.g., ## Concepts, ## History, ## Examples).
-3. Use strict Obsidian WikiLink format: [[Note Title]].
-4. Write a brief 1-sentence description for each link explaining why it is in that category.
-5. If a note seems irrelevant to the topic, put it in a ## Uncategorized / Related section or omit it if completely unrelated.
-6. Start with a ## Overview section summarizing the collection.
+/// Chat with LLM (Context-Aware)
+/// Sent as a system prompt, the note context as a second system message, and the user's message.
+#[wasm_bindgen]
+pub async fn chat_with_llm(
+    endpoint: String,
+    model: String,
+    system_prompt: String,
+    user_message: String,
+    context: String,
+    options: Option<JsValue>,
+) -> Result<String, JsValue> {
+    let options = RequestOptions::from_js(options, "chat_with_llm")?;
+    let messages = context_chat_messages(system_prompt, user_message, context);
+    chat(endpoint, model, messages, options.temperature, &options).await
+}
 
-Output Format (Markdown);
-# {} MOC
+fn context_chat_messages(system_prompt: String, user_message: String, context: String) -> Vec<ChatMessage> {
+    vec![
+        ChatMessage::new("system", system_prompt),
+        ChatMessage::new("system", format!("Context:\n{}", context)),
+        ChatMessage::new("user", user_message),
+    ]
+}
 
-## Overview
-(Brief summary of this knowledge cluster)
+/// One line of a streamed /api/generate or /api/chat response
+#[derive(Deserialize)]
+struct OllamaStreamChunk {
+    /// Delta text from /api/generate
+    #[serde(default)]
+    response: String,
+    /// Delta message from /api/chat
+    #[serde(default)]
+    message: Option<ChatMessage>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
 
-## Category 1
-- [[Note A]] - Description...
-- [[Note B]] - Description...
+/// Read a streamed response, passing each delta to `on_token`; returns the full text.
+/// A throwing callback stops the stream and rejects the call.
+async fn collect_stream(response: Response, on_token: &js_sys::Function, settings: &RequestSettings) -> Result<String, JsValue> {
+    let mut full_text = String::new();
+    http::read_ndjson(response, settings, |line| {
+        let chunk: OllamaStreamChunk = serde_json::from_str(line)
+            .map_err(|e| format!("Parse error: {}", e))?;
+        if let Some(error) = chunk.error {
+            return Err(format!("Ollama error: {}", error));
+        }
 
-## Category 2
-...
+        let delta = chunk.message.as_ref().map_or(chunk.response.as_str(), |m| m.content.as_str());
+        if !delta.is_empty() {
+            full_text.push_str(delta);
+            on_token.call1(&JsValue::NULL, &JsValue::from_str(delta))
+                .map_err(|e| format!("Token callback failed: {:?}", e))?;
+        }
+        Ok(chunk.done)
+    })
+    .await
+    .map_err(|e| JsValue::from_str(&e))?;
 
-{}
-"#,
-        topic,
-        notes_list,
-        topic,
-        thinking_part
-    );
+    Ok(full_text)
+}
 
-    if debug {
-        web_sys::console::log_1(&format!("[DEBUG] MOC Prompt Length: {}", prompt.len()).into());
+/// POST a streaming request; Ok(None) if the route doesn't exist
+async fn open_stream(url: &str, request_json: &str, settings: &RequestSettings) -> Result<Option<Response>, JsValue> {
+    let response = http::post_json(url, request_json, settings)
+        .await
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    if response.status() == 404 {
+        return Ok(None);
+    }
+    if !response.ok() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(JsValue::from_str(&format!("HTTP {}: {}", status, error_text)));
     }
+    Ok(Some(response))
+}
 
-    let response = generate_text_ollama(
-        endpoint,
+pub(crate) async fn generate_text_streaming(
+    endpoint: String,
+    model: String,
+    prompt: String,
+    temperature: Option<f32>,
+    on_token: &js_sys::Function,
+    request: &RequestOptions,
+) -> Result<String, JsValue> {
+    let request_body = OllamaGenerateRequest {
         model,
         prompt,
-        Some(temperature),
-        false // Markdown output, not JSON
-    ).await?;
+        stream: true,
+        format: None,
+        images: None,
+        options: Some(request.sized_model_options().to_ollama(temperature)),
+        keep_alive: request.keep_alive.clone(),
+    };
+    let request_json = serde_json::to_string(&request_body).map_err(|e| JsValue::from_str(&format!("Serialization error in generate_text_streaming: {}", e)))?;
 
-    Ok(response)
+    let settings = request.settings();
+    let response = open_stream(&format!("{}/api/generate", endpoint), &request_json, &settings)
+        .await?
+        .ok_or_else(|| JsValue::from_str("HTTP 404: /api/generate not found"))?;
+    collect_stream(response, on_token, &settings).await
 }
 
-
-/// Transcribe image content (Handwritten/Math)
-#[wasm_bindgen]
-pub async fn transcribe_image_with_llm(
+pub(crate) async fn chat_streaming(
     endpoint: String,
     model: String,
-    image_base64: String,
-    debug: bool,
+    messages: Vec<ChatMessage>,
+    temperature: Option<f32>,
+    on_token: &js_sys::Function,
+    request: &RequestOptions,
 ) -> Result<String, JsValue> {
-    if debug {
-        web_sys::console::log_1(&format!("[DEBUG] transcribe_image_with_llm called. Model: {}", model).into());
+    let request_body = OllamaChatRequest {
+        model: &model,
+        messages: &messages,
+        stream: true,
+        options: Some(request.sized_model_options().to_ollama(temperature)),
+        keep_alive: request.keep_alive.as_deref(),
+    };
+    let request_json = serde_json::to_string(&request_body).map_err(|e| JsValue::from_str(&format!("Serialization error in chat_streaming: {}", e)))?;
+
+    let settings = request.settings();
+    if let Some(response) = open_stream(&format!("{}/api/chat", endpoint), &request_json, &settings).await? {
+        return collect_stream(response, on_token, &settings).await;
     }
 
-    let prompt = r#"Transcribe the text in this image to Markdown verbatim.
-1. The FIRST line must be a descriptive Markdown header.
-2. Output ONLY the transcription. Do not summarize or explain.
-3. Use LaTeX for math ($...$).
-"#.to_string();
+    log_debug!("/api/chat not available, falling back to /api/generate");
+    let prompt = chat_messages_to_prompt(&messages);
+    generate_text_streaming(endpoint, model, prompt, temperature, on_token, request).await
+}
 
-    generate_text_with_images_ollama(
-        endpoint,
-        model,
-        prompt,
-        vec![image_base64],
-        Some(0.1), // Low temp for accurate OCR
-    ).await
+/// Generate text with `stream: true`, calling `on_token(delta)` as text arrives.
+/// Resolves with the full text once Ollama reports done. If `on_token` throws or
+/// `abort_signal` fires, the stream is cancelled and the call rejects.
+/// `options` is as in generate_text_ollama.
+#[wasm_bindgen]
+pub async fn generate_text_ollama_streaming(
+    endpoint: String,
+    model: String,
+    prompt: String,
+    on_token: js_sys::Function,
+    options: Option<JsValue>,
+) -> Result<String, JsValue> {
+    let options = RequestOptions::from_js(options, "generate_text_ollama_streaming")?;
+    generate_text_streaming(endpoint, model, prompt, options.temperature, &on_token, &options).await
 }
 
-/// Detect objects (diagrams/drawings) in an image and return bounding boxes
+/// Streaming variant of chat_with_llm; `on_token` receives each delta of the reply
 #[wasm_bindgen]
-pub async fn detect_objects_with_llm(
+pub async fn chat_with_llm_streaming(
     endpoint: String,
     model: String,
-    image_base64: String,
-    debug: bool,
+    system_prompt: String,
+    user_message: String,
+    context: String,
+    on_token: js_sys::Function,
+    options: Option<JsValue>,
 ) -> Result<String, JsValue> {
-    if debug {
-        web_sys::console::log_1(&format!("[DEBUG] detect_objects_with_llm called. Model: {}", model).into());
-    }
+    let options = RequestOptions::from_js(options, "chat_with_llm_streaming")?;
+    let messages = context_chat_messages(system_prompt, user_message, context);
+    chat_streaming(endpoint, model, messages, options.temperature, &on_token, &options).await
+}
 
-    let prompt = r#"Analyze this image and identify the bounding box of the main hand-drawn diagram, chart, or schematic.
-Ignore lines of text. Focus on the visual illustration.
+/// Answer tokens reserved when budgeting chat_with_vault context
+const VAULT_CHAT_OUTPUT_TOKENS: usize = 1024;
 
-Return the coordinates as a JSON array in the format: [ymin, xmin, ymax, xmax]
-- Values should be integers from 0 to 1000 (representing 0% to 100% of height/width).
-- Example: [100, 200, 500, 800] means top 10%, left 20%, bottom 50%, right 80%.
-- If there is NO diagram, return "null".
-- Output ONLY the JSON array. Do not include any explanation.
-"#.to_string();
+/// Rough per-message token cost of the chat template (role markers, separators)
+const CHAT_MESSAGE_OVERHEAD_TOKENS: usize = 4;
 
-    generate_text_with_images_ollama(
-        endpoint,
-        model,
-        prompt,
-        vec![image_base64],
-        Some(0.1), // Low temp for precision
-    ).await
+const VAULT_CHAT_INSTRUCTIONS: &str = "Use the sources below from the user's vault when they are relevant, \
+and refer to them by title. If they don't cover the question, say so rather than guessing.";
+
+/// Reply from SmartVault::chat_with_vault
+#[derive(Serialize, Debug, Clone)]
+pub struct VaultChatReply {
+    pub answer: String,
+    /// Paths of the notes included as context, in retrieval order
+    pub sources: Vec<String>,
+}
+
+/// Options of SmartVault::chat_with_vault: the shared RequestOptions plus what is retrieved
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct VaultChatOptions {
+    #[serde(flatten)]
+    pub request: RequestOptions,
+    /// Prior conversation turns as [{role, content}]
+    pub history: Vec<ChatMessage>,
+    /// Most notes used as context (default 5)
+    pub top_k: Option<usize>,
+    /// Minimum similarity for a note to be used (default 0.3)
+    pub threshold: Option<f32>,
+    /// Notes never used as context
+    pub exclude_paths: Vec<String>,
+    /// Folders whose notes are never used as context
+    pub exclude_folders: Vec<String>,
+}
+
+impl JsOptions for VaultChatOptions {
+    fn set_abort_signal(&mut self, abort_signal: Option<web_sys::AbortSignal>) {
+        self.request.abort_signal = abort_signal;
+    }
+}
+
+impl VaultChatOptions {
+    /// The prior turns to send; only "user" and "assistant" turns are kept
+    pub(crate) fn take_history(&mut self) -> Vec<ChatMessage> {
+        std::mem::take(&mut self.history).into_iter()
+            .filter(|m| matches!(m.role.as_str(), "user" | "assistant") && !m.content.trim().is_empty())
+            .map(|m| ChatMessage::new(&m.role, m.content))
+            .collect()
+    }
+}
+
+/// Messages for one vault chat turn, fitted to the context window: the system prompt and the
+/// user's message are always sent; history keeps its most recent turns within half the remaining
+/// budget; retrieved notes share the rest, lower-ranked notes trimmed first. Returns the messages
+/// and the paths of the notes that made it in.
+fn build_vault_chat_messages(
+    system_prompt: String,
+    user_message: String,
+    history: Vec<ChatMessage>,
+    notes: &[ContextNote],
+    context_window: Option<u32>,
+) -> (Vec<ChatMessage>, Vec<String>) {
+    let headers: Vec<String> = notes.iter().enumerate()
+        .map(|(i, note)| format!("[Source {}] {} ({})", i + 1, note.display_title(), note.path))
+        .collect();
+    let overhead = estimate_tokens(&system_prompt)
+        + estimate_tokens(&user_message)
+        + estimate_tokens(VAULT_CHAT_INSTRUCTIONS)
+        + headers.iter().map(|h| estimate_tokens(h)).sum::<usize>()
+        + 3 * CHAT_MESSAGE_OVERHEAD_TOKENS;
+    let budget = prompt_budget(context_window, VAULT_CHAT_OUTPUT_TOKENS, overhead);
+
+    // Most recent turns first, stopping at the first one that doesn't fit
+    let mut history_tokens = 0;
+    let mut kept = 0;
+    for message in history.iter().rev() {
+        let cost = estimate_tokens(&message.content) + CHAT_MESSAGE_OVERHEAD_TOKENS;
+        if history_tokens + cost > budget / 2 {
+            break;
+        }
+        history_tokens += cost;
+        kept += 1;
+    }
+    let history = history[history.len() - kept..].to_vec();
+
+    let parts: Vec<(&str, u8)> = notes.iter().enumerate()
+        .map(|(i, note)| (note.content.trim(), u8::try_from(notes.len() - i).unwrap_or(u8::MAX)))
+        .collect();
+    let excerpts = fit_to_budget(parts, budget - history_tokens);
+
+    let mut context = String::new();
+    let mut sources = Vec::new();
+    for ((note, header), excerpt) in notes.iter().zip(&headers).zip(&excerpts) {
+        if excerpt.trim().is_empty() {
+            continue;
+        }
+        context.push_str(&format!("{}\n{}\n\n", header, excerpt.trim()));
+        sources.push(note.path.clone());
+    }
+
+    let mut messages = vec![ChatMessage::new("system", system_prompt)];
+    if !context.is_empty() {
+        messages.push(ChatMessage::new("system", format!("{}\n\n{}", VAULT_CHAT_INSTRUCTIONS, context.trim_end())));
+    }
+    messages.extend(history);
+    messages.push(ChatMessage::new("user", user_message));
+    (messages, sources)
+}
+
+/// Send one vault chat turn with retrieved `notes` (best match first) as context, fitted into
+/// `request.context_window`
+pub(crate) async fn chat_with_context_notes(
+    endpoint: String,
+    model: String,
+    system_prompt: String,
+    user_message: String,
+    history: Vec<ChatMessage>,
+    notes: &[ContextNote],
+    request: &RequestOptions,
+) -> Result<VaultChatReply, JsValue> {
+    let (messages, sources) = build_vault_chat_messages(system_prompt, user_message, history, notes, request.context_window);
+    let answer = chat(endpoint, model, messages, request.temperature, request).await?;
+    Ok(VaultChatReply { answer, sources })
+}
+
+/// Longest excerpt of one note (bytes) included as question-answering context
+const QA_NOTE_LIMIT: usize = 2500;
+
+/// Total context budget (bytes) across all notes for question answering
+const QA_CONTEXT_LIMIT: usize = 10000;
+
+/// Notes smaller than this aren't worth including once the budget is nearly spent
+const QA_MIN_EXCERPT: usize = 200;
+
+/// A retrieved note passed to answer_question_with_llm
+#[derive(Deserialize, Debug, Clone)]
+pub struct ContextNote {
+    pub path: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub content: String,
+}
+
+impl ContextNote {
+    fn display_title(&self) -> String {
+        if self.title.trim().is_empty() {
+            extract_title_from_path(&self.path)
+        } else {
+            self.title.trim().to_string()
+        }
+    }
+}
+
+/// Answer from the vault with the notes it cites
+#[derive(Serialize, Debug, Clone)]
+pub struct VaultAnswer {
+    pub answer: String,
+    /// Paths of cited notes, in order of first citation
+    pub citations: Vec<String>,
+    /// Cited titles that weren't among the context notes; they are unlinked in `answer`
+    pub dropped_citations: Vec<String>,
+}
+
+/// Fit the notes into the context budget, in retrieval order. Returns the prompt context and
+/// the notes that made it in (only these may be cited).
+fn budget_context_notes(notes: &[ContextNote]) -> (String, Vec<&ContextNote>) {
+    let mut context = String::new();
+    let mut included = Vec::new();
+    let mut remaining = QA_CONTEXT_LIMIT;
+
+    for note in notes {
+        if remaining < QA_MIN_EXCERPT {
+            break;
+        }
+        let excerpt = truncate_for_prompt(note.content.trim(), QA_NOTE_LIMIT.min(remaining));
+        remaining = remaining.saturating_sub(excerpt.len());
+        context.push_str(&format!("### [[{}]]\n{}\n\n", note.display_title(), excerpt));
+        included.push(note);
+    }
+
+    (context, included)
+}
+
+fn build_question_prompt(question: &str, context: &str) -> String {
+    format!(
+        r#"Answer the question using only the notes below from the user's vault.
+
+Notes:
+{}
+Question: {}
+
+Instructions:
+- Base the answer on the notes; if they don't contain the answer, say so plainly
+- Cite the notes you use with their exact title as a wiki link, e.g. [[Note Title]]
+- Only cite titles that appear in the notes above
+- Be concise; do not repeat the question
+
+Answer:"#,
+        context, question
+    )
+}
+
+/// Check every [[link]] in `answer` against the context notes (by title or path, ignoring case,
+/// aliases and headings). Citations of unknown notes are replaced by their plain text.
+fn validate_citations(answer: &str, notes: &[&ContextNote]) -> VaultAnswer {
+    let resolve = |target: &str| -> Option<&ContextNote> {
+        let target = target.trim().to_lowercase();
+        let target = target.strip_suffix(".md").unwrap_or(&target);
+        notes.iter().copied().find(|note| {
+            note.display_title().to_lowercase() == target
+                || note.path.to_lowercase().strip_suffix(".md").unwrap_or(&note.path.to_lowercase()) == target
+        })
+    };
+
+    let mut text = String::with_capacity(answer.len());
+    let mut citations: Vec<String> = Vec::new();
+    let mut dropped: Vec<String> = Vec::new();
+    let mut rest = answer;
+
+    while let Some(open) = rest.find("[[") {
+        let Some(close) = rest[open + 2..].find("]]").map(|i| open + 2 + i) else {
+            break;
+        };
+        let inner = &rest[open + 2..close];
+        // [[Target#Heading|Alias]] -> Target
+        let target = inner.split(['|', '#']).next().unwrap_or(inner);
+
+        text.push_str(&rest[..open]);
+        match resolve(target) {
+            Some(note) => {
+                if !citations.contains(&note.path) {
+                    citations.push(note.path.clone());
+                }
+                text.push_str(&rest[open..close + 2]);
+            }
+            None => {
+                log_warn!("Dropping citation of unknown note [[{}]]", target);
+                let label = inner.rsplit('|').next().unwrap_or(inner);
+                text.push_str(label);
+                if !dropped.iter().any(|d| d == target.trim()) {
+                    dropped.push(target.trim().to_string());
+                }
+            }
+        }
+        rest = &rest[close + 2..];
+    }
+    text.push_str(rest);
+
+    VaultAnswer {
+        answer: text.trim().to_string(),
+        citations,
+        dropped_citations: dropped,
+    }
+}
+
+fn parse_context_notes(context_notes_json: &str) -> Result<Vec<ContextNote>, JsValue> {
+    serde_json::from_str(context_notes_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse context notes JSON: {}", e)))
+}
+
+/// Answer a question from retrieved notes (`context_notes_json`: [{path, title, content}], e.g.
+/// from find_similar), citing them as [[Title]]. Returns {answer, citations: [paths],
+/// dropped_citations: [titles]}; citations of notes not in the context are unlinked.
+#[wasm_bindgen]
+pub async fn answer_question_with_llm(
+    endpoint: String,
+    gen_model: String,
+    question: String,
+    context_notes_json: String,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "answer_question_with_llm")?;
+    let RequestOptions { temperature, debug, .. } = options;
+    let notes = parse_context_notes(&context_notes_json)?;
+    let (context, included) = budget_context_notes(&notes);
+
+    if debug {
+        log_debug!("answer_question_with_llm: {} of {} notes fit the context ({} chars)",
+            included.len(), notes.len(), context.len());
+    }
+
+    let response = generate_text(
+        endpoint,
+        gen_model,
+        build_question_prompt(&question, &context),
+        temperature,
+        false,
+        &options,
+    ).await?;
+
+    let result = validate_citations(&response, &included);
+    if debug {
+        log_debug!("Answer cites {} notes, dropped {:?}", result.citations.len(), result.dropped_citations);
+    }
+
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in answer_question_with_llm: {}", e)))
+}
+
+/// Streaming variant of answer_question_with_llm (Ollama only). `on_token` receives the raw
+/// answer as it is written; the returned object has the validated answer and citations.
+#[wasm_bindgen]
+pub async fn answer_question_with_llm_streaming(
+    endpoint: String,
+    gen_model: String,
+    question: String,
+    context_notes_json: String,
+    on_token: js_sys::Function,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "answer_question_with_llm_streaming")?;
+    let RequestOptions { temperature, debug, .. } = options;
+    let notes = parse_context_notes(&context_notes_json)?;
+    let (context, included) = budget_context_notes(&notes);
+
+    if debug {
+        log_debug!("answer_question_with_llm_streaming: {} of {} notes fit the context",
+            included.len(), notes.len());
+    }
+
+    let response = generate_text_streaming(
+        endpoint,
+        gen_model,
+        build_question_prompt(&question, &context),
+        temperature,
+        &on_token,
+        &options,
+    ).await?;
+
+    let result = validate_citations(&answer_only(&response), &included);
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in answer_question_with_llm_streaming: {}", e)))
+}
+
+/// Analyze formatting, grammar, structure, and generate flashcards.
+/// `language` (from detect_language) asks for the answer in the note's language.
+#[wasm_bindgen]
+pub async fn analyze_formatting_with_llm(
+    endpoint: String,
+    model: String,
+    content: String,
+    all_vault_tags: Vec<String>,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "analyze_formatting_with_llm")?;
+    let RequestOptions { temperature, enable_thinking, debug, use_schema, context_window, .. } = options;
+    if debug {
+        log_debug!("analyze_formatting called. Content len: {}", content.len());
+    }
+    let language_part = language_instruction(options.language.as_deref(),
+        "Write corrections, suggestions, flashcards and new tags in the note's language");
+
+    let thinking_part = if enable_thinking {
+        r#"
+Thinking Process:
+1. Scan for grammar/spelling errors.
+2. Evaluate Zettelkasten atomic structure (is it focused? is it connected?).
+3. Identify facts suitable for spaced repetition flashcards.
+4. Select relevant tags from the existing list, and identify any necessary new tags.
+"#
+    } else { "" };
+
+    // Use replace() to avoid format! macro escaping issues with JSON braces
+    let template = r###"Analyze this note content for improvements.
+        
+Existing Vault Tags: [PLACEHOLDER_TAGS]
+
+Content:
+PLACEHOLDER_CONTENT
+
+Tasks:
+1. Grammar: Identify typos or grammar mistakes.
+   - "original" must be copied exactly from the note.
+   - "context": 3-6 words surrounding the mistake, copied exactly, so repeated mistakes can be told apart.
+2. Structure: Suggest concrete improvements.
+   - For each suggestion, provide the specific Markdown text to insert/append.
+3. Flashcards: Generate 1-3 flashcards ("Question::Answer").
+4. Tags: Suggest tags (existing vs new).
+   - "new_tags": Limit to top 1-3 most relevant new tags.
+
+PLACEHOLDER_LANGUAGEResponse Format (JSON ONLY, NO DUPLICATE KEYS):
+{
+  "grammar": [
+    {"original": "teh", "corrected": "the", "reason": "spelling", "context": "is teh main reason"}
+  ],
+  "structure_suggestions": [
+     {
+        "title": "Add Summary", 
+        "description": "Add a summary section at the end.", 
+        "markdown_to_insert": "## Summary\n\nThis note discusses..."
+     }
+  ],
+  "flashcards": [
+     { "question": "What is the mitochondria?", "answer": "The powerhouse of the cell" }
+  ],
+  "existing_tags": ["existing-tag-1"],
+  "new_tags": ["top-new-tag"]
+}
+PLACEHOLDER_THINKING
+"###;
+
+    let build_prompt = |tags: &str, doc_text: &str| template
+        .replace("PLACEHOLDER_TAGS", tags)
+        .replace("PLACEHOLDER_CONTENT", doc_text)
+        .replace("PLACEHOLDER_THINKING", thinking_part)
+        .replace("PLACEHOLDER_LANGUAGE", &language_part);
+
+    // A long vault tag list is trimmed before the note itself
+    let existing_tags_str = all_vault_tags.join(", ");
+    let overhead = estimate_tokens(&build_prompt("", ""));
+    let output_tokens = FORMATTING_OUTPUT_TOKENS + if enable_thinking { THINKING_OUTPUT_TOKENS } else { 0 };
+    let budget = prompt_budget(context_window, output_tokens, overhead);
+    let fitted = fit_to_budget(vec![(existing_tags_str.as_str(), 0), (content.as_str(), 1)], budget);
+    let prompt = build_prompt(&fitted[0], &fitted[1]);
+
+    if debug {
+        log_debug!("Formatting Prompt len: {}", prompt.len());
+    }
+
+    let response = generate_structured(
+        endpoint,
+        model,
+        prompt,
+        temperature,
+        use_schema.then_some(FORMATTING_SCHEMA),
+        true,
+        &options,
+    ).await?;
+
+    if debug {
+        log_debug!("Formatting Response: {}", response);
+    }
+
+    // Attempt to parse
+    let mut analysis = match serde_json::from_str::<FormattingAnalysis>(&response) {
+        Ok(analysis) => analysis,
+        Err(_) => {
+             // Fallback: try to extract JSON object if LLM ignored strictness
+             let json_text = extract_json_object(&response).unwrap_or(response.clone());
+             let json_text = repair_json(&json_text).unwrap_or(json_text);
+             serde_json::from_str::<FormattingAnalysis>(&json_text)
+                 .map_err(|e2| JsValue::from_str(&format!("Failed to parse formatting analysis: {}. Response: {}", e2, response)))?
+        }
+    };
+
+    locate_grammar_issues(&mut analysis, &content);
+    (analysis.existing_tags, analysis.new_tags) = match_vault_tags(&analysis.existing_tags, &analysis.new_tags, &all_vault_tags, MAX_NEW_TAGS);
+    if debug && !analysis.unlocated.is_empty() {
+        log_debug!("{} grammar issues could not be located in the note", analysis.unlocated.len());
+    }
+
+    serde_wasm_bindgen::to_value(&analysis)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in analyze_formatting_with_llm: {}", e)))
+}
+
+/// Analyze organization and suggest placement
+#[wasm_bindgen]
+pub async fn analyze_organization_with_llm(
+    endpoint: String,
+    model: String,
+    file_name: String,
+    content: String,
+    vault_folders: Vec<String>,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "analyze_organization_with_llm")?;
+    let RequestOptions { temperature, enable_thinking, debug, use_schema, .. } = options;
+    if debug {
+        log_debug!("analyze_organization called for {}", file_name);
+    }
+
+    let doc_text = truncate_for_prompt(&content, 1000);
+
+    let folders_list = vault_folders.join("\n- ");
+
+    let thinking_part = if enable_thinking {
+        "Think about the semantic meaning of the note and how it aligns with the existing folder taxonomy."
+    } else { "" };
+
+    let template = r#"Suggest the best folder(s) for this note.
+
+Note Title: "PLACEHOLDER_TITLE"
+Content Snippet:
+PLACEHOLDER_CONTENT
+
+Existing Folders:
+- PLACEHOLDER_FOLDERS
+
+Task:
+1. Identify the BEST existing folder.
+2. Suggest an alternative or NEW folder path if appropriate.
+3. Provide a confidence score (0.0 - 1.0) for each.
+
+Response Format (JSON ONLY):
+{
+  "suggestions": [
+     {
+       "folder": "Selected/Folder",
+       "is_new_path": false,
+       "confidence": 0.9,
+       "reason": "Matches gardening theme."
+     },
+     {
+       "folder": "Another/Option",
+       "is_new_path": true,
+       "confidence": 0.6,
+       "reason": "Alternative categorization."
+     }
+  ]
+}
+PLACEHOLDER_THINKING
+"#;
+
+    let prompt = template
+        .replace("PLACEHOLDER_TITLE", &file_name)
+        .replace("PLACEHOLDER_CONTENT", &doc_text)
+        .replace("PLACEHOLDER_FOLDERS", &folders_list)
+        .replace("PLACEHOLDER_THINKING", thinking_part);
+
+    if debug {
+        log_debug!("Organization Prompt len: {}", prompt.len());
+    }
+
+    let response = generate_structured(
+        endpoint,
+        model,
+        prompt,
+        temperature,
+        use_schema.then_some(ORGANIZATION_SCHEMA),
+        true,
+        &options,
+    ).await?;
+
+    if debug {
+        log_debug!("Organization Response: {}", response);
+    }
+
+    let mut result = parse_organization_result(&response).ok_or_else(|| {
+        JsValue::from_str(&format!("Failed to parse organization output. Response was: {}", response))
+    })?;
+    resolve_suggested_folders(&mut result, &vault_folders);
+
+    if debug && !result.corrections.is_empty() {
+        log_debug!("Folder corrections: {:?}", result.corrections);
+    }
+
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in analyze_organization_with_llm: {}", e)))
+}
+
+/// Parse {suggestions: [...]} or a bare array of candidates, ignoring chatter around the JSON
+fn parse_organization_result(response: &str) -> Option<OrganizationResult> {
+    if let Some(json) = extract_json_object(response) {
+        if let Ok(result) = serde_json::from_str::<OrganizationResult>(&json) {
+            return Some(result);
+        }
+    }
+    if let Some(json) = extract_json_array(response) {
+        if let Ok(suggestions) = serde_json::from_str::<Vec<OrganizationCandidate>>(&json) {
+            return Some(OrganizationResult { suggestions, corrections: Vec::new() });
+        }
+    }
+
+    // Last resort: repair almost-JSON (trailing commas, single quotes, missing brackets)
+    let repaired: serde_json::Value = serde_json::from_str(&repair_json(response)?).ok()?;
+    match repaired {
+        serde_json::Value::Array(_) => {
+            let suggestions = serde_json::from_value(repaired).ok()?;
+            Some(OrganizationResult { suggestions, corrections: Vec::new() })
+        }
+        _ => serde_json::from_value(repaired).ok(),
+    }
+}
+
+/// Folder path with forward slashes, no empty segments and no leading/trailing slashes
+fn normalize_folder_path(folder: &str) -> String {
+    folder.replace('\\', "/")
+        .split('/')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Largest edit distance accepted as a misspelling: 1 for short names, up to 2 for longer ones
+fn max_folder_typo_distance(folder: &str) -> usize {
+    (folder.chars().count() / 6).clamp(1, 2)
+}
+
+/// Match each suggestion to a real folder: exact, then case-insensitive, then the unique closest
+/// folder within a small edit distance. Anything else is marked as a new path. Suggestions that
+/// end up on the same folder are merged, keeping the most confident.
+fn resolve_suggested_folders(result: &mut OrganizationResult, vault_folders: &[String]) {
+    let folders: Vec<String> = vault_folders.iter().map(|f| normalize_folder_path(f)).collect();
+
+    for candidate in result.suggestions.iter_mut() {
+        let original = candidate.folder.clone();
+        let normalized = normalize_folder_path(&original);
+        let mut correct = |candidate: &mut OrganizationCandidate, folder: String, kind: FolderCorrectionKind| {
+            if kind != FolderCorrectionKind::Normalized && kind != FolderCorrectionKind::NewPath {
+                candidate.reason = format!("{} (folder corrected from \"{}\")", candidate.reason.trim(), original);
+            }
+            result.corrections.push(FolderCorrection { original: original.clone(), corrected: folder.clone(), kind });
+            candidate.folder = folder;
+        };
+
+        if folders.contains(&normalized) {
+            if normalized != original {
+                correct(candidate, normalized, FolderCorrectionKind::Normalized);
+            }
+            candidate.is_new_path = false;
+            continue;
+        }
+
+        let lower = normalized.to_lowercase();
+        if let Some(folder) = folders.iter().find(|f| f.to_lowercase() == lower) {
+            correct(candidate, folder.clone(), FolderCorrectionKind::Case);
+            candidate.is_new_path = false;
+            continue;
+        }
+
+        let max_distance = max_folder_typo_distance(&normalized);
+        let mut best: Option<(usize, &String)> = None;
+        let mut tied = false;
+        for folder in &folders {
+            let distance = edit_distance(&lower, &folder.to_lowercase());
+            if distance > max_distance {
+                continue;
+            }
+            match best {
+                Some((best_distance, _)) if distance == best_distance => tied = true,
+                Some((best_distance, _)) if distance > best_distance => {}
+                _ => {
+                    best = Some((distance, folder));
+                    tied = false;
+                }
+            }
+        }
+        if let (Some((_, folder)), false) = (best, tied) {
+            correct(candidate, folder.clone(), FolderCorrectionKind::Fuzzy);
+            candidate.is_new_path = false;
+            continue;
+        }
+
+        if !candidate.is_new_path {
+            correct(candidate, normalized, FolderCorrectionKind::NewPath);
+            candidate.is_new_path = true;
+        } else if normalized != original {
+            correct(candidate, normalized, FolderCorrectionKind::Normalized);
+        }
+    }
+
+    // Keep the most confident suggestion per folder
+    let mut merged: Vec<OrganizationCandidate> = Vec::new();
+    for candidate in result.suggestions.drain(..) {
+        match merged.iter_mut().find(|m| m.folder == candidate.folder) {
+            Some(existing) if candidate.confidence > existing.confidence => *existing = candidate,
+            Some(_) => {}
+            None => merged.push(candidate),
+        }
+    }
+    result.suggestions = merged;
+}
+
+/// A note offered to the MOC generator
+struct MocNote {
+    title: String,
+    path: String,
+    /// Opening text of the note, shown to the model as a snippet
+    context: Option<String>,
+}
+
+/// Snippet length (chars) per note when the caller doesn't set one
+const DEFAULT_MOC_SNIPPET_CHARS: usize = 200;
+
+/// Total chars of snippets in one MOC prompt; snippets shrink evenly to fit
+const MOC_SNIPPET_BUDGET: usize = 6000;
+
+/// Snippets shorter than this aren't worth including
+const MIN_MOC_SNIPPET_CHARS: usize = 40;
+
+/// The {title, path, context?} entries of related_notes_json; entries missing title or path are ignored
+fn parse_moc_notes(related_notes_json: &str) -> Result<Vec<MocNote>, JsValue> {
+    let notes: Vec<serde_json::Value> = serde_json::from_str(related_notes_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse related notes JSON: {}", e)))?;
+    Ok(notes.iter()
+        .filter_map(|note| match (note["title"].as_str(), note["path"].as_str()) {
+            (Some(title), Some(path)) => Some(MocNote {
+                title: title.to_string(),
+                path: path.to_string(),
+                context: note["context"].as_str()
+                    .map(|c| c.split_whitespace().collect::<Vec<_>>().join(" "))
+                    .filter(|c| !c.is_empty()),
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+/// First `max_chars` chars of `text`, with an ellipsis if cut
+fn snippet(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((cut, _)) => format!("{}…", text[..cut].trim_end()),
+        None => text.to_string(),
+    }
+}
+
+/// `snippet_chars` caps each note's snippet (0 disables snippets). The first `max_notes` notes are
+/// listed with snippets, sharing MOC_SNIPPET_BUDGET; the rest are listed by title only.
+fn build_moc_prompt(topic: &str, notes: &[MocNote], enable_thinking: bool, snippet_chars: usize, max_notes: Option<usize>) -> String {
+    let detailed_count = max_notes.map_or(notes.len(), |max| max.min(notes.len()));
+    let (detailed, overflow) = notes.split_at(detailed_count);
+
+    let with_context = detailed.iter().filter(|n| n.context.is_some()).count().max(1);
+    let snippet_chars = snippet_chars.min(MOC_SNIPPET_BUDGET / with_context);
+
+    // Create a summarized list of notes for the prompt
+    let mut notes_list = String::new();
+    for note in detailed {
+        notes_list.push_str(&format!("- [[{}]] (Path: {})\n", note.title, note.path));
+        if let Some(context) = note.context.as_deref().filter(|_| snippet_chars >= MIN_MOC_SNIPPET_CHARS) {
+            notes_list.push_str(&format!("  Snippet: {}\n", snippet(context, snippet_chars)));
+        }
+    }
+    if !overflow.is_empty() {
+        notes_list.push_str("\nAdditional notes (title only):\n");
+        for note in overflow {
+            notes_list.push_str(&format!("- [[{}]]\n", note.title));
+        }
+    }
+
+    let thinking_part = if enable_thinking {
+        "THINKING PROCESS: Analyze the list of notes. Identify 3-5 main sub-themes or categories. Group the notes accordingly."
+    } else { "" };
+
+    let prompt = format!(
+        r#"You are an expert Knowledge Manager. Your task is to organize a chaotic list of notes into a structured "Map of Content" (MOC) for the topic: "{}"
+
+List of Notes to Organize:
+{}
+
+Instructions:
+1. Create a structured Markdown output.
+2. Group the notes into logical categories/sub-headings (e.g., ## Concepts, ## History, ## Examples).
+3. Use strict Obsidian WikiLink format: [[Note Title]].
+4. Write a brief 1-sentence description for each link explaining why it is in that category.
+5. If a note seems irrelevant to the topic, put it in a ## Uncategorized / Related section or omit it if completely unrelated.
+6. Start with a ## Overview section summarizing the collection.
+
+Output Format (Markdown);
+# {} MOC
+
+## Overview
+(Brief summary of this knowledge cluster)
+
+## Category 1
+- [[Note A]] - Description...
+- [[Note B]] - Description...
+
+## Category 2
+...
+
+{}
+"#,
+        topic,
+        notes_list,
+        topic,
+        thinking_part
+    );
+
+    prompt
+}
+
+/// A generated MOC with the link corrections made to it
+#[derive(Serialize, Debug, Clone)]
+pub struct MocResult {
+    pub markdown: String,
+    /// Linked titles that weren't among the provided notes; their links became plain text
+    pub hallucinated_links_removed: Vec<String>,
+    /// Provided notes the model left out, appended under "## Uncategorized"
+    pub missing_notes_added: Vec<String>,
+}
+
+/// Lowercase with whitespace runs collapsed, for matching near-miss titles
+fn title_key(title: &str) -> String {
+    title.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Check the MOC's wiki links against the provided notes. Links that differ from a provided title
+/// (or path) only in case or whitespace are rewritten to the exact title, links to anything else are
+/// unlinked, and provided notes that were never linked are appended under "## <missing_heading>".
+/// Notes sharing a title with another provided note are linked by path so each link resolves to
+/// the right one. Embeds (![[...]]) are left alone.
+fn validate_moc(markdown: &str, notes: &[MocNote], missing_heading: &str) -> MocResult {
+    let mut title_counts: HashMap<String, usize> = HashMap::new();
+    for note in notes {
+        *title_counts.entry(title_key(&note.title)).or_insert(0) += 1;
+    }
+    // Link target for a note, and the alias showing its title when linked by path
+    let link_name = |note: &MocNote| -> (String, Option<String>) {
+        if title_counts.get(&title_key(&note.title)).is_some_and(|&count| count > 1) {
+            (note.path.strip_suffix(".md").unwrap_or(&note.path).to_string(), Some(note.title.clone()))
+        } else {
+            (note.title.clone(), None)
+        }
+    };
+
+    let mut by_key: HashMap<String, &MocNote> = HashMap::new();
+    // Paths first, so a link written with a folder finds that note even if its title is shared
+    for note in notes {
+        let path = note.path.strip_suffix(".md").unwrap_or(&note.path);
+        by_key.entry(title_key(path)).or_insert(note);
+    }
+    for note in notes {
+        by_key.entry(title_key(&note.title)).or_insert(note);
+    }
+
+    let mut output = String::with_capacity(markdown.len());
+    let mut linked: HashSet<&str> = HashSet::new();
+    let mut removed: Vec<String> = Vec::new();
+    let mut cursor = 0;
+
+    for link in parse_wiki_links(markdown) {
+        if link.embed {
+            continue;
+        }
+        output.push_str(&markdown[cursor..link.start]);
+        cursor = link.end;
+
+        match by_key.get(&title_key(&link.target)) {
+            Some(note) => {
+                linked.insert(note.path.as_str());
+                let (name, title_alias) = link_name(note);
+                output.push_str("[[");
+                output.push_str(&name);
+                if let Some(heading) = &link.heading {
+                    output.push('#');
+                    output.push_str(heading);
+                }
+                if let Some(block_ref) = &link.block_ref {
+                    output.push_str("#^");
+                    output.push_str(block_ref);
+                }
+                if let Some(alias) = link.alias.as_ref().or(title_alias.as_ref()) {
+                    output.push('|');
+                    output.push_str(alias);
+                }
+                output.push_str("]]");
+            }
+            None => {
+                output.push_str(link.alias.as_deref().unwrap_or(&link.target).trim());
+                let name = link.target.clone();
+                if !removed.contains(&name) {
+                    removed.push(name);
+                }
+            }
+        }
+    }
+    output.push_str(&markdown[cursor..]);
+
+    let mut missing: Vec<String> = Vec::new();
+    let mut missing_links: Vec<String> = Vec::new();
+    for note in notes {
+        if linked.contains(note.path.as_str()) {
+            continue;
+        }
+        let link = match link_name(note) {
+            (name, Some(title)) => format!("{}|{}", name, title),
+            (name, None) => name,
+        };
+        if !missing_links.contains(&link) {
+            missing_links.push(link);
+            missing.push(note.title.clone());
+        }
+    }
+    if !missing.is_empty() {
+        let mut markdown = output.trim_end().to_string();
+        // Extend the model's own trailing section of that name rather than adding a second one
+        let last_heading = markdown.lines().rev().find(|line| line.starts_with("## "));
+        if last_heading.is_some_and(|heading| heading.trim_start_matches("## ").starts_with(missing_heading)) {
+            markdown.push('\n');
+        } else {
+            markdown.push_str(&format!("\n\n## {}\n", missing_heading));
+        }
+        for link in &missing_links {
+            markdown.push_str(&format!("- [[{}]]\n", link));
+        }
+        output = markdown;
+    }
+
+    MocResult {
+        markdown: output,
+        hallucinated_links_removed: removed,
+        missing_notes_added: missing,
+    }
+}
+
+/// Validate the generated MOC unless `strict` is false, in which case the raw output is returned
+fn finish_moc(response: String, notes: &[MocNote], strict: Option<bool>, debug: bool) -> Result<JsValue, JsValue> {
+    let result = if strict.unwrap_or(true) {
+        validate_moc(&response, notes, "Uncategorized")
+    } else {
+        MocResult { markdown: response, hallucinated_links_removed: Vec::new(), missing_notes_added: Vec::new() }
+    };
+
+    if debug {
+        log_debug!("MOC links removed: {:?}, notes added: {:?}",
+            result.hallucinated_links_removed, result.missing_notes_added);
+    }
+
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in finish_moc: {}", e)))
+}
+
+/// Options of generate_moc_with_llm: the shared RequestOptions plus how notes are listed
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct MocOptions {
+    #[serde(flatten)]
+    pub request: RequestOptions,
+    /// Unlink notes that weren't given and append the ones left out (default true)
+    pub strict: Option<bool>,
+    /// Longest snippet of each note's `context` (default 200)
+    pub snippet_chars: Option<u32>,
+    /// Notes beyond this many are listed by title only
+    pub max_notes: Option<u32>,
+}
+
+impl JsOptions for MocOptions {
+    fn set_abort_signal(&mut self, abort_signal: Option<web_sys::AbortSignal>) {
+        self.request.abort_signal = abort_signal;
+    }
+}
+
+impl MocOptions {
+    fn prompt(&self, topic: &str, notes: &[MocNote]) -> String {
+        build_moc_prompt(topic, notes, self.request.enable_thinking,
+            self.snippet_chars.map_or(DEFAULT_MOC_SNIPPET_CHARS, |c| c as usize), self.max_notes.map(|m| m as usize))
+    }
+}
+
+/// Generate a Map of Content (MOC). `options` is a MocOptions object.
+/// Returns {markdown, hallucinated_links_removed, missing_notes_added}: links to notes outside
+/// `related_notes_json` are unlinked and omitted notes appended, unless `strict` is false.
+/// Each note's optional `context` is shown as a snippet of up to `snippet_chars` (default 200);
+/// beyond `max_notes`, notes are listed by title only.
+#[wasm_bindgen]
+pub async fn generate_moc_with_llm(
+    endpoint: String,
+    model: String,
+    topic: String,
+    related_notes_json: String,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = MocOptions::from_js(options, "generate_moc_with_llm")?;
+    let debug = options.request.debug;
+    if debug {
+        log_debug!("generate_moc called for topic: {}", topic);
+    }
+
+    let notes = parse_moc_notes(&related_notes_json)?;
+    let prompt = options.prompt(&topic, &notes);
+
+    if debug {
+        log_debug!("MOC Prompt Length: {}", prompt.len());
+    }
+
+    let response = generate_text(
+        endpoint,
+        model,
+        prompt,
+        options.request.temperature,
+        false, // Markdown output, not JSON
+        &options.request,
+    ).await?;
+
+    finish_moc(response, &notes, options.strict, debug)
+}
+
+/// Streaming variant of generate_moc_with_llm; `on_token` receives the raw Markdown as it is
+/// written, and the returned result is validated the same way
+#[wasm_bindgen]
+pub async fn generate_moc_with_llm_streaming(
+    endpoint: String,
+    model: String,
+    topic: String,
+    related_notes_json: String,
+    on_token: js_sys::Function,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = MocOptions::from_js(options, "generate_moc_with_llm_streaming")?;
+    let debug = options.request.debug;
+    if debug {
+        log_debug!("generate_moc_streaming called for topic: {}", topic);
+    }
+
+    let notes = parse_moc_notes(&related_notes_json)?;
+    let prompt = options.prompt(&topic, &notes);
+
+    let response = generate_text_streaming(
+        endpoint,
+        model,
+        prompt,
+        options.request.temperature,
+        &on_token,
+        &options.request,
+    ).await?;
+
+    finish_moc(answer_only(&response), &notes, options.strict, debug)
+}
+
+/// Write a "week in review" digest of the notes changed in a period (`period_label`, e.g.
+/// "2024-W20"). `recent_notes_json` is an array of {path, title, content, mtime}. The notes
+/// share the prompt's token budget for `context_window`; when even short snippets of all of them
+/// don't fit, they're summarized in batches and the digest is written from those summaries, all
+/// within this call. Links are checked against the given notes as generate_moc_with_llm does, and
+/// notes left out are listed under "## Other changes". Returns a `DigestResult` whose `markdown`
+/// is ready to save (e.g. as "Digests/2024-W20.md").
+#[wasm_bindgen]
+pub async fn generate_digest_with_llm(
+    endpoint: String,
+    model: String,
+    recent_notes_json: String,
+    period_label: String,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "generate_digest_with_llm")?;
+    let RequestOptions { temperature, debug, context_window, .. } = options;
+    let period = period_label.trim();
+    let notes = parse_digest_notes(&recent_notes_json).map_err(|e| JsValue::from_str(&e))?;
+    if debug {
+        log_debug!("generate_digest called for {} with {} notes", period, notes.len());
+    }
+
+    let mut result = DigestResult {
+        markdown: format!("# {}\n\nNo notes were changed.\n", period),
+        hallucinated_links_removed: Vec::new(),
+        missing_notes_added: Vec::new(),
+        notes: notes.len(),
+        batches: 0,
+    };
+    if !notes.is_empty() {
+        let overhead = [DigestStage::Notes, DigestStage::Batch, DigestStage::Combine].into_iter()
+            .map(|stage| estimate_tokens(&build_digest_prompt(period, "", stage)))
+            .max()
+            .unwrap_or(0);
+        let budget = prompt_budget(context_window, DIGEST_OUTPUT_TOKENS, overhead);
+        let generate = |prompt: String| generate_structured(
+            endpoint.clone(),
+            model.clone(),
+            prompt,
+            temperature,
+            None,
+            false, // Markdown output, not JSON
+            &options,
+        );
+
+        let mut entries: Vec<DigestEntry> = notes.iter().map(DigestEntry::from_note).collect();
+        let mut stage = DigestStage::Notes;
+        let response = loop {
+            let batches = digest_batches(&entries, budget);
+            if batches.len() <= 1 {
+                break generate(build_digest_prompt(period, &fit_digest_entries(&entries, budget), stage)).await?;
+            }
+            if debug {
+                log_debug!("Digest entries don't fit one prompt; summarizing {} entries in {} batches", entries.len(), batches.len());
+            }
+            let mut summaries = Vec::with_capacity(batches.len());
+            for batch in batches {
+                let prompt = build_digest_prompt(period, &fit_digest_entries(&entries[batch], budget), DigestStage::Batch);
+                let summary = generate(prompt).await?;
+                summaries.push(DigestEntry { heading: format!("Part {}", summaries.len() + 1), text: strip_code_fence(&summary) });
+            }
+            result.batches += summaries.len();
+            entries = summaries;
+            stage = DigestStage::Combine;
+        };
+
+        let moc_notes: Vec<MocNote> = notes.iter()
+            .map(|note| MocNote { title: note.title.clone(), path: note.path.clone(), context: None })
+            .collect();
+        let validated = validate_moc(&strip_code_fence(&response), &moc_notes, DIGEST_MISSING_HEADING);
+        result.markdown = with_digest_title(&validated.markdown, period);
+        result.hallucinated_links_removed = validated.hallucinated_links_removed;
+        result.missing_notes_added = validated.missing_notes_added;
+    }
+
+    if debug {
+        log_debug!("Digest links removed: {:?}, notes added: {:?}, batches: {}",
+            result.hallucinated_links_removed, result.missing_notes_added, result.batches);
+    }
+
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in generate_digest_with_llm: {}", e)))
+}
+
+/// Maximum note length (bytes) sent for summarization
+const SUMMARY_DOC_LIMIT: usize = 6000;
+
+/// Layout of a note summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SummaryStyle {
+    Prose,
+    Bullets,
+}
+
+impl SummaryStyle {
+    fn parse(style: Option<&str>) -> Result<Self, JsValue> {
+        match style.map(|s| s.trim().to_lowercase()).as_deref() {
+            None | Some("") | Some("prose") => Ok(SummaryStyle::Prose),
+            Some("bullets") | Some("bullet") | Some("bullet_points") => Ok(SummaryStyle::Bullets),
+            Some(other) => Err(JsValue::from_str(&format!("Unknown summary style: {}", other))),
+        }
+    }
+}
+
+fn build_summary_prompt(title: &str, content: &str, max_sentences: u32, style: SummaryStyle) -> String {
+    match style {
+        SummaryStyle::Prose => format!(
+            r#"Summarize the note titled "{}" in at most {} sentences.
+
+Note Content:
+{}
+
+Rules:
+- Be concise and factual; only state what the note says
+- Write plain prose in a single paragraph
+- Do not add a title, preamble or closing remarks (no "Here is a summary")
+
+Summary:"#,
+            title, max_sentences, content
+        ),
+        SummaryStyle::Bullets => format!(
+            r#"Summarize the note titled "{}" as at most {} bullet points.
+
+Note Content:
+{}
+
+Rules:
+- Be concise and factual; only state what the note says
+- One short sentence per bullet, each line starting with "- "
+- Do not add a title, preamble or closing remarks (no "Here is a summary")
+
+Summary:"#,
+            title, max_sentences, content
+        ),
+    }
+}
+
+/// Remove wrapping code fences, a "Summary:" label and surrounding quotes from model output
+fn clean_summary(response: &str) -> String {
+    let mut text = response.trim();
+
+    if let Some(inner) = text.strip_prefix("```") {
+        // Drop the language tag line, then the closing fence
+        let inner = inner.split_once('\n').map_or("", |(_, rest)| rest);
+        text = inner.trim_end().strip_suffix("```").unwrap_or(inner).trim();
+    }
+
+    for label in ["Summary:", "**Summary:**", "**Summary**:"] {
+        if let Some(rest) = text.strip_prefix(label) {
+            text = rest.trim_start();
+        }
+    }
+
+    for (open, close) in [('"', '"'), ('\u{201C}', '\u{201D}'), ('\'', '\'')] {
+        if text.len() > 1 && text.starts_with(open) && text.ends_with(close) {
+            text = text[open.len_utf8()..text.len() - close.len_utf8()].trim();
+        }
+    }
+
+    text.to_string()
+}
+
+/// Keep at most `max` sentences of prose. A sentence ends at '.', '!' or '?' followed by whitespace.
+fn cap_sentences(text: &str, max: usize) -> String {
+    let mut count = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if !matches!(c, '.' | '!' | '?') {
+            continue;
+        }
+        let at_boundary = chars.peek().is_none_or(|&(_, next)| next.is_whitespace());
+        if at_boundary {
+            count += 1;
+            if count >= max {
+                return text[..i + c.len_utf8()].to_string();
+            }
+        }
+    }
+    text.to_string()
+}
+
+/// Keep at most `max` bullet points, dropping any non-bullet chatter around them
+fn cap_bullets(text: &str, max: usize) -> String {
+    let bullets: Vec<&str> = text.lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("- ") || line.starts_with("* ") || line.starts_with("• "))
+        .take(max)
+        .collect();
+    if bullets.is_empty() {
+        // Model ignored the format; fall back to capping it as prose
+        return cap_sentences(text, max);
+    }
+    bullets.iter()
+        .map(|line| format!("- {}", line[line.chars().next().map_or(0, char::len_utf8)..].trim()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Summarize a note (e.g. for its frontmatter) in at most `max_sentences` sentences.
+/// `style` is "prose" (default) or "bullets"; with bullets, `max_sentences` caps the bullet count.
+/// The model's output is trimmed to the cap if it runs over.
+#[wasm_bindgen]
+pub async fn summarize_with_llm(
+    endpoint: String,
+    model: String,
+    title: String,
+    content: String,
+    max_sentences: u32,
+    style: Option<String>,
+    options: Option<JsValue>,
+) -> Result<String, JsValue> {
+    let options = RequestOptions::from_js(options, "summarize_with_llm")?;
+    let RequestOptions { temperature, debug, .. } = options;
+    let style = SummaryStyle::parse(style.as_deref())?;
+    let max_sentences = max_sentences.max(1);
+
+    if debug {
+        log_debug!("summarize_with_llm called for: {} ({:?}, max {})", title, style, max_sentences);
+    }
+
+    let prompt = build_summary_prompt(&title, &truncate_for_prompt(&content, SUMMARY_DOC_LIMIT), max_sentences, style);
+
+    if debug {
+        log_debug!("Summary prompt length: {} chars", prompt.len());
+    }
+
+    let response = generate_text(
+        endpoint,
+        model,
+        prompt,
+        temperature,
+        false,
+        &options,
+    ).await?;
+
+    if debug {
+        log_debug!("LLM summary response: {}", response);
+    }
+
+    let summary = clean_summary(&response);
+    let summary = match style {
+        SummaryStyle::Prose => cap_sentences(&summary, max_sentences as usize),
+        SummaryStyle::Bullets => cap_bullets(&summary, max_sentences as usize),
+    };
+
+    if summary.is_empty() {
+        return Err(JsValue::from_str("LLM returned an empty summary"));
+    }
+    Ok(summary)
+}
+
+/// Transcribe image content (Handwritten/Math)
+#[wasm_bindgen]
+pub async fn transcribe_image_with_llm(
+    endpoint: String,
+    model: String,
+    image_base64: String,
+    options: Option<JsValue>,
+) -> Result<String, JsValue> {
+    let options = RequestOptions::from_js(options, "transcribe_image_with_llm")?;
+    let RequestOptions { debug, .. } = options;
+    if debug {
+        log_debug!("transcribe_image_with_llm called. Model: {}", model);
+    }
+
+    let prompt = r#"Transcribe the text in this image to Markdown verbatim.
+1. The FIRST line must be a descriptive Markdown header.
+2. Output ONLY the transcription. Do not summarize or explain.
+3. Use LaTeX for math ($...$).
+"#.to_string();
+
+    generate_text_with_images_ollama(
+        endpoint,
+        model,
+        prompt,
+        vec![image_base64],
+        Some(0.1), // Low temp for accurate OCR
+        &options,
+    ).await
+}
+
+/// How much describe_image_with_llm writes
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageDetailLevel {
+    /// One-sentence alt text
+    Brief,
+    /// Alt text plus a detailed paragraph, suitable for embedding and search
+    Detailed,
+}
+
+/// Accessible description of an image
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImageDescription {
+    pub alt_text: String,
+    pub description: String,
+    /// The image contains readable text (worth running transcribe_image_with_llm)
+    pub detected_text_present: bool,
+}
+
+/// Openers models like to start descriptions with
+const DESCRIPTION_PREAMBLES: &[&str] = &[
+    "this image shows", "the image shows", "this image depicts", "the image depicts",
+    "this is an image of", "this is a picture of", "an image of", "a picture of",
+    "the picture shows", "this picture shows",
+];
+
+/// Strip fences, quotes and a leading "This image shows", then capitalize
+fn clean_image_text(text: &str) -> String {
+    let text = clean_summary(text);
+    let lower = text.to_lowercase();
+    let text = DESCRIPTION_PREAMBLES.iter()
+        .find(|preamble| lower.starts_with(*preamble))
+        .map_or(text.as_str(), |preamble| text[preamble.len()..].trim_start_matches([' ', ':', ',']))
+        .trim();
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Read {alt_text, description, has_text} from the model, falling back to treating the whole
+/// answer as the description when it isn't JSON
+fn parse_image_description(response: &str) -> ImageDescription {
+    let parsed = extract_json_object(response)
+        .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok());
+    let field = |name: &str| parsed.as_ref()
+        .and_then(|v| v.get(name))
+        .and_then(|v| v.as_str())
+        .map(clean_image_text)
+        .filter(|text| !text.is_empty());
+
+    let description = field("description");
+    let alt_text = field("alt_text");
+    let (alt_text, description) = match (alt_text, description) {
+        (Some(alt), Some(desc)) => (alt, desc),
+        (Some(alt), None) => (alt.clone(), alt),
+        (None, Some(desc)) => (cap_sentences(&desc, 1), desc),
+        (None, None) => {
+            let description = clean_image_text(response);
+            (cap_sentences(&description, 1), description)
+        }
+    };
+
+    let detected_text_present = parsed.as_ref()
+        .and_then(|v| v.get("has_text").or_else(|| v.get("detected_text_present")))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    ImageDescription { alt_text, description, detected_text_present }
+}
+
+/// Describe an image for alt text and search. Returns {alt_text, description, detected_text_present}.
+/// With Brief, the description is at most a couple of sentences; with Detailed it is a paragraph
+/// that can be embedded like a note so the image shows up in similarity search.
+#[wasm_bindgen]
+pub async fn describe_image_with_llm(
+    endpoint: String,
+    model: String,
+    image_base64: String,
+    detail_level: ImageDetailLevel,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "describe_image_with_llm")?;
+    let RequestOptions { debug, .. } = options;
+    if debug {
+        log_debug!("describe_image_with_llm called. Model: {}, detail: {:?}", model, detail_level);
+    }
+
+    let description_instructions = match detail_level {
+        ImageDetailLevel::Brief => "\"description\": one or two sentences describing the image",
+        ImageDetailLevel::Detailed => "\"description\": a detailed paragraph covering the subject, layout, notable details, and any visible text",
+    };
+
+    let prompt = format!(
+        r#"Describe this image for someone who cannot see it.
+
+Return ONLY a JSON object with these fields:
+- "alt_text": one short sentence suitable as image alt text
+- {}
+- "has_text": true if the image contains readable text, otherwise false
+
+Describe what is shown directly; do not start with "This image shows".
+Example: {{"alt_text": "Hand-drawn graph of a rising curve", "description": "...", "has_text": false}}
+"#,
+        description_instructions
+    );
+
+    let response = generate_text_with_images_ollama(
+        endpoint,
+        model,
+        prompt,
+        vec![image_base64],
+        Some(0.2),
+        &options,
+    ).await?;
+
+    if debug {
+        log_debug!("Image description response: {}", response);
+    }
+
+    let description = parse_image_description(&response);
+    if description.description.is_empty() {
+        return Err(JsValue::from_str("LLM returned an empty image description"));
+    }
+
+    serde_wasm_bindgen::to_value(&description)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in describe_image_with_llm: {}", e)))
+}
+
+/// Bounding boxes use a 0-1000 scale on both axes
+const BBOX_SCALE: f64 = 1000.0;
+
+/// A figure found by detect_objects_with_llm. `bbox` is [ymin, xmin, ymax, xmax] on a 0-1000 scale.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DetectedObject {
+    pub label: String,
+    #[serde(rename = "box")]
+    pub bbox: [u32; 4],
+    pub confidence: f32,
+}
+
+/// Parse a [ymin, xmin, ymax, xmax] box, clamping to 0-1000. Fractional 0-1 boxes are scaled up.
+/// Returns None for malformed or empty boxes.
+fn parse_bbox(value: &serde_json::Value) -> Option<[u32; 4]> {
+    let coords: Vec<f64> = value.as_array()?.iter().map(|v| v.as_f64()).collect::<Option<_>>()?;
+    if coords.len() != 4 || coords.iter().any(|c| !c.is_finite()) {
+        return None;
+    }
+    let fractional = coords.iter().all(|c| (0.0..=1.0).contains(c)) && coords.iter().any(|c| c.fract() != 0.0);
+    let scale = if fractional { BBOX_SCALE } else { 1.0 };
+    let clamped: Vec<u32> = coords.iter().map(|c| (c * scale).round().clamp(0.0, BBOX_SCALE) as u32).collect();
+    let [ymin, xmin, ymax, xmax] = [clamped[0], clamped[1], clamped[2], clamped[3]];
+    (ymin < ymax && xmin < xmax).then_some([ymin, xmin, ymax, xmax])
+}
+
+/// Detections from the model's answer: an array of {label, box, confidence}, an object wrapping
+/// one, or a bare box from older prompts. "null" or no JSON means nothing was found.
+fn parse_detected_objects(response: &str) -> Vec<DetectedObject> {
+    let response = response.trim();
+    if response.is_empty() || response.trim_matches('"').eq_ignore_ascii_case("null") {
+        return Vec::new();
+    }
+    let Some(items) = parse_json_list(response)
+        .or_else(|| extract_json_object(response).and_then(|obj| serde_json::from_str(&obj).ok()).map(|v| vec![v]))
+    else {
+        return Vec::new();
+    };
+
+    // A single bare box: [ymin, xmin, ymax, xmax]
+    if items.len() == 4 && items.iter().all(|v| v.is_number()) {
+        return parse_bbox(&serde_json::Value::Array(items))
+            .map(|bbox| vec![DetectedObject { label: "diagram".to_string(), bbox, confidence: 1.0 }])
+            .unwrap_or_default();
+    }
+
+    let mut objects: Vec<DetectedObject> = items.iter()
+        .filter_map(|item| {
+            let bbox = ["box", "bbox", "box_2d", "bbox_2d"].iter()
+                .find_map(|key| item.get(*key))
+                .or_else(|| item.is_array().then_some(item))
+                .and_then(parse_bbox)?;
+            Some(DetectedObject {
+                label: item.get("label").and_then(|l| l.as_str()).unwrap_or("diagram").to_string(),
+                bbox,
+                confidence: item.get("confidence").and_then(|c| c.as_f64()).map_or(1.0, |c| c.clamp(0.0, 1.0) as f32),
+            })
+        })
+        .collect();
+    objects.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    objects
+}
+
+/// Convert a [ymin, xmin, ymax, xmax] box on the 0-1000 scale to pixel [x, y, width, height]
+/// for an image of the given size, kept inside the image and at least 1px in each dimension.
+#[wasm_bindgen]
+pub fn scale_bounding_box(bbox: Vec<u32>, image_width: u32, image_height: u32) -> Result<Vec<u32>, JsValue> {
+    let [ymin, xmin, ymax, xmax]: [u32; 4] = bbox.try_into()
+        .map_err(|b: Vec<u32>| JsValue::from_str(&format!("Bounding box needs 4 values, got {}", b.len())))?;
+    let to_px = |value: u32, size: u32| ((value.min(1000) as f64 / BBOX_SCALE) * size as f64).floor() as u32;
+
+    let x = to_px(xmin, image_width).min(image_width.saturating_sub(1));
+    let y = to_px(ymin, image_height).min(image_height.saturating_sub(1));
+    let width = to_px(xmax, image_width).saturating_sub(x).clamp(1, (image_width - x).max(1));
+    let height = to_px(ymax, image_height).saturating_sub(y).clamp(1, (image_height - y).max(1));
+    Ok(vec![x, y, width, height])
+}
+
+/// Detect diagrams, charts and figures in an image.
+/// Returns an array of {label, box: [ymin, xmin, ymax, xmax] (0-1000), confidence}, most confident
+/// first; empty if there are none. Use scale_bounding_box to get pixel crop rectangles.
+#[wasm_bindgen]
+pub async fn detect_objects_with_llm(
+    endpoint: String,
+    model: String,
+    image_base64: String,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "detect_objects_with_llm")?;
+    let RequestOptions { debug, .. } = options;
+    if debug {
+        log_debug!("detect_objects_with_llm called. Model: {}", model);
+    }
+
+    let prompt = r#"Analyze this image and find every hand-drawn diagram, chart, figure, or schematic.
+Ignore lines of text. Focus on the visual illustrations.
+
+Return a JSON array with one object per figure:
+[{"label": "short description", "box": [ymin, xmin, ymax, xmax], "confidence": 0.9}]
+- Box values are integers from 0 to 1000 (representing 0% to 100% of height/width).
+- Example: [100, 200, 500, 800] means top 10%, left 20%, bottom 50%, right 80%.
+- confidence is between 0.0 and 1.0.
+- If there are NO figures, return [].
+- Output ONLY the JSON array. Do not include any explanation.
+"#.to_string();
+
+    let response = generate_text_with_images_ollama(
+        endpoint,
+        model,
+        prompt,
+        vec![image_base64],
+        Some(0.1), // Low temp for precision
+        &options,
+    ).await?;
+
+    let objects = parse_detected_objects(&response);
+    if debug {
+        log_debug!("Detection response: {} -> {} objects", response.trim(), objects.len());
+    }
+
+    serde_wasm_bindgen::to_value(&objects)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in detect_objects_with_llm: {}", e)))
+}
+
+/// Estimated tokens of note text per translation request; the reply needs about as many again
+const TRANSLATION_CHUNK_TOKENS: usize = 1500;
+
+/// Result of `translate_note_with_llm`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TranslationResult {
+    pub translated: String,
+    /// Protected segments (code, math, links, terms) whose placeholder the model dropped even
+    /// after a retry; they are missing from `translated`
+    pub unrestored: Vec<String>,
+    /// Number of requests the note was split into
+    pub chunks: usize,
+}
+
+/// A Markdown heading line ("# Title", "## Title"), as opposed to a tag ("#tag")
+fn is_heading_line(line: &str) -> bool {
+    let hashes = line.len() - line.trim_start_matches('#').len();
+    (1..=6).contains(&hashes) && line[hashes..].starts_with([' ', '\t', '\n', '\r'])
+}
+
+/// Split (masked) note text into chunks of whole heading sections of at most `max_tokens`;
+/// a section over the limit is split between paragraphs. The chunks concatenate back to `text`.
+fn split_for_translation(text: &str, max_tokens: usize) -> Vec<String> {
+    let mut sections: Vec<&str> = Vec::new();
+    let (mut start, mut offset) = (0, 0);
+    for line in text.split_inclusive('\n') {
+        if offset > start && is_heading_line(line) {
+            sections.push(&text[start..offset]);
+            start = offset;
+        }
+        offset += line.len();
+    }
+    sections.push(&text[start..]);
+
+    let pieces = sections.into_iter().flat_map(|section| {
+        if estimate_tokens(section) <= max_tokens {
+            vec![section]
+        } else {
+            section.split_inclusive("\n\n").collect()
+        }
+    });
+
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for piece in pieces {
+        if !current.is_empty() && estimate_tokens(&current) + estimate_tokens(piece) > max_tokens {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push_str(piece);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn build_translation_prompt(chunk: &str, target_language: &str, dropped: &[usize]) -> String {
+    let retry_note = if dropped.is_empty() {
+        String::new()
+    } else {
+        let list = dropped.iter().map(|&i| placeholder(i)).collect::<Vec<_>>().join(", ");
+        format!("\n\nYour previous translation left out {}. Every placeholder must appear in the translation.", list)
+    };
+
+    format!(
+        r#"Translate the following Markdown text into {}.
+
+Rules:
+- Keep the Markdown structure exactly: headings, lists, tables, blockquotes, emphasis and line breaks.
+- Tokens like ⟦M0⟧ are placeholders for code, math, links and names. Copy each one unchanged, exactly once, where it belongs in the translated sentence.
+- Do not add explanations, notes or a title. Output ONLY the translated text.{}
+
+Text:
+{}"#,
+        target_language, retry_note, chunk
+    )
+}
+
+/// Whether a masked chunk has anything to translate besides placeholders, whitespace and markup
+fn has_translatable_text(chunk: &str) -> bool {
+    placeholder_regex().replace_all(chunk, "").chars().any(char::is_alphabetic)
+}
+
+/// Translate one masked chunk, asking again once if the model drops placeholders.
+/// The chunk's surrounding whitespace is kept so chunks join back up cleanly.
+async fn translate_chunk(
+    endpoint: &str,
+    model: &str,
+    chunk: &str,
+    target_language: &str,
+    request: &RequestOptions,
+) -> Result<String, JsValue> {
+    if !has_translatable_text(chunk) {
+        return Ok(chunk.to_string());
+    }
+    let expected: HashSet<usize> = placeholder_indices(chunk).into_iter().collect();
+
+    let mut best: Option<(String, usize)> = None;
+    let mut dropped: Vec<usize> = Vec::new();
+    for attempt in 0..2 {
+        let response = generate_text(
+            endpoint.to_string(),
+            model.to_string(),
+            build_translation_prompt(chunk, target_language, &dropped),
+            request.temperature,
+            false,
+            request,
+        ).await?;
+
+        let translated = strip_code_fence(&response);
+        let present: HashSet<usize> = placeholder_indices(&translated).into_iter().collect();
+        dropped = expected.difference(&present).copied().collect();
+        dropped.sort_unstable();
+
+        if request.debug {
+            log_debug!(
+                "Translation attempt {}: {} chars, {} placeholder(s) dropped",
+                attempt + 1, translated.len(), dropped.len()
+            );
+        }
+
+        if best.as_ref().is_none_or(|(_, missing)| dropped.len() < *missing) {
+            best = Some((translated, dropped.len()));
+        }
+        if dropped.is_empty() {
+            break;
+        }
+    }
+
+    let translated = best.map(|(text, _)| text).unwrap_or_default();
+    let leading = &chunk[..chunk.len() - chunk.trim_start().len()];
+    let trailing = &chunk[chunk.trim_end().len()..];
+    Ok(format!("{}{}{}", leading, translated.trim(), trailing))
+}
+
+/// Remove a code fence the model wrapped its whole answer in
+fn strip_code_fence(response: &str) -> String {
+    let text = response.trim();
+    match text.strip_prefix("```") {
+        Some(inner) => {
+            let inner = inner.split_once('\n').map_or("", |(_, rest)| rest);
+            inner.trim_end().strip_suffix("```").unwrap_or(inner).to_string()
+        }
+        None => text.to_string(),
+    }
+}
+
+/// Translate a note into `target_language`, keeping its Markdown intact. Wiki links, code, math,
+/// URLs, frontmatter and the terms in `preserve_terms_json` (a JSON array of strings) are replaced
+/// with placeholders before translation and restored afterwards. Long notes are translated a few
+/// heading sections at a time. Returns a `TranslationResult`; segments the model lost even after
+/// a retry are listed in `unrestored`.
+#[wasm_bindgen]
+pub async fn translate_note_with_llm(
+    endpoint: String,
+    model: String,
+    content: String,
+    target_language: String,
+    preserve_terms_json: String,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "translate_note_with_llm")?;
+    let debug = options.debug;
+    let target_language = target_language.trim();
+    if target_language.is_empty() {
+        return Err(JsValue::from_str("Target language is required"));
+    }
+    let preserve_terms: Vec<String> = if preserve_terms_json.trim().is_empty() {
+        Vec::new()
+    } else {
+        serde_json::from_str(&preserve_terms_json)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse preserve terms JSON: {}", e)))?
+    };
+
+    let masked = mask_protected(&content, &preserve_terms);
+    let chunks = split_for_translation(&masked.text, TRANSLATION_CHUNK_TOKENS);
+
+    if debug {
+        log_debug!(
+            "translate_note_with_llm: {} chars -> {}, {} masked segment(s), {} chunk(s)",
+            content.len(), target_language, masked.segments.len(), chunks.len()
+        );
+    }
+
+    let mut translated = String::with_capacity(masked.text.len());
+    for chunk in &chunks {
+        translated.push_str(&translate_chunk(
+            &endpoint,
+            &model,
+            chunk,
+            target_language,
+            &options,
+        ).await?);
+    }
+
+    let restored = restore_protected(&translated, &masked.segments);
+    if !restored.missing.is_empty() {
+        log_warn!(
+            "Translation dropped {} protected segment(s) that could not be restored",
+            restored.missing.len()
+        );
+    }
+
+    let result = TranslationResult {
+        translated: restored.text,
+        unrestored: restored.missing.iter().map(|&i| masked.segments[i].clone()).collect(),
+        chunks: chunks.len(),
+    };
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in translate_note_with_llm: {}", e)))
+}
+
+/// Parse the atomicity proposal, repairing almost-JSON if needed
+fn parse_atomicity_proposal(response: &str) -> Option<ProposedAtomicity> {
+    if let Ok(proposal) = serde_json::from_str::<ProposedAtomicity>(response) {
+        return Some(proposal);
+    }
+    let json_text = extract_json_object(response).unwrap_or_else(|| response.to_string());
+    let json_text = repair_json(&json_text)?;
+    serde_json::from_str(&json_text).ok()
+}
+
+/// Judge whether a note sticks to one idea (Zettelkasten atomicity) and propose child notes to
+/// split out. Each child's range is checked against the note: it comes from a heading's section
+/// or the fuzzy-matched first/last words the model quoted, and overlapping or missing ranges are
+/// trimmed or dropped. Returns an `AtomicityAnalysis` ready for a split command.
+#[wasm_bindgen]
+pub async fn analyze_atomicity_with_llm(
+    endpoint: String,
+    model: String,
+    title: String,
+    content: String,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "analyze_atomicity_with_llm")?;
+    let RequestOptions { temperature, debug, use_schema, context_window, .. } = options;
+    if debug {
+        log_debug!("analyze_atomicity called for: {} ({} chars)", title, content.len());
+    }
+
+    let template = r###"Judge whether this note is atomic: does it cover exactly one idea, or several topics that should be separate notes?
+
+Note Title: "PLACEHOLDER_TITLE"
+Content:
+PLACEHOLDER_CONTENT
+
+Tasks:
+1. "score": 1.0 if the note is about a single idea, down to 0.0 for many unrelated topics.
+2. "children": if the note should be split, propose child notes (leave the main topic in the parent).
+   - "title": a concise title for the new note.
+   - "heading": the exact heading whose whole section should move, or null if the content is not under its own heading.
+   - "start_quote": the first 5-10 words of the content to move, copied exactly from the note.
+   - "end_quote": the last 5-10 words of the content to move, copied exactly from the note.
+   - "links": wiki link targets the new note should link to, starting with "PLACEHOLDER_TITLE".
+   - Child notes must not overlap. If the note is atomic, return no children.
+
+Response Format (JSON ONLY):
+{
+  "score": 0.4,
+  "reason": "Covers both sourdough starters and oven temperatures.",
+  "children": [
+    {
+      "title": "Oven Temperatures for Bread",
+      "reason": "Separate topic from starter maintenance.",
+      "heading": "Baking Temperatures",
+      "start_quote": "Most loaves bake best between",
+      "end_quote": "until the crust is deep brown.",
+      "links": ["PLACEHOLDER_TITLE"]
+    }
+  ]
+}
+"###;
+
+    let build_prompt = |doc_text: &str| template
+        .replace("PLACEHOLDER_TITLE", &title)
+        .replace("PLACEHOLDER_CONTENT", doc_text);
+
+    let overhead = estimate_tokens(&build_prompt(""));
+    let budget = prompt_budget(context_window, ATOMICITY_OUTPUT_TOKENS, overhead);
+    let fitted = fit_to_budget(vec![(content.as_str(), 1)], budget);
+    let prompt = build_prompt(&fitted[0]);
+
+    let response = generate_structured(
+        endpoint,
+        model,
+        prompt,
+        temperature,
+        use_schema.then_some(ATOMICITY_SCHEMA),
+        true,
+        &options,
+    ).await?;
+
+    if debug {
+        log_debug!("Atomicity Response: {}", response);
+    }
+
+    let proposal = parse_atomicity_proposal(&response).ok_or_else(|| {
+        JsValue::from_str(&format!("Failed to parse atomicity analysis. Response was: {}", response))
+    })?;
+    let analysis = resolve_atomicity(proposal, &title, &content);
+
+    if debug && !analysis.rejected.is_empty() {
+        log_debug!("Dropped child notes: {:?}", analysis.rejected);
+    }
+
+    serde_wasm_bindgen::to_value(&analysis)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in analyze_atomicity_with_llm: {}", e)))
+}
+
+/// Frontmatter fields as the model returns them
+#[derive(Deserialize, Debug, Default)]
+struct FrontmatterResponse {
+    #[serde(default)]
+    summary: String,
+    #[serde(default, alias = "tags")]
+    existing_tags: Vec<String>,
+    #[serde(default)]
+    new_tags: Vec<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
+}
+
+fn parse_frontmatter_response(response: &str) -> Option<FrontmatterResponse> {
+    if let Ok(parsed) = serde_json::from_str::<FrontmatterResponse>(response) {
+        return Some(parsed);
+    }
+    let json_text = extract_json_object(response).unwrap_or_else(|| response.to_string());
+    let json_text = repair_json(&json_text)?;
+    serde_json::from_str(&json_text).ok()
+}
+
+/// Suggest a note's frontmatter (summary, tags, aliases) in one call and render it as YAML.
+/// Tags the vault already uses are preferred and new ones limited, as in
+/// `analyze_formatting_with_llm`. If the note has frontmatter (in `content`, or passed as
+/// `existing_frontmatter` without its --- lines), the result is merged into it, keeping other
+/// keys untouched. Returns a `FrontmatterSuggestion`.
+#[wasm_bindgen]
+pub async fn generate_frontmatter_with_llm(
+    endpoint: String,
+    model: String,
+    title: String,
+    content: String,
+    existing_vault_tags: Vec<String>,
+    existing_frontmatter: Option<String>,
+    options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let options = RequestOptions::from_js(options, "generate_frontmatter_with_llm")?;
+    let RequestOptions { temperature, debug, use_schema, context_window, .. } = options;
+    let (content_frontmatter, body_start) = split_frontmatter(&content);
+    let existing = existing_frontmatter.as_deref().or(content_frontmatter);
+    let body = &content[body_start..];
+
+    if debug {
+        log_debug!("generate_frontmatter called for: {} (existing frontmatter: {})", title, existing.is_some());
+    }
+
+    let template = r###"Write frontmatter metadata for this note.
+
+Note Title: "PLACEHOLDER_TITLE"
+Existing Vault Tags: [PLACEHOLDER_TAGS]
+
+Content:
+PLACEHOLDER_CONTENT
+
+Tasks:
+1. "summary": one or two sentences describing what the note is about.
+2. Tags: prefer tags from the existing vault tags.
+   - "existing_tags": relevant tags copied exactly from the existing list.
+   - "new_tags": Limit to top 1-3 most relevant new tags, only if no existing tag fits.
+3. "aliases": other names someone might use to link to this note (abbreviations, synonyms). Can be empty.
+
+Response Format (JSON ONLY):
+{
+  "summary": "Explains how sourdough starters are made and maintained.",
+  "existing_tags": ["baking"],
+  "new_tags": ["sourdough"],
+  "aliases": ["Levain", "Sourdough Starter"]
+}
+"###;
+
+    let build_prompt = |tags: &str, doc_text: &str| template
+        .replace("PLACEHOLDER_TITLE", &title)
+        .replace("PLACEHOLDER_TAGS", tags)
+        .replace("PLACEHOLDER_CONTENT", doc_text);
+
+    // A long vault tag list is trimmed before the note itself
+    let existing_tags_str = existing_vault_tags.join(", ");
+    let overhead = estimate_tokens(&build_prompt("", ""));
+    let budget = prompt_budget(context_window, FRONTMATTER_OUTPUT_TOKENS, overhead);
+    let fitted = fit_to_budget(vec![(existing_tags_str.as_str(), 0), (body, 1)], budget);
+    let prompt = build_prompt(&fitted[0], &fitted[1]);
+
+    let response = generate_structured(
+        endpoint,
+        model,
+        prompt,
+        temperature,
+        use_schema.then_some(FRONTMATTER_SCHEMA),
+        true,
+        &options,
+    ).await?;
+
+    if debug {
+        log_debug!("Frontmatter Response: {}", response);
+    }
+
+    let parsed = parse_frontmatter_response(&response).ok_or_else(|| {
+        JsValue::from_str(&format!("Failed to parse frontmatter output. Response was: {}", response))
+    })?;
+
+    let summary = clean_summary(&parsed.summary).split_whitespace().collect::<Vec<_>>().join(" ");
+    let (tags, new_tags) = match_vault_tags(&parsed.existing_tags, &parsed.new_tags, &existing_vault_tags, MAX_NEW_TAGS);
+    let aliases = sanitize_aliases(&parsed.aliases, &title);
+    let all_tags: Vec<String> = tags.iter().chain(&new_tags).cloned().collect();
+    let frontmatter = merge_frontmatter(existing, &FrontmatterValues {
+        summary: &summary,
+        tags: &all_tags,
+        aliases: &aliases,
+    });
+
+    let result = FrontmatterSuggestion {
+        summary,
+        tags,
+        new_tags,
+        aliases,
+        frontmatter,
+        merged: existing.is_some(),
+    };
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in generate_frontmatter_with_llm: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn rerank_request(suggestions_json: &str, content: &str) -> RerankRequest {
+        RerankRequest {
+            model: "test-model".to_string(),
+            current_doc_title: "Current".to_string(),
+            current_doc_content: content.to_string(),
+            suggestions_json: suggestions_json.to_string(),
+            temperature: Some(0.0),
+            enable_thinking: false,
+            debug: false,
+            use_schema: false,
+            alpha: DEFAULT_RERANK_ALPHA,
+            current_doc_path: Some("Current.md".to_string()),
+            context_window: None,
+            min_candidates_for_llm: DEFAULT_MIN_CANDIDATES_FOR_LLM,
+        }
+    }
+
+    fn candidates_json(count: usize) -> String {
+        let candidates: Vec<String> = (1..=count)
+            .map(|i| format!(r#"{{"path":"Note {i}.md","title":"Note {i}","similarity":0.{i},"context":"About note {i}"}}"#))
+            .collect();
+        format!("[{}]", candidates.join(","))
+    }
+
+    /// Rerank with a sender that counts its calls and answers `response`
+    fn rerank_counting(request: RerankRequest, response: &str) -> (RerankResult, usize) {
+        let calls = Cell::new(0);
+        let send = |_prompt: String| {
+            calls.set(calls.get() + 1);
+            let response = response.to_string();
+            async move { Ok::<String, JsValue>(response) }
+        };
+        // JsValue errors can't be formatted off wasm, so a failure is reported without them
+        let Ok(result) = futures::executor::block_on(rerank_candidates(request, send)) else {
+            panic!("rerank failed");
+        };
+        (result, calls.get())
+    }
+
+    #[test]
+    fn too_few_candidates_send_no_request() {
+        let (result, calls) = rerank_counting(rerank_request(&candidates_json(2), "Some content"), "");
+        assert_eq!(calls, 0);
+        assert!(result.skipped_llm);
+        assert_eq!(result.ranking_coverage, 0);
+        let paths: Vec<&str> = result.suggestions.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(paths, ["Note 2.md", "Note 1.md"]);
+    }
+
+    #[test]
+    fn empty_content_sends_no_request() {
+        let (result, calls) = rerank_counting(rerank_request(&candidates_json(5), "  \n\t"), "");
+        assert_eq!(calls, 0);
+        assert!(result.skipped_llm);
+        assert_eq!(result.suggestions.len(), 5);
+    }
+
+    #[test]
+    fn enough_candidates_send_one_request() {
+        let response = "Document 1: 9 - Close match\nDocument 2: 2 - Loose\nDocument 3: 5 - Related";
+        let (result, calls) = rerank_counting(rerank_request(&candidates_json(3), "Some content"), response);
+        assert_eq!(calls, 1);
+        assert!(!result.skipped_llm);
+        assert_eq!(result.ranking_coverage, 3);
+        assert_eq!(result.suggestions[0].path, "Note 1.md");
+        assert_eq!(result.suggestions[0].llm_reason.as_deref(), Some("Close match"));
+    }
+
+    #[test]
+    fn truncate_for_prompt_cuts_multibyte_documents_on_char_and_sentence_boundaries() {
+        let documents = [
+            "Привет, мир. Это длинный документ о заметках и ссылках между ними. ".repeat(200),
+            "日本語の文章です。これは長いメモで、リンクの候補を探します。".repeat(200),
+            "Trip 👩\u{200D}👩\u{200D}👧 with 🎉 and 👍🏽 fun. ".repeat(200),
+        ];
+        let limits = [INSERTION_DOC_LIMIT, KEYWORD_DOC_LIMIT, TITLE_DOC_LIMIT, QA_NOTE_LIMIT, SUMMARY_DOC_LIMIT, 1000];
+        for doc in &documents {
+            for limit in limits.iter().flat_map(|&limit| limit..limit + 4) {
+                let truncated = truncate_for_prompt(doc, limit);
+                let Some(kept) = truncated.strip_suffix("...\n\n[Content truncated]") else {
+                    panic!("no truncation marker at limit {}", limit);
+                };
+                assert!(kept.len() <= limit && doc.starts_with(kept), "limit {}", limit);
+                assert!(kept.ends_with(['.', '。']), "limit {} ends mid-sentence", limit);
+            }
+        }
+        assert_eq!(insertion_doc_text(&documents[1]), truncate_for_prompt(&documents[1], INSERTION_DOC_LIMIT));
+    }
+
+    #[test]
+    fn equal_scores_rerank_in_path_order() {
+        let suggestions = r#"[
+            {"path":"C.md","title":"C","similarity":0.5,"context":""},
+            {"path":"A.md","title":"A","similarity":0.5,"context":""},
+            {"path":"B.md","title":"B","similarity":0.5,"context":""}
+        ]"#;
+        let (result, _) = rerank_counting(rerank_request(suggestions, "Some content"), "Document 1: 5\nDocument 2: 5\nDocument 3: 5");
+        let order: Vec<&str> = result.suggestions.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(order, ["A.md", "B.md", "C.md"]);
+    }
+
+    fn generate_request(options: &ModelOptions, keep_alive: Option<&str>) -> OllamaGenerateRequest {
+        OllamaGenerateRequest {
+            model: "llama3".to_string(),
+            prompt: "Hi".to_string(),
+            stream: false,
+            format: Some(ResponseFormat::Json),
+            images: None,
+            options: Some(options.to_ollama(Some(0.5))),
+            keep_alive: keep_alive.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn unset_options_keep_the_request_json_unchanged() {
+        let json = serde_json::to_string(&generate_request(&ModelOptions::default(), None)).unwrap();
+        assert_eq!(json, r#"{"model":"llama3","prompt":"Hi","stream":false,"format":"json","options":{"num_ctx":4096,"num_predict":-1,"temperature":0.5}}"#);
+
+        let embedding = crate::embeddings::OllamaEmbeddingRequest { model: "nomic".to_string(), prompt: "Hi".to_string(), keep_alive: None };
+        assert_eq!(serde_json::to_string(&embedding).unwrap(), r#"{"model":"nomic","prompt":"Hi"}"#);
+    }
+
+    #[test]
+    fn set_options_are_sent_to_ollama() {
+        let Ok(request) = serde_json::from_str::<RequestOptions>(r#"{"keep_alive": "30m", "model_options": {"num_ctx": 8192, "top_p": 0.9, "top_k": 40, "seed": 42, "num_thread": 4}}"#) else {
+            panic!("options not parsed");
+        };
+        let json = serde_json::to_string(&generate_request(&request.sized_model_options(), request.keep_alive.as_deref())).unwrap();
+        assert_eq!(json, r#"{"model":"llama3","prompt":"Hi","stream":false,"format":"json","options":{"num_ctx":8192,"num_predict":-1,"temperature":0.5,"top_p":0.9,"top_k":40,"seed":42,"num_thread":4},"keep_alive":"30m"}"#);
+
+        let embedding = crate::embeddings::OllamaEmbeddingRequest { model: "nomic".to_string(), prompt: "Hi".to_string(), keep_alive: Some("-1".to_string()) };
+        assert_eq!(serde_json::to_string(&embedding).unwrap(), r#"{"model":"nomic","prompt":"Hi","keep_alive":"-1"}"#);
+        assert!(serde_json::from_str::<RequestOptions>("{}").is_ok_and(|request| request.sized_model_options().num_ctx.is_none()));
+    }
+
+    #[test]
+    fn a_known_context_window_sizes_the_model_context() {
+        let Ok(request) = serde_json::from_str::<RequestOptions>(r#"{"context_window": 16384, "model_options": {"num_ctx": 8192, "seed": 7}}"#) else {
+            panic!("options not parsed");
+        };
+        let options = request.sized_model_options();
+        assert_eq!((options.num_ctx, options.seed), (Some(16384), Some(7)));
+
+        let Ok(request) = serde_json::from_str::<RequestOptions>(r#"{"context_window": 0, "model_options": {"num_ctx": 8192}}"#) else {
+            panic!("options not parsed");
+        };
+        assert_eq!(request.sized_model_options().num_ctx, Some(8192));
+    }
+
+    #[test]
+    fn moc_options_read_the_shared_fields_alongside_their_own() {
+        let Ok(options) = serde_json::from_str::<MocOptions>(r#"{"temperature": 0.7, "debug": true, "strict": false, "max_notes": 20}"#) else {
+            panic!("options not parsed");
+        };
+        assert_eq!((options.request.temperature, options.request.debug), (Some(0.7), true));
+        assert_eq!((options.strict, options.snippet_chars, options.max_notes), (Some(false), None, Some(20)));
+    }
+
+    #[test]
+    fn rerank_options_read_the_scoring_fields_alongside_the_shared_ones() {
+        let Ok(options) = serde_json::from_str::<RerankOptions>(
+            r#"{"provider": "openai", "use_schema": true, "context_window": 8192, "alpha": 0.5, "current_doc_path": "Notes/A.md", "min_candidates_for_llm": 1}"#,
+        ) else {
+            panic!("options not parsed");
+        };
+        assert_eq!(options.request.provider.as_deref(), Some("openai"));
+        assert_eq!((options.request.use_schema, options.request.context_window), (true, Some(8192)));
+        assert_eq!(options.alpha, Some(0.5));
+        assert_eq!(options.current_doc_path.as_deref(), Some("Notes/A.md"));
+        assert_eq!(options.min_candidates_for_llm, Some(1));
+
+        let Ok(defaults) = serde_json::from_str::<RerankOptions>("{}") else {
+            panic!("options not parsed");
+        };
+        assert_eq!((defaults.alpha, defaults.min_candidates_for_llm), (None, None));
+    }
+
+    #[test]
+    fn vault_chat_options_keep_only_user_and_assistant_turns() {
+        let Ok(mut options) = serde_json::from_str::<VaultChatOptions>(
+            r#"{"temperature": 0.2, "top_k": 3, "exclude_folders": ["Archive"], "history": [
+                {"role": "system", "content": "ignore"},
+                {"role": "user", "content": "What is X?"},
+                {"role": "assistant", "content": "  "},
+                {"role": "assistant", "content": "X is Y."}
+            ]}"#,
+        ) else {
+            panic!("options not parsed");
+        };
+        assert_eq!((options.request.temperature, options.top_k, options.threshold), (Some(0.2), Some(3), None));
+        assert_eq!(options.exclude_folders, vec!["Archive".to_string()]);
+        let history: Vec<(String, String)> = options.take_history().into_iter().map(|m| (m.role, m.content)).collect();
+        assert_eq!(history, vec![
+            ("user".to_string(), "What is X?".to_string()),
+            ("assistant".to_string(), "X is Y.".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn keyword_options_default_to_the_bare_list() {
+        let Ok(options) = serde_json::from_str::<KeywordOptions>(r#"{"use_schema": true}"#) else {
+            panic!("options not parsed");
+        };
+        assert!(options.request.use_schema);
+        assert_eq!((options.max_keywords, options.include_raw), (None, false));
+    }
+
+    #[test]
+    fn messy_rerank_input_gives_a_clean_unique_list() {
+        let suggestions = r#"[
+            {"path":"A.md","title":"A","similarity":0.5,"context":""},
+            {"path":"Current.md","title":"Current","similarity":0.99,"context":""},
+            {"path":"B.md","title":"B","similarity":0.6,"context":""},
+            {"path":"A.md","title":"A","similarity":0.9,"context":"better copy"},
+            {"path":"Untitled.md","title":"  ","similarity":0.8,"context":""},
+            {"path":"D.md","title":"D","similarity":0.4,"context":""},
+            {"path":"B.md","title":"B","similarity":0.1,"context":"worse copy"}
+        ]"#;
+        let response = r#"[
+            {"index": 1, "score": 8, "reason": "first"},
+            {"index": 1, "score": 2, "reason": "duplicate"},
+            {"index": 7, "score": 9, "reason": "out of range"},
+            {"index": 0, "score": 9, "reason": "zero"},
+            {"index": 3, "score": 6, "reason": "d"}
+        ]"#;
+        let (result, calls) = rerank_counting(rerank_request(suggestions, "Some content"), response);
+        assert_eq!(calls, 1);
+
+        let mut paths: Vec<&str> = result.suggestions.iter().map(|s| s.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, ["A.md", "B.md", "D.md"]);
+        assert_eq!(result.ranking_coverage, 2);
+
+        let by_path = |path: &str| result.suggestions.iter().find(|s| s.path == path).unwrap();
+        assert_eq!((by_path("A.md").similarity, by_path("A.md").llm_score), (0.9, Some(8.0)));
+        assert_eq!(by_path("A.md").context, "better copy");
+        assert_eq!((by_path("B.md").similarity, by_path("B.md").llm_score), (0.6, None));
+        assert_eq!(by_path("D.md").llm_reason.as_deref(), Some("d"));
+    }
+
+    #[test]
+    fn clean_candidates_without_a_current_path_keeps_every_titled_note() {
+        let candidate = |path: &str, title: &str| BaseSuggestion {
+            path: path.to_string(),
+            title: title.to_string(),
+            similarity: 0.5,
+            context: String::new(),
+        };
+        let cleaned = clean_candidates(vec![candidate("Current.md", "Current"), candidate("X.md", ""), candidate("Y.md", "Y")], None);
+        let paths: Vec<&str> = cleaned.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(paths, ["Current.md", "Y.md"]);
+    }
 }
@@ -1,6 +1,6 @@
 import { SmartVaultSettings } from '../settings/types';
 import { CONSTANTS } from '../constants';
-import type { LLMRerankedSuggestion } from './types';
+import type { LLMRerankedSuggestion, LLMRerankResult } from './types';
 import * as wasmNamespace from '../../pkg/obsidian_smart_vault';
 
 /**
@@ -20,15 +20,18 @@ export interface RerankerResult {
 export class RerankerService {
     private wasmModule: typeof wasmNamespace;
     private settings: SmartVaultSettings;
+    // Keeps LLM rankings in the cache index across restarts (keyed by content, candidates and model)
+    private smartVault: wasmNamespace.SmartVault | null;
     // Track in-flight LLM reranking requests to prevent duplicate calls
     private pendingRerankRequests: Map<string, Promise<RerankerResult>> = new Map();
     // Cache recent LLM reranking results (cleared on embedding change)
     private rerankCache: Map<string, { result: RerankerResult; timestamp: number }> = new Map();
     private readonly CACHE_TTL_MS = 60000;  // Cache results for 1 minute
 
-    constructor(wasmModule: typeof wasmNamespace, settings: SmartVaultSettings) {
+    constructor(wasmModule: typeof wasmNamespace, settings: SmartVaultSettings, smartVault: wasmNamespace.SmartVault | null = null) {
         this.wasmModule = wasmModule;
         this.settings = settings;
+        this.smartVault = smartVault;
     }
 
     /**
@@ -57,29 +60,32 @@ export class RerankerService {
 
     /**
      * Call LLM reranking with timeout.
-     * Returns the reranked results or throws on timeout.
+     * Returns the rerank result or throws on timeout.
      */
     private async callLLMWithTimeout(
         candidatesJson: string,
         currentDocTitle: string,
         currentDocContent: string,
         timeoutMs: number
-    ): Promise<LLMRerankedSuggestion[]> {
-        return await Promise.race([
+    ): Promise<LLMRerankResult> {
+        const result = await Promise.race([
             this.wasmModule.rerank_suggestions_with_llm(
                 this.settings.ollamaEndpoint,
                 this.settings.llmModel,
                 currentDocTitle,
                 currentDocContent,
                 candidatesJson,
-                this.settings.llmTemperature,
-                this.settings.enableThinkingMode,
-                this.settings.debugMode
+                {
+                    temperature: this.settings.llmTemperature,
+                    enable_thinking: this.settings.enableThinkingMode,
+                    debug: this.settings.debugMode
+                }
             ),
             new Promise<never>((_, reject) =>
                 setTimeout(() => reject(new Error(`LLM reranking timeout after ${timeoutMs} ms`)), timeoutMs)
             )
-        ]) as LLMRerankedSuggestion[];
+        ]) as LLMRerankResult;
+        return result;
     }
 
     /**
@@ -159,7 +165,9 @@ export class RerankerService {
             currentDocTitle,
             currentDocContent,
             suggestions,
-            cacheKey
+            cacheKey,
+            docPath,
+            forceRefresh
         );
 
         // Track in-flight request
@@ -205,7 +213,9 @@ export class RerankerService {
         currentDocTitle: string,
         currentDocContent: string,
         suggestions: LLMRerankedSuggestion[],
-        cacheKey: string | null
+        cacheKey: string | null,
+        docPath?: string,
+        forceRefresh: boolean = false
     ): Promise<RerankerResult> {
         try {
             // Take top N candidates for LLM reranking, keep the rest as embedding-only
@@ -233,15 +243,33 @@ export class RerankerService {
             const timeoutMs = this.settings.llmTimeout || 15000;
             let reranked: LLMRerankedSuggestion[] = [];
 
+            // Rankings stored in the cache index for this exact content, candidate set and model
+            const storedKey = docPath && this.smartVault
+                ? this.wasmModule.rerank_cache_key(currentDocContent, llmCandidates.map(c => c.path), this.settings.llmModel)
+                : null;
+            const stored = storedKey && !forceRefresh
+                ? this.smartVault?.get_cached_rerank(storedKey) as LLMRerankResult | null
+                : null;
+            if (stored) {
+                if (this.settings.debugMode) {
+                    console.debug(`[DEBUG] Using stored LLM reranking result for ${docPath}`);
+                }
+                reranked = stored.suggestions;
+            }
+
             // Try up to 2 times before giving up
-            for (let attempt = 1; attempt <= 2; attempt++) {
+            for (let attempt = 1; !stored && attempt <= 2; attempt++) {
                 try {
-                    reranked = await this.callLLMWithTimeout(
+                    const result = await this.callLLMWithTimeout(
                         candidatesJson,
                         currentDocTitle,
                         currentDocContent,
                         timeoutMs
                     );
+                    reranked = result.suggestions;
+                    if (storedKey && docPath && !result.skipped_llm) {
+                        this.smartVault?.cache_rerank(storedKey, docPath, result);
+                    }
                     // Success - break out of retry loop
                     break;
                 } catch (error: unknown) {
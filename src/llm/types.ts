@@ -7,10 +7,28 @@ export interface LLMRerankedSuggestion {
     context: string;
 }
 
+/**
+ * Result of rerank_suggestions_with_llm. skipped_llm is set when there were too few
+ * candidates (or no document content) to be worth an LLM call. ranking_coverage counts the
+ * candidates that got a valid LLM score.
+ */
+export interface LLMRerankResult {
+    suggestions: LLMRerankedSuggestion[];
+    skipped_llm: boolean;
+    ranking_coverage: number;
+}
+
 export interface LLMInsertionResult {
     phrase: string | null;
     confidence: number;
     reason: string;
+    /** Where the phrase was found in the note: chars, bytes and UTF-16 units (editor offsets) */
+    start?: number;
+    end?: number;
+    offset_bytes?: number;
+    end_bytes?: number;
+    offset_utf16?: number;
+    end_utf16?: number;
 }
 
 /**
@@ -0,0 +1,141 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU8, Ordering};
+use wasm_bindgen::prelude::*;
+
+/// Severity of a log message; messages below the level set with `set_log_level` are dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl LogLevel {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(LogLevel::Error),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+
+    /// Console prefix, as the messages carried before there were levels
+    fn prefix(self) -> &'static str {
+        match self {
+            LogLevel::Error => "[ERROR]",
+            LogLevel::Warn => "[WARNING]",
+            LogLevel::Info => "[INFO]",
+            LogLevel::Debug => "[DEBUG]",
+            LogLevel::Trace => "[TRACE]",
+        }
+    }
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Warn as u8);
+
+thread_local! {
+    static LOG_CALLBACK: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+}
+
+/// Most verbose level logged: "error", "warn" (the default), "info", "debug" or "trace"
+#[wasm_bindgen]
+pub fn set_log_level(level: &str) -> Result<(), JsValue> {
+    let level = LogLevel::parse(level)
+        .ok_or_else(|| JsValue::from_str(&format!("Unknown log level: {}", level)))?;
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Level set with `set_log_level`
+#[wasm_bindgen]
+pub fn get_log_level() -> String {
+    let level = LOG_LEVEL.load(Ordering::Relaxed);
+    [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace]
+        .into_iter()
+        .find(|l| *l as u8 == level)
+        .unwrap_or(LogLevel::Warn)
+        .as_str()
+        .to_string()
+}
+
+/// Send log messages to `callback(level, message)` instead of the console; None restores the
+/// console. Messages the callback throws on go to the console.
+#[wasm_bindgen]
+pub fn set_log_callback(callback: Option<js_sys::Function>) {
+    LOG_CALLBACK.with(|cb| *cb.borrow_mut() = callback);
+}
+
+/// Whether messages at `level` are logged. The log macros check this before formatting.
+pub(crate) fn enabled(level: LogLevel) -> bool {
+    level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub(crate) fn emit(level: LogLevel, message: &str) {
+    let handled = LOG_CALLBACK.with(|cb| {
+        cb.borrow().as_ref().is_some_and(|callback| {
+            callback.call2(&JsValue::NULL, &level.as_str().into(), &message.into()).is_ok()
+        })
+    });
+    if handled {
+        return;
+    }
+    let line = JsValue::from_str(&format!("{} {}", level.prefix(), message));
+    match level {
+        LogLevel::Error => web_sys::console::error_1(&line),
+        LogLevel::Warn => web_sys::console::warn_1(&line),
+        LogLevel::Info | LogLevel::Debug | LogLevel::Trace => web_sys::console::log_1(&line),
+    }
+}
+
+/// On the host (native builds and `cargo test`) there's no console or JS callback to call
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub(crate) fn emit(level: LogLevel, message: &str) {
+    eprintln!("{} {}", level.prefix(), message);
+}
+
+/// Log a `format!` message at a level; nothing is formatted when the level is off
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::logging::enabled($level) {
+            $crate::logging::emit($level, &format!($($arg)*));
+        }
+    };
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => { log_at!($crate::logging::LogLevel::Error, $($arg)*) };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => { log_at!($crate::logging::LogLevel::Warn, $($arg)*) };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => { log_at!($crate::logging::LogLevel::Info, $($arg)*) };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => { log_at!($crate::logging::LogLevel::Debug, $($arg)*) };
+}
+
+// Only the LLM calls log at trace level
+#[cfg(feature = "wasm")]
+macro_rules! log_trace {
+    ($($arg:tt)*) => { log_at!($crate::logging::LogLevel::Trace, $($arg)*) };
+}
@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use crate::links::{FenceLine, FenceTracker};
+
+/// Text with protected segments (code, math, links, fixed terms) replaced by placeholders
+#[derive(Debug, Clone)]
+pub struct MaskedText {
+    pub text: String,
+    /// Original text of placeholder i
+    pub segments: Vec<String>,
+}
+
+/// Result of putting the protected segments back
+#[derive(Debug, Clone)]
+pub struct RestoredText {
+    pub text: String,
+    /// Indices of segments whose placeholder was missing from the text
+    pub missing: Vec<usize>,
+}
+
+pub fn placeholder(index: usize) -> String {
+    format!("⟦M{}⟧", index)
+}
+
+/// Placeholders as they come back from a model, tolerating inserted spaces ("⟦ M3 ⟧")
+const PLACEHOLDER_PATTERN: &str = r"⟦\s*M\s*(\d+)\s*⟧";
+
+/// PLACEHOLDER_PATTERN, compiled once
+pub(crate) fn placeholder_regex() -> &'static regex::Regex {
+    static PLACEHOLDER: OnceLock<regex::Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| regex::Regex::new(PLACEHOLDER_PATTERN).expect("built-in placeholder pattern is valid"))
+}
+
+/// Wiki links and embeds, inline code, inline/one-line display math, and URLs
+const INLINE_PROTECTED_PATTERN: &str =
+    r"!?\[\[[^\]\n]*\]\]|`[^`\n]+`|\$\$[^\n]+?\$\$|\$[^\s$](?:[^$\n]*[^\s$])?\$|https?://[^\s)>\]]*[^\s)>\].,;:!?]";
+
+/// Byte ranges of whole-line blocks that must not change: leading YAML frontmatter, fenced
+/// code (see `FenceTracker`; unclosed fences run to the end) and $$ display math. Each block's final
+/// newline stays outside it so the placeholder sits on its own line.
+fn protected_blocks(text: &str) -> Vec<(usize, usize)> {
+    let mut lines: Vec<(usize, &str)> = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        lines.push((offset, line));
+        offset += line.len();
+    }
+
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    if lines.first().is_some_and(|(_, line)| line.trim_end() == "---") {
+        if let Some(end) = lines.iter().skip(1).position(|(_, line)| matches!(line.trim_end(), "---" | "...")) {
+            let (last_start, last) = lines[end + 1];
+            blocks.push((0, last_start + last.trim_end_matches('\n').len()));
+            i = end + 2;
+        }
+    }
+
+    while i < lines.len() {
+        let (start, line) = lines[i];
+        let trimmed = line.trim();
+        let mut fence = FenceTracker::default();
+        let is_fence = fence.line(line) == FenceLine::Open;
+        let is_math = trimmed.starts_with("$$") && !(trimmed.len() > 2 && trimmed.ends_with("$$"));
+        if !is_fence && !is_math {
+            i += 1;
+            continue;
+        }
+
+        let mut closes = |l: &str| if is_fence { fence.line(l) == FenceLine::Close } else { l.trim_end().ends_with("$$") };
+        let end_line = (i + 1..lines.len()).find(|&j| closes(lines[j].1)).unwrap_or(lines.len() - 1);
+        let (last_start, last) = lines[end_line];
+        let end = last_start + last.trim_end_matches('\n').len();
+        blocks.push((start, end));
+        i = end_line + 1;
+    }
+    blocks
+}
+
+/// Pattern for `terms` as whole words (where they start/end with a word char), longest first
+pub(crate) fn terms_pattern(terms: &[String]) -> Option<String> {
+    let mut terms: Vec<&str> = terms.iter().map(|t| t.trim()).filter(|t| !t.is_empty()).collect();
+    if terms.is_empty() {
+        return None;
+    }
+    terms.sort_by_key(|t| std::cmp::Reverse(t.len()));
+    terms.dedup();
+    let alternatives: Vec<String> = terms.iter()
+        .map(|term| {
+            let starts_word = term.chars().next().is_some_and(|c| c.is_alphanumeric() || c == '_');
+            let ends_word = term.chars().next_back().is_some_and(|c| c.is_alphanumeric() || c == '_');
+            format!("{}{}{}",
+                if starts_word { r"\b" } else { "" },
+                regex::escape(term),
+                if ends_word { r"\b" } else { "" })
+        })
+        .collect();
+    Some(alternatives.join("|"))
+}
+
+/// Replace code blocks, math, frontmatter, wiki links, inline code, URLs and every occurrence
+/// of `preserve_terms` with numbered placeholders
+pub fn mask_protected(text: &str, preserve_terms: &[String]) -> MaskedText {
+    static INLINE_PROTECTED: OnceLock<regex::Regex> = OnceLock::new();
+    let protected = INLINE_PROTECTED.get_or_init(|| regex::Regex::new(INLINE_PROTECTED_PATTERN).expect("built-in mask pattern is valid"));
+    // The terms change per call, so only a pattern with them is compiled here
+    let with_terms = terms_pattern(preserve_terms)
+        .and_then(|terms| regex::Regex::new(&format!("{}|{}", INLINE_PROTECTED_PATTERN, terms)).ok());
+    let inline = with_terms.as_ref().unwrap_or(protected);
+
+    let mut masked = MaskedText { text: String::with_capacity(text.len()), segments: Vec::new() };
+    let mut cursor = 0;
+    for (start, end) in protected_blocks(text) {
+        mask_inline(&mut masked, &text[cursor..start], inline);
+        push_segment(&mut masked, &text[start..end]);
+        cursor = end;
+    }
+    mask_inline(&mut masked, &text[cursor..], inline);
+    masked
+}
+
+fn push_segment(masked: &mut MaskedText, segment: &str) {
+    masked.text.push_str(&placeholder(masked.segments.len()));
+    masked.segments.push(segment.to_string());
+}
+
+fn mask_inline(masked: &mut MaskedText, span: &str, pattern: &regex::Regex) {
+    let mut last = 0;
+    for m in pattern.find_iter(span) {
+        masked.text.push_str(&span[last..m.start()]);
+        push_segment(masked, m.as_str());
+        last = m.end();
+    }
+    masked.text.push_str(&span[last..]);
+}
+
+/// Put segments back in place of their placeholders. Placeholders for unknown indices are
+/// removed; segments whose placeholder never appears are reported in `missing`.
+pub fn restore_protected(text: &str, segments: &[String]) -> RestoredText {
+    let pattern = placeholder_regex();
+    let mut seen: HashSet<usize> = HashSet::new();
+    let restored = pattern.replace_all(text, |caps: &regex::Captures| {
+        match caps[1].parse::<usize>().ok().and_then(|i| segments.get(i).map(|s| (i, s))) {
+            Some((i, segment)) => {
+                seen.insert(i);
+                segment.clone()
+            }
+            None => String::new(),
+        }
+    });
+    RestoredText {
+        text: restored.into_owned(),
+        missing: (0..segments.len()).filter(|i| !seen.contains(i)).collect(),
+    }
+}
+
+/// Indices of the placeholders present in `text`
+pub fn placeholder_indices(text: &str) -> Vec<usize> {
+    let pattern = placeholder_regex();
+    pattern.captures_iter(text)
+        .filter_map(|caps| caps[1].parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inner_fences_do_not_end_a_protected_block() {
+        let block = "````markdown\n```rust\nlet x = 1;\n```\nstill code\n````";
+        let text = format!("Intro text.\n{}\nOutro text.\n", block);
+        let masked = mask_protected(&text, &[]);
+        assert_eq!(masked.segments, [block]);
+        assert_eq!(masked.text, format!("Intro text.\n{}\nOutro text.\n", placeholder(0)));
+
+        let block = "```\n```rust\n~~~\nmore code\n```";
+        let masked = mask_protected(&format!("{}\nafter\n", block), &[]);
+        assert_eq!(masked.segments, [block]);
+    }
+}
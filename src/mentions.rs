@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::frontmatter::note_aliases;
+use crate::links::unlinked_text;
+use crate::masking::terms_pattern;
+use crate::offsets::OffsetCounter;
+use crate::extract_title_from_path;
+
+/// Names per combined pattern; keeps each compiled regex well under the size limit
+const NAMES_PER_PATTERN: usize = 500;
+
+/// A note's title or alias written as plain text in another note
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UnlinkedMention {
+    pub source_path: String,
+    pub target_path: String,
+    /// 0-based line and char column of the mention
+    pub line: usize,
+    pub column: usize,
+    /// Byte and UTF-16 offsets of the mention in the source note; use the UTF-16 ones with the
+    /// editor API
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+    /// The text as written in the source note
+    pub matched_text: String,
+}
+
+/// Title and frontmatter aliases a note can be mentioned by, trimmed and with at least
+/// `min_len` chars
+pub(crate) fn mention_names(path: &str, content: Option<&str>, min_len: usize) -> Vec<String> {
+    std::iter::once(extract_title_from_path(path))
+        .chain(content.map(note_aliases).unwrap_or_default())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && name.chars().count() >= min_len)
+        .collect()
+}
+
+/// Whole-word, case-insensitive occurrences of `names` (lowercase name -> paths it refers to) in
+/// the prose of `notes`, skipping links, code, frontmatter, a note's mentions of itself and pairs
+/// for which `ignored(source, target)` holds. All names are matched in one pass per line with a
+/// few combined patterns, longest name first. Sorted by source and position.
+pub(crate) fn find_unlinked_mentions(
+    notes: &HashMap<String, String>,
+    names: &HashMap<String, Vec<String>>,
+    ignored: impl Fn(&str, &str) -> bool,
+) -> Vec<UnlinkedMention> {
+    let mut all_names: Vec<String> = names.keys().cloned().collect();
+    all_names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+    let patterns: Vec<regex::Regex> = all_names.chunks(NAMES_PER_PATTERN)
+        .filter_map(terms_pattern)
+        .filter_map(|pattern| {
+            regex::RegexBuilder::new(&pattern)
+                .case_insensitive(true)
+                .size_limit(64 << 20)
+                .build()
+                .ok()
+        })
+        .collect();
+
+    let mut mentions = Vec::new();
+    let mut sources: Vec<&String> = notes.keys().collect();
+    sources.sort();
+    for source in sources {
+        let masked = unlinked_text(&notes[source]);
+        let mut offsets = OffsetCounter::new(&notes[source]);
+        let mut line_start = 0;
+        for (line_num, line) in masked.split('\n').enumerate() {
+            let mut found: Vec<(usize, &str)> = patterns.iter()
+                .flat_map(|pattern| pattern.find_iter(line).map(|m| (m.start(), m.as_str())))
+                .collect();
+            found.sort_unstable();
+            for (start, text) in found {
+                let Some(targets) = names.get(&text.to_lowercase()) else {
+                    continue;
+                };
+                let column = line[..start].chars().count();
+                let (offset_bytes, end_bytes) = (line_start + start, line_start + start + text.len());
+                let (from, to) = (offsets.at(offset_bytes), offsets.at(end_bytes));
+                for target in targets {
+                    if target == source || ignored(source, target) {
+                        continue;
+                    }
+                    mentions.push(UnlinkedMention {
+                        source_path: source.clone(),
+                        target_path: target.clone(),
+                        line: line_num,
+                        column,
+                        offset_bytes,
+                        end_bytes,
+                        offset_utf16: from.utf16,
+                        end_utf16: to.utf16,
+                        matched_text: text.to_string(),
+                    });
+                }
+            }
+            line_start += line.len() + 1;
+        }
+    }
+    mentions
+}
@@ -0,0 +1,343 @@
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::http::{self, RequestSettings, RetryConfig};
+use crate::embeddings::fetch_embedding;
+use crate::llm::{generate_text_with_format, RequestOptions};
+use crate::clock::now_ms;
+
+#[derive(Deserialize)]
+struct OllamaTagsResponse {
+    #[serde(default)]
+    models: Vec<OllamaTagModel>,
+}
+
+#[derive(Deserialize)]
+struct OllamaTagModel {
+    name: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    details: OllamaModelDetails,
+}
+
+#[derive(Deserialize, Default)]
+struct OllamaModelDetails {
+    #[serde(default)]
+    family: Option<String>,
+    #[serde(default)]
+    families: Option<Vec<String>>,
+    #[serde(default)]
+    parameter_size: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OllamaShowRequest<'a> {
+    model: &'a str,
+}
+
+#[derive(Deserialize)]
+struct OllamaShowResponse {
+    #[serde(default)]
+    details: OllamaModelDetails,
+    #[serde(default)]
+    model_info: HashMap<String, serde_json::Value>,
+    /// Reported by newer Ollama versions ("completion", "vision", "embedding", ...)
+    #[serde(default)]
+    capabilities: Option<Vec<String>>,
+}
+
+/// Whether a probe reached the server
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeStatus {
+    /// Server answered; an empty model list means nothing is installed
+    Ok,
+    /// No response at all (Ollama not running, wrong endpoint, timeout)
+    Unreachable,
+    /// Server answered with an error (e.g. unknown model)
+    Error,
+}
+
+/// A model installed on the Ollama server
+#[derive(Serialize, Debug, Clone)]
+pub struct ModelSummary {
+    pub name: String,
+    pub size: u64,
+    pub family: Option<String>,
+    pub parameter_size: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ModelListResult {
+    pub status: ProbeStatus,
+    pub models: Vec<ModelSummary>,
+    pub error: Option<String>,
+}
+
+/// Capabilities and limits of one model, for auto-configuring the plugin
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ModelDetails {
+    pub family: Option<String>,
+    pub families: Vec<String>,
+    /// Maximum context length in tokens, usable as num_ctx
+    pub context_length: Option<u64>,
+    pub embedding_length: Option<u64>,
+    pub supports_vision: bool,
+    pub supports_embedding: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ModelShowResult {
+    pub status: ProbeStatus,
+    pub model: Option<ModelDetails>,
+    pub error: Option<String>,
+}
+
+/// Probes should answer quickly, so don't retry
+fn probe_settings() -> RequestSettings {
+    RequestSettings {
+        retry: RetryConfig::from_options(Some(1), None),
+        ..Default::default()
+    }
+}
+
+fn failure_status(error: &str) -> ProbeStatus {
+    if http::is_network_error(error) {
+        ProbeStatus::Unreachable
+    } else {
+        ProbeStatus::Error
+    }
+}
+
+/// Look up a GGUF metadata key such as "llama.context_length" regardless of architecture
+fn model_info_u64(model_info: &HashMap<String, serde_json::Value>, suffix: &str) -> Option<u64> {
+    model_info.iter()
+        .find(|(key, _)| key.ends_with(suffix))
+        .and_then(|(_, value)| value.as_u64())
+}
+
+async fn fetch_models(endpoint: &str, timeout_ms: Option<u32>) -> Result<Vec<ModelSummary>, String> {
+    let settings = RequestSettings { timeout_ms, ..probe_settings() };
+    http::with_timeout(&settings, |settings| async move {
+        let response = http::get(&format!("{}/api/tags", endpoint), &settings).await?;
+        if !response.ok() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        let tags: OllamaTagsResponse = response.json().await
+            .map_err(|e| format!("Parse error: {}", e))?;
+        Ok(tags.models.into_iter().map(|m| ModelSummary {
+            name: m.name,
+            size: m.size,
+            family: m.details.family,
+            parameter_size: m.details.parameter_size,
+        }).collect())
+    }).await
+}
+
+async fn fetch_model_details(endpoint: &str, model: &str, timeout_ms: Option<u32>) -> Result<ModelDetails, String> {
+    let settings = RequestSettings { timeout_ms, ..probe_settings() };
+    let body = serde_json::to_string(&OllamaShowRequest { model })
+        .map_err(|e| format!("Serialization error in fetch_model_details: {}", e))?;
+
+    http::with_timeout(&settings, |settings| async move {
+        let response = http::post_json(&format!("{}/api/show", endpoint), &body, &settings).await?;
+        if !response.ok() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(format!("HTTP {}: {}", status, error_text));
+        }
+        let show: OllamaShowResponse = response.json().await
+            .map_err(|e| format!("Parse error: {}", e))?;
+
+        let families = show.details.families.unwrap_or_default();
+        let capabilities = show.capabilities.unwrap_or_default();
+        let has_capability = |name: &str| capabilities.iter().any(|c| c == name);
+        // Older servers don't report capabilities; fall back to the projector families and name
+        let supports_vision = has_capability("vision")
+            || families.iter().any(|f| f == "clip" || f == "mllama");
+        let supports_embedding = has_capability("embedding")
+            || (capabilities.is_empty() && model.to_lowercase().contains("embed"));
+
+        Ok(ModelDetails {
+            family: show.details.family,
+            context_length: model_info_u64(&show.model_info, ".context_length"),
+            embedding_length: model_info_u64(&show.model_info, ".embedding_length"),
+            families,
+            supports_vision,
+            supports_embedding,
+        })
+    }).await
+}
+
+/// List the models installed on the Ollama server via /api/tags.
+/// Returns {status: "ok" | "unreachable" | "error", models: [{name, size, family, parameter_size}], error}.
+/// "ok" with an empty list means the server is up but has no models pulled.
+#[wasm_bindgen]
+pub async fn list_ollama_models(endpoint: String, timeout_ms: Option<u32>) -> Result<JsValue, JsValue> {
+    let result = match fetch_models(&endpoint, timeout_ms).await {
+        Ok(models) => ModelListResult { status: ProbeStatus::Ok, models, error: None },
+        Err(e) => ModelListResult { status: failure_status(&e), models: Vec::new(), error: Some(e) },
+    };
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in list_ollama_models: {}", e)))
+}
+
+/// Probe one model via /api/show for its context length, families and capabilities.
+/// Returns {status, model: {family, families, context_length, embedding_length,
+/// supports_vision, supports_embedding}, error}; status is as in list_ollama_models.
+#[wasm_bindgen]
+pub async fn show_ollama_model(endpoint: String, model: String, timeout_ms: Option<u32>) -> Result<JsValue, JsValue> {
+    let result = match fetch_model_details(&endpoint, &model, timeout_ms).await {
+        Ok(details) => ModelShowResult { status: ProbeStatus::Ok, model: Some(details), error: None },
+        Err(e) => ModelShowResult { status: failure_status(&e), model: None, error: Some(e) },
+    };
+    serde_wasm_bindgen::to_value(&result)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in show_ollama_model: {}", e)))
+}
+
+/// Generation can include loading the model into memory, so allow more time than other probes
+const HEALTH_GENERATION_TIMEOUT_MS: u32 = 60_000;
+const HEALTH_PROBE_TIMEOUT_MS: u32 = 10_000;
+
+/// Which step of check_ollama_health failed
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthCheckStep {
+    Ping,
+    ListModels,
+    EmbeddingModel,
+    GenerationModel,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HealthCheckError {
+    pub step: HealthCheckStep,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct HealthReport {
+    pub reachable: bool,
+    pub embedding_model_ok: bool,
+    pub generation_model_ok: bool,
+    pub embedding_dim: Option<usize>,
+    /// Round trip of the root ping
+    pub latency_ms: Option<u32>,
+    pub errors: Vec<HealthCheckError>,
+}
+
+impl HealthReport {
+    fn fail(&mut self, step: HealthCheckStep, message: String) {
+        self.errors.push(HealthCheckError { step, message });
+    }
+}
+
+/// True if `model` names an installed model; "llama3" matches "llama3:latest"
+fn is_model_installed(installed: &[ModelSummary], model: &str) -> bool {
+    installed.iter().any(|m| m.name == model || m.name.strip_suffix(":latest") == Some(model))
+}
+
+async fn ping_endpoint(endpoint: &str, timeout_ms: Option<u32>) -> Result<u32, String> {
+    let settings = RequestSettings { timeout_ms, ..probe_settings() };
+    let started = now_ms();
+    http::with_timeout(&settings, |settings| async move {
+        let response = http::get(&format!("{}/", endpoint), &settings).await?;
+        if !response.ok() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        Ok(())
+    }).await?;
+    Ok((now_ms() - started).max(0.0) as u32)
+}
+
+/// Check whether `model` is in the /api/tags list; None if the list is unavailable
+fn check_installed(report: &mut HealthReport, installed: Option<&[ModelSummary]>, model: &str, step: HealthCheckStep) -> bool {
+    match installed {
+        Some(installed) if !is_model_installed(installed, model) => {
+            report.fail(step, format!("Model '{}' is not installed (run `ollama pull {}`)", model, model));
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Preflight check of an Ollama setup: (1) ping the endpoint root, (2) look both models up in
+/// /api/tags, (3) run a tiny test embedding and a tiny test generation. Every step runs even if
+/// an earlier one failed. An empty model name is reported as not configured.
+/// Returns {reachable, embedding_model_ok, generation_model_ok, embedding_dim, latency_ms,
+/// errors: [{step: "ping" | "list_models" | "embedding_model" | "generation_model", message}]}.
+/// `timeout_ms` bounds the ping, list and embedding steps (default 10 s); generation gets 60 s.
+#[wasm_bindgen]
+pub async fn check_ollama_health(endpoint: String, embedding_model: String, generation_model: String, timeout_ms: Option<u32>) -> Result<JsValue, JsValue> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let timeout_ms = timeout_ms.or(Some(HEALTH_PROBE_TIMEOUT_MS));
+    let mut report = HealthReport::default();
+
+    match ping_endpoint(endpoint, timeout_ms).await {
+        Ok(latency) => {
+            report.reachable = true;
+            report.latency_ms = Some(latency);
+        }
+        Err(e) => report.fail(HealthCheckStep::Ping, e),
+    }
+
+    let installed = match fetch_models(endpoint, timeout_ms).await {
+        Ok(models) => Some(models),
+        Err(e) => {
+            report.fail(HealthCheckStep::ListModels, e);
+            None
+        }
+    };
+
+    let embedding_model = embedding_model.trim();
+    if embedding_model.is_empty() {
+        report.fail(HealthCheckStep::EmbeddingModel, "No embedding model configured".to_string());
+    } else {
+        let listed = check_installed(&mut report, installed.as_deref(), embedding_model, HealthCheckStep::EmbeddingModel);
+        let settings = RequestSettings { timeout_ms, ..probe_settings() };
+        match fetch_embedding(endpoint, embedding_model, "health check".to_string(), None, &settings).await {
+            Ok(embedding) if embedding.is_empty() => {
+                report.fail(HealthCheckStep::EmbeddingModel, format!("Model '{}' returned an empty embedding", embedding_model));
+            }
+            Ok(embedding) => {
+                report.embedding_dim = Some(embedding.len());
+                report.embedding_model_ok = listed;
+            }
+            Err(e) => report.fail(HealthCheckStep::EmbeddingModel, format!("Test embedding failed: {}", e)),
+        }
+    }
+
+    let generation_model = generation_model.trim();
+    if generation_model.is_empty() {
+        report.fail(HealthCheckStep::GenerationModel, "No generation model configured".to_string());
+    } else {
+        let listed = check_installed(&mut report, installed.as_deref(), generation_model, HealthCheckStep::GenerationModel);
+        let request = RequestOptions {
+            max_attempts: Some(1),
+            timeout_ms: Some(HEALTH_GENERATION_TIMEOUT_MS),
+            ..Default::default()
+        };
+        let generation = generate_text_with_format(
+            endpoint.to_string(),
+            generation_model.to_string(),
+            "Reply with the single word OK.".to_string(),
+            Some(0.0),
+            None,
+            &request,
+        ).await;
+        match generation {
+            Ok(text) if text.trim().is_empty() => {
+                report.fail(HealthCheckStep::GenerationModel, format!("Model '{}' returned an empty response", generation_model));
+            }
+            Ok(_) => report.generation_model_ok = listed,
+            Err(e) => {
+                let message = e.as_string().unwrap_or_else(|| format!("{:?}", e));
+                report.fail(HealthCheckStep::GenerationModel, format!("Test generation failed: {}", message));
+            }
+        }
+    }
+
+    serde_wasm_bindgen::to_value(&report)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in check_ollama_health: {}", e)))
+}
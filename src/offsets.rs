@@ -0,0 +1,91 @@
+//! Offsets in results returned to JS. A range is given three ways: `start`/`end` in chars,
+//! `offset_bytes`/`end_bytes` in UTF-8 bytes (for slicing the Rust string), and
+//! `offset_utf16`/`end_utf16` in UTF-16 code units, the unit Obsidian's editor API
+//! (`posToOffset`, `replaceRange`) counts in. Emoji outside the Basic Multilingual Plane are
+//! 4 bytes, 1 char and 2 UTF-16 units.
+
+use wasm_bindgen::prelude::*;
+
+/// UTF-16 code units before `byte_offset` in `content`. An offset inside a char counts from the
+/// start of that char; one past the end counts the whole content.
+#[wasm_bindgen]
+pub fn byte_to_utf16_offset(content: &str, byte_offset: usize) -> usize {
+    OffsetCounter::new(content).at(byte_offset).utf16
+}
+
+/// A position in a note in chars and in UTF-16 units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TextOffset {
+    pub chars: usize,
+    pub utf16: usize,
+}
+
+/// Turns byte offsets into char and UTF-16 offsets, counting on from the previous offset asked
+/// for, so a note's positions in ascending order take one pass over it
+pub(crate) struct OffsetCounter<'a> {
+    content: &'a str,
+    byte: usize,
+    offset: TextOffset,
+}
+
+impl<'a> OffsetCounter<'a> {
+    pub(crate) fn new(content: &'a str) -> Self {
+        OffsetCounter { content, byte: 0, offset: TextOffset { chars: 0, utf16: 0 } }
+    }
+
+    /// Offsets of `byte`, floored to a char boundary. Going backwards starts over from the
+    /// beginning.
+    pub(crate) fn at(&mut self, byte: usize) -> TextOffset {
+        let mut byte = byte.min(self.content.len());
+        while !self.content.is_char_boundary(byte) {
+            byte -= 1;
+        }
+        if byte < self.byte {
+            *self = OffsetCounter::new(self.content);
+        }
+        for c in self.content[self.byte..byte].chars() {
+            self.offset.chars += 1;
+            self.offset.utf16 += c.len_utf16();
+        }
+        self.byte = byte;
+        self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surrogate_pairs_count_two_units() {
+        // 😀 and 𝄞 are 4 bytes and 2 UTF-16 units each; é is 2 bytes and 1 unit
+        let content = "a😀é𝄞b";
+        assert_eq!(byte_to_utf16_offset(content, 0), 0);
+        assert_eq!(byte_to_utf16_offset(content, 1), 1);
+        assert_eq!(byte_to_utf16_offset(content, 5), 3);
+        assert_eq!(byte_to_utf16_offset(content, 7), 4);
+        assert_eq!(byte_to_utf16_offset(content, 11), 6);
+        assert_eq!(byte_to_utf16_offset(content, content.len()), content.encode_utf16().count());
+    }
+
+    #[test]
+    fn offsets_inside_a_surrogate_pair_char_floor_to_its_start() {
+        let content = "x😀y";
+        for byte in 2..5 {
+            assert_eq!(byte_to_utf16_offset(content, byte), 1);
+        }
+        assert_eq!(byte_to_utf16_offset(content, 100), 4);
+    }
+
+    #[test]
+    fn counter_matches_utf16_prefixes_in_any_order() {
+        let content = "👩‍👩‍👧 Ünïcödé 日本語 🎉!";
+        let mut counter = OffsetCounter::new(content);
+        let boundaries: Vec<usize> = content.char_indices().map(|(byte, _)| byte).chain([content.len()]).collect();
+        for &byte in boundaries.iter().chain(boundaries.iter().rev()) {
+            let offset = counter.at(byte);
+            assert_eq!(offset.utf16, content[..byte].encode_utf16().count());
+            assert_eq!(offset.chars, content[..byte].chars().count());
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::frontmatter::split_frontmatter;
+use crate::links::{FenceLine, FenceTracker};
+use crate::offsets::byte_to_utf16_offset;
+
+/// A heading and its section, as found by `parse_headings`. Offsets are bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HeadingSpan {
+    pub level: usize,
+    /// As written, without the '#'s or setext underline
+    pub text: String,
+    /// 0-based line of the heading text
+    pub line: usize,
+    /// Start of the heading line
+    pub start: usize,
+    /// Start of the section's body: after the heading line (and setext underline)
+    pub body_start: usize,
+    /// Where the next heading of the same or a higher level starts, or the end of the note
+    pub end: usize,
+}
+
+/// A heading in the outline returned by `extract_outline`. Offsets are chars into the note.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutlineHeading {
+    pub level: usize,
+    pub text: String,
+    /// 0-based
+    pub line: usize,
+    pub start: usize,
+    /// End of the section, subsections included
+    pub end: usize,
+    /// The same range in bytes and UTF-16 units (see `offsets`)
+    pub offset_bytes: usize,
+    pub end_bytes: usize,
+    pub offset_utf16: usize,
+    pub end_utf16: usize,
+    pub children: Vec<OutlineHeading>,
+}
+
+/// Level of a setext underline ("===" for 1, "---" for 2), if `line` is one
+fn setext_level(line: &str) -> Option<usize> {
+    let trimmed = line.trim_end();
+    let indent = trimmed.len() - trimmed.trim_start().len();
+    let marks = trimmed.trim_start();
+    if indent > 3 || marks.is_empty() {
+        return None;
+    }
+    if marks.chars().all(|c| c == '=') {
+        Some(1)
+    } else if marks.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Level and text of an ATX heading ("## Text ##"), if `line` is one
+fn atx_heading(line: &str) -> Option<(usize, String)> {
+    let trimmed = line.trim_end();
+    let indent = trimmed.len() - trimmed.trim_start().len();
+    let rest = trimmed.trim_start();
+    let level = rest.len() - rest.trim_start_matches('#').len();
+    if indent > 3 || !(1..=6).contains(&level) {
+        return None;
+    }
+    let text = &rest[level..];
+    if !text.is_empty() && !text.starts_with([' ', '\t']) {
+        return None;
+    }
+    // An optional closing run of '#'s preceded by a space is not part of the text
+    let text = text.trim();
+    let without_closing = text.trim_end_matches('#');
+    let text = if without_closing.is_empty() || without_closing.ends_with([' ', '\t']) { without_closing.trim_end() } else { text };
+    Some((level, text.to_string()))
+}
+
+/// Whether a line can be the text of a setext heading (plain paragraph text)
+fn is_setext_text(line: &str) -> bool {
+    let trimmed = line.trim();
+    !trimmed.is_empty()
+        && line.len() - line.trim_start().len() <= 3
+        && !trimmed.starts_with(['>', '|', '-', '*', '+', '#'])
+        && !trimmed.split_once(". ").is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// ATX ("# Heading") and setext (underlined with === or ---) headings outside the frontmatter
+/// and fenced code, in document order
+pub(crate) fn parse_headings(content: &str) -> Vec<HeadingSpan> {
+    let body_start = split_frontmatter(content).1;
+    let mut lines: Vec<(usize, &str)> = Vec::new();
+    let mut offset = body_start;
+    for line in content[body_start..].split_inclusive('\n') {
+        lines.push((offset, line));
+        offset += line.len();
+    }
+    let first_line = content[..body_start].matches('\n').count();
+
+    let mut headings: Vec<HeadingSpan> = Vec::new();
+    let mut fence = FenceTracker::default();
+    // Whether the previous line was paragraph text, which a setext underline would apply to
+    // as a whole; only one-line paragraphs are taken as setext headings
+    let mut after_text = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let (start, line) = lines[i];
+        let trimmed = line.trim();
+        if fence.line(line) != FenceLine::Text {
+            after_text = false;
+            i += 1;
+            continue;
+        }
+
+        let heading = match atx_heading(line.trim_end_matches(['\n', '\r'])) {
+            Some((level, text)) => Some((level, text, 1)),
+            None => lines.get(i + 1)
+                .filter(|_| is_setext_text(line) && !after_text)
+                .and_then(|(_, next)| setext_level(next))
+                .map(|level| (level, trimmed.to_string(), 2)),
+        };
+        let Some((level, text, line_count)) = heading else {
+            after_text = !trimmed.is_empty();
+            i += 1;
+            continue;
+        };
+        after_text = false;
+        let (last_start, last) = lines[i + line_count - 1];
+        headings.push(HeadingSpan {
+            level,
+            text,
+            line: first_line + i,
+            start,
+            body_start: last_start + last.len(),
+            end: content.len(),
+        });
+        i += line_count;
+    }
+
+    for index in 0..headings.len() {
+        let level = headings[index].level;
+        if let Some(next) = headings[index + 1..].iter().find(|h| h.level <= level) {
+            headings[index].end = next.start;
+        }
+    }
+    headings
+}
+
+/// Heading text for comparison: wiki and markdown link markup reduced to the shown text, then
+/// lowercase letters and digits separated by single spaces
+pub(crate) fn normalize_heading_text(text: &str) -> String {
+    let mut shown = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find("[[") {
+        shown.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find("]]").map(|i| open + i) else {
+            break;
+        };
+        let inner = &rest[open + 2..close];
+        shown.push_str(inner.rsplit('|').next().unwrap_or(inner));
+        rest = &rest[close + 2..];
+    }
+    shown.push_str(rest);
+    shown.chars()
+        .map(|c| if c.is_alphanumeric() { c.to_lowercase().next().unwrap_or(c) } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Index of the heading named `wanted`: equal after normalizing case, punctuation and link
+/// markup first, then the first heading containing it (or contained in it)
+pub(crate) fn find_heading_span(headings: &[HeadingSpan], wanted: &str) -> Option<usize> {
+    let wanted = normalize_heading_text(wanted);
+    if wanted.is_empty() {
+        return None;
+    }
+    let normalized: Vec<String> = headings.iter().map(|h| normalize_heading_text(&h.text)).collect();
+    normalized.iter().position(|text| *text == wanted).or_else(|| {
+        normalized.iter().position(|text| !text.is_empty() && (text.contains(&wanted) || wanted.contains(text.as_str())))
+    })
+}
+
+/// Nest headings under the closest preceding heading of a lower level
+fn build_outline(content: &str, headings: &[HeadingSpan]) -> Vec<OutlineHeading> {
+    let char_offset = |byte: usize| content[..byte].chars().count();
+    let mut roots: Vec<OutlineHeading> = Vec::new();
+    // Path of open headings, innermost last
+    let mut stack: Vec<OutlineHeading> = Vec::new();
+    let close = |stack: &mut Vec<OutlineHeading>, roots: &mut Vec<OutlineHeading>| {
+        if let Some(done) = stack.pop() {
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(done),
+                None => roots.push(done),
+            }
+        }
+    };
+    for heading in headings {
+        while stack.last().is_some_and(|open| open.level >= heading.level) {
+            close(&mut stack, &mut roots);
+        }
+        stack.push(OutlineHeading {
+            level: heading.level,
+            text: heading.text.clone(),
+            line: heading.line,
+            start: char_offset(heading.start),
+            end: char_offset(heading.end),
+            offset_bytes: heading.start,
+            end_bytes: heading.end,
+            offset_utf16: byte_to_utf16_offset(content, heading.start),
+            end_utf16: byte_to_utf16_offset(content, heading.end),
+            children: Vec::new(),
+        });
+    }
+    while !stack.is_empty() {
+        close(&mut stack, &mut roots);
+    }
+    roots
+}
+
+/// Heading tree of a note: each `OutlineHeading` holds the headings nested under it. Headings in
+/// fenced code and the frontmatter are ignored; setext headings count as levels 1 and 2.
+#[wasm_bindgen]
+pub fn extract_outline(content: &str) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(&build_outline(content, &parse_headings(content)))
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in extract_outline: {}", e)))
+}
+
+/// Body of the section under the heading named `heading_text` (matched ignoring case,
+/// punctuation and link markup), up to the next heading of the same or a higher level
+#[wasm_bindgen]
+pub fn get_section(content: &str, heading_text: &str) -> Option<String> {
+    let headings = parse_headings(content);
+    find_heading_span(&headings, heading_text)
+        .map(|i| content[headings[i].body_start..headings[i].end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(outline: &[OutlineHeading]) -> Vec<(usize, String, usize)> {
+        outline.iter().map(|h| (h.level, h.text.clone(), h.children.len())).collect()
+    }
+
+    #[test]
+    fn text_before_the_first_heading_belongs_to_no_section() {
+        let content = "Intro without a heading.\n\n## Later\nLater body\n### Deeper\nDeep body\n# Top\nTop body\n";
+        let headings = parse_headings(content);
+        let outline = build_outline(content, &headings);
+        assert_eq!(shape(&outline), [(2, "Later".to_string(), 1), (1, "Top".to_string(), 0)]);
+        assert_eq!(outline[0].line, 2);
+        assert_eq!(outline[0].start, content.find("## Later").unwrap());
+        assert_eq!(outline[0].end_bytes, content.find("# Top").unwrap());
+
+        assert_eq!(get_section(content, "later").as_deref(), Some("Later body\n### Deeper\nDeep body\n"));
+        assert_eq!(get_section(content, "Top").as_deref(), Some("Top body\n"));
+        assert_eq!(get_section(content, "Intro"), None);
+        assert!(build_outline("Just text, no headings.", &parse_headings("Just text, no headings.")).is_empty());
+    }
+
+    #[test]
+    fn headings_with_wiki_links_match_their_shown_text() {
+        let content = "# Notes on [[Rust Book|the book]]\nBook body\n## [[Folder/Ownership]] rules\nOwnership body\n\nSee [[Elsewhere]]\n===\nSetext body\n";
+        let headings = parse_headings(content);
+        let texts: Vec<&str> = headings.iter().map(|h| h.text.as_str()).collect();
+        assert_eq!(texts, ["Notes on [[Rust Book|the book]]", "[[Folder/Ownership]] rules", "See [[Elsewhere]]"]);
+        assert_eq!(normalize_heading_text(texts[0]), "notes on the book");
+        assert_eq!(normalize_heading_text(texts[1]), "folder ownership rules");
+
+        assert_eq!(get_section(content, "Notes on the book").as_deref(), Some("Book body\n## [[Folder/Ownership]] rules\nOwnership body\n\n"));
+        assert_eq!(get_section(content, "[[Folder/Ownership]] rules").as_deref(), Some("Ownership body\n\n"));
+        assert_eq!(get_section(content, "ownership rules").as_deref(), Some("Ownership body\n\n"));
+        assert_eq!(get_section(content, "See Elsewhere").as_deref(), Some("Setext body\n"));
+    }
+
+    #[test]
+    fn headings_inside_nested_fences_are_ignored() {
+        let content = "````markdown\n```python\n# Not a heading\n```\n# Still code\n````\n# Real\n  ~~~\n## In tildes\n  ~~~\n## Also real\n";
+        let texts: Vec<String> = parse_headings(content).into_iter().map(|h| h.text).collect();
+        assert_eq!(texts, ["Real", "Also real"]);
+    }
+}
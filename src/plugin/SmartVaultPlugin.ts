@@ -384,10 +384,11 @@ export default class SmartVaultPlugin extends Plugin {
         wasmModule.init();
 
         this.wasmModule = wasmModule;
+        this.applyLogLevel();
         this.smartVault = new wasmModule.SmartVault();
 
         // Initialize RerankerService
-        this.rerankerService = new RerankerService(this.wasmModule, this.settings);
+        this.rerankerService = new RerankerService(this.wasmModule, this.settings, this.smartVault);
 
         // Initialize CacheManager
         // Initialize Cache Manager
@@ -662,9 +663,11 @@ export default class SmartVaultPlugin extends Plugin {
                             this.settings.llmModel,
                             file.basename,
                             truncatedContent,
-                            this.settings.llmTemperature,
-                            this.settings.enableThinkingMode,
-                            this.settings.debugMode
+                            {
+                                temperature: this.settings.llmTemperature,
+                                enable_thinking: this.settings.enableThinkingMode,
+                                debug: this.settings.debugMode
+                            }
                         ),
                         new Promise<never>((_, reject) =>
                             setTimeout(() => reject(new Error('Keyword extraction timeout')), this.settings.llmTimeout)
@@ -980,6 +983,14 @@ export default class SmartVaultPlugin extends Plugin {
 
     async saveSettings() {
         await this.saveData(this.settings);
+        this.applyLogLevel();
+    }
+
+    /**
+     * WASM logs warnings and errors only, unless debug mode is on.
+     */
+    applyLogLevel() {
+        this.wasmModule?.set_log_level(this.settings.debugMode ? 'debug' : 'warn');
     }
 
     // ============================================================
@@ -1037,19 +1048,25 @@ export default class SmartVaultPlugin extends Plugin {
 
             // 3. Call LLM to generate MOC
             const notesJson = JSON.stringify(relevantSuggestions);
-            const mocContent = await this.wasmModule.generate_moc_with_llm(
+            const mocResult = await this.wasmModule.generate_moc_with_llm(
                 this.settings.ollamaEndpoint,
                 this.settings.organizationModel || this.settings.chatModel || this.settings.llmModel,
                 topic,
                 notesJson,
-                0.7, // temperature
-                this.settings.enableThinkingMode,
-                this.settings.debugMode
+                {
+                    temperature: 0.7,
+                    enable_thinking: this.settings.enableThinkingMode,
+                    debug: this.settings.debugMode
+                }
             );
 
+            if (mocResult.hallucinated_links_removed.length > 0 || mocResult.missing_notes_added.length > 0) {
+                new Notice(`MOC links fixed: ${mocResult.hallucinated_links_removed.length} unknown removed, ${mocResult.missing_notes_added.length} missing added.`);
+            }
+
             // 4. Create File
             const filename = `MOC - ${topic}.md`;
-            const targetFile = await this.app.vault.create(filename, mocContent);
+            const targetFile = await this.app.vault.create(filename, mocResult.markdown);
 
             // Open the new file
             void this.app.workspace.getLeaf().openFile(targetFile);
@@ -1108,92 +1125,69 @@ export default class SmartVaultPlugin extends Plugin {
             // Use 'visionModel' setting (e.g. qwen2.5-vl)
             const visionModel = this.settings.visionModel || 'ministral-3:3b';
 
-            const resultJson = await this.wasmModule.detect_objects_with_llm(
+            const detections: { label: string; box: number[]; confidence: number }[] = await this.wasmModule.detect_objects_with_llm(
                 this.settings.ollamaEndpoint,
                 visionModel,
                 base64,
-                this.settings.debugMode
+                { debug: this.settings.debugMode }
             );
 
             if (this.settings.debugMode) {
-                console.debug('[DEBUG] object detection result:', resultJson);
-            }
-
-            // Clean response (sometimes has markdown blocks)
-            const cleanedJson = resultJson.replace(/```json/g, '').replace(/```/g, '').trim();
-
-            let coords;
-            try {
-                coords = JSON.parse(cleanedJson);
-            } catch {
-                // Try to find array regex
-                const arrMatch = cleanedJson.match(/\[\s*\d+\s*,\s*\d+\s*,\s*\d+\s*,\s*\d+\s*\]/);
-                if (arrMatch) {
-                    coords = JSON.parse(arrMatch[0]);
-                } else {
-                    throw new Error("Could not parse coordinates: " + resultJson);
-                }
+                console.debug('[DEBUG] object detection result:', detections);
             }
 
-            if (!coords || !Array.isArray(coords) || coords.length !== 4) {
-                new Notice('No valid diagram coordinates returned.');
-                return;
-            }
-
-            // [ymin, xmin, ymax, xmax] in 0-1000 scale
-            const [ymin, xmin, ymax, xmax] = coords;
-            if (ymin === null || ymin === undefined) {
+            if (detections.length === 0) {
                 new Notice('No diagram detected.');
                 return;
             }
 
-            new Notice(`Diagram detected at [${ymin}, ${xmin}]. Cropping...`);
+            new Notice(`Detected ${detections.length} diagram(s). Cropping...`);
 
             // Crop Image using HTML Canvas
             const blob = new Blob([arrayBuffer]);
             const imageBitmap = await createImageBitmap(blob);
 
-            const canvas = document.createElement('canvas');
-            const width = imageBitmap.width;
-            const height = imageBitmap.height;
-
-            // Calculate pixel coordinates
-            const x = Math.floor((xmin / 1000) * width);
-            const y = Math.floor((ymin / 1000) * height);
-            const w = Math.floor(((xmax - xmin) / 1000) * width);
-            const h = Math.floor(((ymax - ymin) / 1000) * height);
-
-            canvas.width = w;
-            canvas.height = h;
-
-            const ctx = canvas.getContext('2d');
-            if (!ctx) throw new Error("Could not get canvas context");
-
-            ctx.drawImage(imageBitmap, x, y, w, h, 0, 0, w, h);
-
-            // Convert to blob/buffer
-            // We need to write to vault. Obsidian writes ArrayBuffer.
-            // Canvas -> Blob -> ArrayBuffer
-            canvas.toBlob((croppedBlob) => {
-                void (async () => {
-                    if (!croppedBlob) {
-                        new Notice("Failed to create cropped image blob");
-                        return;
-                    }
-                    const croppedBuffer = await croppedBlob.arrayBuffer();
+            for (const [index, detection] of detections.entries()) {
+                // Pixel rectangle from the 0-1000 [ymin, xmin, ymax, xmax] box
+                const [x, y, w, h] = this.wasmModule.scale_bounding_box(
+                    new Uint32Array(detection.box),
+                    imageBitmap.width,
+                    imageBitmap.height
+                );
+
+                const canvas = document.createElement('canvas');
+                canvas.width = w;
+                canvas.height = h;
+
+                const ctx = canvas.getContext('2d');
+                if (!ctx) throw new Error("Could not get canvas context");
+
+                ctx.drawImage(imageBitmap, x, y, w, h, 0, 0, w, h);
+
+                // Convert to blob/buffer
+                // We need to write to vault. Obsidian writes ArrayBuffer.
+                // Canvas -> Blob -> ArrayBuffer
+                canvas.toBlob((croppedBlob) => {
+                    void (async () => {
+                        if (!croppedBlob) {
+                            new Notice("Failed to create cropped image blob");
+                            return;
+                        }
+                        const croppedBuffer = await croppedBlob.arrayBuffer();
 
-                    // Save file
-                    const timestamp = Date.now();
-                    const newFilename = `Diagram_${timestamp}.png`;
-                    await this.app.vault.createBinary(newFilename, croppedBuffer);
+                        // Save file
+                        const timestamp = Date.now();
+                        const newFilename = `Diagram_${timestamp}_${index + 1}.png`;
+                        await this.app.vault.createBinary(newFilename, croppedBuffer);
 
-                    // Append to note
-                    const editor = view.editor;
-                    editor.replaceSelection(`\n![[${newFilename}]]\n*Extracted Diagram*\n`);
+                        // Append to note
+                        const editor = view.editor;
+                        editor.replaceSelection(`\n![[${newFilename}]]\n*Extracted Diagram: ${detection.label}*\n`);
 
-                    new Notice(`Saved diagram: ${newFilename}`);
-                })();
-            }, 'image/png');
+                        new Notice(`Saved diagram: ${newFilename}`);
+                    })();
+                }, 'image/png');
+            }
 
         } catch (error) {
             console.error('Failed to extract diagram:', error);
@@ -845,7 +845,7 @@ export class CacheManager {
 
     getCachedInsertion(filePath: string, linkTitle: string): import('./types').InsertionResult | null {
         try {
-            const resultJson = this.smartVault.get_cached_insertion(filePath, linkTitle);
+            const resultJson = this.smartVault.get_cached_insertion_json(filePath, linkTitle);
             if (resultJson) {
                 return JSON.parse(resultJson);
             }
@@ -15,6 +15,13 @@ export interface InsertionResult {
     phrase: string | null;
     confidence: number;
     reason: string;
+    /** Where the phrase was found in the note: chars, bytes and UTF-16 units (editor offsets) */
+    start?: number;
+    end?: number;
+    offset_bytes?: number;
+    end_bytes?: number;
+    offset_utf16?: number;
+    end_utf16?: number;
 }
 
 export interface InsertionCache {
@@ -212,7 +212,7 @@ export class HandwrittenNoteWatcher {
                         settings.ollamaEndpoint,
                         settings.visionModel,
                         image,
-                        settings.debugMode || forceDebug
+                        { debug: settings.debugMode || forceDebug }
                     );
                 } finally {
                     clearTimeout(checkTimer);
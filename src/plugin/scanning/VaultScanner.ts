@@ -18,13 +18,23 @@ interface FileToProcess {
     needs_suggestions: boolean;
 }
 
+/**
+ * A file the scan plan leaves out, and why
+ */
+interface SkippedFile {
+    path: string;
+    reason: 'unchanged' | 'excluded' | 'not_included' | 'opted_out';
+    pattern: string | null;
+}
+
 /**
  * Result of Rust scan planning
  */
 interface ScanPlan {
     to_process: FileToProcess[];
-    to_skip: string[];
+    to_skip: SkippedFile[];
     current_file_index: number | null;
+    opted_out: number;
 }
 
 /**
@@ -130,10 +140,10 @@ export class VaultScanner {
             return this.scanVaultLegacy(notice, files, currentFile);
         }
 
-        skippedUnchanged = scanPlan.to_skip.length;
+        skippedUnchanged = scanPlan.to_skip.filter(s => s.reason === 'unchanged').length;
 
         if (this.settings.debugMode) {
-            console.debug(`[DEBUG] Scan plan: ${scanPlan.to_process.length} to process, ${scanPlan.to_skip.length} unchanged`);
+            console.debug(`[DEBUG] Scan plan: ${scanPlan.to_process.length} to process, ${skippedUnchanged} unchanged, ${scanPlan.opted_out} opted out`);
             if (scanPlan.current_file_index !== null) {
                 console.debug(`[DEBUG] Current file index: ${scanPlan.current_file_index}`);
             }
@@ -375,9 +385,11 @@ export class VaultScanner {
                     this.settings.llmModel,
                     file.basename,
                     truncatedContent,
-                    this.settings.llmTemperature,
-                    this.settings.enableThinkingMode,
-                    this.settings.debugMode
+                    {
+                        temperature: this.settings.llmTemperature,
+                        enable_thinking: this.settings.enableThinkingMode,
+                        debug: this.settings.debugMode
+                    }
                 );
 
                 const keywords = await Promise.race([
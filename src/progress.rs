@@ -0,0 +1,92 @@
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use crate::clock::now_ms;
+
+/// Error message for calls cancelled through their AbortSignal, so the plugin can ignore them silently
+pub const ABORTED_ERROR: &str = "aborted";
+
+/// Least time between two progress events of an operation
+const PROGRESS_INTERVAL_MS: f64 = 250.0;
+
+/// Most events an operation sends between its first and last, however fast it runs
+const MAX_PROGRESS_EVENTS: usize = 100;
+
+/// Progress of a long-running operation, passed to the callback set with
+/// `SmartVault::set_progress_callback`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ProgressEvent {
+    /// Name of the method doing the work, e.g. "suggest_links_for_vault"
+    pub operation: String,
+    pub current: usize,
+    pub total: usize,
+    /// What is being worked on: a note path, a report section or a byte count
+    pub detail: Option<String>,
+}
+
+/// Sends ProgressEvents for one run of an operation: the first and last always, those in between
+/// only once both `PROGRESS_INTERVAL_MS` and a hundredth of the work have passed since the
+/// previous one. Nothing is sent once `abort_signal` fires. A callback that throws is logged
+/// and the operation carries on.
+pub(crate) struct Progress<'a> {
+    callback: Option<&'a js_sys::Function>,
+    abort_signal: Option<&'a web_sys::AbortSignal>,
+    operation: &'static str,
+    total: usize,
+    last_current: Option<usize>,
+    last_ms: f64,
+}
+
+impl<'a> Progress<'a> {
+    pub(crate) fn new(callback: Option<&'a js_sys::Function>, operation: &'static str, total: usize) -> Self {
+        Progress { callback, abort_signal: None, operation, total, last_current: None, last_ms: 0.0 }
+    }
+
+    /// Stop sending events, and let the operation stop, once `abort_signal` fires
+    pub(crate) fn with_abort(mut self, abort_signal: Option<&'a web_sys::AbortSignal>) -> Self {
+        self.abort_signal = abort_signal;
+        self
+    }
+
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.abort_signal.is_some_and(|signal| signal.aborted())
+    }
+
+    /// Start counting a new stretch of work (e.g. the next report section) from 0
+    pub(crate) fn restart(&mut self, total: usize) {
+        self.total = total;
+        self.last_current = None;
+    }
+
+    /// Report `current` of `total` done, if enough time and work has passed since the last event
+    pub(crate) fn update(&mut self, current: usize, detail: impl FnOnce() -> Option<String>) {
+        let step = (self.total / MAX_PROGRESS_EVENTS).max(1);
+        let due = match self.last_current {
+            None => true,
+            Some(last) => current >= last + step && now_ms() - self.last_ms >= PROGRESS_INTERVAL_MS,
+        };
+        if due {
+            self.emit(current, detail());
+        }
+    }
+
+    /// Report the work as done, unless that was the last event sent
+    pub(crate) fn finish(&mut self, detail: Option<String>) {
+        if self.last_current != Some(self.total) {
+            self.emit(self.total, detail);
+        }
+    }
+
+    fn emit(&mut self, current: usize, detail: Option<String>) {
+        self.last_current = Some(current);
+        self.last_ms = now_ms();
+        let Some(callback) = self.callback else { return };
+        if self.is_aborted() {
+            return;
+        }
+        let event = ProgressEvent { operation: self.operation.to_string(), current, total: self.total, detail };
+        let Ok(event) = event.serialize(&serde_wasm_bindgen::Serializer::json_compatible()) else { return };
+        if let Err(e) = callback.call1(&JsValue::NULL, &event) {
+            log_warn!("Progress callback for {} failed: {:?}", self.operation, e);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::cache::CacheStats;
+use crate::graph::BrokenLink;
+use crate::LinkSuggestion;
+
+/// Sections and limits for `SmartVault::generate_vault_report`. Every field is optional in the
+/// options JSON; all sections are on by default.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct VaultReportOptions {
+    /// Heading of the report
+    pub title: String,
+    /// Path the report will be saved at, so links resolve as they will from there
+    pub report_path: Option<String>,
+    pub suggestions: bool,
+    pub orphans: bool,
+    pub broken_links: bool,
+    pub near_duplicates: bool,
+    pub cache_stats: bool,
+    /// Minimum similarity for link suggestions (before suggest_links_for_text's adjustments)
+    pub threshold: f32,
+    pub top_k_per_note: usize,
+    pub max_suggestions: usize,
+    /// Minimum similarity for two notes to be listed as near-duplicates
+    pub near_duplicate_threshold: f32,
+    pub max_near_duplicates: usize,
+    /// Every vault file path, attachments included, so links to them aren't reported broken;
+    /// the added notes if not given
+    pub known_paths: Option<Vec<String>>,
+}
+
+impl Default for VaultReportOptions {
+    fn default() -> Self {
+        VaultReportOptions {
+            title: "Vault report".to_string(),
+            report_path: None,
+            suggestions: true,
+            orphans: true,
+            broken_links: true,
+            near_duplicates: true,
+            cache_stats: true,
+            threshold: 0.7,
+            top_k_per_note: 3,
+            max_suggestions: 200,
+            near_duplicate_threshold: 0.95,
+            max_near_duplicates: 50,
+            known_paths: None,
+        }
+    }
+}
+
+/// Two notes whose embeddings are nearly the same
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NearDuplicate {
+    /// The earlier path of the two
+    pub first: String,
+    pub second: String,
+    pub similarity: f32,
+}
+
+/// What goes in a report; None for sections that are turned off
+#[derive(Default)]
+pub(crate) struct VaultReportData {
+    pub suggestions: Option<BTreeMap<String, Vec<LinkSuggestion>>>,
+    pub orphans: Option<Vec<String>>,
+    pub broken_links: Option<Vec<BrokenLink>>,
+    pub near_duplicates: Option<Vec<NearDuplicate>>,
+    pub cache_stats: Option<CacheStats>,
+}
+
+/// Markdown for a vault report. `link` turns a path into wiki-link markup; `link_title` does the
+/// same for a note title, returning None if it matches no note.
+pub(crate) fn render_vault_report(
+    title: &str,
+    data: &VaultReportData,
+    link: impl Fn(&str) -> String,
+    link_title: impl Fn(&str) -> Option<String>,
+) -> String {
+    let mut out = format!("# {}\n", title);
+    let none = |out: &mut String| out.push_str("\n_None_\n");
+
+    if let Some(suggestions) = &data.suggestions {
+        out.push_str("\n## Link suggestions\n");
+        if suggestions.is_empty() {
+            none(&mut out);
+        }
+        for (source, found) in suggestions {
+            out.push_str(&format!("\n### {}\n\n", link(source)));
+            for suggestion in found {
+                out.push_str(&format!("- [ ] {} ({:.2})\n", link(&suggestion.path), suggestion.similarity));
+            }
+        }
+    }
+
+    if let Some(orphans) = &data.orphans {
+        out.push_str("\n## Orphan notes\n");
+        if orphans.is_empty() {
+            none(&mut out);
+        } else {
+            out.push('\n');
+        }
+        for path in orphans {
+            out.push_str(&format!("- {}\n", link(path)));
+        }
+    }
+
+    if let Some(broken) = &data.broken_links {
+        out.push_str("\n## Broken links\n");
+        if broken.is_empty() {
+            none(&mut out);
+        } else {
+            out.push('\n');
+        }
+        for link_info in broken {
+            out.push_str(&format!("- {} line {}: `{}`", link(&link_info.source_path), link_info.line + 1, link_info.raw_link.replace('`', "'")));
+            let closest: Vec<String> = link_info.suggestions.iter().filter_map(|name| link_title(name)).collect();
+            if !closest.is_empty() {
+                out.push_str(&format!(" (did you mean {}?)", closest.join(", ")));
+            }
+            out.push('\n');
+        }
+    }
+
+    if let Some(duplicates) = &data.near_duplicates {
+        out.push_str("\n## Near-duplicates\n");
+        if duplicates.is_empty() {
+            none(&mut out);
+        } else {
+            out.push('\n');
+        }
+        for pair in duplicates {
+            out.push_str(&format!("- {} and {} ({:.3})\n", link(&pair.first), link(&pair.second), pair.similarity));
+        }
+    }
+
+    if let Some(stats) = &data.cache_stats {
+        out.push_str("\n## Cache\n\n");
+        out.push_str(&format!("- Notes: {}\n", stats.file_count));
+        out.push_str(&format!("- Embeddings: {}\n", stats.embedding_count));
+        out.push_str(&format!("- Notes with keywords: {}\n", stats.keyword_count));
+        out.push_str(&format!("- Ignored suggestions: {}\n", stats.ignored_count));
+        out.push_str(&format!("- Cached insertion points: {}\n", stats.insertion_cache_count));
+        if let Some(model) = &stats.active_model {
+            out.push_str(&format!("- Embedding model: {}\n", model));
+        }
+        if let Some(dim) = stats.embedding_dim {
+            out.push_str(&format!("- Embedding dimension: {}\n", dim));
+        }
+    }
+    out
+}
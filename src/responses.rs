@@ -0,0 +1,438 @@
+//! Parsing of LLM responses: reasoning blocks, JSON wrapped in prose, natural-language rankings
+//! and grammar fixes located in the note. Kept apart from the HTTP calls in `llm` so it builds
+//! and tests natively.
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use crate::links::{locate_phrase, PhraseMatchKind};
+
+const REASONING_OPEN_TAGS: [&str; 2] = ["<think>", "<thinking>"];
+const REASONING_CLOSE_TAGS: [&str; 2] = ["</think>", "</thinking>"];
+
+/// Earliest occurrence of any tag at or after `from`, as (start, end) byte offsets.
+/// `haystack` must be ASCII-lowercased so offsets match the original text.
+fn find_tag(haystack: &str, from: usize, tags: &[&str]) -> Option<(usize, usize)> {
+    tags.iter()
+        .filter_map(|tag| haystack[from..].find(tag).map(|i| (from + i, from + i + tag.len())))
+        .min_by_key(|&(start, _)| start)
+}
+
+/// Split a reasoning model's output into (reasoning, answer).
+/// Handles `<think>` and `<thinking>` (any case), multiple blocks, a block left unclosed at
+/// the end, and output that starts mid-reasoning with only a closing tag.
+pub fn strip_reasoning(text: &str) -> (String, String) {
+    let lower = text.to_ascii_lowercase();
+    let mut reasoning: Vec<&str> = Vec::new();
+    let mut answer = String::new();
+    let mut pos = 0;
+
+    // Some chat templates put the opening tag in the prompt, so only the close shows up
+    if let Some((close_start, close_end)) = find_tag(&lower, 0, &REASONING_CLOSE_TAGS) {
+        let opened_before = find_tag(&lower, 0, &REASONING_OPEN_TAGS).is_some_and(|(open, _)| open < close_start);
+        if !opened_before {
+            reasoning.push(&text[..close_start]);
+            pos = close_end;
+        }
+    }
+
+    while let Some((open_start, open_end)) = find_tag(&lower, pos, &REASONING_OPEN_TAGS) {
+        answer.push_str(&text[pos..open_start]);
+        match find_tag(&lower, open_end, &REASONING_CLOSE_TAGS) {
+            Some((close_start, close_end)) => {
+                reasoning.push(&text[open_end..close_start]);
+                pos = close_end;
+            }
+            None => {
+                // Unclosed block (e.g. generation cut off): the rest is reasoning
+                reasoning.push(&text[open_end..]);
+                pos = text.len();
+            }
+        }
+    }
+    answer.push_str(&text[pos..]);
+
+    let reasoning = reasoning.iter().map(|r| r.trim()).filter(|r| !r.is_empty()).collect::<Vec<_>>().join("\n\n");
+    (reasoning, answer.trim().to_string())
+}
+
+/// Drop reasoning blocks from a response, logging what was removed
+pub(crate) fn answer_only(response: &str) -> String {
+    let (reasoning, answer) = strip_reasoning(response);
+    if reasoning.is_empty() {
+        // Keep the response byte-for-byte unless there was an (empty) block to remove
+        return if answer == response.trim() { response.to_string() } else { answer };
+    }
+    log_debug!("Stripped model reasoning ({} chars): {}", reasoning.len(), reasoning);
+    answer
+}
+
+/// `alpha * (llm_score / 10) + (1 - alpha) * similarity`, or similarity alone when the LLM skipped the item.
+/// Both inputs are clamped to 0.0 - 1.0 so boosted similarities can't dominate; NaN counts as 0.0.
+pub(crate) fn combined_score(llm_score: Option<f32>, similarity: f32, alpha: f32) -> f32 {
+    let unit = |value: f32| if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) };
+    let similarity = unit(similarity);
+    match llm_score {
+        Some(score) => alpha * unit(score / 10.0) + (1.0 - alpha) * similarity,
+        None => similarity,
+    }
+}
+
+/// One candidate's score as ranked by the model
+#[derive(Serialize, Deserialize)]
+pub(crate) struct LLMRankingItem {
+    pub index: usize,
+    pub score: f32,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GrammarIssue {
+    pub original: String,
+    pub corrected: String,
+    pub reason: String,
+    /// A few words around the mistake, as quoted by the model; used to pick between repeats
+    #[serde(default)]
+    pub context: String,
+    /// Char offsets of `original` in the analyzed note, and its 1-based line
+    #[serde(default)]
+    pub start: Option<usize>,
+    #[serde(default)]
+    pub end: Option<usize>,
+    #[serde(default)]
+    pub line: Option<usize>,
+    /// The same range in bytes and UTF-16 units (see `offsets`)
+    #[serde(default)]
+    pub offset_bytes: Option<usize>,
+    #[serde(default)]
+    pub end_bytes: Option<usize>,
+    #[serde(default)]
+    pub offset_utf16: Option<usize>,
+    #[serde(default)]
+    pub end_utf16: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StructureChange {
+    pub title: String,
+    pub description: String,
+    pub markdown_to_insert: String, // The actual text to insert
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Flashcard {
+    pub question: String,
+    pub answer: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FormattingAnalysis {
+    pub grammar: Vec<GrammarIssue>,
+    pub structure_suggestions: Vec<StructureChange>,
+    pub flashcards: Vec<Flashcard>,
+    pub existing_tags: Vec<String>,
+    pub new_tags: Vec<String>,
+    /// Grammar issues whose text could not be found in the note
+    #[serde(default)]
+    pub unlocated: Vec<GrammarIssue>,
+}
+
+/// Extract JSON array from text that might have extra content
+/// Looks for the first `[` and last `]` to extract a JSON array
+pub(crate) fn extract_json_array(text: &str) -> Option<String> {
+    let first_bracket = text.find('[')?;
+    let last_bracket = text.rfind(']')?;
+
+    if last_bracket > first_bracket {
+        Some(text[first_bracket..=last_bracket].to_string())
+    } else {
+        None
+    }
+}
+
+/// Extract JSON object from text that might have extra content
+/// Looks for the first `{` and last `}` to extract a JSON object
+pub(crate) fn extract_json_object(text: &str) -> Option<String> {
+    let first_brace = text.find('{')?;
+    let last_brace = text.rfind('}')?;
+
+    if last_brace > first_brace {
+        Some(text[first_brace..=last_brace].to_string())
+    } else {
+        None
+    }
+}
+
+/// One ranking line after markdown is stripped, e.g. "Document 2 (Quantum Mechanics): 8.5 - reason",
+/// "3) 6/10 – reason" or "Doc 4: 85% — reason". Captures: index, score, denominator, percent, reason.
+const RANKING_LINE_PATTERN: &str = r#"(?i)^(?:(?:document|doc|source|candidate|item)\s*#?\s*|#\s*)?(\d+)\s*(?:\([^)]*\)|"[^"]*"|“[^”]*”)?\s*[:.)\-–—]\s*(?:score\s*[:=]?\s*)?(\d+(?:\.\d+)?)\s*(?:/\s*(\d+(?:\.\d+)?)|(%))?(?:\s*[-–—:|,]\s*|\s+|$)(.*)$"#;
+
+/// Remove markdown emphasis, code ticks, quote/heading/bullet markers so "**Document 3**: 7"
+/// reads as "Document 3: 7"
+fn strip_ranking_markup(line: &str) -> String {
+    let line = line.replace(['*', '`'], "").replace("__", "");
+    line.trim()
+        .trim_start_matches(['#', '>', '-', '•'])
+        .trim()
+        .to_string()
+}
+
+/// Score on the 0-10 scale: "7/10" and "3.5/5" are rescaled, "85%" becomes 8.5, then clamped
+fn normalize_rank_score(score: f32, denominator: Option<f32>, percent: bool) -> Option<f32> {
+    let score = match denominator {
+        Some(d) if d > 0.0 => score / d * 10.0,
+        Some(_) => return None,
+        None if percent => score / 10.0,
+        None => score,
+    };
+    score.is_finite().then_some(score.clamp(0.0, 10.0))
+}
+
+/// Parse one ranking line; None for anything else, including restated format lines
+/// ("Document 1: [score] - [reason]") and indices outside 1..=candidate_count
+fn parse_ranking_line(line: &str, candidate_count: usize) -> Option<LLMRankingItem> {
+    static RANKING_LINE: OnceLock<regex::Regex> = OnceLock::new();
+    let pattern = RANKING_LINE.get_or_init(|| regex::Regex::new(RANKING_LINE_PATTERN).expect("built-in ranking pattern is valid"));
+    let line = strip_ranking_markup(line);
+    if line.is_empty() || line.to_lowercase().contains("[score]") {
+        return None;
+    }
+    let caps = pattern.captures(&line)?;
+    let index: usize = caps[1].parse().ok()?;
+    if index == 0 || index > candidate_count {
+        return None;
+    }
+    let score: f32 = caps[2].parse().ok()?;
+    let denominator = caps.get(3).and_then(|d| d.as_str().parse::<f32>().ok());
+    let score = normalize_rank_score(score, denominator, caps.get(4).is_some())?;
+    let reason = caps.get(5).map_or("", |r| r.as_str()).trim();
+    Some(LLMRankingItem {
+        index,
+        score,
+        reason: if reason.is_empty() { "Relevant".to_string() } else { reason.to_string() },
+    })
+}
+
+/// Rankings from a natural-language response, one per matching line
+pub(crate) fn parse_natural_rankings(response: &str, candidate_count: usize) -> Vec<LLMRankingItem> {
+    response.lines()
+        .filter_map(|line| parse_ranking_line(line, candidate_count))
+        .collect()
+}
+
+/// Byte range of `issue.original` in `content`, skipping ranges already claimed by earlier
+/// issues so repeated identical fixes land on successive occurrences. Originals that start or end
+/// with a letter must sit on word boundaries ("teh" never matches inside "tehran"). Between several
+/// candidates, the one inside (or nearest) the model's quoted context wins.
+fn locate_grammar_issue(content: &str, issue: &GrammarIssue, claimed: &[(usize, usize)]) -> Option<(usize, usize)> {
+    let original = issue.original.as_str();
+    if original.trim().is_empty() {
+        return None;
+    }
+    let is_free = |start: usize, end: usize| !claimed.iter().any(|&(s, e)| start < e && s < end);
+
+    let starts_word = original.chars().next().is_some_and(char::is_alphanumeric);
+    let ends_word = original.chars().next_back().is_some_and(char::is_alphanumeric);
+    let candidates: Vec<(usize, usize)> = content.match_indices(original)
+        .map(|(start, _)| (start, start + original.len()))
+        .filter(|&(start, end)| {
+            (!starts_word || content[..start].chars().next_back().is_none_or(|c| !c.is_alphanumeric()))
+                && (!ends_word || content[end..].chars().next().is_none_or(|c| !c.is_alphanumeric()))
+        })
+        .filter(|&(start, end)| is_free(start, end))
+        .collect();
+
+    if candidates.is_empty() {
+        // Typographic quotes, case or spacing may differ from what the model echoed
+        return locate_phrase(content, original)
+            .filter(|found| found.kind == PhraseMatchKind::Normalized)
+            .map(|found| (found.start_byte, found.end_byte))
+            .filter(|&(start, end)| is_free(start, end));
+    }
+
+    if candidates.len() > 1 && !issue.context.trim().is_empty() {
+        if let Some(found) = locate_phrase(content, &issue.context) {
+            return candidates.into_iter().min_by_key(|&(start, end)| {
+                if start >= found.start_byte && end <= found.end_byte {
+                    0
+                } else {
+                    start.abs_diff(found.start_byte) + 1
+                }
+            });
+        }
+    }
+    candidates.first().copied()
+}
+
+/// Attach char, byte and UTF-16 offsets and line numbers to each grammar issue, moving any that can't be found
+/// in `content` (the full, untruncated note) to `unlocated`
+pub(crate) fn locate_grammar_issues(analysis: &mut FormattingAnalysis, content: &str) {
+    let mut claimed: Vec<(usize, usize)> = Vec::new();
+    for mut issue in std::mem::take(&mut analysis.grammar) {
+        let Some((start, end)) = locate_grammar_issue(content, &issue, &claimed) else {
+            analysis.unlocated.push(issue);
+            continue;
+        };
+        claimed.push((start, end));
+        let before = &content[..start];
+        let start_char = before.chars().count();
+        // The note's own wording, in case it was matched after normalization
+        issue.original = content[start..end].to_string();
+        issue.start = Some(start_char);
+        issue.end = Some(start_char + issue.original.chars().count());
+        let start_utf16 = before.encode_utf16().count();
+        issue.offset_bytes = Some(start);
+        issue.end_bytes = Some(end);
+        issue.offset_utf16 = Some(start_utf16);
+        issue.end_utf16 = Some(start_utf16 + issue.original.encode_utf16().count());
+        issue.line = Some(before.matches('\n').count() + 1);
+        analysis.grammar.push(issue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_reasoning_splits_think_blocks_from_the_answer() {
+        let (reasoning, answer) = strip_reasoning("<think>\nThe user wants a list.\n</think>\n\n- one\n- two");
+        assert_eq!(reasoning, "The user wants a list.");
+        assert_eq!(answer, "- one\n- two");
+
+        let (reasoning, answer) = strip_reasoning("Just an answer");
+        assert_eq!(reasoning, "");
+        assert_eq!(answer, "Just an answer");
+    }
+
+    #[test]
+    fn strip_reasoning_edge_cases() {
+        let cases = [
+            // Only a think block
+            ("<think>Nothing to add.</think>", "Nothing to add.", ""),
+            ("<think>\n</think>\n", "", ""),
+            // JSON after the closing tag
+            ("<think>Pick the best phrase.</think>\n{\"phrase\": \"see [[A]]\"}", "Pick the best phrase.", "{\"phrase\": \"see [[A]]\"}"),
+            // Unclosed tag: generation cut off mid-reasoning
+            ("Answer first. <think>Then some reasoning that never", "Then some reasoning that never", "Answer first."),
+            // Multiple blocks
+            ("<think>one</think>A<think>two</think>B", "one\n\ntwo", "AB"),
+            // <thinking> in any case
+            ("<Thinking>Long form</THINKING>Done", "Long form", "Done"),
+            ("<thinking>a</think>b", "a", "b"),
+            // Only the closing tag, the opening one having been in the prompt
+            ("Reasoning so far</think>{\"ok\": true}", "Reasoning so far", "{\"ok\": true}"),
+            // Not a tag
+            ("Use <b>bold</b> and x < y", "", "Use <b>bold</b> and x < y"),
+        ];
+        for (response, reasoning, answer) in cases {
+            assert_eq!(strip_reasoning(response), (reasoning.to_string(), answer.to_string()), "{:?}", response);
+        }
+    }
+
+    #[test]
+    fn answer_only_keeps_responses_without_reasoning_byte_for_byte() {
+        assert_eq!(answer_only("  {\"a\": 1}\n"), "  {\"a\": 1}\n");
+        assert_eq!(answer_only("<think>x</think>\n{\"a\": 1}\n"), "{\"a\": 1}");
+        assert_eq!(answer_only("<think></think> {\"a\": 1}"), "{\"a\": 1}");
+    }
+
+    #[test]
+    fn parse_ranking_line_reads_index_score_and_reason() {
+        let Some(item) = parse_ranking_line("Document 2: 7 - Shares the topic", 3) else {
+            panic!("ranking line not parsed");
+        };
+        assert_eq!((item.index, item.score, item.reason.as_str()), (2, 7.0, "Shares the topic"));
+        assert!(parse_ranking_line("Document 4: 7 - Out of range", 3).is_none());
+        assert!(parse_ranking_line("Some commentary", 3).is_none());
+    }
+
+    #[test]
+    fn parse_ranking_line_fixtures() {
+        let fixtures = [
+            ("Document 3: 7 - Shares the topic", Some((3, 7.0, "Shares the topic"))),
+            ("**Document 3**: 7 - Bold label", Some((3, 7.0, "Bold label"))),
+            ("## Document 1: 6", Some((1, 6.0, "Relevant"))),
+            ("- Doc #2 (Rust notes): 8/10 - Same crate", Some((2, 8.0, "Same crate"))),
+            ("Candidate 1 — 3.5/5, half marks", Some((1, 7.0, "half marks"))),
+            ("3. 85% - Mostly related", Some((3, 8.5, "Mostly related"))),
+            ("Document 2: score: 9 | Direct reference", Some((2, 9.0, "Direct reference"))),
+            ("Document 2 \"Title\": 4 weak link", Some((2, 4.0, "weak link"))),
+            ("Document 2 “Title”: 4 - curly quotes", Some((2, 4.0, "curly quotes"))),
+            ("2) 6", Some((2, 6.0, "Relevant"))),
+            ("#1: 4.5 - hash index", Some((1, 4.5, "hash index"))),
+            ("`Source 3`: 12 - clamped", Some((3, 10.0, "clamped"))),
+            ("Document 1: [score] - [reason]", None),
+            ("Document 0: 5 - zero index", None),
+            ("Document 4: 5 - past the candidates", None),
+            ("Document 1: 3/0 - no scale", None),
+            ("The documents are ranked below.", None),
+            ("", None),
+        ];
+        for (line, expected) in fixtures {
+            let parsed = parse_ranking_line(line, 3).map(|item| (item.index, item.score, item.reason));
+            assert_eq!(parsed.as_ref().map(|(i, s, r)| (*i, *s, r.as_str())), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn natural_rankings_skip_commentary_lines() {
+        let response = "Here is my ranking:\n\nDocument 1: 8 - Same project\nDocument 3: 2/10 - Unrelated\n\nHope this helps!";
+        let rankings: Vec<(usize, f32)> = parse_natural_rankings(response, 3).into_iter().map(|item| (item.index, item.score)).collect();
+        assert_eq!(rankings, vec![(1, 8.0), (3, 2.0)]);
+    }
+
+    #[test]
+    fn combined_score_blends_clamped_inputs() {
+        assert!((combined_score(Some(8.0), 0.5, 0.7) - 0.71).abs() < 1e-6);
+        assert_eq!(combined_score(None, 0.42, 0.7), 0.42);
+        // Boosted similarities and out-of-range scores are clamped before blending
+        assert_eq!(combined_score(Some(15.0), 1.3, 0.5), 1.0);
+        assert_eq!(combined_score(Some(f32::NAN), f32::NAN, 0.5), 0.0);
+    }
+
+    #[test]
+    fn json_is_extracted_from_surrounding_prose() {
+        let response = "Sure! Here you go:\n```json\n{\"tags\": [\"a\", \"b\"]}\n```\nAnything else?";
+        assert_eq!(extract_json_object(response).as_deref(), Some("{\"tags\": [\"a\", \"b\"]}"));
+        assert_eq!(extract_json_array(response).as_deref(), Some("[\"a\", \"b\"]"));
+        assert_eq!(extract_json_object("no json here"), None);
+        assert_eq!(extract_json_array("] backwards ["), None);
+    }
+
+    fn grammar_issue(original: &str, context: &str) -> GrammarIssue {
+        let Ok(issue) = serde_json::from_value::<GrammarIssue>(serde_json::json!({
+            "original": original, "corrected": "", "reason": "", "context": context,
+        })) else {
+            panic!("issue not parsed");
+        };
+        issue
+    }
+
+    #[test]
+    fn grammar_issues_get_char_byte_and_utf16_offsets() {
+        let content = "Café 🎉 teh end.\nSee teh tehran notes and teh rest.";
+        let mut analysis = FormattingAnalysis {
+            grammar: vec![
+                grammar_issue("teh", "teh end"),
+                grammar_issue("teh", "and teh rest"),
+                grammar_issue("recieve", ""),
+            ],
+            structure_suggestions: Vec::new(),
+            flashcards: Vec::new(),
+            existing_tags: Vec::new(),
+            new_tags: Vec::new(),
+            unlocated: Vec::new(),
+        };
+        locate_grammar_issues(&mut analysis, content);
+
+        let ranges: Vec<_> = analysis.grammar.iter()
+            .map(|issue| (issue.start, issue.offset_bytes, issue.offset_utf16, issue.line))
+            .collect();
+        // "Café " is 5 chars and 6 bytes; the party emoji adds 1 char, 4 bytes and 2 UTF-16 units
+        assert_eq!(ranges, vec![
+            (Some(7), Some(11), Some(8), Some(1)),
+            (Some(41), Some(45), Some(42), Some(2)),
+        ]);
+        assert_eq!(analysis.unlocated.len(), 1);
+        assert_eq!(analysis.unlocated[0].original, "recieve");
+    }
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use crate::frontmatter::note_body;
+use crate::links::{FenceLine, FenceTracker};
+
+/// Notes listed as longest and shortest in VaultStats
+const STATS_TOP_N: usize = 10;
+
+/// Reading speed for space-separated words, per minute
+const WORDS_PER_MINUTE: f64 = 200.0;
+
+/// Reading speed for CJK text, in characters per minute
+const CJK_CHARS_PER_MINUTE: f64 = 500.0;
+
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Size and age of one note. Counts cover the body, not the frontmatter.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NoteStats {
+    pub path: String,
+    /// Space-separated words, plus one per CJK character
+    pub word_count: usize,
+    pub char_count: usize,
+    pub heading_count: usize,
+    /// Wiki and markdown links, embeds included
+    pub link_count: usize,
+    pub reading_time_minutes: usize,
+    /// None if the note's modification time isn't known (it has never been processed)
+    pub days_since_modified: Option<u64>,
+}
+
+/// Vault-wide totals returned by `SmartVault::compute_note_stats`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct VaultStats {
+    pub note_count: usize,
+    pub total_words: usize,
+    pub total_chars: usize,
+    pub total_links: usize,
+    pub average_words: f64,
+    pub average_reading_time_minutes: f64,
+    /// Paths of the notes with the most words, longest first
+    pub longest: Vec<String>,
+    /// Paths of the notes with the fewest words, shortest first
+    pub shortest: Vec<String>,
+    /// Paths of the notes untouched for more than the given number of days, stalest first
+    pub stale: Vec<String>,
+    /// Per note, sorted by path
+    pub notes: Vec<NoteStats>,
+}
+
+/// Han, kana and other scripts written without spaces between words
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0xFF66..=0xFF9F | 0x20000..=0x2FA1F)
+}
+
+/// Stats for one note in a single pass over its body. Headings and links inside fenced code
+/// aren't counted; its words are.
+pub(crate) fn note_stats(path: &str, content: &str, mtime: Option<u64>, now_ms: u64) -> NoteStats {
+    let (mut words, mut cjk_chars, mut chars, mut headings, mut links) = (0, 0, 0, 0, 0);
+    let mut fence = FenceTracker::default();
+    for line in note_body(content).lines() {
+        if fence.line(line) == FenceLine::Text {
+            let trimmed = line.trim_start();
+            let level = trimmed.len() - trimmed.trim_start_matches('#').len();
+            if (1..=6).contains(&level) && trimmed[level..].chars().next().is_none_or(char::is_whitespace) {
+                headings += 1;
+            }
+            links += line.matches("[[").count() + line.matches("](").count();
+        }
+
+        let mut in_word = false;
+        for c in line.chars() {
+            chars += 1;
+            if is_cjk(c) {
+                cjk_chars += 1;
+                in_word = false;
+            } else if c.is_whitespace() {
+                in_word = false;
+            } else if c.is_alphanumeric() && !in_word {
+                words += 1;
+                in_word = true;
+            }
+        }
+    }
+
+    let minutes = words as f64 / WORDS_PER_MINUTE + cjk_chars as f64 / CJK_CHARS_PER_MINUTE;
+    NoteStats {
+        path: path.to_string(),
+        word_count: words + cjk_chars,
+        char_count: chars,
+        heading_count: headings,
+        link_count: links,
+        reading_time_minutes: minutes.ceil() as usize,
+        days_since_modified: mtime.map(|mtime| now_ms.saturating_sub(mtime) / MS_PER_DAY),
+    }
+}
+
+/// Totals, averages and rankings over per-note stats
+pub(crate) fn vault_stats(mut notes: Vec<NoteStats>, stale_after_days: u64) -> VaultStats {
+    notes.sort_by(|a, b| a.path.cmp(&b.path));
+    let count = notes.len();
+    let total_words: usize = notes.iter().map(|n| n.word_count).sum();
+    let total_reading: usize = notes.iter().map(|n| n.reading_time_minutes).sum();
+
+    let mut by_length: Vec<&NoteStats> = notes.iter().collect();
+    by_length.sort_by_key(|n| std::cmp::Reverse(n.word_count));
+    let longest = by_length.iter().take(STATS_TOP_N).map(|n| n.path.clone()).collect();
+    let shortest = by_length.iter().rev().take(STATS_TOP_N).map(|n| n.path.clone()).collect();
+
+    let mut stale: Vec<&NoteStats> = notes.iter()
+        .filter(|n| n.days_since_modified.is_some_and(|days| days > stale_after_days))
+        .collect();
+    stale.sort_by_key(|n| std::cmp::Reverse(n.days_since_modified));
+
+    VaultStats {
+        note_count: count,
+        total_words,
+        total_chars: notes.iter().map(|n| n.char_count).sum(),
+        total_links: notes.iter().map(|n| n.link_count).sum(),
+        average_words: if count == 0 { 0.0 } else { total_words as f64 / count as f64 },
+        average_reading_time_minutes: if count == 0 { 0.0 } else { total_reading as f64 / count as f64 },
+        longest,
+        shortest,
+        stale: stale.into_iter().map(|n| n.path.clone()).collect(),
+        notes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_strings_and_shorter_fences_do_not_close_a_block() {
+        let content = "# Title\n````markdown\n```python\n# comment\n[[Not a link]]\n```\n## Still code\n````\n## Real\nSee [[Link]].\n";
+        let stats = note_stats("Note.md", content, None, 0);
+        assert_eq!((stats.heading_count, stats.link_count), (2, 1));
+
+        let tildes = "~~~\n```\n# In tildes\n~~~\n# Out\n";
+        assert_eq!(note_stats("Note.md", tildes, None, 0).heading_count, 1);
+    }
+}
@@ -0,0 +1,267 @@
+use wasm_bindgen::prelude::*;
+
+/// Context window assumed when the caller doesn't know the model's (matches our default num_ctx)
+pub const DEFAULT_CONTEXT_WINDOW: u32 = 4096;
+
+/// Floor for the content budget so a tiny or misreported context window still sends something
+const MIN_CONTENT_TOKENS: usize = 256;
+
+/// Appended where text was cut
+const TRUNCATION_MARKER: &str = "…";
+
+/// Han, kana, Hangul and CJK punctuation/fullwidth forms, which tokenize at roughly one token per char
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{1100}'..='\u{11FF}'
+        | '\u{3000}'..='\u{303F}'
+        | '\u{3040}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF00}'..='\u{FFEF}'
+        | '\u{20000}'..='\u{2A6DF}')
+}
+
+/// Rough token count for `text`. CJK chars count one token each; other words count the larger
+/// of 4/3 tokens per word and one token per 4 ASCII (2 non-ASCII) letters, so long identifiers
+/// and non-Latin scripts aren't undercounted; punctuation and symbols (dense in code) count half.
+#[wasm_bindgen]
+pub fn estimate_tokens(text: &str) -> usize {
+    let mut cjk = 0usize;
+    let mut words = 0usize;
+    let mut ascii_letters = 0usize;
+    let mut other_letters = 0usize;
+    let mut symbols = 0usize;
+    let mut in_word = false;
+
+    for c in text.chars() {
+        if is_cjk(c) {
+            cjk += 1;
+            in_word = false;
+        } else if c.is_alphanumeric() {
+            if !in_word {
+                words += 1;
+            }
+            in_word = true;
+            if c.is_ascii() {
+                ascii_letters += 1;
+            } else {
+                other_letters += 1;
+            }
+        } else {
+            in_word = false;
+            if !c.is_whitespace() {
+                symbols += 1;
+            }
+        }
+    }
+
+    let by_words = (words * 4).div_ceil(3);
+    let by_chars = ascii_letters.div_ceil(4) + other_letters.div_ceil(2);
+    cjk + by_words.max(by_chars) + symbols.div_ceil(2)
+}
+
+/// Tokens available for variable content: the context window minus the model's output and the
+/// fixed prompt template (`overhead_tokens`)
+pub fn prompt_budget(context_window: Option<u32>, output_tokens: usize, overhead_tokens: usize) -> usize {
+    let window = context_window.filter(|&w| w > 0).unwrap_or(DEFAULT_CONTEXT_WINDOW) as usize;
+    window.saturating_sub(output_tokens + overhead_tokens).max(MIN_CONTENT_TOKENS)
+}
+
+/// Whether `c` belongs to the grapheme cluster before it: a combining mark, variation selector,
+/// emoji skin tone or tag, or a zero-width joiner
+fn extends_cluster(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'
+        | '\u{20D0}'..='\u{20FF}'
+        | '\u{3099}'..='\u{309A}'
+        | '\u{200D}'
+        | '\u{FE00}'..='\u{FE0F}'
+        | '\u{1F3FB}'..='\u{1F3FF}'
+        | '\u{E0020}'..='\u{E007F}')
+}
+
+/// Largest byte offset of at most `max_bytes` where `text` can be cut: on a char boundary and
+/// not inside a cluster of combining marks or a ZWJ emoji sequence (which is dropped whole)
+pub(crate) fn cut_boundary(text: &str, max_bytes: usize) -> usize {
+    if max_bytes >= text.len() {
+        return text.len();
+    }
+    let mut cut = max_bytes;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    while let Some(prev) = text[..cut].chars().next_back() {
+        let splits_cluster = prev == '\u{200D}' || text[cut..].chars().next().is_some_and(extends_cluster);
+        if !splits_cluster {
+            break;
+        }
+        cut -= prev.len_utf8();
+    }
+    cut
+}
+
+/// Where to end a cut prefix: after the last sentence end or line break if that keeps at least
+/// half of it, otherwise after the last whitespace, otherwise at the cut itself
+pub(crate) fn natural_cut(prefix: &str) -> usize {
+    let sentence_end = prefix.char_indices()
+        .rev()
+        .find(|&(i, c)| {
+            let next = prefix[i + c.len_utf8()..].chars().next();
+            c == '\n'
+                || matches!(c, '。' | '！' | '？')
+                || (matches!(c, '.' | '!' | '?') && next.is_none_or(char::is_whitespace))
+        })
+        .map(|(i, c)| i + c.len_utf8());
+    if let Some(end) = sentence_end.filter(|&end| end * 2 >= prefix.len()) {
+        return end;
+    }
+    prefix.rfind(char::is_whitespace)
+        .filter(|&i| i > 0)
+        .unwrap_or(prefix.len())
+}
+
+/// Longest prefix of `text` estimated at no more than `max_tokens` (marker included), ending at
+/// a sentence or word boundary where possible. Text already within budget is returned unchanged.
+pub fn truncate_to_tokens(text: &str, max_tokens: usize) -> String {
+    if estimate_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+    let marker_tokens = estimate_tokens(TRUNCATION_MARKER);
+    if max_tokens <= marker_tokens {
+        return String::new();
+    }
+
+    // Binary search over char boundaries; the estimate only grows as the prefix does
+    let mut boundaries: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    boundaries.push(text.len());
+    let (mut low, mut high) = (0usize, boundaries.len() - 1);
+    while low < high {
+        let mid = (low + high).div_ceil(2);
+        if estimate_tokens(&text[..boundaries[mid]]) + marker_tokens <= max_tokens {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+
+    let prefix = &text[..boundaries[low]];
+    let kept = prefix[..natural_cut(prefix)].trim_end();
+    if kept.is_empty() {
+        return String::new();
+    }
+    format!("{}{}", kept, TRUNCATION_MARKER)
+}
+
+/// Fit prompt parts into `budget_tokens`. Each part has a priority (higher is more important);
+/// the lowest-priority parts are trimmed first (later parts before earlier ones on ties), at
+/// sentence boundaries, and dropped entirely if needed. Returns the parts in their original order.
+pub fn fit_to_budget(parts: Vec<(&str, u8)>, budget_tokens: usize) -> Vec<String> {
+    let mut fitted: Vec<String> = parts.iter().map(|(text, _)| text.to_string()).collect();
+    let costs: Vec<usize> = parts.iter().map(|(text, _)| estimate_tokens(text)).collect();
+    let mut total: usize = costs.iter().sum();
+    if total <= budget_tokens {
+        return fitted;
+    }
+
+    let mut order: Vec<usize> = (0..parts.len()).collect();
+    order.sort_by_key(|&i| (parts[i].1, std::cmp::Reverse(i)));
+
+    for i in order {
+        let excess = total - budget_tokens;
+        if costs[i] <= excess {
+            fitted[i].clear();
+            total -= costs[i];
+        } else {
+            fitted[i] = truncate_to_tokens(parts[i].0, costs[i] - excess);
+            total = total - costs[i] + estimate_tokens(&fitted[i]);
+        }
+        if total <= budget_tokens {
+            break;
+        }
+    }
+
+    fitted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILY: &str = "👩\u{200D}👩\u{200D}👧";
+
+    #[test]
+    fn cut_boundary_drops_a_zwj_sequence_straddling_the_limit() {
+        let text = format!("ab {} cd", FAMILY);
+        let start = 3;
+        let end = start + FAMILY.len();
+        for limit in start..end {
+            assert_eq!(cut_boundary(&text, limit), start, "limit {}", limit);
+        }
+        assert_eq!(cut_boundary(&text, end), end);
+        assert_eq!(cut_boundary(&text, text.len() + 10), text.len());
+    }
+
+    #[test]
+    fn cut_boundary_keeps_skin_tones_and_combining_marks_with_their_base() {
+        let text = "ok 👍🏽 e\u{0301}";
+        let thumbs = 3;
+        for limit in thumbs..thumbs + "👍🏽".len() {
+            assert_eq!(cut_boundary(text, limit), thumbs, "limit {}", limit);
+        }
+        let accent = text.len() - 2;
+        assert_eq!(cut_boundary(text, accent), accent - 1);
+    }
+
+    #[test]
+    fn natural_cut_never_ends_inside_a_zwj_sequence() {
+        let text = format!("Family photo {} from the trip. More {} later", FAMILY, FAMILY);
+        for limit in 1..text.len() {
+            let prefix = &text[..cut_boundary(&text, limit)];
+            let kept = &prefix[..natural_cut(prefix)];
+            assert!(!kept.ends_with('\u{200D}'), "limit {}: {:?}", limit, kept);
+            let families = kept.matches(FAMILY).count();
+            assert_eq!(kept.matches('👧').count(), families, "limit {}: {:?}", limit, kept);
+            assert_eq!(kept.matches('👩').count(), families * 2, "limit {}: {:?}", limit, kept);
+        }
+    }
+
+    /// Long documents in scripts whose chars take two to four bytes
+    fn multibyte_documents() -> [String; 3] {
+        [
+            "Привет, мир. Это длинный документ о заметках и ссылках между ними. ".repeat(200),
+            "日本語の文章です。これは長いメモで、リンクの候補を探します。".repeat(200),
+            format!("Trip {} with 🎉 and 👍🏽 fun. ", FAMILY).repeat(200),
+        ]
+    }
+
+    #[test]
+    fn truncate_to_tokens_cuts_multibyte_text_at_sentence_ends() {
+        for doc in multibyte_documents() {
+            for max_tokens in [5, 64, 257, 1000] {
+                let cut = truncate_to_tokens(&doc, max_tokens);
+                let Some(kept) = cut.strip_suffix(TRUNCATION_MARKER) else { panic!("no marker: {:?}", cut) };
+                assert!(doc.starts_with(kept), "{:?}", kept);
+                assert!(estimate_tokens(&cut) <= max_tokens, "{} tokens over {}", estimate_tokens(&cut), max_tokens);
+                if max_tokens >= 64 {
+                    assert!(kept.ends_with(['.', '。']), "{} tokens end mid-sentence", max_tokens);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fit_to_budget_trims_multibyte_parts_within_budget() {
+        let [cyrillic, cjk, emoji] = multibyte_documents();
+        let parts = vec![(cyrillic.as_str(), 0), (cjk.as_str(), 1), (emoji.as_str(), 2)];
+        for budget in [100, 1000, 3000] {
+            let fitted = fit_to_budget(parts.clone(), budget);
+            let total: usize = fitted.iter().map(|part| estimate_tokens(part)).sum();
+            assert!(total <= budget, "{} tokens over {}", total, budget);
+            for (part, (original, _)) in fitted.iter().zip(&parts) {
+                assert!(original.starts_with(part.trim_end_matches(TRUNCATION_MARKER)));
+            }
+        }
+    }
+}
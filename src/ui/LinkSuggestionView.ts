@@ -16,6 +16,8 @@ export interface LinkSuggestion {
     title: string;
     similarity: number;
     context: string;
+    forced?: boolean; // title appears in the note; kept regardless of score
+    unverified?: boolean; // contents not loaded in WASM and existing links not passed
     llm_score?: number;
     llm_reason?: string;
 }
@@ -983,9 +985,11 @@ export class LinkSuggestionView extends ItemView {
                         content,
                         title,
                         linkContext,
-                        this.plugin.settings.llmTemperature,
-                        this.plugin.settings.enableThinkingMode,
-                        this.plugin.settings.debugMode
+                        {
+                            temperature: this.plugin.settings.llmTemperature,
+                            enable_thinking: this.plugin.settings.enableThinkingMode,
+                            debug: this.plugin.settings.debugMode
+                        }
                     );
 
                     // Cache the result
@@ -449,8 +449,8 @@ export class ChatTab extends BaseTab {
                 this.plugin.settings.ollamaEndpoint,
                 model,
                 prompt,
-                temp,
-                false
+                false,
+                { temperature: temp }
             );
 
             this.history.pop();
@@ -633,8 +633,8 @@ export class ChatTab extends BaseTab {
                 this.plugin.settings.ollamaEndpoint,
                 model,
                 prompt,
-                CONSTANTS.SMART_INSERT_TEMPERATURE,
-                false
+                false,
+                { temperature: CONSTANTS.SMART_INSERT_TEMPERATURE }
             );
 
             // Insert at cursor
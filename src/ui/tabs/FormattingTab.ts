@@ -10,6 +10,14 @@ interface GrammarIssue {
     original: string;
     corrected: string;
     reason: string;
+    /** Range of `original` in the note when it was located: chars, bytes and UTF-16 units */
+    start?: number | null;
+    end?: number | null;
+    line?: number | null;
+    offset_bytes?: number | null;
+    end_bytes?: number | null;
+    offset_utf16?: number | null;
+    end_utf16?: number | null;
 }
 
 /**
@@ -144,9 +152,11 @@ export class FormattingTab extends BaseTab {
                 model,
                 content,
                 allTags,
-                this.plugin.settings.llmTemperature,
-                this.plugin.settings.enableThinkingMode,
-                this.plugin.settings.debugMode
+                {
+                    temperature: this.plugin.settings.llmTemperature,
+                    enable_thinking: this.plugin.settings.enableThinkingMode,
+                    debug: this.plugin.settings.debugMode
+                }
             );
 
             const timeoutMs = this.plugin.settings.llmTimeout || 30000;
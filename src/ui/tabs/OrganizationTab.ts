@@ -110,9 +110,11 @@ export class OrganizationTab extends BaseTab {
                 this.currentFile.basename,
                 content,
                 allFolders,
-                this.plugin.settings.llmTemperature,
-                this.plugin.settings.enableThinkingMode,
-                this.plugin.settings.debugMode
+                {
+                    temperature: this.plugin.settings.llmTemperature,
+                    enable_thinking: this.plugin.settings.enableThinkingMode,
+                    debug: this.plugin.settings.debugMode
+                }
             );
 
             const timeoutMs = this.plugin.settings.llmTimeout || 30000;
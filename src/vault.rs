@@ -1,5 +1,11 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::exclusions::PathFilter;
+use crate::frontmatter::split_frontmatter;
+use crate::links::FenceTracker;
+use crate::outline::parse_headings;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct VaultFile {
@@ -15,9 +21,255 @@ pub struct ScanResult {
     pub errors: Vec<String>,
 }
 
+/// A text card on a canvas, which can be embedded like a small note
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CanvasTextNode {
+    pub id: String,
+    pub text: String,
+}
+
+/// An arrow between two canvas nodes, by node id
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CanvasEdge {
+    pub id: String,
+    pub from_node: String,
+    pub to_node: String,
+    pub label: Option<String>,
+}
+
+/// What `parse_canvas` found in a `.canvas` file. Invalid JSON gives an empty result with `error` set.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CanvasContent {
+    /// Vault paths of the file nodes, without duplicates, in node order
+    pub files: Vec<String>,
+    pub text_nodes: Vec<CanvasTextNode>,
+    /// Only edges whose ends are both nodes of the canvas
+    pub edges: Vec<CanvasEdge>,
+    pub error: Option<String>,
+}
+
+/// Whether a vault path is an Obsidian canvas
+pub fn is_canvas_path(path: &str) -> bool {
+    path.to_lowercase().ends_with(".canvas")
+}
+
+/// Read a canvas (JSON Canvas: `nodes` and `edges` arrays). Node types other than file and text,
+/// and fields this doesn't know, are ignored so newer canvases still give what can be read.
+pub(crate) fn read_canvas(content: &str) -> CanvasContent {
+    let invalid = |error: String| CanvasContent { error: Some(error), ..CanvasContent::default() };
+    if content.trim().is_empty() {
+        return CanvasContent::default();
+    }
+    let root = match serde_json::from_str::<Value>(content) {
+        Ok(Value::Object(root)) => root,
+        Ok(_) => return invalid("Canvas is not a JSON object".to_string()),
+        Err(e) => return invalid(format!("Invalid canvas JSON: {}", e)),
+    };
+    let list = |key: &str| match root.get(key) {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::Array(items)) => Ok(items.iter().filter_map(Value::as_object).collect()),
+        Some(_) => Err(format!("Canvas \"{}\" is not an array", key)),
+    };
+    let (nodes, edges) = match (list("nodes"), list("edges")) {
+        (Ok(nodes), Ok(edges)) => (nodes, edges),
+        (Err(e), _) | (_, Err(e)) => return invalid(e),
+    };
+    let text = |object: &serde_json::Map<String, Value>, key: &str| object.get(key).and_then(Value::as_str).map(str::to_string);
+
+    let mut canvas = CanvasContent::default();
+    let mut ids = HashSet::new();
+    for node in nodes {
+        let id = text(node, "id").unwrap_or_default();
+        ids.insert(id.clone());
+        match node.get("type").and_then(Value::as_str) {
+            Some("file") => {
+                if let Some(file) = text(node, "file").filter(|f| !f.trim().is_empty()) {
+                    if !canvas.files.contains(&file) {
+                        canvas.files.push(file);
+                    }
+                }
+            }
+            Some("text") => {
+                if let Some(text) = text(node, "text").filter(|t| !t.trim().is_empty()) {
+                    canvas.text_nodes.push(CanvasTextNode { id, text });
+                }
+            }
+            _ => {}
+        }
+    }
+    for edge in edges {
+        let (Some(from_node), Some(to_node)) = (text(edge, "fromNode"), text(edge, "toNode")) else {
+            continue;
+        };
+        if ids.contains(&from_node) && ids.contains(&to_node) {
+            canvas.edges.push(CanvasEdge {
+                id: text(edge, "id").unwrap_or_default(),
+                from_node,
+                to_node,
+                label: text(edge, "label"),
+            });
+        }
+    }
+    canvas
+}
+
+/// Files, text cards and edges of an Obsidian `.canvas` file, as CanvasContent
+#[wasm_bindgen]
+pub fn parse_canvas(content: &str) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(&read_canvas(content))
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in parse_canvas: {}", e)))
+}
+
+/// A piece of a note for section-level embedding, from `split_into_sections`. Offsets are chars
+/// into the note and `text` is exactly that range.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NoteSection {
+    /// Heading path plus the chunk's index within its section ("Setup > Install#0"); stays the
+    /// same across edits that don't touch the headings above it or the chunks before it
+    pub id: String,
+    /// Enclosing headings, outermost first; empty before the first heading
+    pub heading_path: Vec<String>,
+    pub start_offset: usize,
+    pub end_offset: usize,
+    pub text: String,
+}
+
+/// Section IDs that differ between two `split_into_sections` results
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SectionDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Same ID, different text
+    pub changed: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+/// Byte ranges of the blocks a section can be split between: paragraphs separated by blank
+/// lines, with fenced code kept whole even across blank lines. Tables have no blank lines, so
+/// each is one paragraph.
+fn section_blocks(content: &str, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut blocks = Vec::new();
+    let mut block_start = start;
+    let mut offset = start;
+    let mut fence = FenceTracker::default();
+    for line in content[start..end].split_inclusive('\n') {
+        fence.line(line);
+        offset += line.len();
+        // A blank line ends the paragraph above it
+        if !fence.is_open() && line.trim().is_empty() && !content[block_start..offset - line.len()].trim().is_empty() {
+            blocks.push((block_start, offset));
+            block_start = offset;
+        }
+    }
+    if block_start < end {
+        blocks.push((block_start, end));
+    }
+    blocks
+}
+
+/// Split a note's body into sections at its headings, and sections longer than `max_chars`
+/// chars into chunks of whole paragraphs (a paragraph, code block or table longer than that
+/// stays in one chunk). The frontmatter and blank pieces are left out.
+pub(crate) fn note_sections(content: &str, max_chars: usize) -> Vec<NoteSection> {
+    let max_chars = max_chars.max(1);
+    let body_start = split_frontmatter(content).1;
+    let headings = parse_headings(content);
+
+    // (start, end, heading path) of each heading's own text, up to the next heading of any level
+    let mut sections: Vec<(usize, usize, Vec<String>)> = Vec::new();
+    let first = headings.first().map_or(content.len(), |h| h.start);
+    sections.push((body_start, first, Vec::new()));
+    let mut path: Vec<(usize, String)> = Vec::new();
+    for (i, heading) in headings.iter().enumerate() {
+        path.retain(|(level, _)| *level < heading.level);
+        path.push((heading.level, heading.text.clone()));
+        let end = headings.get(i + 1).map_or(content.len(), |next| next.start);
+        sections.push((heading.start, end, path.iter().map(|(_, text)| text.clone()).collect()));
+    }
+
+    let char_offset = |byte: usize| content[..byte].chars().count();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut result = Vec::new();
+    for (start, end, heading_path) in sections {
+        if content[start..end].trim().is_empty() {
+            continue;
+        }
+        // Repeated heading paths ("Notes" twice under one parent) are told apart by occurrence
+        let mut key = heading_path.join(" > ");
+        let occurrence = seen.entry(key.clone()).or_insert(0);
+        *occurrence += 1;
+        if *occurrence > 1 {
+            key = format!("{} ({})", key, occurrence);
+        }
+
+        let mut chunks: Vec<(usize, usize)> = Vec::new();
+        if content[start..end].chars().count() <= max_chars {
+            chunks.push((start, end));
+        } else {
+            for (block_start, block_end) in section_blocks(content, start, end) {
+                match chunks.last_mut() {
+                    Some(last) if content[last.0..block_end].chars().count() <= max_chars => last.1 = block_end,
+                    _ => chunks.push((block_start, block_end)),
+                }
+            }
+        }
+        for (index, (chunk_start, chunk_end)) in chunks.into_iter().enumerate() {
+            result.push(NoteSection {
+                id: format!("{}#{}", key, index),
+                heading_path: heading_path.clone(),
+                start_offset: char_offset(chunk_start),
+                end_offset: char_offset(chunk_end),
+                text: content[chunk_start..chunk_end].to_string(),
+            });
+        }
+    }
+    result
+}
+
+/// Sections of a note for section-level embeddings (see NoteSection): split at headings, and
+/// between paragraphs where a section is longer than `max_chars` chars. Code blocks and tables
+/// are never split.
+#[wasm_bindgen]
+pub fn split_into_sections(content: &str, max_chars: usize) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(&note_sections(content, max_chars))
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in split_into_sections: {}", e)))
+}
+
+/// Compare two `split_into_sections` results (as JSON arrays) by ID, so only the sections that
+/// were added or changed need embedding again. Returns a SectionDiff with IDs in new-note order
+/// (removed ones in old-note order).
+#[wasm_bindgen]
+pub fn diff_sections(old_json: &str, new_json: &str) -> Result<JsValue, JsValue> {
+    let parse = |json: &str| serde_json::from_str::<Vec<NoteSection>>(json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid sections JSON: {}", e)));
+    let (old, new) = (parse(old_json)?, parse(new_json)?);
+    let old_text: HashMap<&str, &str> = old.iter().map(|s| (s.id.as_str(), s.text.as_str())).collect();
+    let new_ids: HashSet<&str> = new.iter().map(|s| s.id.as_str()).collect();
+
+    let mut diff = SectionDiff::default();
+    for section in &new {
+        match old_text.get(section.id.as_str()) {
+            None => diff.added.push(section.id.clone()),
+            Some(&text) if text != section.text => diff.changed.push(section.id.clone()),
+            Some(_) => diff.unchanged.push(section.id.clone()),
+        }
+    }
+    diff.removed = old.iter().filter(|s| !new_ids.contains(s.id.as_str())).map(|s| s.id.clone()).collect();
+    serde_wasm_bindgen::to_value(&diff)
+        .map_err(|e| JsValue::from_str(&format!("Serialization error in diff_sections: {}", e)))
+}
+
+/// Files added to a scanner, one per path
 #[wasm_bindgen]
 pub struct VaultScanner {
-    files: Vec<VaultFile>,
+    files: BTreeMap<String, VaultFile>,
+    path_filter: PathFilter,
+}
+
+impl Default for VaultScanner {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
@@ -25,28 +277,109 @@ impl VaultScanner {
     #[wasm_bindgen(constructor)]
     pub fn new() -> VaultScanner {
         VaultScanner {
-            files: Vec::new(),
+            files: BTreeMap::new(),
+            path_filter: PathFilter::default(),
         }
     }
 
+    /// Add a scanned file. A path that was already added keeps whichever version has the newest
+    /// `modified`, so re-scanning doesn't duplicate it.
     pub fn add_file(&mut self, path: String, content: String, modified: f64) {
-        self.files.push(VaultFile {
+        if self.files.get(&path).is_some_and(|existing| existing.modified > modified) {
+            return;
+        }
+        self.update_file(path, content, modified);
+    }
+
+    /// Store this version of a file, replacing any other
+    pub fn update_file(&mut self, path: String, content: String, modified: f64) {
+        self.files.insert(path.clone(), VaultFile {
             path,
             content,
             modified,
         });
     }
 
-    pub fn get_files(&self) -> JsValue {
-        serde_wasm_bindgen::to_value(&self.files).unwrap()
+    /// Drop a deleted file; false if it wasn't added
+    pub fn remove_file(&mut self, path: &str) -> bool {
+        self.files.remove(path).is_some()
+    }
+
+    pub fn has_file(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Leave files matching any of these patterns out of get_files and the filter_* lists:
+    /// folders ("Templates/") or globs ("*.excalidraw.md", "Daily/**"), case-sensitive
+    pub fn set_exclusions(&mut self, patterns: Vec<String>) {
+        self.path_filter.set_exclusions(patterns);
+    }
+
+    /// Only list files matching one of these patterns; an empty list lists everything again
+    pub fn set_inclusions(&mut self, patterns: Vec<String>) {
+        self.path_filter.set_inclusions(patterns);
     }
 
-    pub fn filter_markdown_files(&self) -> JsValue {
-        let md_files: Vec<&VaultFile> = self.files
-            .iter()
+    /// Files not filtered out by exclusions or inclusions, sorted by path
+    fn included_files(&self) -> impl Iterator<Item = &VaultFile> {
+        self.files.values().filter(|f| self.path_filter.allows(&f.path))
+    }
+
+    /// Every included file, sorted by path
+    pub fn get_files(&self) -> Result<JsValue, JsValue> {
+        let files: Vec<&VaultFile> = self.included_files().collect();
+        serde_wasm_bindgen::to_value(&files)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in get_files: {}", e)))
+    }
+
+    pub fn filter_markdown_files(&self) -> Result<JsValue, JsValue> {
+        let md_files: Vec<&VaultFile> = self.included_files()
             .filter(|f| f.path.ends_with(".md"))
             .collect();
 
-        serde_wasm_bindgen::to_value(&md_files).unwrap()
+        serde_wasm_bindgen::to_value(&md_files)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in filter_markdown_files: {}", e)))
+    }
+
+    pub fn filter_canvas_files(&self) -> Result<JsValue, JsValue> {
+        let canvas_files: Vec<&VaultFile> = self.included_files()
+            .filter(|f| is_canvas_path(&f.path))
+            .collect();
+
+        serde_wasm_bindgen::to_value(&canvas_files)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in filter_canvas_files: {}", e)))
+    }
+
+    /// ScanResult for the added files: how many there are, how many were modified after
+    /// `since` (ms timestamp), and an error for each file with no content
+    pub fn scan_summary(&self, since: f64) -> Result<JsValue, JsValue> {
+        let result = ScanResult {
+            files_scanned: self.files.len(),
+            files_updated: self.files.values().filter(|f| f.modified > since).count(),
+            errors: self.files.values()
+                .filter(|f| f.content.trim().is_empty())
+                .map(|f| format!("Empty file: {}", f.path))
+                .collect(),
+        };
+        serde_wasm_bindgen::to_value(&result)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error in scan_summary: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_sections_split_between_paragraphs_but_not_inside_code() {
+        let code = "````markdown\n```python\nx = 1\n\ny = 2\n```\n\nstill code\n````\n";
+        let content = format!("# Notes\nFirst paragraph.\n\n{}\nLast paragraph.\n", code);
+        let sections = note_sections(&content, 10);
+        let texts: Vec<&str> = sections.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["# Notes\nFirst paragraph.\n\n", format!("{}\n", code).as_str(), "Last paragraph.\n"]);
     }
 }